@@ -0,0 +1,274 @@
+use crate::kernel::{Tick, Yield};
+use crate::power::SleepMode;
+use crate::time::{Duration, Instant};
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::interrupt::{CriticalSection, Nr};
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+use drogue_async::task::spawn;
+use heapless::{consts::*, Vec};
+
+/// The hardware-specific operations the kernel relies upon.
+///
+/// A `ConnectedKernel<K>` holds a `'static` reference to its backend,
+/// defaulting to `CortexM`. The `Host` backend allows a tree to be
+/// started and exercised on a development machine.
+pub trait Backend {
+    /// Unmask (enable) the given IRQ line.
+    fn unmask(&self, irq: u8);
+
+    /// Enter the low-power `mode`, provided `idle()` still holds.
+    ///
+    /// Implementations must evaluate `idle()` with interrupts masked,
+    /// in such a way that an interrupt arriving after the evaluation
+    /// still wakes the device. The `wake_sources` are the IRQs
+    /// registered with the kernel, which a vendor-specific backend
+    /// may need to configure as wake-up lines for stop modes.
+    fn sleep(&self, mode: SleepMode, wake_sources: &[u8], idle: &dyn Fn() -> bool);
+
+    /// Pend the given IRQ line, so that its handler runs at its own
+    /// priority, as though the interrupt had triggered.
+    fn pend(&self, irq: u8);
+
+    /// The current time, according to this backend's time source.
+    fn now(&self) -> Instant;
+}
+
+/// Backend for Cortex-M microcontrollers, using the `NVIC`, and
+/// the `SysTick` as a millisecond time source.
+pub struct CortexM;
+
+impl Backend for CortexM {
+    fn unmask(&self, irq: u8) {
+        unsafe {
+            NVIC::unmask(IrqNr(irq));
+        }
+    }
+
+    fn sleep(&self, mode: SleepMode, _wake_sources: &[u8], idle: &dyn Fn() -> bool) {
+        cortex_m::interrupt::free(|_| {
+            if !idle() {
+                return;
+            }
+            match mode {
+                SleepMode::WaitForInterrupt => cortex_m::asm::wfi(),
+                SleepMode::WaitForEvent => cortex_m::asm::wfe(),
+                SleepMode::Stop => unsafe {
+                    let scb = &*SCB::ptr();
+                    scb.scr.modify(|scr| scr | SCR_SLEEPDEEP);
+                    cortex_m::asm::wfi();
+                    scb.scr.modify(|scr| scr & !SCR_SLEEPDEEP);
+                },
+            }
+        })
+    }
+
+    fn pend(&self, irq: u8) {
+        NVIC::pend(IrqNr(irq));
+    }
+
+    fn now(&self) -> Instant {
+        loop {
+            let high = TICKS_HIGH.load(Ordering::Acquire);
+            let low = TICKS_LOW.load(Ordering::Acquire);
+            if high == TICKS_HIGH.load(Ordering::Acquire) {
+                return Instant::from_millis(((high as u64) << 32) | low as u64);
+            }
+        }
+    }
+}
+
+const SCR_SLEEPDEEP: u32 = 1 << 2;
+
+static TICKS_LOW: AtomicU32 = AtomicU32::new(0);
+static TICKS_HIGH: AtomicU32 = AtomicU32::new(0);
+
+/// Configure the `SysTick` to fire every millisecond, given the
+/// frequency of the core clock.
+#[doc(hidden)]
+pub fn start_systick(core_hz: u32) {
+    unsafe {
+        let syst = &*SYST::PTR;
+        syst.rvr.write(core_hz / 1000 - 1);
+        syst.cvr.write(0);
+        // processor clock source, interrupt and counter enabled
+        syst.csr.write(0b111);
+    }
+}
+
+/// Advance the `CortexM` time source by one millisecond.
+#[doc(hidden)]
+pub fn systick() {
+    let low = TICKS_LOW.load(Ordering::Acquire).wrapping_add(1);
+    if low == 0 {
+        TICKS_HIGH.store(TICKS_HIGH.load(Ordering::Acquire) + 1, Ordering::Release);
+    }
+    TICKS_LOW.store(low, Ordering::Release);
+}
+
+/// Run `f` with interrupts masked, so that state shared with interrupt
+/// handlers, such as the `SysTick`'s, may be borrowed through a
+/// `cortex_m::interrupt::Mutex` without being preempted.
+///
+/// On the host, where interrupts are only ever simulated from the same
+/// thread, `f` is simply run.
+pub(crate) fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    #[cfg(target_arch = "arm")]
+    {
+        cortex_m::interrupt::free(f)
+    }
+    #[cfg(not(target_arch = "arm"))]
+    {
+        f(unsafe { &CriticalSection::new() })
+    }
+}
+
+/// Backend for running a tree on the host.
+///
+/// Interrupts are simulated by invoking `ConnectedKernel::interrupt(...)`
+/// directly. Unmasked IRQ lines and sleeps are recorded for inspection,
+/// but the host never actually sleeps. Time only advances when
+/// `advance(...)` is invoked, ticking any attached kernel as the
+/// `SysTick` would, then interrupting it with any IRQ pended meanwhile.
+pub struct Host {
+    now: Cell<Instant>,
+    kernel: Cell<Option<&'static dyn Tick>>,
+    unmasked: RefCell<Vec<u8, U16>>,
+    pending: RefCell<Vec<u8, U16>>,
+    sleeps: Cell<usize>,
+    last_sleep: RefCell<Option<(SleepMode, Vec<u8, U16>)>>,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self {
+            now: Cell::new(Instant::from_millis(0)),
+            kernel: Cell::new(None),
+            unmasked: RefCell::new(Vec::new()),
+            pending: RefCell::new(Vec::new()),
+            sleeps: Cell::new(0),
+            last_sleep: RefCell::new(None),
+        }
+    }
+
+    /// Tick `kernel` whenever the simulated time advances, and interrupt it
+    /// with the IRQs pended.
+    pub fn attach(&self, kernel: &'static dyn Tick) {
+        self.kernel.set(Some(kernel));
+    }
+
+    /// Advance the simulated time by a millisecond upon each pass of the
+    /// executor, so that delays and timeouts expire without a `SysTick`.
+    pub fn drive(&'static self) {
+        spawn("systick", async move {
+            loop {
+                Yield::new().await;
+                self.advance(Duration::from_millis(1));
+            }
+        });
+    }
+
+    /// Advance the simulated time source, ticking the attached kernel, then
+    /// interrupting it with any IRQ pended meanwhile, as the `NVIC` would
+    /// once the `SysTick` handler returns.
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+        if let Some(kernel) = self.kernel.get() {
+            kernel.tick();
+            loop {
+                // released before each interrupt, which may pend another
+                let irq = match self.pending.borrow_mut().pop() {
+                    Some(irq) => irq,
+                    None => break,
+                };
+                kernel.interrupt(irq as i16);
+            }
+        }
+    }
+
+    /// Determine if the given IRQ line has been unmasked by the kernel.
+    pub fn is_unmasked(&self, irq: u8) -> bool {
+        self.unmasked.borrow().contains(&irq)
+    }
+
+    /// The number of times the kernel has put the host to sleep.
+    pub fn sleeps(&self) -> usize {
+        self.sleeps.get()
+    }
+
+    /// The mode and wake sources of the most recent sleep, if any.
+    pub fn last_sleep(&self) -> Option<(SleepMode, Vec<u8, U16>)> {
+        self.last_sleep.borrow().clone()
+    }
+}
+
+// SAFETY: without `std` there is but the one thread, running the executor,
+// which simulates every interrupt upon itself. With `std`, the host may not
+// be shared between threads, so is instead leaked by `device!`.
+#[cfg(not(any(test, feature = "std")))]
+unsafe impl Send for Host {}
+#[cfg(not(any(test, feature = "std")))]
+unsafe impl Sync for Host {}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for Host {
+    fn unmask(&self, irq: u8) {
+        let mut unmasked = self.unmasked.borrow_mut();
+        if !unmasked.contains(&irq) {
+            unmasked.push(irq).ok().unwrap();
+        }
+    }
+
+    fn sleep(&self, mode: SleepMode, wake_sources: &[u8], idle: &dyn Fn() -> bool) {
+        if idle() {
+            self.sleeps.set(self.sleeps.get() + 1);
+            self.last_sleep
+                .borrow_mut()
+                .replace((mode, Vec::from_slice(wake_sources).unwrap()));
+        }
+    }
+
+    fn pend(&self, irq: u8) {
+        let mut pending = self.pending.borrow_mut();
+        if !pending.contains(&irq) {
+            pending.push(irq).ok().unwrap();
+        }
+    }
+
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+struct IrqNr(u8);
+
+unsafe impl Nr for IrqNr {
+    fn nr(&self) -> u8 {
+        self.0
+    }
+}
+
+#[doc(hidden)]
+pub fn irq_number<I: Nr>(irq: I) -> i16 {
+    irq.nr() as i16
+}
+
+#[doc(hidden)]
+pub fn halt() -> ! {
+    loop {
+        cortex_m::asm::bkpt();
+    }
+}
+
+#[doc(hidden)]
+pub fn reset() -> ! {
+    SCB::sys_reset()
+}
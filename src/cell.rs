@@ -0,0 +1,133 @@
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const EMPTY: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+
+/// A write-once cell for placing a value, such as a `ConnectedKernel<K>`,
+/// in `static` memory without resorting to `static mut`.
+///
+/// The cell is created empty in a `const` context and initialized exactly
+/// once at runtime, yielding a `&'static` reference which satisfies the
+/// `'static` requirements of `Kernel`, `Component` and `Interrupt` trees.
+///
+/// As a `static` may be shared between threads, the value must be both
+/// `Send` and `Sync`, which a `ConnectedKernel<K>` is only without `std`,
+/// upon a single core.
+///
+/// ```
+/// use drogue_device::cell::StaticCell;
+///
+/// static VALUE: StaticCell<u32> = StaticCell::new();
+///
+/// let value: &'static u32 = VALUE.init(42);
+/// assert_eq!(*value, 42);
+/// assert_eq!(VALUE.try_get(), Some(&42));
+/// ```
+pub struct StaticCell<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> StaticCell<T> {
+    /// Create a new, empty cell.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(EMPTY),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Initialize the cell with `value`, returning a `'static` reference to it.
+    ///
+    /// # Panics
+    ///
+    /// If the cell has already been initialized.
+    pub fn init(&'static self, value: T) -> &'static T {
+        match self.try_init(value) {
+            Some(value) => value,
+            None => panic!("StaticCell already initialized"),
+        }
+    }
+
+    /// Initialize the cell with `value`, returning a `'static` reference to it,
+    /// or `None` if the cell has already been initialized.
+    pub fn try_init(&'static self, value: T) -> Option<&'static T> {
+        if self
+            .state
+            .compare_exchange(EMPTY, INITIALIZING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return None;
+        }
+
+        let value = unsafe {
+            let slot = &mut *self.value.get();
+            slot.as_mut_ptr().write(value);
+            &*slot.as_ptr()
+        };
+        self.state.store(READY, Ordering::Release);
+        Some(value)
+    }
+
+    /// Retrieve the contained value, if the cell has been initialized.
+    ///
+    /// This is primarily useful from exception and interrupt handlers
+    /// which may need to reach the kernel after it has been placed.
+    pub fn try_get(&'static self) -> Option<&'static T> {
+        if self.state.load(Ordering::Acquire) == READY {
+            Some(unsafe { &*(&*self.value.get()).as_ptr() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for StaticCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Only shared references are ever handed out, and only once the value
+// has been fully written, possibly from another context than those reading
+// it, so the value must be safe both to move between and to share among them.
+unsafe impl<T: Send + Sync> Sync for StaticCell<T> {}
+
+/// Place `value` in memory for the remainder of the program, as `device!`
+/// does with `std`, where a kernel may not be shared by a `StaticCell`.
+#[cfg(any(test, feature = "std"))]
+#[doc(hidden)]
+pub fn leak<T>(value: T) -> &'static T {
+    std::boxed::Box::leak(std::boxed::Box::new(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StaticCell;
+
+    #[test]
+    fn init_once() {
+        static CELL: StaticCell<u32> = StaticCell::new();
+
+        assert_eq!(CELL.try_get(), None);
+        assert_eq!(*CELL.init(42), 42);
+        assert_eq!(CELL.try_get(), Some(&42));
+        assert_eq!(CELL.try_init(43), None);
+        assert_eq!(CELL.try_get(), Some(&42));
+    }
+
+    #[test]
+    #[should_panic]
+    fn init_twice_panics() {
+        static CELL: StaticCell<u32> = StaticCell::new();
+
+        CELL.init(1);
+        CELL.init(2);
+    }
+}
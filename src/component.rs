@@ -2,9 +2,13 @@ use crate::context::UpstreamContext;
 use crate::fifo::{AsyncConsumer, AsyncFifo, AsyncProducer};
 use crate::handler::{Handler, Sink};
 use crate::interrupt::Interruptable;
-use core::cell::{RefCell, UnsafeCell};
-use heapless::consts::*;
+use crate::power::{SleepListener, SleepState};
+use crate::time::{Delay, Duration, Instant};
+use crate::watchdog::Liveness;
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::task::Waker;
 pub use drogue_async::task::spawn;
+use heapless::consts::*;
 
 /// A non-root, but possibly leaf (or middle) portion of the component tree.
 ///
@@ -30,6 +34,17 @@ pub trait Component: Sized {
     /// and `ctx.receive().await` may be used to asynchronously receive
     /// messages of `::InboundMessage` type using futures.
     fn start(&'static mut self, ctx: &'static ComponentContext<Self>);
+
+    /// Invoked before the device enters the given sleep state,
+    /// allowing peripherals to be reconfigured, once the component has
+    /// called `ctx.notify_sleep()`.
+    ///
+    /// As the component's tasks may hold it meanwhile, only a shared
+    /// reference is given.
+    fn on_sleep(&self, _state: SleepState) {}
+
+    /// Invoked after the device leaves the given sleep state.
+    fn on_wake(&self, _state: SleepState) {}
 }
 
 /// Context provided to the component upon `start(...)`.
@@ -40,6 +55,9 @@ where
     component: &'static ConnectedComponent<C>,
     consumer: UnsafeCell<AsyncConsumer<'static, C::InboundMessage, U32>>,
     upstream: &'static dyn UpstreamContext<C::OutboundMessage>,
+    sleep_limit: Cell<SleepState>,
+    sleep_notified: Cell<bool>,
+    liveness: Liveness,
 }
 
 impl<C: Component> ComponentContext<C> {
@@ -52,6 +70,9 @@ impl<C: Component> ComponentContext<C> {
             component,
             consumer: UnsafeCell::new(consumer),
             upstream,
+            sleep_limit: Cell::new(SleepState::Stop),
+            sleep_notified: Cell::new(false),
+            liveness: Liveness::new(core::any::type_name::<C>()),
         }
     }
 
@@ -77,6 +98,76 @@ impl<C: Component> ComponentContext<C> {
     pub async fn receive(&'static self) -> C::InboundMessage {
         unsafe { (&mut *self.consumer.get()).dequeue().await }
     }
+
+    /// Declare the deepest sleep state this component can currently
+    /// tolerate, replacing any previous declaration.
+    ///
+    /// For instance, a UART actively receiving may limit the device to
+    /// `SleepState::Sleep` so that its clocks keep running. The kernel
+    /// never enters a state deeper than that tolerated by every component.
+    pub fn limit_sleep(&self, state: SleepState) {
+        let previous = self.sleep_limit.replace(state);
+        if previous != state {
+            self.upstream.vote_sleep(previous, state)
+        }
+    }
+
+    /// Have the kernel invoke this component's `on_sleep(...)` and
+    /// `on_wake(...)` around each sleep of the device, which it otherwise
+    /// does not.
+    pub fn notify_sleep(&self) {
+        if !self.sleep_notified.replace(true) {
+            self.upstream.register_sleep_listener(self.component);
+        }
+    }
+
+    /// The current time, according to the kernel's time source.
+    pub fn now(&self) -> Instant {
+        self.upstream.now()
+    }
+
+    /// The context of the parent, to which outbound messages are sent,
+    /// for tasks which may also be run without a kernel in tests.
+    pub(crate) fn upstream(&self) -> &'static dyn UpstreamContext<C::OutboundMessage> {
+        self.upstream
+    }
+
+    /// Wait, *asynchronously*, for at least the given duration
+    /// according to the kernel's time source.
+    pub async fn delay(&self, duration: Duration) {
+        Delay::new(self.upstream, self.now() + duration).await
+    }
+
+    /// Register this component with the kernel's watchdog, declaring
+    /// that it will `checkin()` at least once every `deadline`.
+    ///
+    /// Should the component fail to do so, the kernel stops feeding the
+    /// hardware watchdog. Registering again changes the deadline.
+    pub fn register_watchdog(&'static self, deadline: Duration) {
+        let registered = self.liveness.is_registered();
+        self.liveness.set_deadline(deadline, self.now());
+        if !registered {
+            self.upstream.register_liveness(&self.liveness);
+        }
+    }
+
+    /// Check in with the kernel's watchdog, demonstrating this component
+    /// is still making progress.
+    pub fn checkin(&self) {
+        self.liveness.checkin(self.now())
+    }
+
+    /// Vote to keep the device awake, or withdraw a previous vote.
+    ///
+    /// This is equivalent to limiting sleep to `SleepState::Awake`,
+    /// or lifting any limit.
+    pub fn keep_awake(&self, awake: bool) {
+        if awake {
+            self.limit_sleep(SleepState::Awake)
+        } else {
+            self.limit_sleep(SleepState::Stop)
+        }
+    }
 }
 
 impl<C: Component> Sink<C::OutboundMessage> for ComponentContext<C> {
@@ -115,6 +206,19 @@ impl<C: Component> ConnectedComponent<C> {
     /// This method should be invoked with the `ctx` passed to it's
     /// parent's own `start(...)` method.
     pub fn start(&'static self, upstream: &'static dyn UpstreamContext<C::OutboundMessage>) {
+        let (component, context) = self.connect(upstream);
+        unsafe { &mut *component }.start(context);
+    }
+
+    /// Connect this component's FIFO and context to `upstream`, without
+    /// starting it, so that tests may poll its tasks directly.
+    ///
+    /// The component is returned as a pointer, which must be dereferenced
+    /// mutably only once, as `start(...)` would.
+    pub(crate) fn connect(
+        &'static self,
+        upstream: &'static dyn UpstreamContext<C::OutboundMessage>,
+    ) -> (*mut C, &'static ComponentContext<C>) {
         let (producer, consumer) = unsafe { &mut *self.fifo.get() }.split();
         self.producer.borrow_mut().replace(producer);
 
@@ -122,7 +226,10 @@ impl<C: Component> ConnectedComponent<C> {
 
         unsafe {
             (&mut *self.context.get()).replace(context);
-            (&mut *self.component.get()).start((&*self.context.get()).as_ref().unwrap());
+            (
+                self.component.get(),
+                (&*self.context.get()).as_ref().unwrap(),
+            )
         }
     }
 
@@ -163,4 +270,38 @@ where
     fn register_irq(&self, irq: u8, interrupt: &'static dyn Interruptable) {
         self.upstream.register_irq(irq, interrupt)
     }
+
+    fn register_sleep_listener(&self, listener: &'static dyn SleepListener) {
+        self.upstream.register_sleep_listener(listener)
+    }
+
+    fn vote_sleep(&self, previous: SleepState, current: SleepState) {
+        self.upstream.vote_sleep(previous, current)
+    }
+
+    fn register_liveness(&self, liveness: &'static Liveness) {
+        self.upstream.register_liveness(liveness)
+    }
+
+    fn now(&self) -> Instant {
+        self.upstream.now()
+    }
+
+    fn schedule(&self, deadline: Instant, waker: Waker) {
+        self.upstream.schedule(deadline, waker)
+    }
+
+    fn pend(&self, irq: u8) {
+        self.upstream.pend(irq)
+    }
+}
+
+impl<C: Component> SleepListener for ConnectedComponent<C> {
+    fn on_sleep(&self, state: SleepState) {
+        unsafe { &*self.component.get() }.on_sleep(state)
+    }
+
+    fn on_wake(&self, state: SleepState) {
+        unsafe { &*self.component.get() }.on_wake(state)
+    }
 }
@@ -1,6 +1,16 @@
 use crate::interrupt::Interruptable;
+use crate::power::{SleepListener, SleepState};
+use crate::time::Instant;
+use crate::watchdog::Liveness;
+use core::task::Waker;
 
 pub trait UpstreamContext<M> {
     fn send(&self, message: M);
     fn register_irq(&self, irq: u8, interrupt: &'static dyn Interruptable);
+    fn register_sleep_listener(&self, listener: &'static dyn SleepListener);
+    fn vote_sleep(&self, previous: SleepState, current: SleepState);
+    fn register_liveness(&self, liveness: &'static Liveness);
+    fn now(&self) -> Instant;
+    fn schedule(&self, deadline: Instant, waker: Waker);
+    fn pend(&self, irq: u8);
 }
@@ -0,0 +1,380 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::kernel::notify_activity;
+use crate::time::Duration;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context as FutureContext, Poll};
+use embedded_hal::adc::{Channel as AdcChannel, OneShot};
+use heapless::{consts::*, Vec};
+
+/// Alarm thresholds of an ADC channel, in raw counts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Threshold {
+    /// Values below this raise `Alarm::Low`.
+    pub low: u16,
+    /// Values above this raise `Alarm::High`.
+    pub high: u16,
+    /// The distance a value must return inside the thresholds
+    /// before the alarm is cleared, avoiding repeated alarms
+    /// from a noisy signal.
+    pub hysteresis: u16,
+}
+
+/// Configuration of a channel of an `Adc`.
+#[derive(Copy, Clone, Debug)]
+pub struct ChannelConfig {
+    /// The number of consecutive conversions averaged into each sample.
+    pub oversample: u8,
+    /// The number of samples, up to 16, in the moving average
+    /// from which the channel's value is taken.
+    pub window: u8,
+    /// Thresholds for alarms, if any.
+    pub threshold: Option<Threshold>,
+    /// Whether each value is sent upstream, or only alarms.
+    pub report: bool,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            oversample: 1,
+            window: 1,
+            threshold: None,
+            report: true,
+        }
+    }
+}
+
+/// Alarms of a channel of an `Adc`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Alarm {
+    High,
+    Low,
+    /// The value has returned within the thresholds.
+    Cleared,
+}
+
+/// Messages sent upstream by an `Adc`. Channels are identified
+/// by the index returned from `Adc::add_channel(...)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdcEvent {
+    Value {
+        channel: u8,
+        value: u16,
+    },
+    Alarm {
+        channel: u8,
+        alarm: Alarm,
+        value: u16,
+    },
+    /// A conversion failed, and the sample was discarded.
+    Failed {
+        channel: u8,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Level {
+    Normal,
+    High,
+    Low,
+}
+
+struct Channel<P> {
+    pin: P,
+    config: ChannelConfig,
+    history: Vec<u16, U16>,
+    next: usize,
+    level: Level,
+}
+
+impl<P> Channel<P> {
+    fn new(pin: P, mut config: ChannelConfig) -> Self {
+        config.oversample = config.oversample.max(1);
+        config.window = config.window.clamp(1, 16);
+        Self {
+            pin,
+            config,
+            history: Vec::new(),
+            next: 0,
+            level: Level::Normal,
+        }
+    }
+
+    /// Add a sample to the moving average, returning its value
+    /// and any alarm raised.
+    fn filter(&mut self, sample: u16) -> (u16, Option<Alarm>) {
+        if self.history.len() < self.config.window as usize {
+            self.history.push(sample).ok();
+        } else {
+            self.history[self.next] = sample;
+        }
+        self.next = (self.next + 1) % self.config.window as usize;
+
+        let sum: u32 = self.history.iter().map(|v| *v as u32).sum();
+        let value = (sum / self.history.len() as u32) as u16;
+
+        let threshold = match self.config.threshold {
+            Some(threshold) => threshold,
+            None => return (value, None),
+        };
+        let level = match self.level {
+            _ if value > threshold.high => Level::High,
+            _ if value < threshold.low => Level::Low,
+            Level::High if value > threshold.high.saturating_sub(threshold.hysteresis) => {
+                Level::High
+            }
+            Level::Low if value < threshold.low.saturating_add(threshold.hysteresis) => Level::Low,
+            _ => Level::Normal,
+        };
+        if level == self.level {
+            return (value, None);
+        }
+        self.level = level;
+        let alarm = match level {
+            Level::High => Alarm::High,
+            Level::Low => Alarm::Low,
+            Level::Normal => Alarm::Cleared,
+        };
+        (value, Some(alarm))
+    }
+}
+
+/// A component periodically converting each of its channels upon an
+/// embedded-hal `OneShot` ADC, sending each value, and any threshold
+/// alarms, upstream as `AdcEvent`s.
+///
+/// Each sample averages `ChannelConfig::oversample` conversions, and the
+/// value reported is the moving average of the last `ChannelConfig::window`
+/// samples. Conversions are polled cooperatively, yielding to the executor
+/// until each completes. As embedded-hal has no abstraction of DMA, any
+/// hardware sequencing or DMA is left to the HAL beneath `OneShot`.
+///
+/// All channels must share one pin type `P`.
+pub struct Adc<A, ADC, P>
+where
+    A: OneShot<ADC, u16, P>,
+    P: AdcChannel<ADC>,
+{
+    adc: A,
+    channels: Vec<Channel<P>, U8>,
+    interval: Duration,
+    _adc: PhantomData<ADC>,
+}
+
+impl<A, ADC, P> Adc<A, ADC, P>
+where
+    A: OneShot<ADC, u16, P>,
+    P: AdcChannel<ADC>,
+{
+    /// Create a new ADC component, sampling every channel every `interval`.
+    pub fn new(adc: A, interval: Duration) -> Self {
+        Self {
+            adc,
+            channels: Vec::new(),
+            interval,
+            _adc: PhantomData,
+        }
+    }
+
+    /// Add a channel, of up to 8, returning the index identifying it
+    /// within `AdcEvent`s.
+    pub fn add_channel(&mut self, pin: P, config: ChannelConfig) -> u8 {
+        self.channels
+            .push(Channel::new(pin, config))
+            .ok()
+            .expect("too many ADC channels");
+        (self.channels.len() - 1) as u8
+    }
+
+    /// Sample the channel at `index`, returning the events to be sent upstream.
+    async fn sample(&mut self, index: usize) -> Vec<AdcEvent, U2> {
+        let mut events = Vec::new();
+        let channel = &mut self.channels[index];
+        let id = index as u8;
+
+        let mut sum = 0u32;
+        for _ in 0..channel.config.oversample {
+            let conversion = Conversion {
+                adc: &mut self.adc,
+                pin: &mut channel.pin,
+                _adc: PhantomData,
+            };
+            match conversion.await {
+                Ok(raw) => sum += raw as u32,
+                Err(_) => {
+                    events.push(AdcEvent::Failed { channel: id }).ok();
+                    return events;
+                }
+            }
+        }
+
+        let (value, alarm) = channel.filter((sum / channel.config.oversample as u32) as u16);
+        if channel.config.report {
+            events.push(AdcEvent::Value { channel: id, value }).ok();
+        }
+        if let Some(alarm) = alarm {
+            events
+                .push(AdcEvent::Alarm {
+                    channel: id,
+                    alarm,
+                    value,
+                })
+                .ok();
+        }
+        events
+    }
+}
+
+impl<A, ADC, P> Component for Adc<A, ADC, P>
+where
+    A: OneShot<ADC, u16, P>,
+    P: AdcChannel<ADC>,
+{
+    type InboundMessage = ();
+    type OutboundMessage = AdcEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        spawn("adc", async move {
+            loop {
+                for index in 0..self.channels.len() {
+                    for event in self.sample(index).await.iter() {
+                        ctx.send(*event);
+                    }
+                }
+                ctx.delay(self.interval).await;
+            }
+        });
+    }
+}
+
+struct Conversion<'a, A, ADC, P>
+where
+    A: OneShot<ADC, u16, P>,
+    P: AdcChannel<ADC>,
+{
+    adc: &'a mut A,
+    pin: &'a mut P,
+    _adc: PhantomData<fn() -> ADC>,
+}
+
+impl<'a, A, ADC, P> Future for Conversion<'a, A, ADC, P>
+where
+    A: OneShot<ADC, u16, P>,
+    P: AdcChannel<ADC>,
+{
+    type Output = Result<u16, A::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.adc.read(this.pin) {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+            Err(nb::Error::WouldBlock) => {
+                notify_activity();
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adc, AdcEvent, Alarm, ChannelConfig, Threshold};
+    use crate::mock::{MockAdc, MockAdcState, Waveform};
+    use crate::testing::{block_on, leak};
+    use crate::time::Duration;
+
+    #[test]
+    fn averaging() {
+        let state = leak(MockAdcState::new());
+        state.set_waveform(0, Waveform::Samples(&[1000, 1010, 990, 1004]));
+        state.set_waveform(
+            1,
+            Waveform::Square {
+                low: 100,
+                high: 300,
+                half_period: 2,
+            },
+        );
+        state.set_latency(3);
+
+        let mut adc: Adc<MockAdc, MockAdc, _> = Adc::new(state.adc(), Duration::from_millis(100));
+        let noisy = adc.add_channel(
+            state.pin(0),
+            ChannelConfig {
+                oversample: 2,
+                ..Default::default()
+            },
+        );
+        let square = adc.add_channel(
+            state.pin(1),
+            ChannelConfig {
+                window: 4,
+                ..Default::default()
+            },
+        );
+
+        let value = |channel, value| AdcEvent::Value { channel, value };
+
+        // oversampled pairs of (1000, 1010) and (990, 1004)
+        assert_eq!(&block_on(adc.sample(0))[..], &[value(noisy, 1005)]);
+        assert_eq!(&block_on(adc.sample(0))[..], &[value(noisy, 997)]);
+        assert_eq!(state.conversions(0), 4);
+
+        // 100, 100, 300, 300, 100, ...
+        let mut values = [0; 6];
+        for v in values.iter_mut() {
+            match block_on(adc.sample(1))[0] {
+                AdcEvent::Value { value, .. } => *v = value,
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+        assert_eq!(values, [100, 100, 166, 200, 200, 200]);
+        assert_eq!(square, 1);
+    }
+
+    #[test]
+    fn alarms() {
+        let state = leak(MockAdcState::new());
+        state.set_waveform(
+            0,
+            Waveform::Samples(&[500, 950, 1010, 960, 990, 890, 40, 60, 120]),
+        );
+
+        let mut adc: Adc<MockAdc, MockAdc, _> = Adc::new(state.adc(), Duration::from_millis(100));
+        adc.add_channel(
+            state.pin(0),
+            ChannelConfig {
+                threshold: Some(Threshold {
+                    low: 50,
+                    high: 1000,
+                    hysteresis: 50,
+                }),
+                report: false,
+                ..Default::default()
+            },
+        );
+
+        let alarm = |alarm, value| AdcEvent::Alarm {
+            channel: 0,
+            alarm,
+            value,
+        };
+        let mut events = heapless::Vec::<AdcEvent, heapless::consts::U8>::new();
+        for _ in 0..9 {
+            events.extend_from_slice(&block_on(adc.sample(0))).unwrap();
+        }
+        assert_eq!(
+            &events[..],
+            &[
+                alarm(Alarm::High, 1010),
+                alarm(Alarm::Cleared, 890),
+                alarm(Alarm::Low, 40),
+                alarm(Alarm::Cleared, 120),
+            ]
+        );
+    }
+}
@@ -0,0 +1,537 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::driver::lock::{BusLock, Flag};
+use crate::driver::serial::SerialPort;
+use crate::time::{Duration, WithTimeout};
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Write as _};
+use embedded_hal::serial;
+use heapless::{consts::*, ArrayLength, String, Vec};
+
+/// The timeout of commands not declaring their own.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Errors reported by an `AtEngine`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AtError {
+    /// The modem did not respond in time.
+    Timeout,
+    /// The modem reported `ERROR`, `FAIL` or `SEND FAIL`.
+    Error,
+    /// The modem reported `+CME ERROR: <n>`.
+    Cme(u16),
+    /// The modem reported `+CMS ERROR: <n>`.
+    Cms(u16),
+    /// The command was too long to be sent.
+    TooLong,
+    /// The response could not be parsed.
+    Parse,
+    /// Writing to the serial port failed.
+    Serial,
+}
+
+/// The definition of an AT command: how it is written, and how its
+/// response is parsed once the modem reports success.
+pub trait AtCommand {
+    type Response;
+
+    /// Write the command, such as `AT+CGMR`, without its line terminator.
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result;
+
+    /// The time within which the modem must respond.
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
+
+    /// Parse the response from the information lines preceding `OK`.
+    fn parse(&self, lines: Lines<'_>) -> Result<Self::Response, AtError>;
+}
+
+/// The unsolicited result codes of a modem, which an `AtEngine`
+/// sends upstream as they are received.
+pub trait Urc: Sized {
+    /// Parse a complete line, if it is an unsolicited result code.
+    fn parse(line: &[u8]) -> Option<Self>;
+
+    /// Determine if an incomplete line is the header of raw data following
+    /// it immediately, such as ESP-AT's `+IPD,0,5:`, returning its length.
+    fn header(_partial: &[u8]) -> Option<usize> {
+        None
+    }
+
+    /// Wrap a chunk of the raw data following `header`, of up to 64 bytes.
+    fn data(_header: &[u8], _data: &[u8]) -> Option<Self> {
+        None
+    }
+}
+
+/// The information lines of a response, each without its line terminator.
+pub struct Lines<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Lines<'a> {
+    fn new(lines: &'a [u8]) -> Self {
+        Self { remaining: lines }
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+        let end = self
+            .remaining
+            .iter()
+            .position(|b| *b == b'\n')
+            .unwrap_or(self.remaining.len());
+        let line = &self.remaining[..end];
+        self.remaining = &self.remaining[(end + 1).min(self.remaining.len())..];
+        Some(line)
+    }
+}
+
+/// The result awaited by a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Expect {
+    Final,
+    /// The `>` prompt for data.
+    Prompt,
+}
+
+/// Raw data being received after a header.
+struct Raw {
+    header: Vec<u8, U32>,
+    remaining: usize,
+    chunk: Vec<u8, U64>,
+}
+
+type EngineContext<TX, N, U> = ComponentContext<&'static AtEngine<TX, N, U>>;
+
+/// An engine for modems driven by AT commands over a `SerialPort`, such as
+/// cellular or WiFi modules, through which drivers perform `AtCommand`s and
+/// receive unsolicited result codes, parsed as `U`.
+///
+/// Requests are performed one at a time, in the order in which the engine
+/// becomes available, while a task spawned upon start reads every line
+/// received. The lines preceding a final result code are collected for the
+/// command to parse, unless parsed as an unsolicited result code, in which
+/// case they are sent upstream immediately, as is any raw data.
+///
+/// Echoed commands are ignored, though drivers should generally turn
+/// echoing off with `ATE0`.
+///
+/// The engine is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static AtEngine<TX, N, U>>`, by the driver handling
+/// its unsolicited result codes. The `Serial` receiving into its port must be
+/// started separately.
+pub struct AtEngine<TX, N, U>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+    U: Urc + 'static,
+{
+    port: &'static SerialPort<TX, N>,
+    lock: BusLock,
+    pending: Cell<Option<Expect>>,
+    result: Cell<Option<Result<(), AtError>>>,
+    responded: Flag,
+    line: RefCell<Vec<u8, U128>>,
+    lines: RefCell<Vec<u8, U256>>,
+    raw: RefCell<Option<Raw>>,
+    ctx: Cell<Option<&'static EngineContext<TX, N, U>>>,
+}
+
+impl<TX, N, U> AtEngine<TX, N, U>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+    U: Urc + 'static,
+{
+    pub fn new(port: &'static SerialPort<TX, N>) -> Self {
+        Self {
+            port,
+            lock: BusLock::new(),
+            pending: Cell::new(None),
+            result: Cell::new(None),
+            responded: Flag::new(),
+            line: RefCell::new(Vec::new()),
+            lines: RefCell::new(Vec::new()),
+            raw: RefCell::new(None),
+            ctx: Cell::new(None),
+        }
+    }
+
+    /// Perform, *asynchronously*, `command`, returning its parsed response.
+    pub async fn request<C: AtCommand>(&self, command: &C) -> Result<C::Response, AtError> {
+        let encoded = Self::encode(command)?;
+        let _guard = self.lock.lock().await;
+        let result = self
+            .exchange(encoded.as_bytes(), Expect::Final, command.timeout())
+            .await;
+        let response = result.and_then(|_| command.parse(Lines::new(&self.lines.borrow())));
+        response
+    }
+
+    /// Perform, *asynchronously*, `command`, writing `data` once the modem
+    /// prompts for it with `>`, such as for `AT+CIPSEND` or `AT+CMGS`.
+    pub async fn request_with_data<C: AtCommand>(
+        &self,
+        command: &C,
+        data: &[u8],
+    ) -> Result<C::Response, AtError> {
+        let encoded = Self::encode(command)?;
+        let _guard = self.lock.lock().await;
+        let mut result = self
+            .exchange(encoded.as_bytes(), Expect::Prompt, command.timeout())
+            .await;
+        if result.is_ok() {
+            result = self.exchange(data, Expect::Final, command.timeout()).await;
+        }
+        let response = result.and_then(|_| command.parse(Lines::new(&self.lines.borrow())));
+        response
+    }
+
+    fn encode<C: AtCommand>(command: &C) -> Result<String<U256>, AtError> {
+        let mut encoded = String::new();
+        command
+            .encode(&mut encoded)
+            .and_then(|_| encoded.write_str("\r\n"))
+            .map_err(|_| AtError::TooLong)?;
+        Ok(encoded)
+    }
+
+    /// Write `data`, and wait for the result `expect`, or an error.
+    /// The lock must be held.
+    async fn exchange(
+        &self,
+        data: &[u8],
+        expect: Expect,
+        timeout: Duration,
+    ) -> Result<(), AtError> {
+        *self.lines.borrow_mut() = Vec::new();
+        self.result.set(None);
+        self.pending.set(Some(expect));
+        let result = match self.port.write(data).await {
+            Ok(()) => match self.ctx.get() {
+                Some(ctx) => WithTimeout::new(self.response(), ctx.delay(timeout))
+                    .await
+                    .unwrap_or(Err(AtError::Timeout)),
+                None => self.response().await,
+            },
+            Err(_) => Err(AtError::Serial),
+        };
+        self.pending.set(None);
+        result
+    }
+
+    async fn response(&self) -> Result<(), AtError> {
+        loop {
+            self.responded.wait().await;
+            // a previous request may have timed out before its result
+            if let Some(result) = self.result.take() {
+                return result;
+            }
+        }
+    }
+
+    /// Read and process, *asynchronously*, everything received from the modem,
+    /// passing each unsolicited result code to `dispatch`.
+    async fn process(&self, mut dispatch: impl FnMut(U)) {
+        let mut buf = [0; 32];
+        loop {
+            let len = self.port.read(&mut buf).await;
+            for b in &buf[..len] {
+                self.feed(*b, &mut dispatch);
+            }
+        }
+    }
+
+    fn feed(&self, b: u8, dispatch: &mut impl FnMut(U)) {
+        let mut raw = self.raw.borrow_mut();
+        if let Some(data) = raw.as_mut() {
+            data.chunk.push(b).ok();
+            data.remaining -= 1;
+            if data.remaining == 0 || data.chunk.len() == data.chunk.capacity() {
+                let chunk = core::mem::replace(&mut data.chunk, Vec::new());
+                let urc = U::data(&data.header, &chunk);
+                if data.remaining == 0 {
+                    *raw = None;
+                }
+                drop(raw);
+                if let Some(urc) = urc {
+                    dispatch(urc);
+                }
+            }
+            return;
+        }
+        drop(raw);
+
+        let mut line = self.line.borrow_mut();
+        match b {
+            b'>' if line.is_empty() && self.pending.get() == Some(Expect::Prompt) => {
+                self.respond(Ok(()))
+            }
+            b'\n' => {
+                let mut complete = core::mem::replace(&mut *line, Vec::new());
+                drop(line);
+                if complete.ends_with(b"\r") {
+                    complete.pop();
+                }
+                self.on_line(&complete, dispatch);
+            }
+            _ => {
+                // overlong lines are truncated
+                line.push(b).ok();
+                if let Some(len) = U::header(&line) {
+                    let header = core::mem::replace(&mut *line, Vec::new());
+                    if len > 0 {
+                        self.raw.borrow_mut().replace(Raw {
+                            header: Vec::from_slice(&header[..header.len().min(32)]).unwrap(),
+                            remaining: len,
+                            chunk: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_line(&self, line: &[u8], dispatch: &mut impl FnMut(U)) {
+        let expect = self.pending.get();
+        match line {
+            b"" => {}
+            b"OK" | b"SEND OK" if expect == Some(Expect::Final) => self.respond(Ok(())),
+            b"ERROR" | b"FAIL" | b"SEND FAIL" => self.respond(Err(AtError::Error)),
+            _ if line.starts_with(b"+CME ERROR: ") => {
+                self.respond(Err(AtError::Cme(parse_code(&line[12..]))))
+            }
+            _ if line.starts_with(b"+CMS ERROR: ") => {
+                self.respond(Err(AtError::Cms(parse_code(&line[12..]))))
+            }
+            _ => {
+                if let Some(urc) = U::parse(line) {
+                    dispatch(urc);
+                } else if expect.is_some() && !line.starts_with(b"AT") {
+                    let mut lines = self.lines.borrow_mut();
+                    if lines.extend_from_slice(line).is_ok() {
+                        lines.push(b'\n').ok();
+                    }
+                }
+            }
+        }
+    }
+
+    fn respond(&self, result: Result<(), AtError>) {
+        if self.pending.take().is_some() {
+            self.result.set(Some(result));
+            self.responded.raise();
+        }
+    }
+}
+
+impl<TX, N, U> Component for &'static AtEngine<TX, N, U>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+    U: Urc + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = U;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.set(Some(ctx));
+        let engine: &'static AtEngine<TX, N, U> = self;
+        spawn("at", async move {
+            engine.process(|urc| ctx.send(urc)).await;
+        });
+    }
+}
+
+/// Parse the numeric code of `+CME ERROR` or `+CMS ERROR`, or zero
+/// should the modem report it verbosely.
+fn parse_code(code: &[u8]) -> u16 {
+    core::str::from_utf8(code)
+        .ok()
+        .and_then(|code| code.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::{AtCommand, AtEngine, AtError, Lines, Urc};
+    use crate::driver::serial::{SerialInterrupt, SerialPort};
+    use crate::mock::{MockModem, MockModemState};
+    use crate::testing::{leak, poll_once};
+    use core::cell::RefCell;
+    use core::fmt;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Poll;
+    use heapless::consts::*;
+    use std::boxed::Box;
+
+    /// Runs futures alongside the processing of everything a `MockModem` sends.
+    pub(crate) struct Harness<U: Urc + 'static> {
+        pub(crate) modem: &'static MockModemState,
+        pub(crate) engine: &'static AtEngine<MockModem, U256, U>,
+        interrupt: SerialInterrupt<MockModem, U256>,
+        process: Pin<Box<dyn Future<Output = ()>>>,
+    }
+
+    impl<U: Urc + 'static> Harness<U> {
+        pub(crate) fn new(dispatch: impl FnMut(U) + 'static) -> Self {
+            let modem = leak(MockModemState::new());
+            let port = leak(SerialPort::new(modem.modem()));
+            let engine = leak(AtEngine::new(port));
+            Self {
+                modem,
+                engine,
+                interrupt: port.interrupt(modem.modem()),
+                process: Box::pin(engine.process(dispatch)),
+            }
+        }
+
+        /// Process everything the modem has sent.
+        pub(crate) fn process(&mut self) {
+            for _ in 0..16 {
+                self.interrupt.service(|e| panic!("{:?}", e));
+                assert_eq!(poll_once(self.process.as_mut()), Poll::Pending);
+            }
+        }
+
+        /// Run `future` to completion, alongside the processing
+        /// of everything the modem sends.
+        pub(crate) fn run<F: Future>(&mut self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            for _ in 0..1000 {
+                if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                    return output;
+                }
+                self.interrupt.service(|e| panic!("{:?}", e));
+                assert_eq!(poll_once(self.process.as_mut()), Poll::Pending);
+            }
+            panic!("future never completed")
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum TestUrc {
+        Ring,
+        Registration(u8),
+        Data(heapless::Vec<u8, U64>),
+    }
+
+    impl Urc for TestUrc {
+        fn parse(line: &[u8]) -> Option<Self> {
+            match line {
+                b"RING" => Some(TestUrc::Ring),
+                [b'+', b'C', b'R', b'E', b'G', b':', b' ', stat] => {
+                    Some(TestUrc::Registration(stat - b'0'))
+                }
+                _ => None,
+            }
+        }
+
+        fn header(partial: &[u8]) -> Option<usize> {
+            match partial {
+                [b'+', b'D', b'A', b'T', b'A', b',', len @ b'0'..=b'9', b':'] => {
+                    Some((len - b'0') as usize)
+                }
+                _ => None,
+            }
+        }
+
+        fn data(_header: &[u8], data: &[u8]) -> Option<Self> {
+            Some(TestUrc::Data(heapless::Vec::from_slice(data).unwrap()))
+        }
+    }
+
+    /// `AT+CGMR`, querying the firmware revision.
+    struct Revision;
+
+    impl AtCommand for Revision {
+        type Response = heapless::Vec<u8, U32>;
+
+        fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+            command.write_str("AT+CGMR")
+        }
+
+        fn parse(&self, mut lines: Lines<'_>) -> Result<Self::Response, AtError> {
+            let line = lines.next().ok_or(AtError::Parse)?;
+            heapless::Vec::from_slice(line).map_err(|_| AtError::Parse)
+        }
+    }
+
+    /// `AT+CMGS`, sending a text message.
+    struct SendMessage;
+
+    impl AtCommand for SendMessage {
+        type Response = u8;
+
+        fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+            command.write_str("AT+CMGS=\"+15555550100\"")
+        }
+
+        fn parse(&self, lines: Lines<'_>) -> Result<u8, AtError> {
+            lines
+                .filter_map(|line| line.strip_prefix(b"+CMGS: "))
+                .find_map(|reference| core::str::from_utf8(reference).ok()?.parse().ok())
+                .ok_or(AtError::Parse)
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let urcs = leak(RefCell::new(std::vec::Vec::new()));
+        let mut h = Harness::new(move |urc: TestUrc| urcs.borrow_mut().push(urc));
+        let m = h.modem;
+        let engine = h.engine;
+
+        // echoed, with an unsolicited result code amidst the response
+        m.expect(
+            b"AT+CGMR\r\n",
+            b"AT+CGMR\r\r\nBG96MAR02A07M1G\r\n+CREG: 5\r\n\r\nOK\r\n",
+        );
+        assert_eq!(
+            &h.run(engine.request(&Revision)).unwrap()[..],
+            b"BG96MAR02A07M1G"
+        );
+
+        m.expect(b"AT+CGMR\r\n", b"\r\n+CME ERROR: 10\r\n");
+        assert_eq!(h.run(engine.request(&Revision)), Err(AtError::Cme(10)));
+        m.expect(b"AT+CGMR\r\n", b"\r\nERROR\r\n");
+        assert_eq!(h.run(engine.request(&Revision)), Err(AtError::Error));
+
+        m.expect(b"AT+CMGS=\"+15555550100\"\r\n", b"\r\n> ");
+        m.expect(b"hello\x1a", b"\r\n+CMGS: 42\r\n\r\nOK\r\n");
+        assert_eq!(
+            h.run(engine.request_with_data(&SendMessage, b"hello\x1a")),
+            Ok(42)
+        );
+
+        m.expect(b"AT+CMGS=\"+15555550100\"\r\n", b"\r\n+CMS ERROR: 304\r\n");
+        assert_eq!(
+            h.run(engine.request_with_data(&SendMessage, b"hello\x1a")),
+            Err(AtError::Cms(304))
+        );
+        assert!(m.is_done());
+
+        m.inject(b"\r\nRING\r\n+DATA,5:a\r\nbc\r\n+CREG: 1\r\n");
+        h.process();
+        assert_eq!(
+            &urcs.borrow()[..],
+            &[
+                TestUrc::Registration(5),
+                TestUrc::Ring,
+                TestUrc::Data(heapless::Vec::from_slice(b"a\r\nbc").unwrap()),
+                TestUrc::Registration(1),
+            ]
+        );
+    }
+}
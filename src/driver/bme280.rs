@@ -0,0 +1,306 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::driver::i2c::{I2cDevice, I2cPeripheral};
+use crate::sensor::{
+    Celsius, Hectopascals, Reading, RelativeHumidity, SensorError, SensorEvent, SensorRequest,
+};
+use core::cell::Cell;
+use heapless::{consts::*, Vec};
+
+/// The I2C address of the BME280, with SDO pulled low.
+pub const ADDRESS: u8 = 0x76;
+/// The I2C address of the BME280, with SDO pulled high.
+pub const ALTERNATE_ADDRESS: u8 = 0x77;
+
+const CALIB_00: u8 = 0x88;
+const ID: u8 = 0xD0;
+const CALIB_26: u8 = 0xE1;
+const CTRL_HUM: u8 = 0xF2;
+const CTRL_MEAS: u8 = 0xF4;
+const CONFIG: u8 = 0xF5;
+const PRESS_MSB: u8 = 0xF7;
+
+const IDENTITY: u8 = 0x60;
+
+const CTRL_HUM_OSRS_H_1: u8 = 0x01;
+const CTRL_MEAS_OSRS_T_1: u8 = 0x20;
+const CTRL_MEAS_OSRS_P_1: u8 = 0x04;
+const CTRL_MEAS_MODE_NORMAL: u8 = 0x03;
+const CONFIG_T_SB_1000MS: u8 = 0xA0;
+
+/// The value of a temperature output for which no measurement has been taken.
+const SKIPPED: u32 = 0x80000;
+
+/// Factory calibration, used by the compensation formulas of the datasheet.
+#[derive(Copy, Clone, Debug)]
+struct Calibration {
+    t: [f64; 3],
+    p: [f64; 9],
+    h: [f64; 6],
+}
+
+impl Calibration {
+    fn parse(first: &[u8; 26], second: &[u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([first[i], first[i + 1]]) as f64;
+        let i16_at = |i: usize| i16::from_le_bytes([first[i], first[i + 1]]) as f64;
+
+        let mut p = [u16_at(6); 9];
+        for (n, p) in p.iter_mut().enumerate().skip(1) {
+            *p = i16_at(6 + n * 2);
+        }
+
+        Self {
+            t: [u16_at(0), i16_at(2), i16_at(4)],
+            p,
+            h: [
+                first[25] as f64,
+                i16::from_le_bytes([second[0], second[1]]) as f64,
+                second[2] as f64,
+                ((second[3] as i8 as i16) << 4 | (second[4] & 0x0F) as i16) as f64,
+                ((second[5] as i8 as i16) << 4 | (second[4] >> 4) as i16) as f64,
+                second[6] as i8 as f64,
+            ],
+        }
+    }
+
+    /// The fine temperature, shared by every compensation.
+    fn t_fine(&self, adc_t: u32) -> f64 {
+        let [t1, t2, t3] = self.t;
+        let adc_t = adc_t as f64;
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * t2;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0) * (adc_t / 131072.0 - t1 / 8192.0) * t3;
+        var1 + var2
+    }
+
+    fn temperature(&self, t_fine: f64) -> Celsius {
+        Celsius((t_fine / 5120.0) as f32)
+    }
+
+    fn pressure(&self, t_fine: f64, adc_p: u32) -> Hectopascals {
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p6 / 32768.0;
+        var2 += var1 * p5 * 2.0;
+        var2 = var2 / 4.0 + p4 * 65536.0;
+        var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p1;
+        if var1 == 0.0 {
+            return Hectopascals(0.0);
+        }
+        let mut p = 1048576.0 - adc_p as f64;
+        p = (p - var2 / 4096.0) * 6250.0 / var1;
+        var1 = p9 * p * p / 2147483648.0;
+        var2 = p * p8 / 32768.0;
+        p += (var1 + var2 + p7) / 16.0;
+        Hectopascals((p / 100.0) as f32)
+    }
+
+    fn humidity(&self, t_fine: f64, adc_h: u16) -> RelativeHumidity {
+        let [h1, h2, h3, h4, h5, h6] = self.h;
+        let mut h = t_fine - 76800.0;
+        h = (adc_h as f64 - (h4 * 64.0 + h5 / 16384.0 * h))
+            * (h2 / 65536.0 * (1.0 + h6 / 67108864.0 * h * (1.0 + h3 / 67108864.0 * h)));
+        h *= 1.0 - h1 * h / 524288.0;
+        RelativeHumidity(h.clamp(0.0, 100.0) as f32)
+    }
+}
+
+/// A driver for the Bosch BME280 temperature, pressure and humidity
+/// sensor, attached to an `I2cBus`.
+///
+/// Once started, the sensor measures continuously, once per second, and
+/// a temperature, pressure and humidity `Reading` are sent upstream
+/// whenever a `SensorRequest::Sample` is received. The BME280 has no
+/// data-ready line, so it should be sampled periodically, such as by
+/// a `Sampler`.
+pub struct Bme280<I2C, E>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+{
+    device: I2cDevice<I2C, E>,
+    calibration: Cell<Option<Calibration>>,
+}
+
+impl<I2C, E> Bme280<I2C, E>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+{
+    pub fn new(device: I2cDevice<I2C, E>) -> Self {
+        Self {
+            device,
+            calibration: Cell::new(None),
+        }
+    }
+
+    /// Identify the sensor, read its calibration, and begin measuring.
+    async fn initialize(&self) -> Result<(), SensorError> {
+        let mut id = [0];
+        self.device
+            .write_read(&[ID], &mut id)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        if id[0] != IDENTITY {
+            return Err(SensorError::NotFound);
+        }
+
+        let mut first = [0; 26];
+        let mut second = [0; 7];
+        self.device
+            .write_read(&[CALIB_00], &mut first)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        self.device
+            .write_read(&[CALIB_26], &mut second)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        self.calibration
+            .set(Some(Calibration::parse(&first, &second)));
+
+        // changes to ctrl_hum only take effect after writing ctrl_meas
+        for (register, value) in [
+            (CONFIG, CONFIG_T_SB_1000MS),
+            (CTRL_HUM, CTRL_HUM_OSRS_H_1),
+            (
+                CTRL_MEAS,
+                CTRL_MEAS_OSRS_T_1 | CTRL_MEAS_OSRS_P_1 | CTRL_MEAS_MODE_NORMAL,
+            ),
+        ]
+        .iter()
+        {
+            self.device
+                .write(&[*register, *value])
+                .await
+                .map_err(|_| SensorError::Bus)?;
+        }
+        Ok(())
+    }
+
+    /// Read the most recent temperature, pressure and humidity.
+    async fn read(&self) -> Result<Vec<Reading, U3>, SensorError> {
+        let calibration = self.calibration.get().ok_or(SensorError::NotReady)?;
+
+        let mut out = [0; 8];
+        self.device
+            .write_read(&[PRESS_MSB], &mut out)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        let u20_at =
+            |i: usize| (out[i] as u32) << 12 | (out[i + 1] as u32) << 4 | (out[i + 2] as u32) >> 4;
+        let adc_p = u20_at(0);
+        let adc_t = u20_at(3);
+        let adc_h = u16::from_be_bytes([out[6], out[7]]);
+        if adc_t == SKIPPED {
+            return Err(SensorError::NotReady);
+        }
+
+        let t_fine = calibration.t_fine(adc_t);
+        let mut readings = Vec::new();
+        readings
+            .push(Reading::Temperature(calibration.temperature(t_fine)))
+            .ok();
+        readings
+            .push(Reading::Pressure(calibration.pressure(t_fine, adc_p)))
+            .ok();
+        readings
+            .push(Reading::Humidity(calibration.humidity(t_fine, adc_h)))
+            .ok();
+        Ok(readings)
+    }
+
+    async fn sample(&self, ctx: &ComponentContext<Self>) {
+        match self.read().await {
+            Ok(readings) => {
+                for reading in readings.iter() {
+                    ctx.send(SensorEvent::Reading(*reading));
+                }
+            }
+            Err(error) => ctx.send(SensorEvent::Error(error)),
+        }
+    }
+}
+
+impl<I2C, E> Component for Bme280<I2C, E>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+{
+    type InboundMessage = SensorRequest;
+    type OutboundMessage = SensorEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        let sensor: &'static Self = self;
+        spawn("bme280", async move {
+            if let Err(error) = sensor.initialize().await {
+                ctx.send(SensorEvent::Error(error));
+                return;
+            }
+            loop {
+                match ctx.receive().await {
+                    SensorRequest::Sample => sensor.sample(ctx).await,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bme280, ADDRESS};
+    use crate::driver::i2c::{I2cBus, I2cError};
+    use crate::mock::MockI2cState;
+    use crate::sensor::{Reading, SensorError};
+    use crate::testing::{block_on, leak};
+
+    fn set_registers(state: &MockI2cState, start: u8, values: &[u8]) {
+        for (offset, value) in values.iter().enumerate() {
+            state.set_register(ADDRESS, start + offset as u8, *value);
+        }
+    }
+
+    #[test]
+    fn readings() {
+        let state = leak(MockI2cState::new());
+        state.add_device(ADDRESS);
+        state.set_register(ADDRESS, 0xD0, 0x60);
+        #[rustfmt::skip]
+        set_registers(state, 0x88, &[
+            0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC,
+            0x7D, 0x8E, 0x43, 0xD6, 0xD0, 0x0B, 0x27, 0x0B, 0x8C, 0x00,
+            0xF9, 0xFF, 0x8C, 0x3C, 0xF8, 0xC6, 0x70, 0x17,
+            0x00, 0x4B,
+        ]);
+        set_registers(state, 0xE1, &[0x6A, 0x01, 0x00, 0x14, 0x24, 0x03, 0x1E]);
+        set_registers(
+            state,
+            0xF7,
+            &[0x80, 0x00, 0x00, 0x80, 0x00, 0x00, 0x80, 0x00],
+        );
+
+        let bus = leak(I2cBus::new(state.i2c(), |e: &I2cError| *e));
+        let sensor = Bme280::new(bus.device(ADDRESS));
+
+        assert_eq!(block_on(sensor.read()), Err(SensorError::NotReady));
+        block_on(sensor.initialize()).unwrap();
+        assert_eq!(state.register(ADDRESS, 0xF2), 0x01);
+        assert_eq!(state.register(ADDRESS, 0xF4), 0x27);
+        assert_eq!(state.register(ADDRESS, 0xF5), 0xA0);
+        assert_eq!(block_on(sensor.read()), Err(SensorError::NotReady));
+
+        set_registers(
+            state,
+            0xF7,
+            &[0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x75, 0x30],
+        );
+        let readings = block_on(sensor.read()).unwrap();
+        let close = |actual: f32, expected: f32| (actual - expected).abs() < 0.01;
+        match readings[..] {
+            [Reading::Temperature(t), Reading::Pressure(p), Reading::Humidity(h)] => {
+                assert!(close(t.0, 25.08));
+                assert!(close(p.0, 1006.53));
+                assert!(close(h.0, 51.08));
+            }
+            _ => panic!("unexpected readings {:?}", readings),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::driver::exti::ExtiPin;
+use crate::interrupt::{Interrupt, InterruptContext};
+use crate::time::{Duration, Instant};
+use embedded_hal::digital::v2::InputPin;
+use heapless::{consts::*, Vec};
+
+/// Messages sent upstream by a `Button`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    /// Sent upon release, immediately before `Released`, when the
+    /// button was held for at least `ButtonConfig::long_press`.
+    LongPress,
+    /// Sent immediately after `Pressed`, when the button was released
+    /// no longer than `ButtonConfig::double_click` beforehand.
+    DoubleClick,
+}
+
+/// The pin level at which the button is considered pressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Active {
+    High,
+    Low,
+}
+
+/// Timing configuration of a `Button`.
+#[derive(Copy, Clone, Debug)]
+pub struct ButtonConfig {
+    /// Edges arriving within this duration of the previous accepted
+    /// edge are considered bounces, and ignored.
+    pub debounce: Duration,
+    /// The minimum duration of a press for it to be considered long.
+    pub long_press: Duration,
+    /// The maximum duration between a release and the following
+    /// press for them to be considered a double-click.
+    pub double_click: Duration,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(20),
+            long_press: Duration::from_millis(1000),
+            double_click: Duration::from_millis(300),
+        }
+    }
+}
+
+/// An interrupt for a button attached to a GPIO pin routed to
+/// an EXTI line, triggering on both edges.
+///
+/// The EXTI pending flag is cleared upon each interrupt, and edges
+/// are debounced using the kernel's time source, the pin being sampled
+/// again once any bouncing should have settled.
+pub struct Button<P: InputPin + ExtiPin> {
+    pin: P,
+    irq: u8,
+    active: Active,
+    config: ButtonConfig,
+    pressed: bool,
+    last_edge: Option<Instant>,
+    settle_at: Option<Instant>,
+    pressed_at: Instant,
+    last_click: Option<Instant>,
+    double_clicked: bool,
+}
+
+impl<P: InputPin + ExtiPin> Button<P> {
+    pub fn new(pin: P, irq: u8, active: Active) -> Self {
+        Self::with_config(pin, irq, active, ButtonConfig::default())
+    }
+
+    pub fn with_config(pin: P, irq: u8, active: Active, config: ButtonConfig) -> Self {
+        let mut button = Self {
+            pin,
+            irq,
+            active,
+            config,
+            pressed: false,
+            last_edge: None,
+            settle_at: None,
+            pressed_at: Instant::from_millis(0),
+            last_click: None,
+            double_clicked: false,
+        };
+        button.pressed = button.read().unwrap_or(false);
+        button
+    }
+
+    /// The debounced state of the button.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    fn read(&self) -> Option<bool> {
+        let high = self.pin.is_high().ok()?;
+        Some(high == (self.active == Active::High))
+    }
+
+    fn update(&mut self, now: Instant) -> Vec<ButtonEvent, U2> {
+        let mut events = Vec::new();
+
+        let pressed = match self.read() {
+            Some(pressed) if pressed != self.pressed => pressed,
+            _ => return events,
+        };
+
+        if let Some(last_edge) = self.last_edge {
+            if now - last_edge < self.config.debounce {
+                // possibly a genuine edge, so sampled again once settled
+                self.settle_at.replace(last_edge + self.config.debounce);
+                return events;
+            }
+        }
+        self.last_edge.replace(now);
+        self.pressed = pressed;
+
+        if pressed {
+            self.pressed_at = now;
+            events.push(ButtonEvent::Pressed).ok();
+            if let Some(click) = self.last_click.take() {
+                if now - click <= self.config.double_click {
+                    self.double_clicked = true;
+                    events.push(ButtonEvent::DoubleClick).ok();
+                }
+            }
+        } else {
+            if now - self.pressed_at >= self.config.long_press {
+                events.push(ButtonEvent::LongPress).ok();
+            } else if !self.double_clicked {
+                self.last_click.replace(now);
+            }
+            self.double_clicked = false;
+            events.push(ButtonEvent::Released).ok();
+        }
+
+        events
+    }
+}
+
+impl<P: InputPin + ExtiPin> Interrupt for Button<P> {
+    type OutboundMessage = ButtonEvent;
+
+    fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    fn on_interrupt(&mut self, context: &InterruptContext<Self>) {
+        self.pin.clear_pending();
+        for event in self.update(context.now()).iter() {
+            context.send(*event);
+        }
+        if let Some(deadline) = self.settle_at.take() {
+            context.interrupt_at(deadline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Active, Button, ButtonEvent, ButtonEvent::*};
+    use crate::backend::Host;
+    use crate::handler::Handler;
+    use crate::interrupt::ConnectedInterrupt;
+    use crate::kernel::{ConnectedKernel, Kernel, KernelContext};
+    use crate::mock::{MockPin, MockPinState};
+    use crate::testing::leak;
+    use crate::time::{Duration, Instant};
+    use core::cell::RefCell;
+    use heapless::{consts::*, Vec};
+
+    #[test]
+    fn events() {
+        let pin = leak(MockPinState::new(true));
+        let mut button = Button::new(pin.pin(), 6, Active::Low);
+        assert!(!button.is_pressed());
+
+        let mut at = |millis: u64, high: bool| {
+            if high {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+            button.update(Instant::from_millis(millis))
+        };
+
+        // press, with a bounce
+        assert_eq!(&at(1000, false)[..], &[Pressed]);
+        assert_eq!(&at(1005, true)[..], &[]);
+        assert_eq!(&at(1010, false)[..], &[]);
+        assert_eq!(&at(1100, true)[..], &[Released]);
+
+        // double-click
+        assert_eq!(&at(1300, false)[..], &[Pressed, DoubleClick]);
+        assert_eq!(&at(1400, true)[..], &[Released]);
+
+        // a third click is not another double-click
+        assert_eq!(&at(1500, false)[..], &[Pressed]);
+        assert_eq!(&at(1600, true)[..], &[Released]);
+
+        // long press
+        assert_eq!(&at(3000, false)[..], &[Pressed]);
+        assert_eq!(&at(4500, true)[..], &[LongPress, Released]);
+        assert_eq!(&at(4600, false)[..], &[Pressed]);
+    }
+
+    struct Device {
+        button: ConnectedInterrupt<Button<MockPin>>,
+        events: &'static RefCell<Vec<ButtonEvent, U8>>,
+    }
+
+    impl Kernel for Device {
+        fn start(&'static self, ctx: &'static KernelContext<Self>) {
+            self.button.start(ctx);
+        }
+    }
+
+    impl Handler<ButtonEvent> for Device {
+        fn on_message(&mut self, message: ButtonEvent) {
+            self.events.borrow_mut().push(message).unwrap();
+        }
+    }
+
+    #[test]
+    fn settles() {
+        let host = leak(Host::new());
+        let pin = leak(MockPinState::new(true));
+        let events = leak(RefCell::new(Vec::new()));
+        let kernel = leak(ConnectedKernel::with_backend(
+            Device {
+                button: ConnectedInterrupt::new(Button::new(pin.pin(), 6, Active::Low)),
+                events,
+            },
+            host,
+        ));
+        host.attach(kernel);
+        kernel.start();
+        host.advance(Duration::from_millis(1000));
+
+        pin.set_low();
+        kernel.interrupt(6);
+        assert_eq!(&events.borrow()[..], &[Pressed]);
+
+        // released within the debounce window
+        host.advance(Duration::from_millis(10));
+        pin.set_high();
+        kernel.interrupt(6);
+        host.advance(Duration::from_millis(9));
+        assert_eq!(&events.borrow()[..], &[Pressed]);
+
+        // and noticed once it has settled, the IRQ pended by the tick
+        host.advance(Duration::from_millis(1));
+        assert_eq!(&events.borrow()[..], &[Pressed, Released]);
+        assert_eq!(pin.pending_cleared(), 3);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::driver::exti::ExtiPin;
+use crate::interrupt::{Interrupt, InterruptContext};
+use crate::time::{Duration, Instant};
+use embedded_hal::digital::v2::InputPin;
+
+/// Steps further apart than this are considered separate movements,
+/// restarting the estimation of velocity.
+const IDLE: Duration = Duration::from_millis(500);
+
+/// Messages sent upstream by an `Encoder`, one per detent.
+///
+/// The `velocity` is an estimate, in detents per second, of the speed
+/// at which the encoder is being turned, or zero for the first detent of
+/// a movement, allowing applications to accelerate their response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncoderEvent {
+    Clockwise { velocity: u16 },
+    CounterClockwise { velocity: u16 },
+}
+
+/// Configuration of an `Encoder`.
+#[derive(Copy, Clone, Debug)]
+pub struct EncoderConfig {
+    /// The number of quadrature transitions between detents, typically
+    /// 4, or 2 for encoders resting at both `00` and `11`.
+    pub steps_per_detent: u8,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            steps_per_detent: 4,
+        }
+    }
+}
+
+/// The change in position for each transition from one quadrature state
+/// to another, indexed by `previous << 2 | current`, where each state is
+/// `a << 1 | b`. Invalid transitions, having skipped a state, are ignored.
+#[rustfmt::skip]
+const TRANSITIONS: [i8; 16] = [
+     0, -1,  1,  0,
+     1,  0,  0, -1,
+    -1,  0,  0,  1,
+     0,  1, -1,  0,
+];
+
+/// An interrupt decoding the quadrature signals of a rotary encoder
+/// from two GPIO pins routed to EXTI lines, triggering on both edges.
+///
+/// Both pins must trigger the same IRQ, such as a shared `EXTI9_5`. The
+/// encoder is considered to turn clockwise when `a` leads `b`.
+pub struct Encoder<A, B>
+where
+    A: InputPin + ExtiPin,
+    B: InputPin + ExtiPin,
+{
+    a: A,
+    b: B,
+    irq: u8,
+    config: EncoderConfig,
+    state: u8,
+    steps: i8,
+    position: i32,
+    last_detent: Option<(Instant, bool)>,
+    velocity: u16,
+}
+
+impl<A, B> Encoder<A, B>
+where
+    A: InputPin + ExtiPin,
+    B: InputPin + ExtiPin,
+{
+    pub fn new(a: A, b: B, irq: u8) -> Self {
+        Self::with_config(a, b, irq, EncoderConfig::default())
+    }
+
+    pub fn with_config(a: A, b: B, irq: u8, config: EncoderConfig) -> Self {
+        let mut encoder = Self {
+            a,
+            b,
+            irq,
+            config,
+            state: 0,
+            steps: 0,
+            position: 0,
+            last_detent: None,
+            velocity: 0,
+        };
+        encoder.state = encoder.read().unwrap_or(0);
+        encoder
+    }
+
+    /// The net number of detents turned clockwise since creation.
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    /// The most recent estimate of velocity, in detents per second.
+    pub fn velocity(&self) -> u16 {
+        self.velocity
+    }
+
+    fn read(&self) -> Option<u8> {
+        let a = self.a.is_high().ok()? as u8;
+        let b = self.b.is_high().ok()? as u8;
+        Some(a << 1 | b)
+    }
+
+    fn update(&mut self, now: Instant) -> Option<EncoderEvent> {
+        let state = self.read()?;
+        let delta = TRANSITIONS[(self.state << 2 | state) as usize];
+        self.state = state;
+        self.steps += delta;
+
+        let steps_per_detent = self.config.steps_per_detent.max(1) as i8;
+        let clockwise = if self.steps >= steps_per_detent {
+            true
+        } else if self.steps <= -steps_per_detent {
+            false
+        } else {
+            if state == 0 {
+                // resting, so discard any partial movement from bounces
+                self.steps = 0;
+            }
+            return None;
+        };
+        self.steps = 0;
+        self.position += if clockwise { 1 } else { -1 };
+        self.velocity = self.estimate(now, clockwise);
+
+        let velocity = self.velocity;
+        Some(if clockwise {
+            EncoderEvent::Clockwise { velocity }
+        } else {
+            EncoderEvent::CounterClockwise { velocity }
+        })
+    }
+
+    /// Estimate velocity, smoothing the instantaneous velocity
+    /// since the previous detent in the same direction.
+    fn estimate(&mut self, now: Instant, clockwise: bool) -> u16 {
+        let previous = self.last_detent.replace((now, clockwise));
+        match previous {
+            Some((at, direction)) if direction == clockwise && now - at <= IDLE => {
+                let millis = (now - at).as_millis().max(1) as u32;
+                let instantaneous = (1000 / millis) as u16;
+                if self.velocity == 0 {
+                    instantaneous
+                } else {
+                    ((self.velocity as u32 + instantaneous as u32) / 2) as u16
+                }
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl<A, B> Interrupt for Encoder<A, B>
+where
+    A: InputPin + ExtiPin,
+    B: InputPin + ExtiPin,
+{
+    type OutboundMessage = EncoderEvent;
+
+    fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    fn on_interrupt(&mut self, context: &InterruptContext<Self>) {
+        self.a.clear_pending();
+        self.b.clear_pending();
+        if let Some(event) = self.update(context.now()) {
+            context.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoder, EncoderConfig, EncoderEvent::*};
+    use crate::mock::{MockPin, MockPinState};
+    use crate::testing::leak;
+    use crate::time::Instant;
+
+    struct Harness {
+        a: &'static MockPinState,
+        b: &'static MockPinState,
+        encoder: Encoder<MockPin, MockPin>,
+        events: heapless::Vec<super::EncoderEvent, heapless::consts::U16>,
+    }
+
+    impl Harness {
+        fn new(config: EncoderConfig) -> Self {
+            let a = leak(MockPinState::new(false));
+            let b = leak(MockPinState::new(false));
+            Self {
+                a,
+                b,
+                encoder: Encoder::with_config(a.pin(), b.pin(), 23, config),
+                events: heapless::Vec::new(),
+            }
+        }
+
+        /// Drive the pins through the given `ab` states, one per millisecond from `at`.
+        fn drive(&mut self, at: u64, states: &[u8]) {
+            for (n, state) in states.iter().enumerate() {
+                if state & 0b10 != 0 {
+                    self.a.set_high()
+                } else {
+                    self.a.set_low()
+                }
+                if state & 0b01 != 0 {
+                    self.b.set_high()
+                } else {
+                    self.b.set_low()
+                }
+                if let Some(event) = self.encoder.update(Instant::from_millis(at + n as u64)) {
+                    self.events.push(event).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quadrature() {
+        let mut h = Harness::new(EncoderConfig::default());
+
+        // a full detent clockwise, one counter-clockwise
+        h.drive(0, &[0b10, 0b11, 0b01, 0b00]);
+        h.drive(100, &[0b01, 0b11, 0b10, 0b00]);
+        assert_eq!(
+            &h.events[..],
+            &[Clockwise { velocity: 0 }, CounterClockwise { velocity: 0 }]
+        );
+        assert_eq!(h.encoder.position(), 0);
+
+        // bouncing upon a, then settling without a detent
+        h.events = heapless::Vec::new();
+        h.drive(200, &[0b10, 0b00, 0b10, 0b11, 0b10, 0b00]);
+        assert!(h.events.is_empty());
+
+        // turning clockwise quickly: 100ms, then 50ms per detent
+        h.drive(300, &[0b10, 0b11, 0b01, 0b00]);
+        h.drive(400, &[0b10, 0b11, 0b01, 0b00]);
+        h.drive(450, &[0b10, 0b11, 0b01, 0b00]);
+        assert_eq!(
+            &h.events[..],
+            &[
+                Clockwise { velocity: 0 },
+                Clockwise { velocity: 10 },
+                Clockwise { velocity: 15 },
+            ]
+        );
+        assert_eq!(h.encoder.position(), 3);
+        assert_eq!(h.encoder.velocity(), 15);
+    }
+
+    #[test]
+    fn half_detents() {
+        let mut h = Harness::new(EncoderConfig {
+            steps_per_detent: 2,
+        });
+        h.drive(0, &[0b01, 0b11, 0b10, 0b00]);
+        assert_eq!(
+            &h.events[..],
+            &[
+                CounterClockwise { velocity: 0 },
+                CounterClockwise { velocity: 500 },
+            ]
+        );
+        assert_eq!(h.encoder.position(), -2);
+    }
+}
@@ -0,0 +1,1002 @@
+use crate::component::{Component, ComponentContext, ConnectedComponent};
+use crate::driver::at::{AtCommand, AtEngine, AtError, Lines, Urc};
+use crate::driver::lock::{BusLock, Flag};
+use crate::handler::Handler;
+use crate::net::{Ipv4Address, SocketAddress, TcpStack, UdpStack};
+use crate::time::Duration;
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Write};
+use embedded_hal::serial;
+use heapless::spsc::Queue;
+use heapless::{consts::*, ArrayLength, String, Vec};
+
+/// The number of simultaneous connections supported by the module.
+const LINKS: usize = 5;
+
+/// The most data accepted by a single `AT+CIPSEND`.
+const MAX_SEND: usize = 2048;
+
+const JOIN_TIMEOUT: Duration = Duration::from_secs(20);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The header of data retrieved by `AT+CIPRECVDATA`.
+const RETRIEVED: &[u8] = b"+CIPRECVDATA";
+
+/// Errors reported by an `EspWifi`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WifiError {
+    /// The module did not respond in time.
+    Timeout,
+    /// The module reported an error, or responded unexpectedly.
+    Error,
+    /// The network could not be joined.
+    JoinFailed,
+    /// Every connection of the module is in use.
+    NoSocket,
+    /// The socket has been closed.
+    Closed,
+    /// A command, such as with a long host name, or a datagram, was too
+    /// long to be sent.
+    TooLong,
+    /// Data pushed by the module upon a TCP connection did not fit its
+    /// buffer and was lost, so the socket must be closed.
+    Overrun,
+    /// Writing to the serial port failed.
+    Serial,
+}
+
+impl From<AtError> for WifiError {
+    fn from(error: AtError) -> Self {
+        match error {
+            AtError::Timeout => WifiError::Timeout,
+            AtError::TooLong => WifiError::TooLong,
+            AtError::Serial => WifiError::Serial,
+            _ => WifiError::Error,
+        }
+    }
+}
+
+/// A TCP connection or UDP socket of an `EspWifi`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Socket(u8);
+
+impl Socket {
+    /// The link ID of this connection within the module.
+    pub fn id(&self) -> u8 {
+        self.0
+    }
+}
+
+/// Messages sent upstream by an `EspWifi`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WifiEvent {
+    /// The network has been joined, and an address obtained.
+    Joined,
+    Disconnected,
+    /// The socket has been closed, either locally or by its peer.
+    Closed(Socket),
+}
+
+/// The unsolicited result codes of the ESP-AT firmware, handled by an `EspWifi`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EspUrc {
+    GotIp,
+    Disconnected,
+    Closed(u8),
+    /// A chunk of the data of `+IPD`, `len` bytes long in total.
+    Data {
+        link: u8,
+        len: usize,
+        data: Vec<u8, U64>,
+    },
+    /// Data held by the module for `link`, in passive receive mode, to be
+    /// retrieved with `AT+CIPRECVDATA`.
+    Available {
+        link: u8,
+        len: usize,
+    },
+    /// A chunk of the data retrieved by `AT+CIPRECVDATA`.
+    Retrieved {
+        data: Vec<u8, U64>,
+    },
+}
+
+impl Urc for EspUrc {
+    fn parse(line: &[u8]) -> Option<Self> {
+        match line {
+            b"WIFI GOT IP" => Some(EspUrc::GotIp),
+            b"WIFI DISCONNECT" => Some(EspUrc::Disconnected),
+            [link @ b'0'..=b'4', b',', b'C', b'L', b'O', b'S', b'E', b'D'] => {
+                Some(EspUrc::Closed(link - b'0'))
+            }
+            [b'+', b'I', b'P', b'D', b',', fields @ ..] => {
+                let (link, len) = parse_ipd(fields)?;
+                Some(EspUrc::Available {
+                    link: link as u8,
+                    len,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn header(partial: &[u8]) -> Option<usize> {
+        match partial {
+            [b'+', b'I', b'P', b'D', b',', fields @ .., b':'] => Some(parse_ipd(fields)?.1),
+            _ => match partial.strip_prefix(RETRIEVED)? {
+                // as by ESP-AT, or by the ESP8266 NONOS firmware
+                [b':', len @ .., b','] | [b',', len @ .., b':'] => {
+                    core::str::from_utf8(len).ok()?.parse().ok()
+                }
+                _ => None,
+            },
+        }
+    }
+
+    fn data(header: &[u8], data: &[u8]) -> Option<Self> {
+        let data = Vec::from_slice(data).ok()?;
+        if header.starts_with(RETRIEVED) {
+            return Some(EspUrc::Retrieved { data });
+        }
+        let (link, len) = parse_ipd(&header[5..header.len() - 1])?;
+        Some(EspUrc::Data {
+            link: link as u8,
+            len,
+            data,
+        })
+    }
+}
+
+/// A command configuring the module, resulting only in `OK`.
+struct Configure(&'static str);
+
+impl AtCommand for Configure {
+    type Response = ();
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        command.write_str(self.0)
+    }
+
+    fn parse(&self, _: Lines<'_>) -> Result<(), AtError> {
+        Ok(())
+    }
+}
+
+struct Join<'a> {
+    ssid: &'a str,
+    password: &'a str,
+}
+
+impl<'a> AtCommand for Join<'a> {
+    type Response = ();
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        command.write_str("AT+CWJAP=")?;
+        quote(command, self.ssid)?;
+        command.write_char(',')?;
+        quote(command, self.password)
+    }
+
+    fn timeout(&self) -> Duration {
+        JOIN_TIMEOUT
+    }
+
+    fn parse(&self, _: Lines<'_>) -> Result<(), AtError> {
+        Ok(())
+    }
+}
+
+struct Resolve<'a> {
+    host: &'a str,
+}
+
+impl<'a> AtCommand for Resolve<'a> {
+    type Response = Ipv4Address;
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        command.write_str("AT+CIPDOMAIN=")?;
+        quote(command, self.host)
+    }
+
+    fn timeout(&self) -> Duration {
+        CONNECT_TIMEOUT
+    }
+
+    fn parse(&self, mut lines: Lines<'_>) -> Result<Ipv4Address, AtError> {
+        lines.find_map(parse_address).ok_or(AtError::Parse)
+    }
+}
+
+struct Connect<'a> {
+    link: u8,
+    protocol: &'static str,
+    host: &'a str,
+    port: u16,
+}
+
+impl<'a> AtCommand for Connect<'a> {
+    type Response = ();
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        write!(command, "AT+CIPSTART={},\"{}\",", self.link, self.protocol)?;
+        quote(command, self.host)?;
+        write!(command, ",{}", self.port)
+    }
+
+    fn timeout(&self) -> Duration {
+        CONNECT_TIMEOUT
+    }
+
+    fn parse(&self, _: Lines<'_>) -> Result<(), AtError> {
+        Ok(())
+    }
+}
+
+struct Send {
+    link: u8,
+    len: usize,
+}
+
+impl AtCommand for Send {
+    type Response = ();
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        write!(command, "AT+CIPSEND={},{}", self.link, self.len)
+    }
+
+    fn timeout(&self) -> Duration {
+        SEND_TIMEOUT
+    }
+
+    fn parse(&self, _: Lines<'_>) -> Result<(), AtError> {
+        Ok(())
+    }
+}
+
+/// Retrieve up to `len` bytes of the data held for `link`.
+struct Retrieve {
+    link: u8,
+    len: usize,
+}
+
+impl AtCommand for Retrieve {
+    type Response = ();
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        write!(command, "AT+CIPRECVDATA={},{}", self.link, self.len)
+    }
+
+    fn parse(&self, _: Lines<'_>) -> Result<(), AtError> {
+        Ok(())
+    }
+}
+
+struct Close {
+    link: u8,
+}
+
+impl AtCommand for Close {
+    type Response = ();
+
+    fn encode(&self, command: &mut dyn fmt::Write) -> fmt::Result {
+        write!(command, "AT+CIPCLOSE={}", self.link)
+    }
+
+    fn parse(&self, _: Lines<'_>) -> Result<(), AtError> {
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LinkState {
+    Free,
+    Connecting,
+    Open,
+    /// Closed by the peer, with data possibly remaining to be read.
+    Closed,
+    /// Pushed more TCP data than could be buffered.
+    Overrun,
+}
+
+struct Link {
+    state: Cell<LinkState>,
+    udp: Cell<bool>,
+    rx: RefCell<Queue<u8, U512>>,
+    /// For TCP, whether the module may hold data yet to be retrieved.
+    unread: Cell<bool>,
+    /// For UDP, the number of datagrams fully received, each preceded in
+    /// `rx` by its length.
+    datagrams: Cell<usize>,
+    /// For UDP, the bytes of the datagram being received yet to arrive.
+    remaining: Cell<usize>,
+    /// For UDP, whether the datagram being received is buffered, or dropped.
+    keep: Cell<bool>,
+    readable: Flag,
+}
+
+impl Link {
+    fn reset(&self, state: LinkState, udp: bool) {
+        self.state.set(state);
+        self.udp.set(udp);
+        *self.rx.borrow_mut() = Queue::new();
+        self.unread.set(false);
+        self.datagrams.set(0);
+        self.remaining.set(0);
+    }
+
+    fn on_data(&self, len: usize, data: Vec<u8, U64>) {
+        let mut rx = self.rx.borrow_mut();
+        if !self.udp.get() {
+            // only should the module not be in passive receive mode
+            for b in data {
+                if rx.enqueue(b).is_err() {
+                    self.state.set(LinkState::Overrun);
+                    break;
+                }
+            }
+            self.readable.raise();
+            return;
+        }
+
+        if self.remaining.get() == 0 {
+            // only datagrams fitting whole are kept
+            let keep = rx.capacity() - rx.len() >= len + 2;
+            if keep {
+                for b in &(len as u16).to_be_bytes() {
+                    rx.enqueue(*b).ok();
+                }
+            }
+            self.keep.set(keep);
+            self.remaining.set(len);
+        }
+        self.remaining
+            .set(self.remaining.get().saturating_sub(data.len()));
+        if self.keep.get() {
+            for b in data {
+                rx.enqueue(b).ok();
+            }
+            if self.remaining.get() == 0 {
+                self.datagrams.set(self.datagrams.get() + 1);
+                self.readable.raise();
+            }
+        }
+    }
+}
+
+/// A WiFi adapter driving an ESP8266 or ESP32 running the ESP-AT firmware
+/// through an `AtEngine`, and providing TCP and UDP sockets to other
+/// components, as a `TcpStack` and `UdpStack`.
+///
+/// Data received upon a socket is buffered, up to 512 bytes per socket,
+/// until read through `receive(...)`. The module holds TCP data, in the
+/// passive receive mode set upon `join(...)`, until there is room for it.
+/// UDP data is instead pushed as it arrives, so any datagram which does
+/// not fit whole is dropped.
+///
+/// The adapter is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static EspWifi<TX, N>>`, through which it starts
+/// its engine and reports `WifiEvent`s upstream.
+pub struct EspWifi<TX, N>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    engine: &'static AtEngine<TX, N, EspUrc>,
+    at: ConnectedComponent<&'static AtEngine<TX, N, EspUrc>>,
+    links: Vec<Link, U5>,
+    /// Held while data is retrieved, for the link recorded.
+    retrieving: BusLock,
+    retrieving_link: Cell<Option<u8>>,
+    joined: Cell<bool>,
+    ctx: Cell<Option<&'static ComponentContext<&'static EspWifi<TX, N>>>>,
+}
+
+impl<TX, N> EspWifi<TX, N>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    pub fn new(engine: &'static AtEngine<TX, N, EspUrc>) -> Self {
+        let mut links = Vec::new();
+        for _ in 0..LINKS {
+            links
+                .push(Link {
+                    state: Cell::new(LinkState::Free),
+                    udp: Cell::new(false),
+                    rx: RefCell::new(Queue::new()),
+                    unread: Cell::new(false),
+                    datagrams: Cell::new(0),
+                    remaining: Cell::new(0),
+                    keep: Cell::new(false),
+                    readable: Flag::new(),
+                })
+                .ok();
+        }
+        Self {
+            engine,
+            at: ConnectedComponent::new(engine),
+            links,
+            retrieving: BusLock::new(),
+            retrieving_link: Cell::new(None),
+            joined: Cell::new(false),
+            ctx: Cell::new(None),
+        }
+    }
+
+    /// Determine if a network has been joined, and an address obtained.
+    pub fn is_joined(&self) -> bool {
+        self.joined.get()
+    }
+
+    /// Join, *asynchronously*, the network `ssid`, first configuring the
+    /// module as a station accepting multiple connections, and holding
+    /// TCP data until retrieved.
+    pub async fn join(&self, ssid: &str, password: &str) -> Result<(), WifiError> {
+        for command in &["ATE0", "AT+CWMODE=1", "AT+CIPMUX=1", "AT+CIPRECVMODE=1"] {
+            self.engine.request(&Configure(command)).await?;
+        }
+        match self.engine.request(&Join { ssid, password }).await {
+            Err(AtError::Error) => Err(WifiError::JoinFailed),
+            result => Ok(result?),
+        }
+    }
+
+    /// Resolve, *asynchronously*, the IPv4 address of `host`.
+    pub async fn resolve(&self, host: &str) -> Result<Ipv4Address, WifiError> {
+        Ok(self.engine.request(&Resolve { host }).await?)
+    }
+
+    /// Open, *asynchronously*, a TCP connection to `port` of `host`,
+    /// given as either a name or an address.
+    pub async fn connect(&self, host: &str, port: u16) -> Result<Socket, WifiError> {
+        self.open("TCP", host, port).await
+    }
+
+    async fn open(
+        &self,
+        protocol: &'static str,
+        host: &str,
+        port: u16,
+    ) -> Result<Socket, WifiError> {
+        let link = self
+            .links
+            .iter()
+            .position(|link| link.state.get() == LinkState::Free)
+            .ok_or(WifiError::NoSocket)? as u8;
+        let state = &self.links[link as usize];
+        state.reset(LinkState::Connecting, protocol == "UDP");
+
+        let command = Connect {
+            link,
+            protocol,
+            host,
+            port,
+        };
+        match self.engine.request(&command).await {
+            Ok(()) => {
+                state.state.set(LinkState::Open);
+                Ok(Socket(link))
+            }
+            Err(e) => {
+                state.state.set(LinkState::Free);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Send, *asynchronously*, all of `data` upon `socket`.
+    pub async fn send(&self, socket: Socket, data: &[u8]) -> Result<usize, WifiError> {
+        if self.links[socket.0 as usize].state.get() != LinkState::Open {
+            return Err(WifiError::Closed);
+        }
+        for chunk in data.chunks(MAX_SEND) {
+            let command = Send {
+                link: socket.0,
+                len: chunk.len(),
+            };
+            self.engine.request_with_data(&command, chunk).await?;
+        }
+        Ok(data.len())
+    }
+
+    /// Receive, *asynchronously*, one datagram from the UDP `socket` into
+    /// `buf`, discarding any of it not fitting.
+    async fn receive_datagram(&self, socket: Socket, buf: &mut [u8]) -> Result<usize, WifiError> {
+        let link = &self.links[socket.0 as usize];
+        loop {
+            if link.datagrams.get() > 0 {
+                let mut rx = link.rx.borrow_mut();
+                let mut len = [0; 2];
+                for b in len.iter_mut() {
+                    *b = rx.dequeue().unwrap_or(0);
+                }
+                let len = u16::from_be_bytes(len) as usize;
+                for i in 0..len {
+                    let b = rx.dequeue().unwrap_or(0);
+                    if let Some(slot) = buf.get_mut(i) {
+                        *slot = b;
+                    }
+                }
+                link.datagrams.set(link.datagrams.get() - 1);
+                return Ok(len.min(buf.len()));
+            }
+            match link.state.get() {
+                LinkState::Open => link.readable.wait().await,
+                _ => return Err(WifiError::Closed),
+            }
+        }
+    }
+
+    /// Receive, *asynchronously*, at least one byte from `socket` into `buf`,
+    /// returning the number of bytes received, or zero once the socket has
+    /// been closed by its peer and every byte received has been read.
+    pub async fn receive(&self, socket: Socket, buf: &mut [u8]) -> Result<usize, WifiError> {
+        let link = &self.links[socket.0 as usize];
+        loop {
+            let mut len = 0;
+            {
+                let mut rx = link.rx.borrow_mut();
+                while len < buf.len() {
+                    match rx.dequeue() {
+                        Some(b) => {
+                            buf[len] = b;
+                            len += 1;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+            if link.unread.replace(false) {
+                match self.retrieve(socket.0).await {
+                    Ok(()) => continue,
+                    // the data of a connection closed by the peer may be gone
+                    Err(_) if link.state.get() == LinkState::Closed => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            match link.state.get() {
+                LinkState::Open => link.readable.wait().await,
+                LinkState::Closed => return Ok(0),
+                LinkState::Overrun => return Err(WifiError::Overrun),
+                _ => return Err(WifiError::Closed),
+            }
+        }
+    }
+
+    /// Retrieve, *asynchronously*, as much of the TCP data held by the
+    /// module for `link` as there is room for.
+    async fn retrieve(&self, link: u8) -> Result<(), WifiError> {
+        let state = &self.links[link as usize];
+        let _guard = self.retrieving.lock().await;
+        let (room, before) = {
+            let rx = state.rx.borrow();
+            (rx.capacity() - rx.len(), rx.len())
+        };
+        self.retrieving_link.set(Some(link));
+        let result = self.engine.request(&Retrieve { link, len: room }).await;
+        self.retrieving_link.set(None);
+        result?;
+        // the module may hold yet more
+        if state.rx.borrow().len() - before == room {
+            state.unread.set(true);
+        }
+        Ok(())
+    }
+
+    /// Close, *asynchronously*, `socket`, discarding any data not yet received.
+    pub async fn close(&self, socket: Socket) {
+        let link = &self.links[socket.0 as usize];
+        if matches!(link.state.get(), LinkState::Open | LinkState::Overrun) {
+            // the peer may have closed it meanwhile
+            self.engine.request(&Close { link: socket.0 }).await.ok();
+        }
+        link.reset(LinkState::Free, false);
+        // ending any receive still awaiting data
+        link.readable.raise();
+    }
+
+    fn on_urc(&self, urc: EspUrc) {
+        match urc {
+            EspUrc::GotIp => {
+                self.joined.set(true);
+                self.notify(WifiEvent::Joined);
+            }
+            EspUrc::Disconnected => {
+                self.joined.set(false);
+                self.notify(WifiEvent::Disconnected);
+            }
+            EspUrc::Closed(link) => {
+                let state = &self.links[link as usize];
+                if state.state.get() != LinkState::Free {
+                    state.state.set(LinkState::Closed);
+                    state.readable.raise();
+                }
+                self.notify(WifiEvent::Closed(Socket(link)));
+            }
+            EspUrc::Data { link, len, data } => {
+                if let Some(state) = self.links.get(link as usize) {
+                    state.on_data(len, data);
+                }
+            }
+            EspUrc::Available { link, .. } => {
+                if let Some(state) = self.links.get(link as usize) {
+                    if state.state.get() != LinkState::Free {
+                        state.unread.set(true);
+                        state.readable.raise();
+                    }
+                }
+            }
+            EspUrc::Retrieved { data } => {
+                if let Some(link) = self.retrieving_link.get() {
+                    // never more than there was room for
+                    let mut rx = self.links[link as usize].rx.borrow_mut();
+                    for b in data {
+                        rx.enqueue(b).ok();
+                    }
+                }
+            }
+        }
+    }
+
+    fn notify(&self, event: WifiEvent) {
+        if let Some(ctx) = self.ctx.get() {
+            ctx.send(event);
+        }
+    }
+}
+
+/// Format `ip` as the host argument of `AT+CIPSTART`.
+fn host(ip: Ipv4Address) -> String<U15> {
+    let mut host = String::new();
+    write!(host, "{}", ip).ok();
+    host
+}
+
+impl<TX, N> TcpStack for EspWifi<TX, N>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    type Socket = Socket;
+    type Error = WifiError;
+
+    async fn connect(&self, remote: SocketAddress) -> Result<Socket, WifiError> {
+        self.open("TCP", &host(remote.ip), remote.port).await
+    }
+
+    async fn send(&self, socket: Socket, data: &[u8]) -> Result<usize, WifiError> {
+        EspWifi::send(self, socket, data).await
+    }
+
+    async fn receive(&self, socket: Socket, buf: &mut [u8]) -> Result<usize, WifiError> {
+        EspWifi::receive(self, socket, buf).await
+    }
+
+    async fn close(&self, socket: Socket) {
+        EspWifi::close(self, socket).await
+    }
+}
+
+impl<TX, N> UdpStack for EspWifi<TX, N>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    type Socket = Socket;
+    type Error = WifiError;
+
+    async fn open(&self, remote: SocketAddress) -> Result<Socket, WifiError> {
+        EspWifi::open(self, "UDP", &host(remote.ip), remote.port).await
+    }
+
+    async fn send(&self, socket: Socket, data: &[u8]) -> Result<(), WifiError> {
+        if data.len() > MAX_SEND {
+            return Err(WifiError::TooLong);
+        }
+        EspWifi::send(self, socket, data).await.map(|_| ())
+    }
+
+    async fn receive(&self, socket: Socket, buf: &mut [u8]) -> Result<usize, WifiError> {
+        self.receive_datagram(socket, buf).await
+    }
+
+    async fn close(&self, socket: Socket) {
+        EspWifi::close(self, socket).await
+    }
+}
+
+impl<TX, N> Component for &'static EspWifi<TX, N>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = WifiEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.set(Some(ctx));
+        let wifi: &'static EspWifi<TX, N> = self;
+        wifi.at.start(ctx);
+    }
+}
+
+impl<TX, N> Handler<EspUrc> for &'static EspWifi<TX, N>
+where
+    TX: serial::Write<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    fn on_message(&mut self, message: EspUrc) {
+        self.on_urc(message)
+    }
+}
+
+/// Write `value` as a quoted string argument, escaping as ESP-AT requires.
+fn quote(command: &mut dyn fmt::Write, value: &str) -> fmt::Result {
+    command.write_char('"')?;
+    for c in value.chars() {
+        if matches!(c, '"' | ',' | '\\') {
+            command.write_char('\\')?;
+        }
+        command.write_char(c)?;
+    }
+    command.write_char('"')
+}
+
+/// Parse the `<id>,<len>` of `+IPD,<id>,<len>:<data>`.
+fn parse_ipd(fields: &[u8]) -> Option<(usize, usize)> {
+    let fields = core::str::from_utf8(fields).ok()?;
+    let mut fields = fields.split(',');
+    let id = fields.next()?.parse().ok()?;
+    let len = fields.next()?.parse().ok()?;
+    Some((id, len))
+}
+
+/// Parse the address of `+CIPDOMAIN:<address>`, possibly quoted.
+fn parse_address(line: &[u8]) -> Option<Ipv4Address> {
+    let line = core::str::from_utf8(line).ok()?;
+    let address = line.strip_prefix("+CIPDOMAIN:")?.trim_matches('"');
+    let mut octets = [0; 4];
+    let mut parts = address.split('.');
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Address(octets)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_address, EspUrc, EspWifi, Socket, WifiError};
+    use crate::driver::at::tests::Harness;
+    use crate::mock::MockModem;
+    use crate::net::{Ipv4Address, SocketAddress, TcpStack, UdpStack};
+    use crate::testing::leak;
+    use core::cell::Cell;
+    use core::fmt::Write;
+    use heapless::{consts::*, String};
+
+    type Wifi = EspWifi<MockModem, U256>;
+
+    fn setup() -> (Harness<EspUrc>, &'static Wifi) {
+        let wifi: &'static Cell<Option<&'static Wifi>> = leak(Cell::new(None));
+        let h = Harness::new(move |urc| wifi.get().unwrap().on_urc(urc));
+        wifi.set(Some(leak(EspWifi::new(h.engine))));
+        (h, wifi.get().unwrap())
+    }
+
+    #[test]
+    fn join_and_resolve() {
+        let (mut h, wifi) = setup();
+        let m = h.modem;
+        m.expect(b"ATE0\r\n", b"ATE0\r\r\n\r\nOK\r\n");
+        m.expect(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n");
+        m.expect(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n");
+        m.expect(b"AT+CIPRECVMODE=1\r\n", b"\r\nOK\r\n");
+        m.expect(
+            b"AT+CWJAP=\"drogue\",\"s3cr\\,t\"\r\n",
+            b"WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n",
+        );
+        m.expect(
+            b"AT+CIPDOMAIN=\"example.com\"\r\n",
+            b"+CIPDOMAIN:93.184.216.34\r\n\r\nOK\r\n",
+        );
+        m.expect(b"AT+CIPDOMAIN=\"nowhere\"\r\n", b"DNS Fail\r\nERROR\r\n");
+
+        assert!(!wifi.is_joined());
+        assert_eq!(h.run(wifi.join("drogue", "s3cr,t")), Ok(()));
+        assert!(wifi.is_joined());
+        assert_eq!(
+            h.run(wifi.resolve("example.com")),
+            Ok(Ipv4Address::new(93, 184, 216, 34))
+        );
+        assert_eq!(h.run(wifi.resolve("nowhere")), Err(WifiError::Error));
+
+        m.expect(b"ATE0\r\n", b"\r\nOK\r\n");
+        m.expect(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n");
+        m.expect(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n");
+        m.expect(b"AT+CIPRECVMODE=1\r\n", b"\r\nOK\r\n");
+        m.expect(
+            b"AT+CWJAP=\"drogue\",\"wrong\"\r\n",
+            b"WIFI DISCONNECT\r\n+CWJAP:2\r\n\r\nFAIL\r\n",
+        );
+        assert_eq!(
+            h.run(wifi.join("drogue", "wrong")),
+            Err(WifiError::JoinFailed)
+        );
+        assert!(!wifi.is_joined());
+        assert!(m.is_done());
+
+        assert_eq!(
+            parse_address(b"+CIPDOMAIN:\"10.0.0.1\""),
+            Some(Ipv4Address::new(10, 0, 0, 1))
+        );
+        assert_eq!(parse_address(b"+CIPDOMAIN:10.0.0"), None);
+    }
+
+    #[test]
+    fn sockets() {
+        let (mut h, wifi) = setup();
+        let m = h.modem;
+
+        m.expect(
+            b"AT+CIPSTART=0,\"TCP\",\"192.168.1.2\",8080\r\n",
+            b"0,CONNECT\r\n\r\nOK\r\n",
+        );
+        m.expect(
+            b"AT+CIPSTART=1,\"TCP\",\"192.168.1.3\",80\r\n",
+            b"1,CONNECT FAIL\r\n1,CLOSED\r\n\r\nERROR\r\n",
+        );
+        let socket = h.run(wifi.connect("192.168.1.2", 8080)).unwrap();
+        assert_eq!(socket, Socket(0));
+        assert_eq!(
+            h.run(wifi.connect("192.168.1.3", 80)),
+            Err(WifiError::Error)
+        );
+
+        m.expect(b"AT+CIPSEND=0,5\r\n", b"\r\nOK\r\n> ");
+        m.expect(b"hello", b"\r\nRecv 5 bytes\r\n\r\nSEND OK\r\n");
+        assert_eq!(h.run(wifi.send(socket, b"hello")), Ok(5));
+
+        // data held by the module is retrieved only as there is room for it
+        let room = wifi.links[0].rx.borrow().capacity();
+        m.inject(b"\r\n+IPD,0,10\r\n");
+        h.process();
+        m.expect(
+            leak(retrieve(0, room)).as_bytes(),
+            b"+CIPRECVDATA:10,world!\r\nok\r\n\r\nOK\r\n",
+        );
+        let mut buf = [0; 4];
+        assert_eq!(h.run(wifi.receive(socket, &mut buf)), Ok(4));
+        assert_eq!(&buf, b"worl");
+        let mut buf = [0; 16];
+        assert_eq!(h.run(wifi.receive(socket, &mut buf)), Ok(6));
+        assert_eq!(&buf[..6], b"d!\r\nok");
+
+        // data already received remains readable once closed by the peer
+        m.inject(b"+IPD,0,3\r\n0,CLOSED\r\n");
+        h.process();
+        m.expect(
+            leak(retrieve(0, room)).as_bytes(),
+            b"+CIPRECVDATA,3:bye\r\nOK\r\n",
+        );
+        assert_eq!(h.run(wifi.receive(socket, &mut buf)), Ok(3));
+        assert_eq!(&buf[..3], b"bye");
+        assert_eq!(h.run(wifi.receive(socket, &mut buf)), Ok(0));
+        assert_eq!(h.run(wifi.send(socket, b"hello")), Err(WifiError::Closed));
+
+        h.run(wifi.close(socket));
+        assert_eq!(
+            h.run(wifi.receive(socket, &mut buf)),
+            Err(WifiError::Closed)
+        );
+
+        m.expect(
+            b"AT+CIPSTART=0,\"TCP\",\"192.168.1.2\",8080\r\n",
+            b"0,CONNECT\r\n\r\nOK\r\n",
+        );
+        m.expect(b"AT+CIPCLOSE=0\r\n", b"0,CLOSED\r\n\r\nOK\r\n");
+        let socket = h.run(wifi.connect("192.168.1.2", 8080)).unwrap();
+        h.run(wifi.close(socket));
+        assert!(m.is_done());
+    }
+
+    #[test]
+    fn stacks() {
+        let (mut h, wifi) = setup();
+        let m = h.modem;
+        let remote = SocketAddress::new(Ipv4Address::new(10, 0, 0, 1), 5683);
+
+        m.expect(
+            b"AT+CIPSTART=0,\"TCP\",\"10.0.0.1\",5683\r\n",
+            b"0,CONNECT\r\n\r\nOK\r\n",
+        );
+        m.expect(
+            b"AT+CIPSTART=1,\"UDP\",\"10.0.0.1\",5683\r\n",
+            b"1,CONNECT\r\n\r\nOK\r\n",
+        );
+        let tcp = h.run(TcpStack::connect(wifi, remote)).unwrap();
+        let udp = h.run(UdpStack::open(wifi, remote)).unwrap();
+        assert_eq!(udp, Socket(1));
+
+        m.expect(b"AT+CIPSEND=1,4\r\n", b"\r\nOK\r\n> ");
+        m.expect(b"ping", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n");
+        assert_eq!(h.run(UdpStack::send(wifi, udp, b"ping")), Ok(()));
+        assert_eq!(
+            h.run(UdpStack::send(wifi, udp, &[0; 2049])),
+            Err(WifiError::TooLong)
+        );
+
+        // datagrams remain distinct, and are truncated to fit
+        m.inject(b"\r\n+IPD,1,5:pong!\r\n+IPD,1,2:hi\r\n+IPD,0,3\r\n");
+        h.process();
+        let room = wifi.links[0].rx.borrow().capacity();
+        m.expect(
+            leak(retrieve(0, room)).as_bytes(),
+            b"+CIPRECVDATA:3,tcp\r\nOK\r\n",
+        );
+        let mut buf = [0; 4];
+        assert_eq!(h.run(UdpStack::receive(wifi, udp, &mut buf)), Ok(4));
+        assert_eq!(&buf, b"pong");
+        assert_eq!(h.run(UdpStack::receive(wifi, udp, &mut buf)), Ok(2));
+        assert_eq!(&buf[..2], b"hi");
+        assert_eq!(h.run(TcpStack::receive(wifi, tcp, &mut buf)), Ok(3));
+        assert_eq!(&buf[..3], b"tcp");
+
+        m.expect(b"AT+CIPCLOSE=1\r\n", b"1,CLOSED\r\n\r\nOK\r\n");
+        h.run(UdpStack::close(wifi, udp));
+        assert_eq!(
+            h.run(UdpStack::receive(wifi, udp, &mut buf)),
+            Err(WifiError::Closed)
+        );
+        assert!(m.is_done());
+    }
+
+    #[test]
+    fn overrun() {
+        let (mut h, wifi) = setup();
+        let m = h.modem;
+
+        // pushed by a module not in passive receive mode
+        m.expect(
+            b"AT+CIPSTART=0,\"TCP\",\"192.168.1.2\",8080\r\n",
+            b"0,CONNECT\r\n\r\nOK\r\n",
+        );
+        let socket = h.run(wifi.connect("192.168.1.2", 8080)).unwrap();
+        let room = wifi.links[0].rx.borrow().capacity();
+        let mut pushed: String<U32> = String::new();
+        write!(pushed, "+IPD,0,{}:", room + 1).unwrap();
+        m.inject(pushed.as_bytes());
+        for i in 0..=room {
+            m.inject(b"x");
+            if i.is_multiple_of(128) {
+                h.process();
+            }
+        }
+        h.process();
+
+        // what was buffered is read, though the rest was lost
+        let mut buf = [0; 1024];
+        assert_eq!(h.run(wifi.receive(socket, &mut buf)), Ok(room));
+        assert_eq!(
+            h.run(wifi.receive(socket, &mut buf)),
+            Err(WifiError::Overrun)
+        );
+
+        m.expect(b"AT+CIPCLOSE=0\r\n", b"0,CLOSED\r\n\r\nOK\r\n");
+        h.run(wifi.close(socket));
+        assert!(m.is_done());
+    }
+
+    fn retrieve(link: u8, len: usize) -> String<U32> {
+        let mut command = String::new();
+        write!(command, "AT+CIPRECVDATA={},{}\r\n", link, len).unwrap();
+        command
+    }
+}
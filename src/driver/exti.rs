@@ -0,0 +1,45 @@
+use crate::interrupt::{Interrupt, InterruptContext};
+
+/// A GPIO pin routed to an EXTI line, whose pending flag must be
+/// cleared by its interrupt handler.
+///
+/// HALs expose this differently, so applications implement this trait
+/// for their HAL's pin types, typically delegating to a method such as
+/// `clear_interrupt_pending_bit()`.
+pub trait ExtiPin {
+    /// Clear the pending flag of this pin's EXTI line.
+    fn clear_pending(&mut self);
+}
+
+/// Message sent upstream by a `DataReadyInterrupt`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DataReady;
+
+/// An interrupt for a peripheral's data-ready line, attached to a
+/// GPIO pin routed to an EXTI line.
+///
+/// The EXTI pending flag is cleared upon each interrupt, before
+/// `DataReady` is sent upstream.
+pub struct DataReadyInterrupt<P: ExtiPin> {
+    pin: P,
+    irq: u8,
+}
+
+impl<P: ExtiPin> DataReadyInterrupt<P> {
+    pub fn new(pin: P, irq: u8) -> Self {
+        Self { pin, irq }
+    }
+}
+
+impl<P: ExtiPin> Interrupt for DataReadyInterrupt<P> {
+    type OutboundMessage = DataReady;
+
+    fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    fn on_interrupt(&mut self, context: &InterruptContext<Self>) {
+        self.pin.clear_pending();
+        context.send(DataReady);
+    }
+}
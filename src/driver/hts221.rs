@@ -0,0 +1,295 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::driver::exti::{DataReady, DataReadyInterrupt, ExtiPin};
+use crate::driver::i2c::{I2cDevice, I2cPeripheral};
+use crate::driver::lock::Flag;
+use crate::handler::Handler;
+use crate::interrupt::ConnectedInterrupt;
+use crate::sensor::{Celsius, Reading, RelativeHumidity, SensorError, SensorEvent, SensorRequest};
+use core::cell::Cell;
+use heapless::{consts::*, Vec};
+
+/// The I2C address of the HTS221.
+pub const ADDRESS: u8 = 0x5F;
+
+const WHO_AM_I: u8 = 0x0F;
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG3: u8 = 0x22;
+const STATUS: u8 = 0x27;
+const HUMIDITY_OUT_L: u8 = 0x28;
+const CALIB_0: u8 = 0x30;
+
+const IDENTITY: u8 = 0xBC;
+/// Set within a register address to read or write consecutive registers.
+const AUTO_INCREMENT: u8 = 0x80;
+
+const CTRL_REG1_PD: u8 = 0x80;
+const CTRL_REG1_BDU: u8 = 0x04;
+const CTRL_REG1_ODR_1HZ: u8 = 0x01;
+const CTRL_REG3_DRDY_EN: u8 = 0x04;
+
+const STATUS_T_DA: u8 = 0x01;
+const STATUS_H_DA: u8 = 0x02;
+
+/// Factory calibration, linearly interpolating raw outputs.
+#[derive(Copy, Clone, Debug)]
+struct Calibration {
+    h0: f32,
+    h1: f32,
+    h0_out: i16,
+    h1_out: i16,
+    t0: f32,
+    t1: f32,
+    t0_out: i16,
+    t1_out: i16,
+}
+
+impl Calibration {
+    fn parse(data: &[u8; 16]) -> Self {
+        let i16_at = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]);
+        let msb = data[5];
+        Self {
+            h0: data[0] as f32 / 2.0,
+            h1: data[1] as f32 / 2.0,
+            t0: (((msb as u16 & 0x03) << 8) | data[2] as u16) as f32 / 8.0,
+            t1: (((msb as u16 & 0x0C) << 6) | data[3] as u16) as f32 / 8.0,
+            h0_out: i16_at(6),
+            h1_out: i16_at(10),
+            t0_out: i16_at(12),
+            t1_out: i16_at(14),
+        }
+    }
+
+    fn temperature(&self, out: i16) -> Result<Celsius, SensorError> {
+        interpolate(out, self.t0_out, self.t1_out, self.t0, self.t1).map(Celsius)
+    }
+
+    fn humidity(&self, out: i16) -> Result<RelativeHumidity, SensorError> {
+        let h = interpolate(out, self.h0_out, self.h1_out, self.h0, self.h1)?;
+        Ok(RelativeHumidity(h.clamp(0.0, 100.0)))
+    }
+}
+
+/// Interpolate `out` between two calibration points, which must differ.
+fn interpolate(out: i16, out0: i16, out1: i16, v0: f32, v1: f32) -> Result<f32, SensorError> {
+    if out1 == out0 {
+        return Err(SensorError::Calibration);
+    }
+    Ok(v0 + (out as f32 - out0 as f32) * (v1 - v0) / (out1 as f32 - out0 as f32))
+}
+
+/// A driver for the ST HTS221 temperature and humidity sensor, attached
+/// to an `I2cBus`, with its DRDY line routed to an EXTI interrupt.
+///
+/// Once started, the sensor measures continuously at 1Hz, and both a
+/// temperature and a humidity `Reading` are sent upstream whenever new
+/// data is ready, and whenever a `SensorRequest::Sample` is received.
+pub struct Hts221<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    device: I2cDevice<I2C, E>,
+    data_ready: ConnectedInterrupt<DataReadyInterrupt<P>>,
+    ready: Flag,
+    calibration: Cell<Option<Calibration>>,
+}
+
+impl<I2C, E, P> Hts221<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    /// Create a new driver for the sensor at `device`, its DRDY line
+    /// attached to `drdy` triggering `irq`.
+    pub fn new(device: I2cDevice<I2C, E>, drdy: P, irq: u8) -> Self {
+        Self {
+            device,
+            data_ready: ConnectedInterrupt::new(DataReadyInterrupt::new(drdy, irq)),
+            ready: Flag::new(),
+            calibration: Cell::new(None),
+        }
+    }
+
+    /// Identify the sensor, read its calibration, and begin measuring.
+    async fn initialize(&self) -> Result<(), SensorError> {
+        let mut id = [0];
+        self.device
+            .write_read(&[WHO_AM_I], &mut id)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        if id[0] != IDENTITY {
+            return Err(SensorError::NotFound);
+        }
+
+        let mut data = [0; 16];
+        self.device
+            .write_read(&[CALIB_0 | AUTO_INCREMENT], &mut data)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        self.calibration.set(Some(Calibration::parse(&data)));
+
+        self.device
+            .write(&[CTRL_REG3, CTRL_REG3_DRDY_EN])
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        self.device
+            .write(&[CTRL_REG1, CTRL_REG1_PD | CTRL_REG1_BDU | CTRL_REG1_ODR_1HZ])
+            .await
+            .map_err(|_| SensorError::Bus)
+    }
+
+    /// Read the most recent temperature and humidity.
+    async fn read(&self) -> Result<Vec<Reading, U2>, SensorError> {
+        let calibration = self.calibration.get().ok_or(SensorError::NotReady)?;
+
+        let mut status = [0];
+        self.device
+            .write_read(&[STATUS], &mut status)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        if status[0] & (STATUS_T_DA | STATUS_H_DA) == 0 {
+            return Err(SensorError::NotReady);
+        }
+
+        let mut out = [0; 4];
+        self.device
+            .write_read(&[HUMIDITY_OUT_L | AUTO_INCREMENT], &mut out)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        let humidity = i16::from_le_bytes([out[0], out[1]]);
+        let temperature = i16::from_le_bytes([out[2], out[3]]);
+
+        let mut readings = Vec::new();
+        readings
+            .push(Reading::Temperature(calibration.temperature(temperature)?))
+            .ok();
+        readings
+            .push(Reading::Humidity(calibration.humidity(humidity)?))
+            .ok();
+        Ok(readings)
+    }
+
+    async fn sample(&self, ctx: &ComponentContext<Self>) {
+        match self.read().await {
+            Ok(readings) => {
+                for reading in readings.iter() {
+                    ctx.send(SensorEvent::Reading(*reading));
+                }
+            }
+            Err(error) => ctx.send(SensorEvent::Error(error)),
+        }
+    }
+}
+
+impl<I2C, E, P> Component for Hts221<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    type InboundMessage = SensorRequest;
+    type OutboundMessage = SensorEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.data_ready.start(ctx);
+
+        let sensor: &'static Self = self;
+        spawn("hts221", async move {
+            if let Err(error) = sensor.initialize().await {
+                ctx.send(SensorEvent::Error(error));
+                return;
+            }
+            loop {
+                sensor.ready.wait().await;
+                sensor.sample(ctx).await;
+            }
+        });
+        spawn("hts221-requests", async move {
+            loop {
+                match ctx.receive().await {
+                    SensorRequest::Sample => sensor.sample(ctx).await,
+                }
+            }
+        });
+    }
+}
+
+impl<I2C, E, P> Handler<DataReady> for Hts221<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    fn on_message(&mut self, _message: DataReady) {
+        self.ready.raise();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hts221, ADDRESS};
+    use crate::driver::i2c::{I2cBus, I2cError};
+    use crate::mock::{MockI2c, MockI2cState, MockPin, MockPinState};
+    use crate::sensor::{Celsius, Reading, RelativeHumidity, SensorError};
+    use crate::testing::{block_on, leak};
+
+    fn setup(state: &'static MockI2cState) -> Hts221<MockI2c, I2cError, MockPin> {
+        let bus: &'static I2cBus<MockI2c, I2cError> = leak(I2cBus::new(state.i2c(), |e| *e));
+        let drdy = leak(MockPinState::new(false));
+        Hts221::new(bus.device(ADDRESS), drdy.pin(), 23)
+    }
+
+    #[test]
+    fn readings() {
+        let state = leak(MockI2cState::new());
+        state.add_device(ADDRESS);
+        state.set_increment_flag(ADDRESS, 0x80);
+        let sensor = setup(state);
+
+        assert_eq!(block_on(sensor.initialize()), Err(SensorError::NotFound));
+        assert_eq!(block_on(sensor.read()), Err(SensorError::NotReady));
+
+        state.set_register(ADDRESS, 0x0F, 0xBC);
+        // 30%rH at 0, 70%rH at 8000
+        state.set_register(ADDRESS, 0x30, 60);
+        state.set_register(ADDRESS, 0x31, 140);
+        state.set_register(ADDRESS, 0x3A, 0x40);
+        state.set_register(ADDRESS, 0x3B, 0x1F);
+        // 20°C at 0, 25°C at 1000, with the 9th bit of T1 set, adding 32°C
+        state.set_register(ADDRESS, 0x32, 160);
+        state.set_register(ADDRESS, 0x33, 200);
+        state.set_register(ADDRESS, 0x35, 0x04);
+        state.set_register(ADDRESS, 0x3E, 0xE8);
+        state.set_register(ADDRESS, 0x3F, 0x03);
+
+        block_on(sensor.initialize()).unwrap();
+        assert_eq!(state.register(ADDRESS, 0x20), 0x85);
+        assert_eq!(state.register(ADDRESS, 0x22), 0x04);
+
+        assert_eq!(block_on(sensor.read()), Err(SensorError::NotReady));
+
+        state.set_register(ADDRESS, 0x27, 0x03);
+        // 4000, then 500
+        state.set_register(ADDRESS, 0x28, 0xA0);
+        state.set_register(ADDRESS, 0x29, 0x0F);
+        state.set_register(ADDRESS, 0x2A, 0xF4);
+        state.set_register(ADDRESS, 0x2B, 0x01);
+
+        let readings = block_on(sensor.read()).unwrap();
+        assert_eq!(
+            &readings[..],
+            &[
+                Reading::Temperature(Celsius(38.5)),
+                Reading::Humidity(RelativeHumidity(50.0)),
+            ]
+        );
+
+        // a blank calibration cannot be interpolated
+        state.set_register(ADDRESS, 0x3E, 0x00);
+        state.set_register(ADDRESS, 0x3F, 0x00);
+        block_on(sensor.initialize()).unwrap();
+        assert_eq!(block_on(sensor.read()), Err(SensorError::Calibration));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::component::{Component, ComponentContext};
+use crate::driver::lock::BusLock;
+use crate::power::SleepState;
+use core::cell::{Cell, RefCell};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// Errors reported by an `I2cBus`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum I2cError {
+    /// The device did not acknowledge its address or data, such as
+    /// when absent or busy.
+    Nack,
+    /// Another controller won arbitration of the bus.
+    ArbitrationLost,
+    /// A device stretched the clock for longer than the HAL tolerates.
+    Timeout,
+    /// A misplaced start or stop condition was detected.
+    Bus,
+    /// Any other error reported by the HAL.
+    Other,
+}
+
+impl I2cError {
+    /// Determine if the bus may have been left in an unusable state, in
+    /// which case an `I2cBus` attempts recovery immediately.
+    pub fn is_bus_fault(&self) -> bool {
+        matches!(
+            self,
+            I2cError::ArbitrationLost | I2cError::Timeout | I2cError::Bus
+        )
+    }
+}
+
+/// An embedded-hal I2C peripheral supporting every request of an
+/// `I2cBus`, with a common error type.
+pub trait I2cPeripheral<E>: Read<Error = E> + Write<Error = E> + WriteRead<Error = E> {}
+
+impl<T, E> I2cPeripheral<E> for T where T: Read<Error = E> + Write<Error = E> + WriteRead<Error = E> {}
+
+/// A shared I2C bus, owning an embedded-hal I2C peripheral and serialising
+/// the requests of several `I2cDevice`s, each identified by its address.
+///
+/// Requests are performed one at a time, in the order in which the bus
+/// becomes available, and HAL errors are mapped to `I2cError`s using the
+/// provided `classify` function. Should an error leave the bus faulted,
+/// the optional `recover` function is invoked with the peripheral, for
+/// instance to reset it or clock out a stuck device.
+///
+/// The bus is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`. It should additionally be started as a
+/// component, as `ConnectedComponent<&'static I2cBus<I2C, E>>`, through
+/// which it keeps the device from entering `SleepState::Stop` while a
+/// request is in progress.
+pub struct I2cBus<I2C: 'static, E: 'static> {
+    i2c: RefCell<I2C>,
+    lock: BusLock,
+    classify: fn(&E) -> I2cError,
+    recover: Option<fn(&mut I2C)>,
+    ctx: Cell<Option<&'static ComponentContext<&'static I2cBus<I2C, E>>>>,
+}
+
+impl<I2C, E> I2cBus<I2C, E>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+{
+    pub fn new(i2c: I2C, classify: fn(&E) -> I2cError) -> Self {
+        Self {
+            i2c: RefCell::new(i2c),
+            lock: BusLock::new(),
+            classify,
+            recover: None,
+            ctx: Cell::new(None),
+        }
+    }
+
+    /// Create a new bus which invokes `recover` upon each bus fault.
+    pub fn with_recovery(i2c: I2C, classify: fn(&E) -> I2cError, recover: fn(&mut I2C)) -> Self {
+        Self {
+            recover: Some(recover),
+            ..Self::new(i2c, classify)
+        }
+    }
+
+    /// Create a device upon this bus at the given 7-bit address.
+    pub fn device(&'static self, address: u8) -> I2cDevice<I2C, E> {
+        I2cDevice { bus: self, address }
+    }
+
+    /// Determine if a request is currently in progress.
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    /// Write, *asynchronously*, `bytes` to the device at `address`.
+    pub async fn write(&self, address: u8, bytes: &[u8]) -> Result<(), I2cError> {
+        self.request(|i2c| i2c.write(address, bytes)).await
+    }
+
+    /// Read, *asynchronously*, enough bytes to fill `buffer` from the device at `address`.
+    pub async fn read(&self, address: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.request(|i2c| i2c.read(address, buffer)).await
+    }
+
+    /// Write, *asynchronously*, `bytes` to the device at `address`, followed
+    /// by a repeated start reading enough bytes to fill `buffer`.
+    pub async fn write_read(
+        &self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), I2cError> {
+        self.request(|i2c| i2c.write_read(address, bytes, buffer))
+            .await
+    }
+
+    async fn request(&self, op: impl FnOnce(&mut I2C) -> Result<(), E>) -> Result<(), I2cError> {
+        let _guard = self.lock.lock().await;
+        if let Some(ctx) = self.ctx.get() {
+            ctx.limit_sleep(SleepState::Sleep);
+        }
+
+        let result = self.perform(op);
+
+        if let Some(ctx) = self.ctx.get() {
+            ctx.limit_sleep(SleepState::Stop);
+        }
+        result
+    }
+
+    fn perform(&self, op: impl FnOnce(&mut I2C) -> Result<(), E>) -> Result<(), I2cError> {
+        let mut i2c = self.i2c.borrow_mut();
+        op(&mut *i2c).map_err(|e| {
+            let error = (self.classify)(&e);
+            if error.is_bus_fault() {
+                if let Some(recover) = self.recover {
+                    recover(&mut *i2c);
+                }
+            }
+            error
+        })
+    }
+}
+
+impl<I2C: 'static, E: 'static> Component for &'static I2cBus<I2C, E> {
+    type InboundMessage = ();
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.set(Some(ctx));
+    }
+}
+
+/// A device upon an `I2cBus`, to be held by the component driving it.
+pub struct I2cDevice<I2C: 'static, E: 'static> {
+    bus: &'static I2cBus<I2C, E>,
+    address: u8,
+}
+
+impl<I2C, E> I2cDevice<I2C, E>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+{
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Write, *asynchronously*, `bytes` to this device.
+    pub async fn write(&self, bytes: &[u8]) -> Result<(), I2cError> {
+        self.bus.write(self.address, bytes).await
+    }
+
+    /// Read, *asynchronously*, enough bytes to fill `buffer` from this device.
+    pub async fn read(&self, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.bus.read(self.address, buffer).await
+    }
+
+    /// Write, *asynchronously*, `bytes` to this device, followed by a
+    /// repeated start reading enough bytes to fill `buffer`.
+    pub async fn write_read(&self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.bus.write_read(self.address, bytes, buffer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{I2cBus, I2cError};
+    use crate::mock::{MockI2c, MockI2cState};
+    use crate::testing::{block_on, leak, poll_once};
+    use core::task::Poll;
+    use std::boxed::Box;
+
+    #[test]
+    fn requests() {
+        let state = leak(MockI2cState::new());
+        state.add_device(0x5F);
+        state.set_register(0x5F, 0x0F, 0xBC);
+        state.set_register(0x5F, 0x10, 0x1B);
+
+        let bus: &'static I2cBus<MockI2c, I2cError> =
+            leak(I2cBus::with_recovery(state.i2c(), |e| *e, MockI2c::recover));
+        let sensor = bus.device(0x5F);
+        let absent = bus.device(0x6A);
+
+        let mut buf = [0; 2];
+        block_on(sensor.write_read(&[0x0F], &mut buf)).unwrap();
+        assert_eq!(buf, [0xBC, 0x1B]);
+
+        block_on(sensor.write(&[0x20, 0x81, 0x01])).unwrap();
+        assert_eq!(state.register(0x5F, 0x20), 0x81);
+        assert_eq!(state.register(0x5F, 0x21), 0x01);
+        block_on(sensor.read(&mut buf)).unwrap();
+        assert_eq!(buf, [0x00, 0x00]);
+
+        assert_eq!(block_on(absent.write(&[0x0F])), Err(I2cError::Nack));
+        assert_eq!(state.recoveries(), 0);
+
+        state.fail_next(I2cError::Timeout);
+        assert_eq!(block_on(sensor.read(&mut buf)), Err(I2cError::Timeout));
+        assert_eq!(state.recoveries(), 1);
+        assert!(!bus.is_locked());
+    }
+
+    #[test]
+    fn serialised() {
+        let state = leak(MockI2cState::new());
+        state.add_device(0x76);
+        let bus: &'static I2cBus<MockI2c, I2cError> = leak(I2cBus::new(state.i2c(), |e| *e));
+
+        // another request is in progress
+        let guard = block_on(bus.lock.lock());
+        let mut pending = Box::pin(bus.write(0x76, &[0xF4, 0x27]));
+        assert_eq!(poll_once(pending.as_mut()), Poll::Pending);
+        assert_eq!(state.register(0x76, 0xF4), 0x00);
+
+        drop(guard);
+        assert_eq!(poll_once(pending.as_mut()), Poll::Ready(Ok(())));
+        assert_eq!(state.register(0x76, 0xF4), 0x27);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::time::Duration;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Messages accepted by a `Led`.
+pub enum LedMessage {
+    On,
+    Off,
+    Toggle,
+    /// Blink `count` times, each blink lasting `period`, before
+    /// returning to the current state.
+    Blink {
+        count: u32,
+        period: Duration,
+    },
+}
+
+/// A component driving an LED through any embedded-hal `OutputPin`.
+///
+/// The LED is considered lit while the pin is driven high.
+pub struct Led<P: OutputPin> {
+    pin: P,
+    on: bool,
+}
+
+impl<P: OutputPin> Led<P> {
+    /// Create a new LED, driving it off.
+    pub fn new(mut pin: P) -> Self {
+        pin.set_low().ok();
+        Self { pin, on: false }
+    }
+
+    pub fn on(&mut self) {
+        self.pin.set_high().ok();
+        self.on = true;
+    }
+
+    pub fn off(&mut self) {
+        self.pin.set_low().ok();
+        self.on = false;
+    }
+
+    pub fn toggle(&mut self) {
+        if self.on {
+            self.off()
+        } else {
+            self.on()
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    async fn run(&mut self, ctx: &'static ComponentContext<Self>) {
+        loop {
+            match ctx.receive().await {
+                LedMessage::On => self.on(),
+                LedMessage::Off => self.off(),
+                LedMessage::Toggle => self.toggle(),
+                LedMessage::Blink { count, period } => {
+                    for _ in 0..count * 2 {
+                        self.toggle();
+                        ctx.delay(period / 2).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<P: OutputPin> Component for Led<P> {
+    type InboundMessage = LedMessage;
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        spawn("led", self.run(ctx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{Led, LedMessage};
+    use crate::backend::Host;
+    use crate::component::ConnectedComponent;
+    use crate::kernel::{ConnectedKernel, Kernel, KernelContext};
+    use crate::mock::MockPinState;
+    use crate::testing::{leak, poll_once};
+    use crate::time::Duration;
+    use core::cell::Cell;
+    use std::boxed::Box;
+
+    #[test]
+    fn transitions() {
+        let pin = leak(MockPinState::new(true));
+        let mut led = Led::new(pin.pin());
+
+        assert!(!led.is_on());
+        led.on();
+        led.on();
+        led.toggle();
+        led.toggle();
+        led.off();
+
+        assert!(!led.is_on());
+        assert_eq!(&pin.transitions()[..], &[false, true, false, true, false]);
+    }
+
+    struct Device {
+        ctx: &'static Cell<Option<&'static KernelContext<Device>>>,
+    }
+
+    impl Kernel for Device {
+        fn start(&'static self, ctx: &'static KernelContext<Self>) {
+            self.ctx.set(Some(ctx));
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let host = leak(Host::new());
+        let slot = leak(Cell::new(None));
+        let kernel = leak(ConnectedKernel::with_backend(Device { ctx: slot }, host));
+        host.attach(kernel);
+        kernel.start();
+        let ctx = slot.get().unwrap();
+
+        let pin = leak(MockPinState::new(true));
+        let led = leak(ConnectedComponent::new(Led::new(pin.pin())));
+        let (component, context) = led.connect(ctx);
+        let mut task = Box::pin(unsafe { &mut *component }.run(context));
+
+        led.send(LedMessage::Toggle);
+        assert!(poll_once(task.as_mut()).is_pending());
+        assert_eq!(&pin.transitions()[..], &[false, true]);
+
+        led.send(LedMessage::Blink {
+            count: 2,
+            period: Duration::from_millis(100),
+        });
+        led.send(LedMessage::Off);
+        assert!(poll_once(task.as_mut()).is_pending());
+        assert_eq!(&pin.transitions()[..], &[false, true, false]);
+
+        // each half-period only elapses as the host's time advances
+        for _ in 0..3 {
+            host.advance(Duration::from_millis(49));
+            assert!(poll_once(task.as_mut()).is_pending());
+            host.advance(Duration::from_millis(1));
+            assert!(poll_once(task.as_mut()).is_pending());
+        }
+        assert_eq!(
+            &pin.transitions()[..],
+            &[false, true, false, true, false, true]
+        );
+
+        host.advance(Duration::from_millis(50));
+        assert!(poll_once(task.as_mut()).is_pending());
+        assert_eq!(
+            &pin.transitions()[..],
+            &[false, true, false, true, false, true, false]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::fifo::Signaller;
+use crate::kernel::notify_activity;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context as FutureContext, Poll, Waker};
+use heapless::{consts::*, Vec};
+
+/// Cooperative lock arbitrating a bus between the tasks of several components.
+pub(crate) struct BusLock {
+    locked: Cell<bool>,
+    waiters: RefCell<Vec<Waker, U8>>,
+}
+
+impl BusLock {
+    pub(crate) fn new() -> Self {
+        Self {
+            locked: Cell::new(false),
+            waiters: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn is_locked(&self) -> bool {
+        self.locked.get()
+    }
+
+    /// Acquire, *asynchronously*, the lock, which is held until the
+    /// returned guard is dropped, including should the task holding
+    /// it be cancelled.
+    pub(crate) fn lock(&self) -> Lock<'_> {
+        Lock { lock: self }
+    }
+
+    fn unlock(&self) {
+        self.locked.set(false);
+        let mut waiters = self.waiters.borrow_mut();
+        if !waiters.is_empty() {
+            notify_activity();
+        }
+        while let Some(waker) = waiters.pop() {
+            waker.wake();
+        }
+    }
+
+    fn try_lock(&self, waker: &Waker) -> bool {
+        if !self.locked.get() {
+            self.locked.set(true);
+            return true;
+        }
+        let mut waiters = self.waiters.borrow_mut();
+        if !waiters.iter().any(|w| w.will_wake(waker)) && waiters.push(waker.clone()).is_err() {
+            // too many waiters to track, so poll again instead
+            notify_activity();
+            waker.wake_by_ref();
+        }
+        false
+    }
+}
+
+pub(crate) struct Lock<'l> {
+    lock: &'l BusLock,
+}
+
+impl<'l> Future for Lock<'l> {
+    type Output = BusGuard<'l>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        if self.lock.try_lock(cx.waker()) {
+            Poll::Ready(BusGuard { lock: self.lock })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Holds a `BusLock`, releasing it when dropped.
+pub(crate) struct BusGuard<'l> {
+    lock: &'l BusLock,
+}
+
+impl<'l> Drop for BusGuard<'l> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// A flag raised synchronously, such as from a `Handler` invoked by an
+/// interrupt, and awaited by a component's task.
+pub(crate) struct Flag {
+    raised: AtomicBool,
+    signaller: Signaller,
+}
+
+impl Flag {
+    pub(crate) fn new() -> Self {
+        Self {
+            raised: AtomicBool::new(false),
+            signaller: Signaller::new(),
+        }
+    }
+
+    pub(crate) fn raise(&self) {
+        self.raised.store(true, Ordering::Release);
+        self.signaller.wake();
+    }
+
+    pub(crate) fn is_raised(&self) -> bool {
+        self.raised.load(Ordering::Acquire)
+    }
+
+    /// Lower the flag, should it have been raised without being waited for.
+    pub(crate) fn lower(&self) {
+        self.raised.store(false, Ordering::Release);
+    }
+
+    /// Wait, *asynchronously*, until the flag is raised, lowering it again.
+    pub(crate) fn wait(&self) -> Wait<'_> {
+        Wait { flag: self }
+    }
+}
+
+pub(crate) struct Wait<'f> {
+    flag: &'f Flag,
+}
+
+impl<'f> Future for Wait<'f> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        // registered before testing, lest a raise in between go unnoticed
+        self.flag.signaller.set_waker(cx.waker().clone());
+        if self.flag.raised.swap(false, Ordering::AcqRel) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::time::Duration;
+use core::fmt;
+use core::future::Future;
+
+/// The number of symbols of the preamble of each packet.
+pub const PREAMBLE_SYMBOLS: u32 = 8;
+
+/// The spreading factor of a LoRa modulation, trading data rate for range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpreadingFactor {
+    SF7 = 7,
+    SF8 = 8,
+    SF9 = 9,
+    SF10 = 10,
+    SF11 = 11,
+    SF12 = 12,
+}
+
+/// The bandwidth of a LoRa modulation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bandwidth {
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    pub fn hz(&self) -> u32 {
+        match self {
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+}
+
+/// The coding rate of a LoRa modulation, the proportion of each
+/// codeword carrying data rather than error correction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CodingRate {
+    Cr4_5 = 1,
+    Cr4_6 = 2,
+    Cr4_7 = 3,
+    Cr4_8 = 4,
+}
+
+/// The modulation and channel with which a packet is transmitted or received.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LoRaConfig {
+    /// The carrier frequency, in Hz.
+    pub frequency: u32,
+    pub spreading_factor: SpreadingFactor,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// The transmit power, in dBm.
+    pub power: i8,
+    /// Whether the I and Q signals are inverted, as they are for
+    /// LoRaWAN downlinks, so that devices do not hear one another.
+    pub invert_iq: bool,
+    /// Whether the payload is followed by a CRC.
+    pub crc: bool,
+}
+
+impl LoRaConfig {
+    /// The duration of one symbol.
+    pub fn symbol_time(&self) -> Duration {
+        Duration::from_micros(
+            ((1u64 << self.spreading_factor as u32) * 1_000_000) / self.bandwidth.hz() as u64,
+        )
+    }
+
+    /// Determine if the low data rate optimization is required, as it is
+    /// whenever a symbol lasts longer than 16ms.
+    pub fn low_data_rate(&self) -> bool {
+        self.symbol_time() > Duration::from_millis(16)
+    }
+
+    /// The time on air of a packet with a payload of `len` bytes, sent
+    /// with an explicit header.
+    pub fn airtime(&self, len: usize) -> Duration {
+        let sf = self.spreading_factor as i64;
+        let de = self.low_data_rate() as i64;
+        let crc = self.crc as i64;
+        let numerator = 8 * len as i64 - 4 * sf + 28 + 16 * crc;
+        let denominator = 4 * (sf - 2 * de);
+        let blocks = ((numerator + denominator - 1) / denominator).max(0);
+        let payload_symbols = 8 + blocks as u64 * (self.coding_rate as u64 + 4);
+
+        let symbol = self.symbol_time().as_micros() as u64;
+        // the preamble is followed by 4.25 symbols of sync word
+        let preamble = (4 * PREAMBLE_SYMBOLS as u64 + 17) * symbol / 4;
+        Duration::from_micros(preamble + payload_symbols * symbol)
+    }
+}
+
+/// A packet received by a `Radio`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Received {
+    /// The length of the payload.
+    pub len: usize,
+    /// The signal strength of the packet, in dBm.
+    pub rssi: i16,
+    /// The signal to noise ratio of the packet, in dB.
+    pub snr: i8,
+}
+
+/// A LoRa radio, transmitting and receiving one packet at a time.
+///
+/// Radio driver components, such as `Sx127x`, implement this trait, and
+/// are shared by reference with the components using them, as a `TcpStack`
+/// is, the radio sleeping between each transmission or reception.
+pub trait Radio {
+    type Error: fmt::Debug;
+
+    /// Transmit, *asynchronously*, `data` as one packet, completing once sent.
+    fn transmit(
+        &self,
+        config: &LoRaConfig,
+        data: &[u8],
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Listen, *asynchronously*, for a packet whose preamble is detected
+    /// within `window`, receiving its payload into `buf`, or returning
+    /// `None` should none be. Should `buf` be too short, the remainder
+    /// is discarded.
+    fn receive(
+        &self,
+        config: &LoRaConfig,
+        buf: &mut [u8],
+        window: Duration,
+    ) -> impl Future<Output = Result<Option<Received>, Self::Error>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bandwidth, CodingRate, LoRaConfig, SpreadingFactor};
+    use crate::time::Duration;
+
+    #[test]
+    fn airtime() {
+        let mut config = LoRaConfig {
+            frequency: 868_100_000,
+            spreading_factor: SpreadingFactor::SF7,
+            bandwidth: Bandwidth::Khz125,
+            coding_rate: CodingRate::Cr4_5,
+            power: 14,
+            invert_iq: false,
+            crc: true,
+        };
+        assert_eq!(config.symbol_time(), Duration::from_micros(1024));
+        assert!(!config.low_data_rate());
+        assert_eq!(config.airtime(13), Duration::from_micros(46_336));
+
+        config.spreading_factor = SpreadingFactor::SF12;
+        assert!(config.low_data_rate());
+        assert_eq!(config.airtime(23), Duration::from_micros(1_482_752));
+
+        config.bandwidth = Bandwidth::Khz500;
+        assert!(!config.low_data_rate());
+    }
+}
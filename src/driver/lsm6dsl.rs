@@ -0,0 +1,231 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::driver::exti::{DataReady, DataReadyInterrupt, ExtiPin};
+use crate::driver::i2c::{I2cDevice, I2cPeripheral};
+use crate::driver::lock::Flag;
+use crate::handler::Handler;
+use crate::interrupt::ConnectedInterrupt;
+use crate::sensor::{Acceleration, Celsius, Reading, SensorError, SensorEvent, SensorRequest};
+use heapless::{consts::*, Vec};
+
+/// The I2C address of the LSM6DSL, with SA0 pulled low.
+pub const ADDRESS: u8 = 0x6A;
+/// The I2C address of the LSM6DSL, with SA0 pulled high.
+pub const ALTERNATE_ADDRESS: u8 = 0x6B;
+
+const INT1_CTRL: u8 = 0x0D;
+const WHO_AM_I: u8 = 0x0F;
+const CTRL1_XL: u8 = 0x10;
+const CTRL3_C: u8 = 0x12;
+const STATUS_REG: u8 = 0x1E;
+const OUT_TEMP_L: u8 = 0x20;
+
+const IDENTITY: u8 = 0x6A;
+
+const INT1_DRDY_XL: u8 = 0x01;
+const CTRL1_XL_ODR_104HZ: u8 = 0x40;
+const CTRL3_C_BDU: u8 = 0x40;
+const CTRL3_C_IF_INC: u8 = 0x04;
+
+const STATUS_XLDA: u8 = 0x01;
+
+/// Sensitivity at the ±2g full scale, in g per LSB.
+const SENSITIVITY_2G: f32 = 0.000_061;
+/// Temperature sensitivity, in LSB per °C, from 25°C.
+const TEMPERATURE_SENSITIVITY: f32 = 256.0;
+
+/// A driver for the accelerometer and temperature sensor of the
+/// ST LSM6DSL IMU, attached to an `I2cBus`, with its INT1 line routed
+/// to an EXTI interrupt.
+///
+/// Once started, the accelerometer measures continuously at 104Hz with a
+/// full scale of ±2g, and both an acceleration and a temperature `Reading`
+/// are sent upstream whenever new data is ready, and whenever a
+/// `SensorRequest::Sample` is received. The gyroscope remains powered down.
+pub struct Lsm6dsl<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    device: I2cDevice<I2C, E>,
+    data_ready: ConnectedInterrupt<DataReadyInterrupt<P>>,
+    ready: Flag,
+}
+
+impl<I2C, E, P> Lsm6dsl<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    /// Create a new driver for the sensor at `device`, its INT1 line
+    /// attached to `int1` triggering `irq`.
+    pub fn new(device: I2cDevice<I2C, E>, int1: P, irq: u8) -> Self {
+        Self {
+            device,
+            data_ready: ConnectedInterrupt::new(DataReadyInterrupt::new(int1, irq)),
+            ready: Flag::new(),
+        }
+    }
+
+    /// Identify the sensor, and begin measuring.
+    async fn initialize(&self) -> Result<(), SensorError> {
+        let mut id = [0];
+        self.device
+            .write_read(&[WHO_AM_I], &mut id)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        if id[0] != IDENTITY {
+            return Err(SensorError::NotFound);
+        }
+
+        for (register, value) in [
+            (CTRL3_C, CTRL3_C_BDU | CTRL3_C_IF_INC),
+            (INT1_CTRL, INT1_DRDY_XL),
+            (CTRL1_XL, CTRL1_XL_ODR_104HZ),
+        ]
+        .iter()
+        {
+            self.device
+                .write(&[*register, *value])
+                .await
+                .map_err(|_| SensorError::Bus)?;
+        }
+        Ok(())
+    }
+
+    /// Read the most recent acceleration and temperature.
+    async fn read(&self) -> Result<Vec<Reading, U2>, SensorError> {
+        let mut status = [0];
+        self.device
+            .write_read(&[STATUS_REG], &mut status)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        if status[0] & STATUS_XLDA == 0 {
+            return Err(SensorError::NotReady);
+        }
+
+        // temperature, gyroscope and accelerometer outputs are consecutive
+        let mut out = [0; 14];
+        self.device
+            .write_read(&[OUT_TEMP_L], &mut out)
+            .await
+            .map_err(|_| SensorError::Bus)?;
+        let i16_at = |i: usize| i16::from_le_bytes([out[i], out[i + 1]]) as f32;
+
+        let acceleration = Acceleration::from_g(
+            i16_at(8) * SENSITIVITY_2G,
+            i16_at(10) * SENSITIVITY_2G,
+            i16_at(12) * SENSITIVITY_2G,
+        );
+        let temperature = Celsius(25.0 + i16_at(0) / TEMPERATURE_SENSITIVITY);
+
+        let mut readings = Vec::new();
+        readings.push(Reading::Acceleration(acceleration)).ok();
+        readings.push(Reading::Temperature(temperature)).ok();
+        Ok(readings)
+    }
+
+    async fn sample(&self, ctx: &ComponentContext<Self>) {
+        match self.read().await {
+            Ok(readings) => {
+                for reading in readings.iter() {
+                    ctx.send(SensorEvent::Reading(*reading));
+                }
+            }
+            Err(error) => ctx.send(SensorEvent::Error(error)),
+        }
+    }
+}
+
+impl<I2C, E, P> Component for Lsm6dsl<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    type InboundMessage = SensorRequest;
+    type OutboundMessage = SensorEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.data_ready.start(ctx);
+
+        let sensor: &'static Self = self;
+        spawn("lsm6dsl", async move {
+            if let Err(error) = sensor.initialize().await {
+                ctx.send(SensorEvent::Error(error));
+                return;
+            }
+            loop {
+                sensor.ready.wait().await;
+                sensor.sample(ctx).await;
+            }
+        });
+        spawn("lsm6dsl-requests", async move {
+            loop {
+                match ctx.receive().await {
+                    SensorRequest::Sample => sensor.sample(ctx).await,
+                }
+            }
+        });
+    }
+}
+
+impl<I2C, E, P> Handler<DataReady> for Lsm6dsl<I2C, E, P>
+where
+    I2C: I2cPeripheral<E> + 'static,
+    E: 'static,
+    P: ExtiPin + 'static,
+{
+    fn on_message(&mut self, _message: DataReady) {
+        self.ready.raise();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lsm6dsl, ADDRESS};
+    use crate::driver::i2c::{I2cBus, I2cError};
+    use crate::mock::{MockI2cState, MockPinState};
+    use crate::sensor::{Acceleration, Celsius, Reading, SensorError, STANDARD_GRAVITY};
+    use crate::testing::{block_on, leak};
+
+    #[test]
+    fn readings() {
+        let state = leak(MockI2cState::new());
+        state.add_device(ADDRESS);
+        state.set_register(ADDRESS, 0x0F, 0x6A);
+
+        let bus = leak(I2cBus::new(state.i2c(), |e: &I2cError| *e));
+        let int1 = leak(MockPinState::new(false));
+        let sensor = Lsm6dsl::new(bus.device(ADDRESS), int1.pin(), 23);
+
+        block_on(sensor.initialize()).unwrap();
+        assert_eq!(state.register(ADDRESS, 0x12), 0x44);
+        assert_eq!(state.register(ADDRESS, 0x0D), 0x01);
+        assert_eq!(state.register(ADDRESS, 0x10), 0x40);
+
+        assert_eq!(block_on(sensor.read()), Err(SensorError::NotReady));
+
+        state.set_register(ADDRESS, 0x1E, 0x05);
+        // 27°C
+        state.set_register(ADDRESS, 0x20, 0x00);
+        state.set_register(ADDRESS, 0x21, 0x02);
+        // x: -0.5g, z: 1g
+        state.set_register(ADDRESS, 0x28, 0xFB);
+        state.set_register(ADDRESS, 0x29, 0xDF);
+        state.set_register(ADDRESS, 0x2C, 0x09);
+        state.set_register(ADDRESS, 0x2D, 0x40);
+
+        let readings = block_on(sensor.read()).unwrap();
+        match readings[0] {
+            Reading::Acceleration(Acceleration { x, y, z }) => {
+                assert!((x + STANDARD_GRAVITY / 2.0).abs() < 0.01);
+                assert_eq!(y, 0.0);
+                assert!((z - STANDARD_GRAVITY).abs() < 0.01);
+            }
+            _ => panic!("expected an acceleration"),
+        }
+        assert_eq!(readings[1], Reading::Temperature(Celsius(27.0)));
+    }
+}
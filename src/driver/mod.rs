@@ -0,0 +1,50 @@
+/// Support for pins triggering EXTI interrupts.
+pub mod exti;
+
+/// Support for LEDs driven by a GPIO output pin.
+pub mod led;
+
+/// Support for debounced buttons attached to a GPIO input pin.
+pub mod button;
+
+/// Support for interrupt-driven serial ports.
+pub mod serial;
+
+/// Support for devices sharing an SPI bus.
+pub mod spi;
+
+/// Support for devices sharing an I2C bus.
+pub mod i2c;
+
+/// Support for the HTS221 temperature and humidity sensor.
+pub mod hts221;
+
+/// Support for the LSM6DSL inertial measurement unit.
+pub mod lsm6dsl;
+
+/// Support for the BME280 environmental sensor.
+pub mod bme280;
+
+/// Support for periodically sampling ADC channels.
+pub mod adc;
+
+/// Support for PWM outputs, such as for dimming LEDs or positioning servos.
+pub mod pwm;
+
+/// Support for decoding rotary encoders.
+pub mod encoder;
+
+/// Support for modems driven by AT commands.
+pub mod at;
+
+/// Support for WiFi through ESP8266 and ESP32 modules running the ESP-AT firmware.
+pub mod esp_at;
+
+/// Support for LoRa radios.
+pub mod lora;
+
+/// Support for the Semtech SX127x family of LoRa radios.
+pub mod sx127x;
+
+/// Primitives synchronizing the tasks of components, and their interrupts.
+pub(crate) mod lock;
@@ -0,0 +1,259 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::driver::lock::Flag;
+use crate::time::Duration;
+use core::cell::{Cell, RefCell};
+use core::convert::TryFrom;
+use embedded_hal::Pwm as HalPwm;
+
+/// The interval between each step of a fade.
+const FADE_STEP: Duration = Duration::from_millis(10);
+
+/// A duty cycle, as a fraction of the PWM period.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duty(u16);
+
+impl Duty {
+    pub const OFF: Duty = Duty(0);
+    pub const FULL: Duty = Duty(u16::MAX);
+
+    pub fn percent(percent: u8) -> Self {
+        Self::from_fraction(percent.min(100) as u32, 100)
+    }
+
+    /// The duty cycle `numerator / denominator`, saturating at `Duty::FULL`.
+    pub fn from_fraction(numerator: u32, denominator: u32) -> Self {
+        if denominator == 0 || numerator >= denominator {
+            return Self::FULL;
+        }
+        Duty((numerator as u64 * u16::MAX as u64 / denominator as u64) as u16)
+    }
+
+    /// The duty cycle producing pulses of `pulse` with the given `period`,
+    /// such as between 1ms and 2ms every 20ms to position a servo.
+    pub fn from_pulse(pulse: Duration, period: Duration) -> Self {
+        Self::from_fraction(pulse.as_micros() as u32, period.as_micros() as u32)
+    }
+
+    /// Scale this duty cycle to the range `0..=max`.
+    fn scale(&self, max: u32) -> u32 {
+        (self.0 as u64 * max as u64 / u16::MAX as u64) as u32
+    }
+}
+
+/// Messages accepted by a `Pwm`.
+#[derive(Copy, Clone, Debug)]
+pub enum PwmMessage<T> {
+    /// Set the duty cycle immediately, cancelling any fade.
+    SetDuty(Duty),
+    /// Change linearly from the current duty cycle to `to` over
+    /// the duration `over`, cancelling any previous fade.
+    Fade {
+        to: Duty,
+        over: Duration,
+    },
+    /// Set the frequency of the PWM peripheral, shared by all of its
+    /// channels, expressed in the HAL's `Pwm::Time`.
+    SetFrequency(T),
+    Enable,
+    Disable,
+}
+
+/// The intermediate duty cycles of a linear fade.
+struct Fade {
+    from: Duty,
+    to: Duty,
+    steps: u32,
+    step: u32,
+}
+
+impl Fade {
+    fn new(from: Duty, to: Duty, over: Duration) -> Self {
+        let steps = (over.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+        Self {
+            from,
+            to,
+            steps,
+            step: 0,
+        }
+    }
+}
+
+impl Iterator for Fade {
+    type Item = Duty;
+
+    fn next(&mut self) -> Option<Duty> {
+        if self.step == self.steps {
+            return None;
+        }
+        self.step += 1;
+        let from = self.from.0 as i64;
+        let delta = (self.to.0 as i64 - from) * self.step as i64 / self.steps as i64;
+        Some(Duty((from + delta) as u16))
+    }
+}
+
+/// A component driving a channel of an embedded-hal `Pwm` peripheral,
+/// such as for dimming an LED or positioning a servo.
+///
+/// Fades are performed by a task spawned upon start, stepping the duty
+/// cycle every 10ms using the kernel's time source, while messages
+/// continue to be received.
+pub struct Pwm<P>
+where
+    P: HalPwm,
+{
+    pwm: RefCell<P>,
+    channel: P::Channel,
+    duty: Cell<Duty>,
+    fade: Cell<Option<(Duty, Duration)>>,
+    fading: Flag,
+}
+
+impl<P> Pwm<P>
+where
+    P: HalPwm,
+    P::Channel: Copy,
+    P::Duty: Into<u32> + TryFrom<u32>,
+{
+    /// Create a new component driving `channel` of `pwm`, initially
+    /// disabled with a duty cycle of zero.
+    pub fn new(mut pwm: P, channel: P::Channel) -> Self {
+        pwm.disable(channel);
+        let pwm = Self {
+            pwm: RefCell::new(pwm),
+            channel,
+            duty: Cell::new(Duty::OFF),
+            fade: Cell::new(None),
+            fading: Flag::new(),
+        };
+        pwm.apply(Duty::OFF);
+        pwm
+    }
+
+    /// The current duty cycle.
+    pub fn duty(&self) -> Duty {
+        self.duty.get()
+    }
+
+    fn apply(&self, duty: Duty) {
+        let mut pwm = self.pwm.borrow_mut();
+        let raw = duty.scale(pwm.get_max_duty().into());
+        if let Ok(raw) = P::Duty::try_from(raw) {
+            pwm.set_duty(self.channel, raw);
+        }
+        self.duty.set(duty);
+    }
+
+    fn on_message(&self, message: PwmMessage<P::Time>) {
+        match message {
+            PwmMessage::SetDuty(duty) => {
+                self.fade.set(None);
+                self.apply(duty);
+            }
+            PwmMessage::Fade { to, over } => {
+                self.fade.set(Some((to, over)));
+                self.fading.raise();
+            }
+            PwmMessage::SetFrequency(frequency) => {
+                self.pwm.borrow_mut().set_period(frequency);
+                // the maximum duty may have changed
+                self.apply(self.duty.get());
+            }
+            PwmMessage::Enable => self.pwm.borrow_mut().enable(self.channel),
+            PwmMessage::Disable => self.pwm.borrow_mut().disable(self.channel),
+        }
+    }
+}
+
+impl<P> Component for Pwm<P>
+where
+    P: HalPwm + 'static,
+    P::Channel: Copy,
+    P::Duty: Into<u32> + TryFrom<u32>,
+{
+    type InboundMessage = PwmMessage<P::Time>;
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        let pwm: &'static Self = self;
+        spawn("pwm", async move {
+            loop {
+                pwm.on_message(ctx.receive().await);
+            }
+        });
+        spawn("pwm-fade", async move {
+            loop {
+                pwm.fading.wait().await;
+                let (to, over) = match pwm.fade.get() {
+                    Some(fade) => fade,
+                    None => continue,
+                };
+                for duty in Fade::new(pwm.duty(), to, over) {
+                    ctx.delay(FADE_STEP).await;
+                    // cancelled or superseded
+                    if pwm.fade.get() != Some((to, over)) || pwm.fading.is_raised() {
+                        break;
+                    }
+                    pwm.apply(duty);
+                }
+                if pwm.fade.get() == Some((to, over)) && !pwm.fading.is_raised() {
+                    pwm.fade.set(None);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Duty, Fade, Pwm, PwmMessage};
+    use crate::mock::MockPwmState;
+    use crate::testing::leak;
+    use crate::time::Duration;
+
+    #[test]
+    fn duty() {
+        assert_eq!(Duty::percent(50), Duty::from_fraction(1, 2));
+        assert_eq!(Duty::percent(150), Duty::FULL);
+        assert_eq!(
+            Duty::from_pulse(Duration::from_micros(1500), Duration::from_millis(20)).scale(20000),
+            1499
+        );
+
+        let fade: heapless::Vec<Duty, heapless::consts::U8> =
+            Fade::new(Duty::FULL, Duty::OFF, Duration::from_millis(45)).collect();
+        assert_eq!(
+            &fade[..],
+            &[Duty(49152), Duty(32768), Duty(16384), Duty::OFF]
+        );
+    }
+
+    #[test]
+    fn messages() {
+        let state = leak(MockPwmState::new(1000));
+        let pwm = Pwm::new(state.pwm(), 2);
+        assert!(!state.is_enabled(2));
+        assert_eq!(state.duty(2), 0);
+
+        pwm.on_message(PwmMessage::Enable);
+        pwm.on_message(PwmMessage::SetDuty(Duty::percent(25)));
+        assert!(state.is_enabled(2));
+        assert_eq!(state.duty(2), 249);
+
+        // doubling the frequency halves the maximum duty
+        pwm.on_message(PwmMessage::SetFrequency(2000));
+        assert_eq!(state.frequency(), 2000);
+        assert_eq!(state.duty(2), 124);
+
+        pwm.on_message(PwmMessage::Fade {
+            to: Duty::FULL,
+            over: Duration::from_secs(1),
+        });
+        assert!(pwm.fading.is_raised());
+        pwm.on_message(PwmMessage::SetDuty(Duty::OFF));
+        assert_eq!(pwm.fade.get(), None);
+
+        pwm.on_message(PwmMessage::Disable);
+        assert!(!state.is_enabled(2));
+    }
+}
@@ -0,0 +1,441 @@
+use crate::component::{Component, ComponentContext};
+use crate::driver::lock::BusLock;
+use crate::fifo::Signaller;
+use crate::handler::Handler;
+use crate::interrupt::{ConnectedInterrupt, Interrupt, InterruptContext};
+use crate::kernel::notify_activity;
+use crate::power::SleepState;
+use core::cell::{RefCell, UnsafeCell};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context as FutureContext, Poll};
+use embedded_hal::serial;
+use heapless::spsc::{Consumer, Producer, Queue};
+use heapless::ArrayLength;
+
+/// Errors reported upstream by a `Serial`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    Framing,
+    Noise,
+    Overrun,
+    Parity,
+    /// The receive buffer of the `SerialPort` was full, and a byte was dropped.
+    BufferOverrun,
+    /// Any other error reported by the HAL.
+    Other,
+}
+
+/// The shared half of a serial driver, through which any component may
+/// asynchronously `read(...)` and `write(...)`.
+///
+/// Received bytes are placed by the `Serial`'s RX interrupt into a
+/// statically-sized ring buffer of `N` bytes held within the port, so
+/// the port should itself be placed in static memory, such as a `StaticCell`.
+/// Readers are served one at a time, in the order in which they began.
+pub struct SerialPort<TX, N>
+where
+    TX: serial::Write<u8>,
+    N: ArrayLength<u8>,
+{
+    tx: RefCell<TX>,
+    rx: UnsafeCell<Queue<u8, N>>,
+    consumer: RefCell<Option<Consumer<'static, u8, N>>>,
+    // held by the one reader awaiting the RX interrupt's single waker
+    reading: BusLock,
+    signaller: Signaller,
+}
+
+impl<TX, N> SerialPort<TX, N>
+where
+    TX: serial::Write<u8>,
+    N: ArrayLength<u8>,
+{
+    pub fn new(tx: TX) -> Self {
+        Self {
+            tx: RefCell::new(tx),
+            rx: UnsafeCell::new(Queue::new()),
+            consumer: RefCell::new(None),
+            reading: BusLock::new(),
+            signaller: Signaller::new(),
+        }
+    }
+
+    fn split(&'static self) -> Producer<'static, u8, N> {
+        let (producer, consumer) = unsafe { &mut *self.rx.get() }.split();
+        self.consumer.borrow_mut().replace(consumer);
+        producer
+    }
+
+    /// Read, *asynchronously*, at least one byte into `buf`, returning
+    /// the number of bytes read.
+    pub async fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let _guard = self.reading.lock().await;
+        RxReady { port: self }.await;
+        self.drain(buf)
+    }
+
+    /// Read, *asynchronously*, until `delimiter` has been read, returning the
+    /// number of bytes read including the delimiter.
+    ///
+    /// Should `buf` fill before the delimiter is read, `buf.len()` is returned
+    /// and the remaining bytes are left in the ring buffer.
+    pub async fn read_until(&self, delimiter: u8, buf: &mut [u8]) -> usize {
+        let _guard = self.reading.lock().await;
+        let mut len = 0;
+        while len < buf.len() {
+            RxReady { port: self }.await;
+            let mut consumer = self.consumer.borrow_mut();
+            let consumer = consumer.as_mut().unwrap();
+            while len < buf.len() {
+                match consumer.dequeue() {
+                    Some(b) => {
+                        buf[len] = b;
+                        len += 1;
+                        if b == delimiter {
+                            return len;
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+        len
+    }
+
+    /// Write, *asynchronously*, all of `data`, followed by a flush.
+    ///
+    /// The transmitter is polled cooperatively, yielding to the
+    /// executor whenever it is not ready to accept another byte.
+    /// Being busy-waited rather than driven by an interrupt, a write
+    /// keeps the device from sleeping, whatever the idle policy, until
+    /// it completes.
+    pub async fn write(&self, data: &[u8]) -> Result<(), TX::Error> {
+        for b in data {
+            TxPoll {
+                port: self,
+                op: |tx: &mut TX| tx.write(*b),
+            }
+            .await?;
+        }
+        TxPoll {
+            port: self,
+            op: |tx: &mut TX| tx.flush(),
+        }
+        .await
+    }
+
+    /// Create an RX interrupt filling this port, as `Serial::new(...)` does,
+    /// for exercising drivers upon a port without a kernel.
+    #[cfg(test)]
+    pub(crate) fn interrupt<RX: serial::Read<u8>>(&'static self, rx: RX) -> SerialInterrupt<RX, N> {
+        SerialInterrupt {
+            rx,
+            irq: 0,
+            producer: self.split(),
+            signaller: &self.signaller,
+            classify: |_| SerialError::Other,
+        }
+    }
+
+    fn drain(&self, buf: &mut [u8]) -> usize {
+        let mut consumer = self.consumer.borrow_mut();
+        let consumer = consumer.as_mut().unwrap();
+        let mut len = 0;
+        while len < buf.len() {
+            match consumer.dequeue() {
+                Some(b) => {
+                    buf[len] = b;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        len
+    }
+}
+
+struct RxReady<'p, TX, N>
+where
+    TX: serial::Write<u8>,
+    N: ArrayLength<u8>,
+{
+    port: &'p SerialPort<TX, N>,
+}
+
+impl<'p, TX, N> Future for RxReady<'p, TX, N>
+where
+    TX: serial::Write<u8>,
+    N: ArrayLength<u8>,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        // registered before checking, lest a byte arriving in between go unnoticed
+        self.port.signaller.set_waker(cx.waker().clone());
+        let ready = self.port.consumer.borrow().as_ref().unwrap().ready();
+        if ready {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct TxPoll<'p, TX, N, F>
+where
+    TX: serial::Write<u8>,
+    N: ArrayLength<u8>,
+    F: FnMut(&mut TX) -> nb::Result<(), TX::Error>,
+{
+    port: &'p SerialPort<TX, N>,
+    op: F,
+}
+
+impl<'p, TX, N, F> Future for TxPoll<'p, TX, N, F>
+where
+    TX: serial::Write<u8>,
+    N: ArrayLength<u8>,
+    F: FnMut(&mut TX) -> nb::Result<(), TX::Error> + Unpin,
+{
+    type Output = Result<(), TX::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        let port = self.port;
+        match (self.op)(&mut *port.tx.borrow_mut()) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+            Err(nb::Error::WouldBlock) => {
+                // polled again at once, so the idle policy must not sleep meanwhile
+                notify_activity();
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The RX interrupt of a `Serial`, filling the ring buffer of its `SerialPort`.
+pub struct SerialInterrupt<RX, N>
+where
+    RX: serial::Read<u8>,
+    N: ArrayLength<u8>,
+{
+    rx: RX,
+    irq: u8,
+    producer: Producer<'static, u8, N>,
+    signaller: &'static Signaller,
+    classify: fn(&RX::Error) -> SerialError,
+}
+
+impl<RX, N> SerialInterrupt<RX, N>
+where
+    RX: serial::Read<u8>,
+    N: ArrayLength<u8>,
+{
+    /// Read every available byte into the ring buffer.
+    pub(crate) fn service(&mut self, mut report: impl FnMut(SerialError)) {
+        let mut received = false;
+        loop {
+            match self.rx.read() {
+                Ok(b) => {
+                    if self.producer.enqueue(b).is_err() {
+                        report(SerialError::BufferOverrun);
+                    } else {
+                        received = true;
+                    }
+                }
+                Err(nb::Error::Other(e)) => report((self.classify)(&e)),
+                Err(nb::Error::WouldBlock) => break,
+            }
+        }
+        if received {
+            self.signaller.wake();
+        }
+    }
+}
+
+impl<RX, N> Interrupt for SerialInterrupt<RX, N>
+where
+    RX: serial::Read<u8>,
+    N: ArrayLength<u8>,
+{
+    type OutboundMessage = SerialError;
+
+    fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    fn on_interrupt(&mut self, context: &InterruptContext<Self>) {
+        self.service(|error| context.send(error));
+    }
+}
+
+/// A serial driver, receiving through its RX interrupt into a
+/// `SerialPort`, and reporting errors upstream as `SerialError`s.
+///
+/// While started, the driver limits sleep to `SleepState::Sleep`,
+/// so that the UART remains clocked and able to receive.
+pub struct Serial<RX, N>
+where
+    RX: serial::Read<u8> + 'static,
+    N: ArrayLength<u8> + 'static,
+{
+    interrupt: ConnectedInterrupt<SerialInterrupt<RX, N>>,
+    ctx: Option<&'static ComponentContext<Self>>,
+}
+
+impl<RX, N> Serial<RX, N>
+where
+    RX: serial::Read<u8>,
+    N: ArrayLength<u8>,
+{
+    /// Create a new serial driver, receiving from `rx` upon `irq` into `port`.
+    ///
+    /// HAL errors are mapped to `SerialError`s using `classify`.
+    pub fn new<TX: serial::Write<u8>>(
+        port: &'static SerialPort<TX, N>,
+        rx: RX,
+        irq: u8,
+        classify: fn(&RX::Error) -> SerialError,
+    ) -> Self {
+        Self {
+            interrupt: ConnectedInterrupt::new(SerialInterrupt {
+                rx,
+                irq,
+                producer: port.split(),
+                signaller: &port.signaller,
+                classify,
+            }),
+            ctx: None,
+        }
+    }
+}
+
+impl<RX, N> Component for Serial<RX, N>
+where
+    RX: serial::Read<u8>,
+    N: ArrayLength<u8>,
+{
+    type InboundMessage = ();
+    type OutboundMessage = SerialError;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.replace(ctx);
+        ctx.limit_sleep(SleepState::Sleep);
+        self.interrupt.start(ctx);
+    }
+}
+
+impl<RX, N> Handler<SerialError> for Serial<RX, N>
+where
+    RX: serial::Read<u8>,
+    N: ArrayLength<u8>,
+{
+    fn on_message(&mut self, message: SerialError) {
+        if let Some(ctx) = self.ctx {
+            ctx.send(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{SerialError, SerialInterrupt, SerialPort};
+    use crate::mock::{MockSerial, MockSerialState};
+    use crate::testing::{block_on, leak, poll_once};
+    use core::task::Poll;
+    use heapless::consts::*;
+
+    fn setup(
+        state: &'static MockSerialState,
+    ) -> (
+        &'static SerialPort<MockSerial, U8>,
+        SerialInterrupt<MockSerial, U8>,
+    ) {
+        let port = leak(SerialPort::new(state.serial()));
+        let interrupt = SerialInterrupt {
+            rx: state.serial(),
+            irq: 37,
+            producer: port.split(),
+            signaller: &port.signaller,
+            classify: |e| *e,
+        };
+        (port, interrupt)
+    }
+
+    #[test]
+    fn loopback() {
+        let state = leak(MockSerialState::loopback());
+        let (port, mut interrupt) = setup(state);
+
+        block_on(port.write(b"AT\r\nOK\r\n")).unwrap();
+        assert_eq!(&state.written()[..], b"AT\r\nOK\r\n");
+
+        let mut errors = heapless::Vec::<SerialError, U4>::new();
+        interrupt.service(|e| errors.push(e).unwrap());
+        assert!(errors.is_empty());
+
+        let mut buf = [0; 16];
+        assert_eq!(block_on(port.read_until(b'\n', &mut buf)), 4);
+        assert_eq!(&buf[..4], b"AT\r\n");
+        assert_eq!(block_on(port.read(&mut buf)), 4);
+        assert_eq!(&buf[..4], b"OK\r\n");
+
+        let mut read = std::boxed::Box::pin(port.read(&mut buf));
+        assert_eq!(poll_once(read.as_mut()), Poll::Pending);
+    }
+
+    #[test]
+    fn readers() {
+        let state = leak(MockSerialState::new());
+        let (port, mut interrupt) = setup(state);
+
+        // served in turn, rather than the latter taking the former's wakeup
+        let (mut one, mut two) = ([0; 4], [0; 4]);
+        let mut first = std::boxed::Box::pin(port.read(&mut one));
+        let mut second = std::boxed::Box::pin(port.read_until(b'\n', &mut two));
+        assert_eq!(poll_once(first.as_mut()), Poll::Pending);
+        assert_eq!(poll_once(second.as_mut()), Poll::Pending);
+
+        state.inject(b"ab");
+        interrupt.service(|_| {});
+        assert_eq!(poll_once(second.as_mut()), Poll::Pending);
+        assert_eq!(poll_once(first.as_mut()), Poll::Ready(2));
+
+        assert_eq!(poll_once(second.as_mut()), Poll::Pending);
+        state.inject(b"c\n");
+        interrupt.service(|_| {});
+        assert_eq!(poll_once(second.as_mut()), Poll::Ready(2));
+    }
+
+    #[test]
+    fn errors() {
+        let state = leak(MockSerialState::new());
+        let (port, mut interrupt) = setup(state);
+
+        state.inject(b"0123");
+        state.inject_error(SerialError::Framing);
+        state.inject(b"456789");
+
+        let mut errors = heapless::Vec::<SerialError, U4>::new();
+        interrupt.service(|e| errors.push(e).unwrap());
+        assert_eq!(
+            &errors[..],
+            &[
+                SerialError::Framing,
+                SerialError::BufferOverrun,
+                SerialError::BufferOverrun
+            ]
+        );
+
+        let mut buf = [0; 16];
+        assert_eq!(block_on(port.read(&mut buf)), 8);
+        assert_eq!(&buf[..8], b"01234567");
+    }
+}
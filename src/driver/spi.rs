@@ -0,0 +1,185 @@
+use crate::component::{Component, ComponentContext};
+use crate::driver::lock::{BusGuard, BusLock};
+use crate::power::SleepState;
+use core::cell::{Cell, RefCell};
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+
+/// A shared SPI bus, owning an embedded-hal SPI peripheral and
+/// arbitrating transactions between several `SpiDevice`s, each
+/// selected through its own chip-select pin.
+///
+/// The bus is shared by reference, so should itself be placed in
+/// static memory, such as a `StaticCell`. It should additionally be
+/// started as a component, as `ConnectedComponent<&'static SpiBus<SPI>>`,
+/// through which it keeps the device from entering `SleepState::Stop`
+/// while a transaction is in progress.
+pub struct SpiBus<SPI: 'static> {
+    spi: RefCell<SPI>,
+    lock: BusLock,
+    ctx: Cell<Option<&'static ComponentContext<&'static SpiBus<SPI>>>>,
+}
+
+impl<SPI: 'static> SpiBus<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self {
+            spi: RefCell::new(spi),
+            lock: BusLock::new(),
+            ctx: Cell::new(None),
+        }
+    }
+
+    /// Create a device upon this bus, selected by driving `cs` low.
+    ///
+    /// The device is initially deselected.
+    pub fn device<CS: OutputPin>(&'static self, mut cs: CS) -> SpiDevice<SPI, CS> {
+        cs.set_high().ok();
+        SpiDevice {
+            bus: self,
+            cs: RefCell::new(cs),
+        }
+    }
+
+    /// Determine if a transaction is currently in progress.
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    async fn lock(&self) -> BusGuard<'_> {
+        let guard = self.lock.lock().await;
+        if let Some(ctx) = self.ctx.get() {
+            ctx.limit_sleep(SleepState::Sleep);
+        }
+        guard
+    }
+
+    /// Lift the sleep limit of a transaction, prior to its guard being dropped.
+    fn release(&self) {
+        if let Some(ctx) = self.ctx.get() {
+            ctx.limit_sleep(SleepState::Stop);
+        }
+    }
+}
+
+impl<SPI: 'static> Component for &'static SpiBus<SPI> {
+    type InboundMessage = ();
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.set(Some(ctx));
+    }
+}
+
+/// A device upon a `SpiBus`, to be held by the component driving it.
+pub struct SpiDevice<SPI: 'static, CS: OutputPin> {
+    bus: &'static SpiBus<SPI>,
+    cs: RefCell<CS>,
+}
+
+impl<SPI: 'static, CS: OutputPin> SpiDevice<SPI, CS> {
+    /// Begin, *asynchronously*, a transaction with this device, waiting
+    /// until any transaction of another device upon the bus has completed.
+    ///
+    /// The device remains selected, and the bus unavailable to other
+    /// devices, until the returned `SpiTransaction` is dropped. Being
+    /// arbitrated by the bus, transactions may be begun through a shared
+    /// reference, such as by each task of the component driving the device.
+    pub async fn transaction(&self) -> SpiTransaction<'_, SPI, CS> {
+        let guard = self.bus.lock().await;
+        self.cs.borrow_mut().set_low().ok();
+        SpiTransaction {
+            bus: self.bus,
+            cs: &self.cs,
+            _guard: guard,
+        }
+    }
+}
+
+/// An in-progress transaction with a `SpiDevice`, during which the
+/// device has exclusive use of the bus.
+pub struct SpiTransaction<'d, SPI: 'static, CS: OutputPin> {
+    bus: &'static SpiBus<SPI>,
+    cs: &'d RefCell<CS>,
+    _guard: BusGuard<'static>,
+}
+
+impl<'d, SPI, CS> SpiTransaction<'d, SPI, CS>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+{
+    /// Exchange `words` with the device, returning the words received.
+    pub fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], SPI::Error> {
+        self.bus.spi.borrow_mut().transfer(words)
+    }
+}
+
+impl<'d, SPI, CS> SpiTransaction<'d, SPI, CS>
+where
+    SPI: Write<u8>,
+    CS: OutputPin,
+{
+    /// Send `words` to the device, discarding any words received.
+    pub fn write(&mut self, words: &[u8]) -> Result<(), SPI::Error> {
+        self.bus.spi.borrow_mut().write(words)
+    }
+}
+
+impl<'d, SPI: 'static, CS: OutputPin> Drop for SpiTransaction<'d, SPI, CS> {
+    fn drop(&mut self) {
+        self.cs.borrow_mut().set_high().ok();
+        self.bus.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::SpiBus;
+    use crate::mock::{MockPinState, MockSpi, MockSpiState};
+    use crate::testing::{block_on, leak, poll_once};
+    use core::task::Poll;
+    use std::boxed::Box;
+
+    #[test]
+    fn arbitration() {
+        let spi = leak(MockSpiState::new());
+        let bus: &'static SpiBus<MockSpi> = leak(SpiBus::new(spi.spi()));
+        let cs_a = leak(MockPinState::new(false));
+        let cs_b = leak(MockPinState::new(false));
+        let a = bus.device(cs_a.pin());
+        let b = bus.device(cs_b.pin());
+        assert!(cs_a.is_high() && cs_b.is_high());
+
+        spi.expect(&[0x8F, 0x00], &[0x00, 0xBC]);
+        spi.expect(&[0x20, 0x81], &[]);
+        spi.expect(&[0x0F], &[]);
+
+        let mut transaction = block_on(a.transaction());
+        assert!(!cs_a.is_high());
+        assert!(bus.is_locked());
+
+        let mut pending = Box::pin(b.transaction());
+        assert!(matches!(poll_once(pending.as_mut()), Poll::Pending));
+        assert!(cs_b.is_high());
+
+        let mut words = [0x8F, 0x00];
+        assert_eq!(transaction.transfer(&mut words), Ok(&[0x00, 0xBC][..]));
+        assert_eq!(transaction.write(&[0x20, 0x81]), Ok(()));
+        drop(transaction);
+        assert!(cs_a.is_high());
+        assert!(!bus.is_locked());
+
+        match poll_once(pending.as_mut()) {
+            Poll::Ready(mut transaction) => {
+                assert!(!cs_b.is_high());
+                assert_eq!(transaction.write(&[0x0F]), Ok(()));
+            }
+            Poll::Pending => panic!("bus not released"),
+        }
+        drop(pending);
+        assert!(cs_b.is_high());
+        assert!(spi.is_done());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::component::{Component, ComponentContext};
+use crate::context::UpstreamContext;
+use crate::driver::exti::{DataReady, DataReadyInterrupt, ExtiPin};
+use crate::driver::lock::Flag;
+use crate::driver::lora::{Bandwidth, LoRaConfig, Radio, Received, PREAMBLE_SYMBOLS};
+use crate::driver::spi::SpiDevice;
+use crate::handler::Handler;
+use crate::interrupt::ConnectedInterrupt;
+use crate::time::{Delay, Duration, WithTimeout};
+use core::cell::Cell;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+use heapless::{consts::*, Vec};
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_MODEM_STAT: u8 = 0x18;
+const REG_PKT_SNR_VALUE: u8 = 0x19;
+const REG_MODEM_CONFIG_1: u8 = 0x1D;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_PREAMBLE_MSB: u8 = 0x20;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_INVERT_IQ: u8 = 0x33;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_INVERT_IQ_2: u8 = 0x3B;
+const REG_DIO_MAPPING_1: u8 = 0x40;
+const REG_VERSION: u8 = 0x42;
+
+const WRITE: u8 = 0x80;
+const VERSION: u8 = 0x12;
+
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_SINGLE: u8 = 0x06;
+
+const PA_BOOST: u8 = 0x80;
+const MODEM_CONFIG_2_CRC_ON: u8 = 0x04;
+const MODEM_CONFIG_3_LOW_DATA_RATE: u8 = 0x08;
+const MODEM_CONFIG_3_AGC_AUTO: u8 = 0x04;
+const MODEM_STAT_SIGNAL_DETECTED: u8 = 0x01;
+
+const IRQ_RX_DONE: u8 = 0x40;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+const IRQ_TX_DONE: u8 = 0x08;
+
+const DIO0_RX_DONE: u8 = 0x00;
+const DIO0_TX_DONE: u8 = 0x40;
+
+/// The sync word of public LoRaWAN networks.
+const SYNC_WORD_PUBLIC: u8 = 0x34;
+
+/// The RSSI offset of the high frequency port, in dBm.
+const RSSI_OFFSET: i16 = -157;
+
+/// The time allowed, beyond its time on air, for a packet to be sent.
+const TX_MARGIN: Duration = Duration::from_millis(100);
+
+/// Errors reported by an `Sx127x`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sx127xError {
+    /// The radio has not been started.
+    NotStarted,
+    /// Communication with the radio failed.
+    Spi,
+    /// The radio did not identify itself as an SX127x.
+    NotFound,
+    /// The radio did not complete a transmission in time.
+    Timeout,
+}
+
+/// A driver for the LoRa modem of the Semtech SX1276/77/78/79, attached
+/// to an `SpiBus`, with its DIO0 line routed to an EXTI interrupt, and
+/// its RF output upon the PA_BOOST pin.
+///
+/// The radio is identified, and placed in LoRa mode, upon its first use,
+/// and sleeps between each transmission or reception, which DIO0 signals
+/// the completion of.
+///
+/// The radio is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static Sx127x<SPI, CS, P>>`, once the `SpiBus` is.
+pub struct Sx127x<SPI, CS, P>
+where
+    SPI: Transfer<u8> + Write<u8> + 'static,
+    CS: OutputPin + 'static,
+    P: ExtiPin + 'static,
+{
+    device: SpiDevice<SPI, CS>,
+    dio0: ConnectedInterrupt<DataReadyInterrupt<P>>,
+    done: Flag,
+    initialized: Cell<bool>,
+    upstream: Cell<Option<&'static dyn UpstreamContext<()>>>,
+}
+
+impl<SPI, CS, P> Sx127x<SPI, CS, P>
+where
+    SPI: Transfer<u8> + Write<u8> + 'static,
+    CS: OutputPin + 'static,
+    P: ExtiPin + 'static,
+{
+    /// Create a new driver for the radio at `device`, its DIO0 line
+    /// attached to `dio0` triggering `irq`.
+    pub fn new(device: SpiDevice<SPI, CS>, dio0: P, irq: u8) -> Self {
+        Self {
+            device,
+            dio0: ConnectedInterrupt::new(DataReadyInterrupt::new(dio0, irq)),
+            done: Flag::new(),
+            initialized: Cell::new(false),
+            upstream: Cell::new(None),
+        }
+    }
+
+    async fn write(&self, register: u8, values: &[u8]) -> Result<(), Sx127xError> {
+        let mut words = Vec::<u8, U256>::new();
+        words.push(register | WRITE).ok();
+        words
+            .extend_from_slice(values)
+            .map_err(|_| Sx127xError::Spi)?;
+        let mut transaction = self.device.transaction().await;
+        transaction.write(&words).map_err(|_| Sx127xError::Spi)
+    }
+
+    async fn read(&self, register: u8, values: &mut [u8]) -> Result<(), Sx127xError> {
+        let mut words = Vec::<u8, U256>::new();
+        words
+            .resize(values.len() + 1, 0)
+            .map_err(|_| Sx127xError::Spi)?;
+        words[0] = register & !WRITE;
+        let mut transaction = self.device.transaction().await;
+        let received = transaction
+            .transfer(&mut words)
+            .map_err(|_| Sx127xError::Spi)?;
+        values.copy_from_slice(&received[1..]);
+        Ok(())
+    }
+
+    async fn set_mode(&self, mode: u8) -> Result<(), Sx127xError> {
+        self.write(REG_OP_MODE, &[MODE_LONG_RANGE | mode]).await
+    }
+
+    /// Identify the radio, and place it in LoRa mode, which
+    /// may only be entered while sleeping.
+    async fn initialize(&self) -> Result<(), Sx127xError> {
+        let mut version = [0];
+        self.read(REG_VERSION, &mut version).await?;
+        if version[0] != VERSION {
+            return Err(Sx127xError::NotFound);
+        }
+        self.write(REG_OP_MODE, &[MODE_SLEEP]).await?;
+        self.set_mode(MODE_SLEEP).await?;
+        self.write(REG_SYNC_WORD, &[SYNC_WORD_PUBLIC]).await?;
+        self.initialized.set(true);
+        Ok(())
+    }
+
+    /// Prepare, from standby, to transmit or receive with `config`, for
+    /// reception timing out after `symbols`, and with the FIFO emptied.
+    async fn configure(&self, config: &LoRaConfig, symbols: u16) -> Result<(), Sx127xError> {
+        if !self.initialized.get() {
+            self.initialize().await?;
+        }
+        self.set_mode(MODE_STANDBY).await?;
+
+        let frf = ((config.frequency as u64) << 19) / 32_000_000;
+        self.write(
+            REG_FRF_MSB,
+            &[(frf >> 16) as u8, (frf >> 8) as u8, frf as u8],
+        )
+        .await?;
+        let power = (config.power.clamp(2, 17) - 2) as u8;
+        self.write(REG_PA_CONFIG, &[PA_BOOST | power]).await?;
+
+        let bandwidth = match config.bandwidth {
+            Bandwidth::Khz125 => 0x70,
+            Bandwidth::Khz250 => 0x80,
+            Bandwidth::Khz500 => 0x90,
+        };
+        let crc = if config.crc { MODEM_CONFIG_2_CRC_ON } else { 0 };
+        // the symbol timeout straddles the second and third registers
+        self.write(
+            REG_MODEM_CONFIG_1,
+            &[
+                bandwidth | ((config.coding_rate as u8) << 1),
+                ((config.spreading_factor as u8) << 4) | crc | ((symbols >> 8) as u8 & 0x03),
+                symbols as u8,
+            ],
+        )
+        .await?;
+        let low_data_rate = if config.low_data_rate() {
+            MODEM_CONFIG_3_LOW_DATA_RATE
+        } else {
+            0
+        };
+        self.write(
+            REG_MODEM_CONFIG_3,
+            &[low_data_rate | MODEM_CONFIG_3_AGC_AUTO],
+        )
+        .await?;
+        self.write(REG_PREAMBLE_MSB, &[0, PREAMBLE_SYMBOLS as u8])
+            .await?;
+
+        let (invert, invert_2) = if config.invert_iq {
+            (0x66, 0x19)
+        } else {
+            (0x27, 0x1D)
+        };
+        self.write(REG_INVERT_IQ, &[invert]).await?;
+        self.write(REG_INVERT_IQ_2, &[invert_2]).await?;
+
+        // the FIFO pointer, and the transmit and receive base addresses
+        self.write(REG_FIFO_ADDR_PTR, &[0, 0, 0]).await
+    }
+
+    /// Clear every interrupt, and sleep.
+    async fn finish(&self) -> Result<(), Sx127xError> {
+        self.write(REG_IRQ_FLAGS, &[0xFF]).await?;
+        self.set_mode(MODE_SLEEP).await
+    }
+
+    /// Transmit, *asynchronously*, `data` as one packet with `config`.
+    pub async fn transmit(&self, config: &LoRaConfig, data: &[u8]) -> Result<(), Sx127xError> {
+        let upstream = self.upstream.get().ok_or(Sx127xError::NotStarted)?;
+        self.configure(config, 0).await?;
+        self.write(REG_FIFO, data).await?;
+        self.write(REG_PAYLOAD_LENGTH, &[data.len() as u8]).await?;
+        self.write(REG_DIO_MAPPING_1, &[DIO0_TX_DONE]).await?;
+        self.write(REG_IRQ_FLAGS, &[0xFF]).await?;
+
+        self.done.lower();
+        self.set_mode(MODE_TX).await?;
+        let deadline = upstream.now() + config.airtime(data.len()) + TX_MARGIN;
+        WithTimeout::new(self.done.wait(), Delay::new(upstream, deadline)).await;
+
+        let mut flags = [0];
+        self.read(REG_IRQ_FLAGS, &mut flags).await?;
+        self.finish().await?;
+        if flags[0] & IRQ_TX_DONE == 0 {
+            return Err(Sx127xError::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Listen, *asynchronously*, with `config`, for a packet whose preamble
+    /// is detected within `window`, receiving its payload into `buf`.
+    pub async fn receive(
+        &self,
+        config: &LoRaConfig,
+        buf: &mut [u8],
+        window: Duration,
+    ) -> Result<Option<Received>, Sx127xError> {
+        let upstream = self.upstream.get().ok_or(Sx127xError::NotStarted)?;
+        let symbol = config.symbol_time().as_micros().max(1);
+        let symbols = (window.as_micros() / symbol).clamp(4, 0x3FF) as u16;
+        self.configure(config, symbols).await?;
+        self.write(REG_DIO_MAPPING_1, &[DIO0_RX_DONE]).await?;
+        self.write(REG_IRQ_FLAGS, &[0xFF]).await?;
+
+        self.done.lower();
+        self.set_mode(MODE_RX_SINGLE).await?;
+        let deadline = upstream.now() + window;
+        if WithTimeout::new(self.done.wait(), Delay::new(upstream, deadline))
+            .await
+            .is_none()
+        {
+            // a packet detected within the window is received in full
+            let mut status = [0];
+            self.read(REG_MODEM_STAT, &mut status).await?;
+            if status[0] & MODEM_STAT_SIGNAL_DETECTED != 0 {
+                let deadline = upstream.now() + config.airtime(255);
+                WithTimeout::new(self.done.wait(), Delay::new(upstream, deadline)).await;
+            }
+        }
+
+        // the current address, the interrupt mask and flags, and the length
+        let mut state = [0; 4];
+        self.read(REG_FIFO_RX_CURRENT_ADDR, &mut state).await?;
+        let [address, _, flags, len] = state;
+        let received = if flags & IRQ_RX_DONE != 0 && flags & IRQ_PAYLOAD_CRC_ERROR == 0 {
+            let mut quality = [0; 2];
+            self.read(REG_PKT_SNR_VALUE, &mut quality).await?;
+            let len = (len as usize).min(buf.len());
+            self.write(REG_FIFO_ADDR_PTR, &[address]).await?;
+            self.read(REG_FIFO, &mut buf[..len]).await?;
+            Some(Received {
+                len,
+                rssi: RSSI_OFFSET + quality[1] as i16,
+                snr: quality[0] as i8 / 4,
+            })
+        } else {
+            None
+        };
+        self.finish().await?;
+        Ok(received)
+    }
+}
+
+impl<SPI, CS, P> Radio for Sx127x<SPI, CS, P>
+where
+    SPI: Transfer<u8> + Write<u8> + 'static,
+    CS: OutputPin + 'static,
+    P: ExtiPin + 'static,
+{
+    type Error = Sx127xError;
+
+    async fn transmit(&self, config: &LoRaConfig, data: &[u8]) -> Result<(), Self::Error> {
+        Sx127x::transmit(self, config, data).await
+    }
+
+    async fn receive(
+        &self,
+        config: &LoRaConfig,
+        buf: &mut [u8],
+        window: Duration,
+    ) -> Result<Option<Received>, Self::Error> {
+        Sx127x::receive(self, config, buf, window).await
+    }
+}
+
+impl<SPI, CS, P> Component for &'static Sx127x<SPI, CS, P>
+where
+    SPI: Transfer<u8> + Write<u8> + 'static,
+    CS: OutputPin + 'static,
+    P: ExtiPin + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.upstream.set(Some(ctx.upstream()));
+        let radio: &'static Sx127x<SPI, CS, P> = self;
+        radio.dio0.start(ctx);
+    }
+}
+
+impl<SPI, CS, P> Handler<DataReady> for &'static Sx127x<SPI, CS, P>
+where
+    SPI: Transfer<u8> + Write<u8> + 'static,
+    CS: OutputPin + 'static,
+    P: ExtiPin + 'static,
+{
+    fn on_message(&mut self, _message: DataReady) {
+        self.done.raise();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::Sx127x;
+    use crate::driver::lora::{Bandwidth, CodingRate, LoRaConfig, Received, SpreadingFactor};
+    use crate::driver::spi::SpiBus;
+    use crate::mock::{MockPin, MockPinState, MockSpi, MockSpiState};
+    use crate::testing::{leak, poll_once, Upstream};
+    use crate::time::Duration;
+    use core::future::Future;
+    use core::task::Poll;
+    use std::boxed::Box;
+
+    /// Run `future` until it awaits DIO0, then signal its completion.
+    fn complete<F: Future>(radio: &Sx127x<MockSpi, MockPin, MockPin>, future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        assert!(poll_once(future.as_mut()).is_pending());
+        radio.done.raise();
+        match poll_once(future.as_mut()) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future never completed"),
+        }
+    }
+
+    #[test]
+    fn transmit_and_receive() {
+        let spi = leak(MockSpiState::new());
+        let bus: &'static SpiBus<MockSpi> = leak(SpiBus::new(spi.spi()));
+        let cs = leak(MockPinState::new(true));
+        let dio0 = leak(MockPinState::new(false));
+        let radio = leak(Sx127x::new(bus.device(cs.pin()), dio0.pin(), 10));
+        radio.upstream.set(Some(leak(Upstream::new())));
+
+        let mut config = LoRaConfig {
+            frequency: 868_100_000,
+            spreading_factor: SpreadingFactor::SF7,
+            bandwidth: Bandwidth::Khz125,
+            coding_rate: CodingRate::Cr4_5,
+            power: 14,
+            invert_iq: false,
+            crc: true,
+        };
+
+        // identified and placed in LoRa mode upon first use
+        spi.expect(&[0x42, 0x00], &[0x00, 0x12]);
+        spi.expect(&[0x81, 0x00], &[]);
+        spi.expect(&[0x81, 0x80], &[]);
+        spi.expect(&[0xB9, 0x34], &[]);
+        let configure = |symbols: u8, crc: u8, invert: [u8; 2]| {
+            spi.expect(&[0x81, 0x81], &[]);
+            spi.expect(&[0x86, 0xD9, 0x06, 0x66], &[]);
+            spi.expect(&[0x89, 0x8C], &[]);
+            spi.expect(&[0x9D, 0x72, 0x70 | crc, symbols], &[]);
+            spi.expect(&[0xA6, 0x04], &[]);
+            spi.expect(&[0xA0, 0x00, 0x08], &[]);
+            spi.expect(&[0xB3, invert[0]], &[]);
+            spi.expect(&[0xBB, invert[1]], &[]);
+            spi.expect(&[0x8D, 0x00, 0x00, 0x00], &[]);
+        };
+        configure(0, 0x04, [0x27, 0x1D]);
+        spi.expect(&[0x80, 1, 2, 3], &[]);
+        spi.expect(&[0xA2, 3], &[]);
+        spi.expect(&[0xC0, 0x40], &[]);
+        spi.expect(&[0x92, 0xFF], &[]);
+        spi.expect(&[0x81, 0x83], &[]);
+        spi.expect(&[0x12, 0x00], &[0x00, 0x08]);
+        spi.expect(&[0x92, 0xFF], &[]);
+        spi.expect(&[0x81, 0x80], &[]);
+        assert_eq!(complete(radio, radio.transmit(&config, &[1, 2, 3])), Ok(()));
+        assert!(spi.is_done());
+
+        config.invert_iq = true;
+        config.crc = false;
+        // a window of 97 symbols
+        configure(97, 0x00, [0x66, 0x19]);
+        spi.expect(&[0xC0, 0x00], &[]);
+        spi.expect(&[0x92, 0xFF], &[]);
+        spi.expect(&[0x81, 0x86], &[]);
+        spi.expect(&[0x10, 0, 0, 0, 0], &[0, 0x20, 0x00, 0x40, 3]);
+        spi.expect(&[0x19, 0, 0], &[0, 20, 100]);
+        spi.expect(&[0x8D, 0x20], &[]);
+        spi.expect(&[0x00, 0, 0, 0], &[0, 9, 8, 7]);
+        spi.expect(&[0x92, 0xFF], &[]);
+        spi.expect(&[0x81, 0x80], &[]);
+        let mut buf = [0; 16];
+        let received = complete(
+            radio,
+            radio.receive(&config, &mut buf, Duration::from_millis(100)),
+        );
+        assert_eq!(
+            received,
+            Ok(Some(Received {
+                len: 3,
+                rssi: -57,
+                snr: 5,
+            }))
+        );
+        assert_eq!(&buf[..3], &[9, 8, 7]);
+        assert!(spi.is_done());
+    }
+}
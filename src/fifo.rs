@@ -1,31 +1,36 @@
+use crate::backend;
 use crate::component::Component;
+use crate::kernel::notify_activity;
 use core::cell::RefCell;
 use core::cell::UnsafeCell;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::Context as FutureContext;
 use core::task::{Poll, Waker};
+use cortex_m::interrupt::Mutex;
 use heapless::spsc::{Consumer, Producer, Queue};
 use heapless::ArrayLength;
 
 pub struct Signaller {
-    waker: RefCell<Option<Waker>>,
+    // set by tasks, and taken by interrupt handlers
+    waker: Mutex<RefCell<Option<Waker>>>,
 }
 
 impl Signaller {
     pub fn new() -> Self {
         Self {
-            waker: RefCell::new(None),
+            waker: Mutex::new(RefCell::new(None)),
         }
     }
 
     pub fn set_waker(&self, waker: Waker) {
-        self.waker.borrow_mut().replace(waker);
+        backend::free(|cs| self.waker.borrow(cs).borrow_mut().replace(waker));
     }
 
     pub fn wake(&self) {
-        let waker = self.waker.borrow_mut().take();
+        let waker = backend::free(|cs| self.waker.borrow(cs).borrow_mut().take());
         if let Some(waker) = waker {
+            notify_activity();
             waker.wake()
         }
     }
@@ -1,5 +1,7 @@
 use crate::context::UpstreamContext;
-use core::cell::UnsafeCell;
+use crate::time::Instant;
+use core::cell::{Cell, UnsafeCell};
+use core::task::{RawWaker, RawWakerVTable, Waker};
 
 /// A leaf component representing IRQ logic.
 ///
@@ -22,18 +24,21 @@ pub struct InterruptContext<I: Interrupt>
 where
     I: 'static,
 {
-    _interrupt: &'static ConnectedInterrupt<I>,
+    interrupt: &'static ConnectedInterrupt<I>,
     upstream: &'static dyn UpstreamContext<I::OutboundMessage>,
+    irq: u8,
 }
 
 impl<I: Interrupt> InterruptContext<I> {
     fn new(
         interrupt: &'static ConnectedInterrupt<I>,
         upstream: &'static dyn UpstreamContext<I::OutboundMessage>,
+        irq: u8,
     ) -> Self {
         Self {
-            _interrupt: interrupt,
+            interrupt,
             upstream,
+            irq,
         }
     }
 
@@ -53,6 +58,19 @@ impl<I: Interrupt> InterruptContext<I> {
     pub fn send(&self, message: I::OutboundMessage) {
         self.upstream.send(message)
     }
+
+    /// The current time, according to the kernel's time source.
+    pub fn now(&self) -> Instant {
+        self.upstream.now()
+    }
+
+    /// Pend this interrupt's IRQ once the kernel's time source reaches
+    /// `deadline`, so that `on_interrupt(...)` is invoked again, at the
+    /// interrupt's own priority, as though it had triggered, such as to
+    /// sample an input once it has settled.
+    pub fn interrupt_at(&self, deadline: Instant) {
+        self.upstream.schedule(deadline, self.interrupt.waker())
+    }
 }
 
 /// Wrapper for an `Interrupt` to be held by the `Kernel`
@@ -65,6 +83,8 @@ where
 {
     interrupt: UnsafeCell<I>,
     context: UnsafeCell<Option<InterruptContext<I>>>,
+    // this interrupt, once started, pended by the wakers of `interrupt_at(...)`
+    this: Cell<Option<&'static dyn Pend>>,
 }
 
 impl<I: Interrupt> ConnectedInterrupt<I> {
@@ -73,6 +93,7 @@ impl<I: Interrupt> ConnectedInterrupt<I> {
         Self {
             interrupt: UnsafeCell::new(interrupt),
             context: UnsafeCell::new(None),
+            this: Cell::new(None),
         }
     }
 
@@ -81,15 +102,56 @@ impl<I: Interrupt> ConnectedInterrupt<I> {
     /// This method should be invoked with the `ctx` passed to it's
     /// parent's own `start(...)` method.
     pub fn start(&'static self, upstream: &'static dyn UpstreamContext<I::OutboundMessage>) {
-        let context = InterruptContext::new(self, upstream);
+        let context =
+            InterruptContext::new(self, upstream, unsafe { &*self.interrupt.get() }.irq());
 
         unsafe {
-            context
-                .upstream
-                .register_irq((&*self.interrupt.get()).irq(), self);
+            context.upstream.register_irq(context.irq, self);
 
             (&mut *self.context.get()).replace(context);
         }
+        self.this.set(Some(self));
+    }
+
+    fn waker(&'static self) -> Waker {
+        let this = &self.this as *const Cell<Option<&'static dyn Pend>>;
+        unsafe { Waker::from_raw(RawWaker::new(this as *const (), &INTERRUPT_VTABLE)) }
+    }
+}
+
+/// Wakers pending the IRQ of a started interrupt, as scheduled by
+/// `interrupt_at(...)`. They are woken by the kernel's tick, which on a
+/// device is the `SysTick` handler, so must not invoke the interrupt itself.
+static INTERRUPT_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    clone_interrupt,
+    wake_interrupt,
+    wake_interrupt,
+    drop_interrupt,
+);
+
+unsafe fn clone_interrupt(this: *const ()) -> RawWaker {
+    RawWaker::new(this, &INTERRUPT_VTABLE)
+}
+
+unsafe fn wake_interrupt(this: *const ()) {
+    let this = &*(this as *const Cell<Option<&'static dyn Pend>>);
+    if let Some(interrupt) = this.get() {
+        interrupt.pend();
+    }
+}
+
+unsafe fn drop_interrupt(_this: *const ()) {}
+
+/// The pending of a started interrupt's IRQ, independent of its type.
+trait Pend {
+    fn pend(&self);
+}
+
+impl<I: Interrupt> Pend for ConnectedInterrupt<I> {
+    fn pend(&self) {
+        if let Some(context) = unsafe { &*self.context.get() } {
+            context.upstream.pend(context.irq);
+        }
     }
 }
 
@@ -1,9 +1,18 @@
+use crate::backend::{self, Backend, CortexM};
 use crate::context::UpstreamContext;
 use crate::handler::{Handler, Sink};
 use crate::interrupt::Interruptable;
-use core::cell::{RefCell, UnsafeCell};
-use cortex_m::interrupt::Nr;
-use cortex_m::peripheral::NVIC;
+use crate::power::{IdlePolicy, SleepListener, SleepState};
+use crate::time::Instant;
+use crate::watchdog::{HardwareWatchdog, Liveness};
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use core::task::{Context as FutureContext, Poll, Waker};
+use cortex_m::interrupt::Mutex;
+use drogue_async::task::spawn;
 use heapless::{consts::*, Vec};
 
 #[doc(hidden)]
@@ -29,6 +38,11 @@ pub trait Kernel: Sized {
     /// started in an application-appropriate order, passing
     /// the `ctx` through to them.
     fn start(&'static self, ctx: &'static KernelContext<Self>);
+
+    /// Invoked when a component registered with the watchdog has
+    /// failed to check in within its deadline, immediately before
+    /// the kernel stops feeding the hardware watchdog.
+    fn on_watchdog_stall(&self, _component: &'static str) {}
 }
 
 #[doc(hidden)]
@@ -39,31 +53,325 @@ where
     kernel: UnsafeCell<K>,
     context: UnsafeCell<Option<KernelContext<K>>>,
     irq_registry: RefCell<IrqRegistry>,
+    backend: &'static dyn Backend,
+    idle_policy: Cell<IdlePolicy>,
+    sleep_registry: RefCell<SleepRegistry>,
+    activity: Activity,
+    // shared with `tick()`, invoked from the `SysTick` handler
+    watchdog_registry: Mutex<RefCell<WatchdogRegistry>>,
+    timer_registry: Mutex<RefCell<TimerRegistry>>,
 }
 
+// SAFETY: without `std`, the kernel is shared only between thread mode and
+// the interrupt handlers of a single core, never between threads. The
+// registries the `SysTick` handler mutates are guarded by critical sections,
+// and the IRQ registry is only mutated during `start()`, before any interrupt
+// has been unmasked. With `std`, the kernel may not be shared between threads.
+#[cfg(not(any(test, feature = "std")))]
+unsafe impl<K: Kernel> Send for ConnectedKernel<K> {}
+#[cfg(not(any(test, feature = "std")))]
+unsafe impl<K: Kernel> Sync for ConnectedKernel<K> {}
+
 impl<K: Kernel> ConnectedKernel<K> {
     pub fn new(kernel: K) -> Self {
+        Self::with_backend(kernel, &CortexM)
+    }
+
+    pub fn with_backend(kernel: K, backend: &'static dyn Backend) -> Self {
         Self {
             kernel: UnsafeCell::new(kernel),
             context: UnsafeCell::new(None),
             irq_registry: RefCell::new(IrqRegistry::new()),
+            backend,
+            idle_policy: Cell::new(IdlePolicy::Spin),
+            sleep_registry: RefCell::new(SleepRegistry::new()),
+            activity: Activity::new(),
+            watchdog_registry: Mutex::new(RefCell::new(WatchdogRegistry::new())),
+            timer_registry: Mutex::new(RefCell::new(TimerRegistry::new())),
         }
     }
 
+    /// Set the hardware watchdog to be fed while all components
+    /// registered with the watchdog remain live.
+    ///
+    /// This must be set prior to `start()`.
+    pub fn set_watchdog(&self, watchdog: &'static dyn HardwareWatchdog) {
+        backend::free(|cs| {
+            self.watchdog_registry
+                .borrow(cs)
+                .borrow_mut()
+                .watchdog
+                .replace(watchdog)
+        });
+    }
+
+    /// The component which stalled, if any.
+    pub fn stalled(&self) -> Option<&'static str> {
+        backend::free(|cs| self.watchdog_registry.borrow(cs).borrow().stalled)
+    }
+
+    /// The current time, according to the backend's time source.
+    pub fn now(&self) -> Instant {
+        self.backend.now()
+    }
+
+    /// Perform periodic housekeeping, such as waking expired timers
+    /// and servicing the watchdog.
+    ///
+    /// The `device!` macro invokes this every millisecond from the
+    /// `SysTick` when its `systick` option is provided, or whenever the
+    /// `Host` backend advances its time.
+    pub fn tick(&self) {
+        let now = self.now();
+        let (expired, stalled) = backend::free(|cs| {
+            let expired = self.timer_registry.borrow(cs).borrow_mut().expire(now);
+            let stalled = self.watchdog_registry.borrow(cs).borrow_mut().service(now);
+            (expired, stalled)
+        });
+        // woken once released, as a waker may schedule another timer
+        if !expired.is_empty() {
+            self.activity.notify();
+        }
+        for waker in expired {
+            waker.wake();
+        }
+
+        if let Some(component) = stalled {
+            unsafe { &*self.kernel.get() }.on_watchdog_stall(component);
+        }
+    }
+
+    /// Set the policy to apply whenever the executor has found
+    /// no further work to do since the policy was last applied.
+    ///
+    /// This must be set prior to `start()`.
+    pub fn set_idle_policy(&self, policy: IdlePolicy) {
+        self.idle_policy.set(policy);
+    }
+
     pub fn start(&'static self) {
         let context = KernelContext::new(&self);
         unsafe {
             (&mut *self.context.get()).replace(context);
             (&*self.kernel.get()).start((&*self.context.get()).as_ref().unwrap());
         }
-        self.irq_registry.borrow().unmask_all();
+        self.irq_registry.borrow().unmask_all(self.backend);
+        self.activity.list();
+
+        if !matches!(self.idle_policy.get(), IdlePolicy::Spin) {
+            spawn("idle", async move {
+                loop {
+                    Yield::new().await;
+                    if !self.idle() {
+                        Parked::new(&self.activity).await;
+                    }
+                }
+            });
+        }
+    }
+
+    /// Apply the idle policy, returning `false` should there be nothing
+    /// to do until some task is woken, or the sleep votes change.
+    fn idle(&self) -> bool {
+        let allowed = self.sleep_registry.borrow().allowed();
+        if allowed == SleepState::Awake {
+            return false;
+        }
+
+        match self.idle_policy.get() {
+            IdlePolicy::Spin => false,
+            IdlePolicy::Sleep(mode) => {
+                let mode = match mode.limit(allowed) {
+                    Some(mode) => mode,
+                    None => return false,
+                };
+                if self.activity.take() {
+                    return true;
+                }
+                let wake_sources = self.irq_registry.borrow().wake_sources();
+                // copied, as listeners may vote while notified
+                let listeners = self.sleep_registry.borrow().listeners.clone();
+                for listener in listeners.iter() {
+                    listener.on_sleep(mode.state());
+                }
+                self.backend
+                    .sleep(mode, &wake_sources, &|| !self.activity.take());
+                for listener in listeners.iter() {
+                    listener.on_wake(mode.state());
+                }
+                true
+            }
+            IdlePolicy::Custom(hook) => {
+                if !self.activity.take() {
+                    hook();
+                }
+                true
+            }
+        }
     }
 
     pub fn interrupt(&self, irqn: i16) {
+        self.activity.notify();
         self.irq_registry.borrow().interrupt(irqn);
     }
 }
 
+/// The periodic housekeeping and interrupts of a kernel, independent of its
+/// `Kernel` type, for backends simulating the `SysTick` and `NVIC`.
+#[doc(hidden)]
+pub trait Tick {
+    fn tick(&self);
+    fn interrupt(&self, irqn: i16);
+}
+
+impl<K: Kernel> Tick for ConnectedKernel<K> {
+    fn tick(&self) {
+        ConnectedKernel::tick(self)
+    }
+
+    fn interrupt(&self, irqn: i16) {
+        ConnectedKernel::interrupt(self, irqn)
+    }
+}
+
+/// Whether any task may have been woken since a kernel last applied its
+/// idle policy, along with its idle task, should it be parked.
+struct Activity {
+    pending: AtomicBool,
+    // taken by interrupt handlers
+    idle: Mutex<RefCell<Option<Waker>>>,
+    listed: AtomicBool,
+    next: AtomicPtr<Activity>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(true),
+            idle: Mutex::new(RefCell::new(None)),
+            listed: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Add this to the activities notified, once only, however often its
+    /// kernel is started.
+    fn list(&'static self) {
+        if self.listed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let this = self as *const _ as *mut _;
+        let mut head = STARTED.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match STARTED.compare_exchange_weak(head, this, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn notify(&self) {
+        self.pending.store(true, Ordering::Release);
+        self.wake_idle();
+    }
+
+    fn wake_idle(&self) {
+        if let Some(waker) = backend::free(|cs| self.idle.borrow(cs).borrow_mut().take()) {
+            waker.wake();
+        }
+    }
+
+    fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// The activities of every kernel started, most recent first; on a device
+/// there is only one. Entries are never removed, as kernels are `'static`.
+static STARTED: AtomicPtr<Activity> = AtomicPtr::new(ptr::null_mut());
+
+/// Record that some task may have been woken, deferring the idle policy
+/// of every started kernel until the executor has had a chance to poll it.
+///
+/// All kernels share the one executor, so any of them may own the task
+/// woken; each tracks its own activity, and each is notified, so that
+/// none parks its idle task while a task of its own is runnable.
+pub(crate) fn notify_activity() {
+    let mut activity = STARTED.load(Ordering::Acquire);
+    while !activity.is_null() {
+        let current = unsafe { &*activity };
+        current.notify();
+        activity = current.next.load(Ordering::Acquire);
+    }
+}
+
+/// Future parking the idle task until activity is next notified, or the
+/// sleep votes change.
+struct Parked<'a> {
+    activity: &'a Activity,
+    parked: bool,
+}
+
+impl<'a> Parked<'a> {
+    fn new(activity: &'a Activity) -> Self {
+        Self {
+            activity,
+            parked: false,
+        }
+    }
+}
+
+impl<'a> Future for Parked<'a> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        if self.parked {
+            return Poll::Ready(());
+        }
+        // registered before testing, lest a notification in between go unnoticed
+        backend::free(|cs| {
+            self.activity
+                .idle
+                .borrow(cs)
+                .borrow_mut()
+                .replace(cx.waker().clone())
+        });
+        if self.activity.pending.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            self.parked = true;
+            Poll::Pending
+        }
+    }
+}
+
+/// Future which yields once to the executor, allowing all other
+/// woken tasks to be polled before it completes.
+pub(crate) struct Yield {
+    yielded: bool,
+}
+
+impl Yield {
+    pub(crate) fn new() -> Self {
+        Self { yielded: false }
+    }
+}
+
+impl Future for Yield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
 /// Context used when calling `start(...)` on a `Kernel` implementation.
 pub struct KernelContext<K: Kernel>
 where
@@ -101,6 +409,53 @@ where
             .borrow_mut()
             .register(irq, interrupt);
     }
+
+    fn register_sleep_listener(&self, listener: &'static dyn SleepListener) {
+        self.kernel
+            .sleep_registry
+            .borrow_mut()
+            .register(listener);
+    }
+
+    fn vote_sleep(&self, previous: SleepState, current: SleepState) {
+        self.kernel
+            .sleep_registry
+            .borrow_mut()
+            .vote(previous, current);
+        self.kernel.activity.wake_idle();
+    }
+
+    fn register_liveness(&self, liveness: &'static Liveness) {
+        backend::free(|cs| {
+            self.kernel
+                .watchdog_registry
+                .borrow(cs)
+                .borrow_mut()
+                .register(liveness)
+        });
+    }
+
+    fn now(&self) -> Instant {
+        self.kernel.now()
+    }
+
+    fn schedule(&self, deadline: Instant, waker: Waker) {
+        let scheduled = backend::free(|cs| {
+            self.kernel
+                .timer_registry
+                .borrow(cs)
+                .borrow_mut()
+                .schedule(deadline, waker)
+        });
+        // woken once released, its task polling again until a timer is free
+        if let Err(waker) = scheduled {
+            waker.wake();
+        }
+    }
+
+    fn pend(&self, irq: u8) {
+        self.kernel.backend.pend(irq)
+    }
 }
 
 impl<K: Kernel> Handler<()> for K {
@@ -130,18 +485,19 @@ impl IrqRegistry {
         }
     }
 
-    pub fn unmask_all(&self) {
+    pub fn wake_sources(&self) -> Vec<u8, U16> {
+        let mut sources: Vec<u8, U16> = Vec::new();
         for irq in self.entries.iter().map(|e| e.irq) {
-            struct IrqNr(u8);
-            unsafe impl Nr for IrqNr {
-                fn nr(&self) -> u8 {
-                    self.0
-                }
+            if !sources.contains(&irq) {
+                sources.push(irq).ok().unwrap();
             }
+        }
+        sources
+    }
 
-            unsafe {
-                NVIC::unmask(IrqNr(irq));
-            }
+    pub fn unmask_all(&self, backend: &dyn Backend) {
+        for irq in self.entries.iter().map(|e| e.irq) {
+            backend.unmask(irq);
         }
     }
 }
@@ -156,3 +512,419 @@ struct IrqEntry {
     irq: u8,
     interrupt: &'static dyn Interruptable,
 }
+
+struct SleepRegistry {
+    listeners: Vec<&'static dyn SleepListener, U32>,
+    votes: [u16; 3],
+}
+
+impl SleepRegistry {
+    pub fn new() -> Self {
+        Self {
+            listeners: Vec::new(),
+            votes: [0; 3],
+        }
+    }
+
+    pub fn register(&mut self, listener: &'static dyn SleepListener) {
+        self.listeners.push(listener).ok().unwrap();
+    }
+
+    pub fn vote(&mut self, previous: SleepState, current: SleepState) {
+        let previous = &mut self.votes[previous as usize];
+        *previous = previous.saturating_sub(1);
+        self.votes[current as usize] += 1;
+    }
+
+    /// The deepest state tolerated by every voting component.
+    pub fn allowed(&self) -> SleepState {
+        if self.votes[SleepState::Awake as usize] > 0 {
+            SleepState::Awake
+        } else if self.votes[SleepState::Sleep as usize] > 0 {
+            SleepState::Sleep
+        } else {
+            SleepState::Stop
+        }
+    }
+}
+
+struct TimerRegistry {
+    entries: Vec<(Instant, Waker), U16>,
+}
+
+impl TimerRegistry {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Wake `waker` at `deadline`, alongside any other deadline already
+    /// scheduled for it.
+    ///
+    /// Should every entry be taken, `waker` is instead woken no later than
+    /// its earliest deadline, or is returned, to be woken at once, should
+    /// it have none.
+    pub fn schedule(&mut self, deadline: Instant, waker: Waker) -> Result<(), Waker> {
+        let mut earliest: Option<(usize, Instant)> = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.1.will_wake(&waker) {
+                if entry.0 == deadline {
+                    return Ok(());
+                }
+                match earliest {
+                    Some((_, at)) if at <= entry.0 => {}
+                    _ => earliest = Some((i, entry.0)),
+                }
+            }
+        }
+        match self.entries.push((deadline, waker)) {
+            Ok(()) => Ok(()),
+            Err((deadline, waker)) => match earliest {
+                Some((i, at)) => {
+                    self.entries[i].0 = at.min(deadline);
+                    Ok(())
+                }
+                None => Err(waker),
+            },
+        }
+    }
+
+    /// Remove every timer whose deadline has passed, returning
+    /// their wakers.
+    pub fn expire(&mut self, now: Instant) -> Vec<Waker, U16> {
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < self.entries.len() {
+            if self.entries[i].0 <= now {
+                expired.push(self.entries.swap_remove(i).1).ok();
+            } else {
+                i += 1;
+            }
+        }
+        expired
+    }
+}
+
+struct WatchdogRegistry {
+    entries: Vec<&'static Liveness, U16>,
+    watchdog: Option<&'static dyn HardwareWatchdog>,
+    stalled: Option<&'static str>,
+}
+
+impl WatchdogRegistry {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            watchdog: None,
+            stalled: None,
+        }
+    }
+
+    pub fn register(&mut self, liveness: &'static Liveness) {
+        self.entries.push(liveness).ok().unwrap();
+    }
+
+    /// Feed the watchdog if every entry is live, otherwise return
+    /// the name of the first stalled entry, once.
+    pub fn service(&mut self, now: Instant) -> Option<&'static str> {
+        let watchdog = self.watchdog?;
+        if self.stalled.is_some() {
+            return None;
+        }
+
+        match self.entries.iter().find(|e| e.is_stalled(now)) {
+            Some(entry) => {
+                self.stalled.replace(entry.name());
+                self.stalled
+            }
+            None => {
+                watchdog.feed();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{notify_activity, ConnectedKernel, Kernel, KernelContext, Parked};
+    use crate::backend::{Backend, Host};
+    use crate::context::UpstreamContext;
+    use crate::interrupt::Interruptable;
+    use crate::power::{IdlePolicy, SleepListener, SleepMode, SleepState};
+    use crate::testing::leak;
+    use crate::time::Duration;
+    use crate::watchdog::{Liveness, SimulatedWatchdog};
+    use core::cell::{Cell, RefCell};
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::Context as FutureContext;
+    use heapless::{consts::*, Vec};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    struct Device;
+
+    impl Kernel for Device {
+        fn start(&'static self, _ctx: &'static KernelContext<Self>) {}
+    }
+
+    struct Irq;
+
+    impl Interruptable for Irq {
+        fn interrupt(&self) {}
+    }
+
+    static IRQ: Irq = Irq;
+
+    #[derive(Default)]
+    struct Listener {
+        events: RefCell<Vec<(bool, SleepState), U8>>,
+        /// The context through which to vote to remain awake upon waking.
+        voting: Cell<Option<&'static KernelContext<Device>>>,
+    }
+
+    impl SleepListener for Listener {
+        fn on_sleep(&self, state: SleepState) {
+            self.events.borrow_mut().push((true, state)).unwrap();
+        }
+
+        fn on_wake(&self, state: SleepState) {
+            self.events.borrow_mut().push((false, state)).unwrap();
+            if let Some(context) = self.voting.get() {
+                context.vote_sleep(SleepState::Stop, SleepState::Awake);
+            }
+        }
+    }
+
+    #[test]
+    fn idle_policy() {
+        let host = leak(Host::new());
+        let kernel = leak(ConnectedKernel::with_backend(Device, host));
+        let context = leak(KernelContext::new(kernel));
+        let listener = leak(Listener::default());
+        context.register_sleep_listener(listener);
+        context.register_irq(7, &IRQ);
+        context.register_irq(3, &IRQ);
+        context.register_irq(7, &IRQ);
+
+        // spinning never sleeps
+        assert!(!kernel.idle());
+        assert_eq!(host.sleeps(), 0);
+
+        kernel.set_idle_policy(IdlePolicy::Sleep(SleepMode::Stop));
+
+        // pending activity defers sleep by one pass
+        kernel.activity.notify();
+        assert!(kernel.idle());
+        assert_eq!(host.sleeps(), 0);
+        assert!(kernel.idle());
+        assert_eq!(host.sleeps(), 1);
+        let (mode, wake_sources) = host.last_sleep().unwrap();
+        assert_eq!(mode, SleepMode::Stop);
+        assert_eq!(&wake_sources[..], &[7, 3]);
+
+        // any vote to remain awake prevents sleep
+        context.vote_sleep(SleepState::Stop, SleepState::Awake);
+        context.vote_sleep(SleepState::Stop, SleepState::Awake);
+        assert!(!kernel.idle());
+        context.vote_sleep(SleepState::Awake, SleepState::Stop);
+        assert!(!kernel.idle());
+        assert_eq!(host.sleeps(), 1);
+
+        // the shallowest vote limits the mode entered
+        context.vote_sleep(SleepState::Awake, SleepState::Sleep);
+        kernel.idle();
+        assert_eq!(host.sleeps(), 2);
+        assert_eq!(host.last_sleep().unwrap().0, SleepMode::WaitForInterrupt);
+        context.vote_sleep(SleepState::Sleep, SleepState::Stop);
+        kernel.idle();
+        assert_eq!(host.sleeps(), 3);
+        assert_eq!(host.last_sleep().unwrap().0, SleepMode::Stop);
+
+        // listeners are notified around each sleep
+        assert_eq!(
+            &listener.events.borrow()[..],
+            &[
+                (true, SleepState::Stop),
+                (false, SleepState::Stop),
+                (true, SleepState::Sleep),
+                (false, SleepState::Sleep),
+                (true, SleepState::Stop),
+                (false, SleepState::Stop),
+            ]
+        );
+
+        // and may vote while notified
+        listener.voting.set(Some(context));
+        kernel.idle();
+        assert_eq!(host.sleeps(), 4);
+        assert!(!kernel.idle());
+        assert_eq!(host.sleeps(), 4);
+
+        host.unmask(7);
+        assert!(host.is_unmasked(7));
+    }
+
+    struct Wakes(AtomicUsize);
+
+    impl Wake for Wakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn host_ticks() {
+        let host = leak(Host::new());
+        let kernel = leak(ConnectedKernel::with_backend(Device, host));
+        let context = leak(KernelContext::new(kernel));
+        let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+
+        context.schedule(host.now() + Duration::from_millis(10), wakes.clone().into());
+        host.advance(Duration::from_millis(10));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+
+        // once attached, advancing time ticks the kernel as the SysTick would
+        host.attach(kernel);
+        context.schedule(host.now() + Duration::from_millis(10), wakes.clone().into());
+        host.advance(Duration::from_millis(9));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        host.advance(Duration::from_millis(1));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn timers_full() {
+        let host = leak(Host::new());
+        let kernel = leak(ConnectedKernel::with_backend(Device, host));
+        let context = leak(KernelContext::new(kernel));
+        let wakes: std::vec::Vec<_> = (0..17)
+            .map(|_| Arc::new(Wakes(AtomicUsize::new(0))))
+            .collect();
+        let deadline = host.now() + Duration::from_millis(10);
+        for waker in &wakes[..16] {
+            context.schedule(deadline, waker.clone().into());
+        }
+
+        // once full, a waker's further deadline is folded into its earliest
+        context.schedule(
+            deadline + Duration::from_millis(10),
+            wakes[0].clone().into(),
+        );
+        // and one without a timer is woken at once
+        context.schedule(deadline, wakes[16].clone().into());
+        assert_eq!(wakes[0].0.load(Ordering::Relaxed), 0);
+        assert_eq!(wakes[16].0.load(Ordering::Relaxed), 1);
+
+        host.attach(kernel);
+        host.advance(Duration::from_millis(10));
+        assert!(wakes[..16].iter().all(|w| w.0.load(Ordering::Relaxed) == 1));
+        host.advance(Duration::from_millis(10));
+        assert_eq!(wakes[0].0.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn idle_parks() {
+        let host = leak(Host::new());
+        let kernel = leak(ConnectedKernel::with_backend(Device, host));
+        let context = leak(KernelContext::new(kernel));
+        let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+        let waker = wakes.clone().into();
+        let mut cx = FutureContext::from_waker(&waker);
+
+        // pending activity is never parked upon
+        let mut parked = Parked::new(&kernel.activity);
+        assert!(Pin::new(&mut parked).poll(&mut cx).is_ready());
+        assert!(kernel.activity.take());
+
+        // parked until notified, as by an interrupt
+        let mut parked = Parked::new(&kernel.activity);
+        assert!(Pin::new(&mut parked).poll(&mut cx).is_pending());
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+        kernel.interrupt(3);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert!(Pin::new(&mut parked).poll(&mut cx).is_ready());
+        assert!(kernel.activity.take());
+
+        // or until the sleep votes change
+        let mut parked = Parked::new(&kernel.activity);
+        assert!(Pin::new(&mut parked).poll(&mut cx).is_pending());
+        context.vote_sleep(SleepState::Stop, SleepState::Awake);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 2);
+        assert!(Pin::new(&mut parked).poll(&mut cx).is_ready());
+        assert!(!kernel.activity.take());
+    }
+
+    #[test]
+    fn activity_reaches_every_kernel() {
+        let first = leak(ConnectedKernel::with_backend(Device, leak(Host::new())));
+        let second = leak(ConnectedKernel::with_backend(Device, leak(Host::new())));
+        first.activity.list();
+        second.activity.list();
+        second.activity.list();
+        first.activity.take();
+        second.activity.take();
+
+        notify_activity();
+        assert!(first.activity.take());
+        assert!(second.activity.take());
+    }
+
+    struct Watched {
+        stalled: Cell<Option<&'static str>>,
+    }
+
+    impl Kernel for Watched {
+        fn start(&'static self, _ctx: &'static KernelContext<Self>) {}
+
+        fn on_watchdog_stall(&self, component: &'static str) {
+            self.stalled.set(Some(component));
+        }
+    }
+
+    #[test]
+    fn watchdog() {
+        let host = leak(Host::new());
+        let watchdog = leak(SimulatedWatchdog::new(host, Duration::from_millis(100)));
+        let kernel = leak(ConnectedKernel::with_backend(
+            Watched {
+                stalled: Cell::new(None),
+            },
+            host,
+        ));
+        kernel.set_watchdog(watchdog);
+        let context = leak(KernelContext::new(kernel));
+
+        let sensor = leak(Liveness::new("sensor"));
+        sensor.set_deadline(Duration::from_millis(50), host.now());
+        context.register_liveness(sensor);
+        let radio = leak(Liveness::new("radio"));
+        radio.set_deadline(Duration::from_millis(20), host.now());
+        context.register_liveness(radio);
+
+        for _ in 0..10 {
+            host.advance(Duration::from_millis(10));
+            sensor.checkin(host.now());
+            radio.checkin(host.now());
+            kernel.tick();
+        }
+        assert_eq!(watchdog.feeds(), 10);
+        assert!(!watchdog.has_expired());
+
+        // the radio stops checking in
+        for _ in 0..20 {
+            host.advance(Duration::from_millis(10));
+            sensor.checkin(host.now());
+            kernel.tick();
+        }
+        assert_eq!(watchdog.feeds(), 12);
+        assert_eq!(kernel.stalled(), Some("radio"));
+        assert_eq!(unsafe { &*kernel.kernel.get() }.stalled.get(), Some("radio"));
+        assert!(watchdog.has_expired());
+    }
+}
@@ -18,6 +18,38 @@ pub mod macros;
 /// Support for handling messages outbound from child to parent.
 pub mod handler;
 
+/// Support for placing the tree in static memory.
+pub mod cell;
+
+/// Hardware-specific support for the kernel.
+pub mod backend;
+
+/// Support for low-power operation while idle.
+pub mod power;
+
+/// Support for the kernel's time source.
+pub mod time;
+
+/// Support for feeding a hardware watchdog based upon component liveness.
+pub mod watchdog;
+
+/// Support for sensors and the periodic sampling of their readings.
+pub mod sensor;
+
+/// Reusable components for common peripherals.
+pub mod driver;
+
+/// Support for networking through TCP and UDP stacks.
+pub mod net;
+
+/// Mock peripherals for exercising drivers on the host backend, each split
+/// into a `'static` state, shared with the test, and a cheap handle moved
+/// into the driver under test.
+pub mod mock;
+
+#[cfg(test)]
+mod testing;
+
 mod fifo;
 
 /// Quick imports of common traits and structs.
@@ -39,6 +71,7 @@ pub mod prelude {
             InterruptContext,
         },
         handler::Handler,
+        cell::StaticCell,
         device,
     };
 }
@@ -47,56 +80,16 @@ pub mod prelude {
 mod tests {
     use crate::component::{Component, ComponentContext, ConnectedComponent};
     use crate::handler::Handler;
-    use crate::interrupt::{ConnectedInterrupt, Interrupt, InterruptContext};
-    use crate::kernel::{Kernel, KernelContext};
-    use drogue_async::task::spawn;
-
-    pub enum ButtonEvent {
-        Pressed,
-        Released,
-    }
-
-    pub struct Button {}
-
-    impl Interrupt for Button {
-        type OutboundMessage = ButtonEvent;
-
-        fn on_interrupt(&mut self, context: &InterruptContext<Self>) {
-            unimplemented!()
-        }
-
-        fn irq(&self) -> u8 {
-            unimplemented!()
-        }
-    }
-
-    pub enum LEDState {
-        On,
-        Off,
-    }
-
-    pub struct LED {}
-
-    impl Component for LED {
-        type InboundMessage = LEDState;
-        type OutboundMessage = ();
-
-        fn start(&mut self, ctx: &'static ComponentContext<Self>) {
-            spawn("led", async move {
-                loop {
-                    let message = ctx.receive().await;
-                    match message {
-                        LEDState::On => {}
-                        LEDState::Off => {}
-                    }
-                }
-            });
-        }
-    }
+    use crate::interrupt::ConnectedInterrupt;
+    use crate::kernel::{ConnectedKernel, Kernel, KernelContext};
+    use crate::driver::button::{Active, Button, ButtonEvent};
+    use crate::driver::led::{Led, LedMessage};
+    use crate::mock::{MockPin, MockPinState};
+    use crate::testing::leak;
 
     pub struct Flashlight {
-        led: ConnectedComponent<LED>,
-        button: ConnectedInterrupt<Button>,
+        led: ConnectedComponent<Led<MockPin>>,
+        button: ConnectedInterrupt<Button<MockPin>>,
     }
 
     pub enum FlashlightStatus {
@@ -118,11 +111,12 @@ mod tests {
         fn on_message(&mut self, message: ButtonEvent) {
             match message {
                 ButtonEvent::Pressed => {
-                    self.led.send(LEDState::On);
+                    self.led.send(LedMessage::On);
                 }
                 ButtonEvent::Released => {
-                    self.led.send(LEDState::Off);
+                    self.led.send(LedMessage::Off);
                 }
+                _ => {}
             }
         }
     }
@@ -147,9 +141,13 @@ mod tests {
     fn the_api() {
         use crate::device;
 
+        // neither may be shared between threads, so is leaked rather than placed in a `StaticCell`
+        let led_pin = leak(MockPinState::new(false));
+        let button_pin = leak(MockPinState::new(true));
+
         let flashlight = Flashlight {
-            led: ConnectedComponent::new(LED {}),
-            button: ConnectedInterrupt::new(Button {}),
+            led: ConnectedComponent::new(Led::new(led_pin.pin())),
+            button: ConnectedInterrupt::new(Button::new(button_pin.pin(), 6, Active::Low)),
         };
 
         let kernel = Device {
@@ -157,5 +155,8 @@ mod tests {
         };
 
         //device!( Device => kernel);
+
+        let device = leak(ConnectedKernel::new(kernel));
+        assert!(device.stalled().is_none());
     }
 }
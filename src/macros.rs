@@ -2,6 +2,11 @@
 ///
 /// Additionally, allocate some number of bytes for the async executor.
 ///
+/// The kernel is placed in a `StaticCell`, so no `static mut` is
+/// involved, or, upon the `host` with `std`, leaked, as it may not be
+/// shared between threads. Applications which need to construct the
+/// kernel themselves (for instance, in tests) may use `StaticCell` directly.
+///
 /// For example:
 ///
 /// ```
@@ -19,26 +24,166 @@
 ///
 /// device!( MyDevice => Kernel; 1024 );
 /// ```
+///
+/// An extended form accepts additional options, which must be
+/// provided in the following order, each being optional except `memory`:
+///
+/// * `memory`: bytes to allocate for the async executor.
+/// * `backend`: either `cortex_m` (the default) or `host`.
+/// * `interrupts`: named interrupts to bind to the kernel, in addition
+///   to the `DefaultHandler`. Both the PAC's `interrupt` attribute and
+///   `Interrupt` enum must be in scope.
+/// * `systick`: the core clock frequency in Hz, used to configure the
+///   `SysTick` as the kernel's millisecond time source.
+/// * `watchdog`: a `&'static dyn HardwareWatchdog` to be fed by the kernel.
+/// * `idle`: the `IdlePolicy` applied when the executor has nothing to do.
+/// * `panic`: either `halt` or `reset`, to provide a panic handler.
+///
+/// The `interrupts`, `systick`, `watchdog` and `panic` options only apply
+/// to the `cortex_m` backend. On the `host`, simulated time instead
+/// advances a millisecond upon each pass of the executor.
+///
+/// ```ignore
+/// use stm32l4xx_hal::stm32::{interrupt, Interrupt};
+///
+/// device!( MyDevice => kernel;
+///     memory: 1024,
+///     backend: cortex_m,
+///     interrupts: [EXTI15_10, USART2],
+///     systick: 80_000_000,
+///     watchdog: WATCHDOG.init(HalWatchdog::new(iwdg)),
+///     idle: IdlePolicy::Sleep(SleepMode::WaitForInterrupt),
+///     panic: reset,
+/// );
+/// ```
 #[macro_export]
 macro_rules! device {
-    ($ty:ty => $kernel:expr; $memory:literal) => {
+    (@start [] $($rest:tt)*) => {
+        $crate::device!(@start [cortex_m] $($rest)*)
+    };
+
+    (@start [cortex_m] $ty:ty, $kernel:expr, $memory:literal,
+        [$($irq:ident),*], [$($systick:expr)?], [$($watchdog:expr)?],
+        [$($idle:expr)?], [$($panic:ident)?]) => {
         $crate::kernel::init_executor!(memory: $memory);
-        static mut KERNEL: Option<$crate::kernel::ConnectedKernel<$ty>> = None;
+        static KERNEL: $crate::cell::StaticCell<$crate::kernel::ConnectedKernel<$ty>> =
+            $crate::cell::StaticCell::new();
 
-        let kernel = unsafe {
-            KERNEL.replace($crate::kernel::ConnectedKernel::new($kernel));
-            KERNEL.as_ref().unwrap()
-        };
+        let kernel = KERNEL.init($crate::kernel::ConnectedKernel::new($kernel));
+        $(
+            kernel.set_watchdog($watchdog);
+        )?
+        $(
+            kernel.set_idle_policy($idle);
+        )?
 
         kernel.start();
 
+        $(
+            $crate::backend::start_systick($systick);
+
+            #[exception]
+            fn SysTick() {
+                $crate::backend::systick();
+                if let Some(kernel) = KERNEL.try_get() {
+                    kernel.tick();
+                }
+            }
+        )?
+
         #[exception]
         fn DefaultHandler(irqn: i16) {
-            unsafe {
-                KERNEL.as_ref().unwrap().interrupt(irqn);
+            if let Some(kernel) = KERNEL.try_get() {
+                kernel.interrupt(irqn);
             }
         }
 
+        $(
+            #[interrupt]
+            fn $irq() {
+                if let Some(kernel) = KERNEL.try_get() {
+                    kernel.interrupt($crate::backend::irq_number(Interrupt::$irq));
+                }
+            }
+        )*
+
+        $(
+            $crate::device!(@panic $panic);
+        )?
+
+        $crate::kernel::run_forever()
+    };
+
+    (@start [host] $ty:ty, $kernel:expr, $memory:literal,
+        [], [], [], [$($idle:expr)?], []) => {
+        $crate::kernel::init_executor!(memory: $memory);
+
+        let host = $crate::__place!($crate::backend::Host, $crate::backend::Host::new());
+        let kernel = $crate::__place!(
+            $crate::kernel::ConnectedKernel<$ty>,
+            $crate::kernel::ConnectedKernel::with_backend($kernel, host)
+        );
+        $(
+            kernel.set_idle_policy($idle);
+        )?
+
+        host.attach(kernel);
+        kernel.start();
+        host.drive();
+
         $crate::kernel::run_forever()
     };
+
+    (@panic halt) => {
+        #[panic_handler]
+        fn panic(_info: &core::panic::PanicInfo) -> ! {
+            $crate::backend::halt()
+        }
+    };
+
+    (@panic reset) => {
+        #[panic_handler]
+        fn panic(_info: &core::panic::PanicInfo) -> ! {
+            $crate::backend::reset()
+        }
+    };
+
+    ($ty:ty => $kernel:expr; $memory:literal) => {
+        $crate::device!(@start [] $ty, $kernel, $memory, [], [], [], [], [])
+    };
+
+    ($ty:ty => $kernel:expr;
+        memory: $memory:literal
+        $(, backend: $backend:ident)?
+        $(, interrupts: [$($irq:ident),* $(,)?])?
+        $(, systick: $systick:expr)?
+        $(, watchdog: $watchdog:expr)?
+        $(, idle: $idle:expr)?
+        $(, panic: $panic:ident)?
+        $(,)?) => {
+        $crate::device!(@start [$($backend)?] $ty, $kernel, $memory,
+            [$($($irq),*)?], [$($systick)?], [$($watchdog)?], [$($idle)?], [$($panic)?])
+    };
+}
+
+/// Place a value for the remainder of the program, in a `StaticCell`.
+#[cfg(not(any(test, feature = "std")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __place {
+    ($ty:ty, $value:expr) => {{
+        static CELL: $crate::cell::StaticCell<$ty> = $crate::cell::StaticCell::new();
+        CELL.init($value)
+    }};
+}
+
+/// Place a value for the remainder of the program, leaked, as with `std`
+/// it may not be shared between threads.
+#[cfg(any(test, feature = "std"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __place {
+    ($ty:ty, $value:expr) => {
+        $crate::cell::leak::<$ty>($value)
+    };
 }
@@ -0,0 +1,150 @@
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use embedded_hal::adc::{Channel, OneShot};
+
+/// The number of channels of a `MockAdc`.
+const CHANNELS: usize = 8;
+
+/// A waveform replayed by a channel of a `MockAdc`, one value per conversion.
+#[derive(Copy, Clone, Debug)]
+pub enum Waveform {
+    Constant(u16),
+    /// Alternating between `low` and `high`, each for `half_period` conversions.
+    Square {
+        low: u16,
+        high: u16,
+        half_period: u32,
+    },
+    /// Rising linearly from `from` to `to` over `steps` conversions, then repeating.
+    Ramp {
+        from: u16,
+        to: u16,
+        steps: u32,
+    },
+    /// Scripted values, repeating once exhausted.
+    Samples(&'static [u16]),
+}
+
+impl Waveform {
+    fn at(&self, n: u32) -> u16 {
+        match *self {
+            Waveform::Constant(value) => value,
+            Waveform::Square {
+                low,
+                high,
+                half_period,
+            } => {
+                if (n / half_period.max(1)) % 2 == 1 {
+                    high
+                } else {
+                    low
+                }
+            }
+            Waveform::Ramp { from, to, steps } => {
+                let steps = steps.max(1);
+                let delta = (to as i32 - from as i32) * (n % steps) as i32 / steps as i32;
+                (from as i32 + delta) as u16
+            }
+            Waveform::Samples([]) => 0,
+            Waveform::Samples(samples) => samples[n as usize % samples.len()],
+        }
+    }
+}
+
+/// The shared state of a `MockAdc`, holding the waveform of each of its
+/// 8 channels and counting the conversions of each.
+pub struct MockAdcState {
+    waveforms: RefCell<[Waveform; CHANNELS]>,
+    conversions: RefCell<[u32; CHANNELS]>,
+    latency: Cell<u8>,
+    pending: Cell<u8>,
+}
+
+impl MockAdcState {
+    /// Create a new state, with every channel reading zero.
+    pub fn new() -> Self {
+        Self {
+            waveforms: RefCell::new([Waveform::Constant(0); CHANNELS]),
+            conversions: RefCell::new([0; CHANNELS]),
+            latency: Cell::new(0),
+            pending: Cell::new(0),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal `OneShot` trait.
+    pub fn adc(&'static self) -> MockAdc {
+        MockAdc { state: self }
+    }
+
+    /// Obtain a pin for the given channel.
+    pub fn pin(&self, channel: u8) -> MockAdcPin {
+        assert!((channel as usize) < CHANNELS);
+        MockAdcPin { channel }
+    }
+
+    /// Replay `waveform` on `channel`, from its start.
+    pub fn set_waveform(&self, channel: u8, waveform: Waveform) {
+        self.waveforms.borrow_mut()[channel as usize] = waveform;
+        self.conversions.borrow_mut()[channel as usize] = 0;
+    }
+
+    /// Require each conversion to be polled `polls` times before it completes.
+    pub fn set_latency(&self, polls: u8) {
+        self.latency.set(polls);
+    }
+
+    /// The number of conversions completed on `channel`.
+    pub fn conversions(&self, channel: u8) -> u32 {
+        self.conversions.borrow()[channel as usize]
+    }
+}
+
+impl Default for MockAdcState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A pin of a `MockAdc`.
+///
+/// Since every channel shares this type, the mock identifies the
+/// channel by the pin itself, and `Channel::channel()` is meaningless.
+#[derive(Copy, Clone, Debug)]
+pub struct MockAdcPin {
+    channel: u8,
+}
+
+impl Channel<MockAdc> for MockAdcPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        0
+    }
+}
+
+/// A mock ADC, for exercising ADC-based drivers on the host.
+///
+/// `MockAdc` is additionally the marker type identifying its channels.
+#[derive(Copy, Clone)]
+pub struct MockAdc {
+    state: &'static MockAdcState,
+}
+
+impl OneShot<MockAdc, u16, MockAdcPin> for MockAdc {
+    type Error = Infallible;
+
+    fn read(&mut self, pin: &mut MockAdcPin) -> nb::Result<u16, Self::Error> {
+        let state = self.state;
+        if state.pending.get() < state.latency.get() {
+            state.pending.set(state.pending.get() + 1);
+            return Err(nb::Error::WouldBlock);
+        }
+        state.pending.set(0);
+
+        let channel = pin.channel as usize;
+        let mut conversions = state.conversions.borrow_mut();
+        let value = state.waveforms.borrow()[channel].at(conversions[channel]);
+        conversions[channel] += 1;
+        Ok(value)
+    }
+}
@@ -0,0 +1,168 @@
+use crate::driver::i2c::I2cError;
+use core::cell::{Cell, RefCell};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use heapless::{consts::*, Vec};
+
+struct Device {
+    address: u8,
+    registers: [u8; 256],
+    pointer: u8,
+    increment_flag: u8,
+}
+
+/// The shared state of a `MockI2c`, holding a map of devices by address,
+/// each exposing 256 byte-wide registers.
+///
+/// As is common for sensors, the first byte written to a device selects a
+/// register, and each byte subsequently written or read advances to the next.
+pub struct MockI2cState {
+    devices: RefCell<Vec<Device, U8>>,
+    failure: Cell<Option<I2cError>>,
+    recoveries: Cell<usize>,
+}
+
+impl MockI2cState {
+    pub fn new() -> Self {
+        Self {
+            devices: RefCell::new(Vec::new()),
+            failure: Cell::new(None),
+            recoveries: Cell::new(0),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal I2C traits.
+    pub fn i2c(&'static self) -> MockI2c {
+        MockI2c { state: self }
+    }
+
+    /// Attach a device at `address`, with every register zeroed.
+    pub fn add_device(&self, address: u8) {
+        let device = Device {
+            address,
+            registers: [0; 256],
+            pointer: 0,
+            increment_flag: 0,
+        };
+        self.devices.borrow_mut().push(device).ok().unwrap();
+    }
+
+    /// Ignore `flag` within the register selected by the first byte
+    /// written to the device at `address`, for devices which require
+    /// it to be set to advance through multiple registers.
+    pub fn set_increment_flag(&self, address: u8, flag: u8) {
+        self.device(address, |device| device.increment_flag = flag)
+            .unwrap()
+    }
+
+    /// Set a register of the device at `address`.
+    pub fn set_register(&self, address: u8, register: u8, value: u8) {
+        self.device(address, |device| {
+            device.registers[register as usize] = value
+        })
+        .unwrap()
+    }
+
+    /// The value of a register of the device at `address`.
+    pub fn register(&self, address: u8, register: u8) -> u8 {
+        self.device(address, |device| device.registers[register as usize])
+            .unwrap()
+    }
+
+    /// Fail the next request with `error`, regardless of address.
+    pub fn fail_next(&self, error: I2cError) {
+        self.failure.set(Some(error));
+    }
+
+    /// The number of times `MockI2c::recover(...)` has been invoked.
+    pub fn recoveries(&self) -> usize {
+        self.recoveries.get()
+    }
+
+    fn request<R>(&self, address: u8, f: impl FnOnce(&mut Device) -> R) -> Result<R, I2cError> {
+        match self.failure.take() {
+            Some(error) => Err(error),
+            None => self.device(address, f),
+        }
+    }
+
+    fn device<R>(&self, address: u8, f: impl FnOnce(&mut Device) -> R) -> Result<R, I2cError> {
+        let mut devices = self.devices.borrow_mut();
+        match devices.iter_mut().find(|device| device.address == address) {
+            Some(device) => Ok(f(device)),
+            None => Err(I2cError::Nack),
+        }
+    }
+}
+
+impl Default for MockI2cState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device {
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some((register, data)) = bytes.split_first() {
+            self.pointer = *register & !self.increment_flag;
+            for b in data {
+                self.registers[self.pointer as usize] = *b;
+                self.pointer = self.pointer.wrapping_add(1);
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for b in buffer {
+            *b = self.registers[self.pointer as usize];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+/// A mock I2C peripheral, for exercising I2C-based drivers on the host.
+///
+/// Requests to an address without a device are not acknowledged.
+#[derive(Copy, Clone)]
+pub struct MockI2c {
+    state: &'static MockI2cState,
+}
+
+impl MockI2c {
+    /// Simulate recovering a faulted bus, suitable as the `recover`
+    /// function of an `I2cBus`.
+    pub fn recover(&mut self) {
+        self.state.recoveries.set(self.state.recoveries.get() + 1);
+    }
+}
+
+impl Write for MockI2c {
+    type Error = I2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.state.request(address, |device| device.write(bytes))
+    }
+}
+
+impl Read for MockI2c {
+    type Error = I2cError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.state.request(address, |device| device.read(buffer))
+    }
+}
+
+impl WriteRead for MockI2c {
+    type Error = I2cError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.state.request(address, |device| {
+            device.write(bytes);
+            device.read(buffer);
+        })
+    }
+}
@@ -0,0 +1,116 @@
+use crate::net::tcpip::MacIrq;
+use core::cell::{Cell, RefCell};
+use heapless::spsc::Queue;
+use heapless::{consts::*, Vec};
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+
+/// The largest Ethernet frame, excluding its frame check sequence.
+const MTU: usize = 1514;
+
+type Frame = Vec<u8, U2048>;
+
+/// The shared state of a `MockMac`, an in-memory loopback device through
+/// which every frame transmitted is received in turn.
+pub struct MockMacState {
+    frames: RefCell<Queue<Frame, U8>>,
+    transmitted: Cell<usize>,
+    pending_cleared: Cell<usize>,
+}
+
+impl MockMacState {
+    pub fn new() -> Self {
+        Self {
+            frames: RefCell::new(Queue::new()),
+            transmitted: Cell::new(0),
+            pending_cleared: Cell::new(0),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing smoltcp's `Device`.
+    pub fn mac(&'static self) -> MockMac {
+        MockMac { state: self }
+    }
+
+    /// The number of frames transmitted, and so looped back, so far.
+    pub fn transmitted(&self) -> usize {
+        self.transmitted.get()
+    }
+
+    /// The number of times the MAC's interrupt pending flags have been cleared.
+    pub fn pending_cleared(&self) -> usize {
+        self.pending_cleared.get()
+    }
+}
+
+impl Default for MockMacState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mock Ethernet MAC looping frames back, for exercising the
+/// `TcpIpStack` on the host. Frames beyond the 8 awaiting reception
+/// are dropped, as by a real MAC's exhausted descriptors.
+#[derive(Copy, Clone)]
+pub struct MockMac {
+    state: &'static MockMacState,
+}
+
+impl phy::Device for MockMac {
+    type RxToken<'a> = MockRxToken;
+    type TxToken<'a> = MockTxToken;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(MockRxToken, MockTxToken)> {
+        let frame = self.state.frames.borrow_mut().dequeue()?;
+        Some((MockRxToken(frame), MockTxToken(self.state)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<MockTxToken> {
+        Some(MockTxToken(self.state))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ethernet;
+        capabilities.max_transmission_unit = MTU;
+        capabilities
+    }
+}
+
+impl MacIrq for MockMac {
+    fn clear_pending(&mut self) {
+        self.state
+            .pending_cleared
+            .set(self.state.pending_cleared.get() + 1);
+    }
+}
+
+#[doc(hidden)]
+pub struct MockRxToken(Frame);
+
+impl phy::RxToken for MockRxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+#[doc(hidden)]
+pub struct MockTxToken(&'static MockMacState);
+
+impl phy::TxToken for MockTxToken {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = Frame::new();
+        frame.resize(len.min(MTU), 0).ok();
+        let result = f(&mut frame);
+        self.0.transmitted.set(self.0.transmitted.get() + 1);
+        self.0.frames.borrow_mut().enqueue(frame).ok();
+        result
+    }
+}
@@ -0,0 +1,21 @@
+mod adc;
+mod i2c;
+#[cfg(feature = "tcpip")]
+mod mac;
+mod modem;
+mod pin;
+mod pwm;
+mod radio;
+mod serial;
+mod spi;
+
+pub use adc::{MockAdc, MockAdcPin, MockAdcState, Waveform};
+pub use i2c::{MockI2c, MockI2cState};
+#[cfg(feature = "tcpip")]
+pub use mac::{MockMac, MockMacState};
+pub use modem::{MockModem, MockModemState};
+pub use pin::{MockPin, MockPinState};
+pub use pwm::{MockPwm, MockPwmState};
+pub use radio::{MockPacket, MockRadio, MockRadioState, MOCK_RSSI, MOCK_SNR};
+pub use serial::{MockSerial, MockSerialState};
+pub use spi::{MockSpi, MockSpiState};
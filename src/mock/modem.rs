@@ -0,0 +1,115 @@
+use crate::driver::serial::SerialError;
+use core::cell::{Cell, RefCell};
+use embedded_hal::serial;
+use heapless::spsc::Queue;
+use heapless::{consts::*, Vec};
+
+/// A command expected by a `MockModem`, and its reply.
+type Exchange = (&'static [u8], &'static [u8]);
+
+/// The shared state of a `MockModem`, replaying a script of exchanges:
+/// once the bytes written match the next expected command, its reply is
+/// queued for reception.
+pub struct MockModemState {
+    script: RefCell<Vec<Exchange, U16>>,
+    next: Cell<usize>,
+    written: RefCell<Vec<u8, U256>>,
+    rx: RefCell<Queue<u8, U512>>,
+}
+
+impl MockModemState {
+    pub fn new() -> Self {
+        Self {
+            script: RefCell::new(Vec::new()),
+            next: Cell::new(0),
+            written: RefCell::new(Vec::new()),
+            rx: RefCell::new(Queue::new()),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal serial traits.
+    pub fn modem(&'static self) -> MockModem {
+        MockModem { state: self }
+    }
+
+    /// Expect `command` to be written next, replying with `reply`.
+    pub fn expect(&self, command: &'static [u8], reply: &'static [u8]) {
+        self.script
+            .borrow_mut()
+            .push((command, reply))
+            .expect("script too long");
+    }
+
+    /// Simulate the unsolicited reception of `data`.
+    pub fn inject(&self, data: &[u8]) {
+        let mut rx = self.rx.borrow_mut();
+        for b in data {
+            rx.enqueue(*b).expect("reception overflowed");
+        }
+    }
+
+    /// Determine if every expected command has been written.
+    pub fn is_done(&self) -> bool {
+        self.next.get() == self.script.borrow().len()
+    }
+
+    fn write(&self, byte: u8) {
+        let script = self.script.borrow();
+        let (command, reply) = match script.get(self.next.get()) {
+            Some(exchange) => *exchange,
+            None => panic!("unexpected write of {:?}", byte as char),
+        };
+        let mut written = self.written.borrow_mut();
+        written.push(byte).expect("command too long");
+        if !command.starts_with(&written) {
+            panic!(
+                "unexpected command {:?}, expected {:?}",
+                core::str::from_utf8(&written),
+                core::str::from_utf8(command)
+            );
+        }
+        if written.len() == command.len() {
+            *written = Vec::new();
+            self.next.set(self.next.get() + 1);
+            self.inject(reply);
+        }
+    }
+}
+
+impl Default for MockModemState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mock modem attached to a serial port, for exercising drivers
+/// of AT-command modules on the host.
+#[derive(Copy, Clone)]
+pub struct MockModem {
+    state: &'static MockModemState,
+}
+
+impl serial::Read<u8> for MockModem {
+    type Error = SerialError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.state
+            .rx
+            .borrow_mut()
+            .dequeue()
+            .ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl serial::Write<u8> for MockModem {
+    type Error = SerialError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.state.write(word);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
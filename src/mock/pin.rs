@@ -0,0 +1,125 @@
+use crate::driver::exti::ExtiPin;
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+use heapless::{consts::*, Vec};
+
+/// The shared state of a `MockPin`, recording each transition
+/// driven through it.
+pub struct MockPinState {
+    high: Cell<bool>,
+    transitions: RefCell<Vec<bool, U64>>,
+    pending_cleared: Cell<usize>,
+}
+
+impl MockPinState {
+    /// Create a new pin state, initially at the given level.
+    pub fn new(high: bool) -> Self {
+        Self {
+            high: Cell::new(high),
+            transitions: RefCell::new(Vec::new()),
+            pending_cleared: Cell::new(0),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal pin traits.
+    pub fn pin(&'static self) -> MockPin {
+        MockPin { state: self }
+    }
+
+    /// The current level of the pin.
+    pub fn is_high(&self) -> bool {
+        self.high.get()
+    }
+
+    /// The levels driven through the pin, in order. Setting
+    /// the pin to its current level is not a transition.
+    pub fn transitions(&self) -> Vec<bool, U64> {
+        self.transitions.borrow().clone()
+    }
+
+    /// Forget all transitions recorded so far.
+    pub fn clear(&self) {
+        *self.transitions.borrow_mut() = Vec::new();
+    }
+
+    /// Simulate an external signal driving the pin high.
+    pub fn set_high(&self) {
+        self.set(true)
+    }
+
+    /// Simulate an external signal driving the pin low.
+    pub fn set_low(&self) {
+        self.set(false)
+    }
+
+    /// The number of times the pin's EXTI pending flag has been cleared.
+    pub fn pending_cleared(&self) -> usize {
+        self.pending_cleared.get()
+    }
+
+    fn set(&self, high: bool) {
+        if self.high.replace(high) != high {
+            self.transitions.borrow_mut().push(high).ok();
+        }
+    }
+}
+
+/// A mock pin, for exercising pin-based drivers on the host.
+#[derive(Copy, Clone)]
+pub struct MockPin {
+    state: &'static MockPinState,
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.state.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.state.set(true);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for MockPin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.state.is_high())
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.state.is_high())
+    }
+}
+
+impl ToggleableOutputPin for MockPin {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        self.state.set(!self.state.is_high());
+        Ok(())
+    }
+}
+
+impl InputPin for MockPin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.state.is_high())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.state.is_high())
+    }
+}
+
+impl ExtiPin for MockPin {
+    fn clear_pending(&mut self) {
+        self.state
+            .pending_cleared
+            .set(self.state.pending_cleared.get() + 1);
+    }
+}
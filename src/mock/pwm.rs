@@ -0,0 +1,92 @@
+use core::cell::{Cell, RefCell};
+use embedded_hal::Pwm;
+
+/// The number of channels of a `MockPwm`.
+const CHANNELS: usize = 4;
+
+/// The clock of a `MockPwm`, from which its maximum duty is derived.
+const CLOCK_HZ: u32 = 1_000_000;
+
+/// The shared state of a `MockPwm`, with 4 channels sharing a
+/// frequency, as though driven by a timer clocked at 1MHz.
+pub struct MockPwmState {
+    frequency: Cell<u32>,
+    duty: RefCell<[u16; CHANNELS]>,
+    enabled: RefCell<[bool; CHANNELS]>,
+}
+
+impl MockPwmState {
+    /// Create a new state at the given frequency, in Hz, with
+    /// every channel disabled.
+    pub fn new(frequency: u32) -> Self {
+        Self {
+            frequency: Cell::new(frequency),
+            duty: RefCell::new([0; CHANNELS]),
+            enabled: RefCell::new([false; CHANNELS]),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal `Pwm` trait.
+    pub fn pwm(&'static self) -> MockPwm {
+        MockPwm { state: self }
+    }
+
+    /// The current frequency, in Hz.
+    pub fn frequency(&self) -> u32 {
+        self.frequency.get()
+    }
+
+    /// The raw duty of `channel`, out of the current maximum duty.
+    pub fn duty(&self, channel: u8) -> u16 {
+        self.duty.borrow()[channel as usize]
+    }
+
+    pub fn is_enabled(&self, channel: u8) -> bool {
+        self.enabled.borrow()[channel as usize]
+    }
+}
+
+/// A mock PWM peripheral, for exercising PWM-based drivers on the host.
+///
+/// Channels are numbered from zero, and the period is set as a frequency in Hz.
+#[derive(Copy, Clone)]
+pub struct MockPwm {
+    state: &'static MockPwmState,
+}
+
+impl Pwm for MockPwm {
+    type Channel = u8;
+    type Time = u32;
+    type Duty = u16;
+
+    fn disable(&mut self, channel: u8) {
+        self.state.enabled.borrow_mut()[channel as usize] = false;
+    }
+
+    fn enable(&mut self, channel: u8) {
+        self.state.enabled.borrow_mut()[channel as usize] = true;
+    }
+
+    fn get_period(&self) -> u32 {
+        self.state.frequency.get()
+    }
+
+    fn get_duty(&self, channel: u8) -> u16 {
+        self.state.duty(channel)
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        (CLOCK_HZ / self.state.frequency.get().max(1)).min(u16::MAX as u32) as u16
+    }
+
+    fn set_duty(&mut self, channel: u8, duty: u16) {
+        self.state.duty.borrow_mut()[channel as usize] = duty;
+    }
+
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<u32>,
+    {
+        self.state.frequency.set(period.into());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::driver::lora::{LoRaConfig, Radio, Received};
+use crate::time::Duration;
+use core::cell::RefCell;
+use core::convert::Infallible;
+use heapless::spsc::Queue;
+use heapless::{consts::*, Vec};
+
+/// The payload of a packet transmitted or received by a `MockRadio`.
+pub type MockPacket = Vec<u8, U256>;
+
+/// The signal strength of every packet received, in dBm.
+pub const MOCK_RSSI: i16 = -80;
+
+/// The signal to noise ratio of every packet received, in dB.
+pub const MOCK_SNR: i8 = 7;
+
+/// The shared state of a `MockRadio`, recording each packet transmitted,
+/// and holding packets to be received, each upon the first listening with
+/// a matching frequency, spreading factor, bandwidth and IQ inversion.
+///
+/// Packets are received at once, or not at all, regardless of the window
+/// listened within, so a stand-in for the far end of the link, such as a
+/// LoRaWAN network server, may respond to each packet transmitted before
+/// the receiving radio listens for the response.
+pub struct MockRadioState {
+    transmitted: RefCell<Queue<(LoRaConfig, MockPacket), U8>>,
+    pending: RefCell<Vec<(LoRaConfig, MockPacket), U4>>,
+    listened: RefCell<Queue<LoRaConfig, U16>>,
+}
+
+impl MockRadioState {
+    pub fn new() -> Self {
+        Self {
+            transmitted: RefCell::new(Queue::new()),
+            pending: RefCell::new(Vec::new()),
+            listened: RefCell::new(Queue::new()),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the `Radio` trait.
+    pub fn radio(&'static self) -> MockRadio {
+        MockRadio { state: self }
+    }
+
+    /// Take the earliest packet transmitted, and the configuration
+    /// with which it was, not yet taken.
+    pub fn take_transmitted(&self) -> Option<(LoRaConfig, MockPacket)> {
+        self.transmitted.borrow_mut().dequeue()
+    }
+
+    /// Hold `data` to be received by the next listening matching `config`.
+    pub fn respond(&self, config: &LoRaConfig, data: &[u8]) {
+        let packet = (*config, Vec::from_slice(data).expect("packet too long"));
+        self.pending
+            .borrow_mut()
+            .push(packet)
+            .expect("too many packets pending");
+    }
+
+    /// Take the configuration of the earliest listening not yet taken,
+    /// of the most recent 16.
+    pub fn take_listened(&self) -> Option<LoRaConfig> {
+        self.listened.borrow_mut().dequeue()
+    }
+
+    fn transmit(&self, config: &LoRaConfig, data: &[u8]) {
+        let packet = (*config, Vec::from_slice(data).expect("packet too long"));
+        self.transmitted
+            .borrow_mut()
+            .enqueue(packet)
+            .expect("too many packets transmitted");
+    }
+
+    fn receive(&self, config: &LoRaConfig, buf: &mut [u8]) -> Option<Received> {
+        {
+            let mut listened = self.listened.borrow_mut();
+            if listened.len() == listened.capacity() {
+                listened.dequeue();
+            }
+            listened.enqueue(*config).ok();
+        }
+
+        let mut pending = self.pending.borrow_mut();
+        let index = pending.iter().position(|(c, _)| {
+            c.frequency == config.frequency
+                && c.spreading_factor == config.spreading_factor
+                && c.bandwidth == config.bandwidth
+                && c.invert_iq == config.invert_iq
+        })?;
+        let (_, packet) = pending.swap_remove(index);
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Some(Received {
+            len,
+            rssi: MOCK_RSSI,
+            snr: MOCK_SNR,
+        })
+    }
+}
+
+impl Default for MockRadioState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mock LoRa radio, for exercising radio-based components on the host.
+#[derive(Copy, Clone)]
+pub struct MockRadio {
+    state: &'static MockRadioState,
+}
+
+impl Radio for MockRadio {
+    type Error = Infallible;
+
+    async fn transmit(&self, config: &LoRaConfig, data: &[u8]) -> Result<(), Infallible> {
+        self.state.transmit(config, data);
+        Ok(())
+    }
+
+    async fn receive(
+        &self,
+        config: &LoRaConfig,
+        buf: &mut [u8],
+        _window: Duration,
+    ) -> Result<Option<Received>, Infallible> {
+        Ok(self.state.receive(config, buf))
+    }
+}
@@ -0,0 +1,100 @@
+use crate::driver::serial::SerialError;
+use core::cell::RefCell;
+use embedded_hal::serial;
+use heapless::spsc::Queue;
+use heapless::{consts::*, Vec};
+
+/// The shared state of a `MockSerial`, holding bytes (and errors)
+/// to be received, and recording bytes written.
+pub struct MockSerialState {
+    loopback: bool,
+    rx: RefCell<Queue<Result<u8, SerialError>, U256>>,
+    tx: RefCell<Vec<u8, U256>>,
+}
+
+impl MockSerialState {
+    pub fn new() -> Self {
+        Self::with_loopback(false)
+    }
+
+    /// Create a new state in which every byte written is also received.
+    pub fn loopback() -> Self {
+        Self::with_loopback(true)
+    }
+
+    fn with_loopback(loopback: bool) -> Self {
+        Self {
+            loopback,
+            rx: RefCell::new(Queue::new()),
+            tx: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal serial traits.
+    pub fn serial(&'static self) -> MockSerial {
+        MockSerial { state: self }
+    }
+
+    /// Simulate the reception of `data`.
+    pub fn inject(&self, data: &[u8]) {
+        let mut rx = self.rx.borrow_mut();
+        for b in data {
+            rx.enqueue(Ok(*b)).ok().unwrap();
+        }
+    }
+
+    /// Simulate a reception error, reported after any bytes already injected.
+    pub fn inject_error(&self, error: SerialError) {
+        self.rx.borrow_mut().enqueue(Err(error)).ok().unwrap();
+    }
+
+    /// The bytes written so far.
+    pub fn written(&self) -> Vec<u8, U256> {
+        self.tx.borrow().clone()
+    }
+
+    /// Forget the bytes written so far.
+    pub fn clear(&self) {
+        *self.tx.borrow_mut() = Vec::new();
+    }
+}
+
+impl Default for MockSerialState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mock serial port, for exercising serial-based drivers on the host.
+#[derive(Copy, Clone)]
+pub struct MockSerial {
+    state: &'static MockSerialState,
+}
+
+impl serial::Read<u8> for MockSerial {
+    type Error = SerialError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.state.rx.borrow_mut().dequeue() {
+            Some(Ok(b)) => Ok(b),
+            Some(Err(e)) => Err(nb::Error::Other(e)),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl serial::Write<u8> for MockSerial {
+    type Error = SerialError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.state.tx.borrow_mut().push(word).ok().unwrap();
+        if self.state.loopback {
+            self.state.inject(&[word]);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
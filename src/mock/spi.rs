@@ -0,0 +1,89 @@
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use heapless::spsc::Queue;
+use heapless::{consts::*, Vec};
+
+struct Exchange {
+    sent: Vec<u8, U32>,
+    reply: Vec<u8, U32>,
+}
+
+/// The shared state of a `MockSpi`, holding a script of
+/// expected exchanges to be replayed in order.
+pub struct MockSpiState {
+    script: RefCell<Queue<Exchange, U64>>,
+}
+
+impl MockSpiState {
+    pub fn new() -> Self {
+        Self {
+            script: RefCell::new(Queue::new()),
+        }
+    }
+
+    /// Obtain a handle to this state, implementing the embedded-hal SPI traits.
+    pub fn spi(&'static self) -> MockSpi {
+        MockSpi { state: self }
+    }
+
+    /// Expect the next exchange to send `sent`, replying with `reply`.
+    ///
+    /// For a transfer, `reply` must be as long as `sent`. For a write,
+    /// `reply` is ignored.
+    pub fn expect(&self, sent: &[u8], reply: &[u8]) {
+        let exchange = Exchange {
+            sent: Vec::from_slice(sent).unwrap(),
+            reply: Vec::from_slice(reply).unwrap(),
+        };
+        self.script.borrow_mut().enqueue(exchange).ok().unwrap();
+    }
+
+    /// Determine if every expected exchange has been replayed.
+    pub fn is_done(&self) -> bool {
+        self.script.borrow().is_empty()
+    }
+
+    fn next(&self, sent: &[u8]) -> Exchange {
+        let exchange = self
+            .script
+            .borrow_mut()
+            .dequeue()
+            .expect("unexpected SPI exchange");
+        assert_eq!(&exchange.sent[..], sent, "unexpected SPI words sent");
+        exchange
+    }
+}
+
+impl Default for MockSpiState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mock SPI peripheral, for exercising SPI-based drivers on the host.
+///
+/// Any exchange not matching the script panics.
+#[derive(Copy, Clone)]
+pub struct MockSpi {
+    state: &'static MockSpiState,
+}
+
+impl Transfer<u8> for MockSpi {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        let exchange = self.state.next(words);
+        words.copy_from_slice(&exchange.reply);
+        Ok(words)
+    }
+}
+
+impl Write<u8> for MockSpi {
+    type Error = Infallible;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.state.next(words);
+        Ok(())
+    }
+}
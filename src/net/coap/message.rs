@@ -0,0 +1,241 @@
+use heapless::{consts::*, String, Vec};
+
+/// The longest message sent or received.
+pub(crate) const MAX_MESSAGE: usize = 512;
+
+pub(crate) type Encoded = Vec<u8, U512>;
+
+pub(crate) const OBSERVE: u16 = 6;
+pub(crate) const URI_PATH: u16 = 11;
+pub(crate) const CONTENT_FORMAT: u16 = 12;
+
+const PAYLOAD_MARKER: u8 = 0xff;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Type {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+/// A message received.
+#[derive(Debug)]
+pub(crate) struct Message<'a> {
+    pub(crate) ty: Type,
+    pub(crate) code: u8,
+    pub(crate) id: u16,
+    pub(crate) token: &'a [u8],
+    pub(crate) observe: Option<u32>,
+    /// The `Uri-Path` options, joined by `/`.
+    pub(crate) path: String<U64>,
+    pub(crate) content_format: Option<u16>,
+    /// Whether an option was neither understood nor elective.
+    pub(crate) unknown_critical: bool,
+    pub(crate) payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Decode `data`, or `None` should it be malformed.
+    pub(crate) fn decode(data: &'a [u8]) -> Option<Self> {
+        let (&first, rest) = data.split_first()?;
+        if first >> 6 != 1 {
+            return None;
+        }
+        let ty = match (first >> 4) & 0x03 {
+            0 => Type::Confirmable,
+            1 => Type::NonConfirmable,
+            2 => Type::Acknowledgement,
+            _ => Type::Reset,
+        };
+        let token_len = (first & 0x0f) as usize;
+        if token_len > 8 || rest.len() < 3 + token_len {
+            return None;
+        }
+        let mut message = Message {
+            ty,
+            code: rest[0],
+            id: u16::from_be_bytes([rest[1], rest[2]]),
+            token: &rest[3..3 + token_len],
+            observe: None,
+            path: String::new(),
+            content_format: None,
+            unknown_critical: false,
+            payload: &[],
+        };
+
+        let mut rest = &rest[3 + token_len..];
+        let mut number: u16 = 0;
+        while let Some((&byte, after)) = rest.split_first() {
+            if byte == PAYLOAD_MARKER {
+                if after.is_empty() {
+                    return None;
+                }
+                message.payload = after;
+                break;
+            }
+            let (delta, after) = extended(byte >> 4, after)?;
+            let (len, after) = extended(byte & 0x0f, after)?;
+            let value = after.get(..len as usize)?;
+            rest = &after[len as usize..];
+            number = number.checked_add(delta)?;
+            match number {
+                OBSERVE => message.observe = Some(uint(value)?),
+                URI_PATH => {
+                    if !message.path.is_empty() {
+                        message.path.push('/').ok()?;
+                    }
+                    message
+                        .path
+                        .push_str(core::str::from_utf8(value).ok()?)
+                        .ok()?;
+                }
+                CONTENT_FORMAT => message.content_format = Some(uint(value)? as u16),
+                // odd options are critical
+                _ if number & 1 == 1 => message.unknown_critical = true,
+                _ => {}
+            }
+        }
+        Some(message)
+    }
+}
+
+/// Decode the extended option delta or length following the nibble `value`.
+fn extended(value: u8, data: &[u8]) -> Option<(u16, &[u8])> {
+    match value {
+        13 => Some((*data.first()? as u16 + 13, &data[1..])),
+        14 => {
+            let value = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+            Some((value.checked_add(269)?, &data[2..]))
+        }
+        15 => None,
+        _ => Some((value as u16, data)),
+    }
+}
+
+fn uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+}
+
+/// Builds a message, whose options must be added in ascending order.
+pub(crate) struct Builder {
+    encoded: Encoded,
+    number: u16,
+    overflowed: bool,
+}
+
+impl Builder {
+    pub(crate) fn new(ty: Type, code: u8, id: u16, token: &[u8]) -> Self {
+        let mut builder = Self {
+            encoded: Encoded::new(),
+            number: 0,
+            overflowed: false,
+        };
+        let header = [0x40 | (ty as u8) << 4 | token.len() as u8, code];
+        builder.extend(&header);
+        builder.extend(&id.to_be_bytes());
+        builder.extend(token);
+        builder
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        if self.encoded.extend_from_slice(data).is_err() {
+            self.overflowed = true;
+        }
+    }
+
+    pub(crate) fn option(mut self, number: u16, value: &[u8]) -> Self {
+        let delta = number - self.number;
+        self.number = number;
+        let (delta_nibble, delta_ext) = nibble(delta);
+        let (len_nibble, len_ext) = nibble(value.len() as u16);
+        self.extend(&[delta_nibble << 4 | len_nibble]);
+        self.extend(&delta_ext);
+        self.extend(&len_ext);
+        self.extend(value);
+        self
+    }
+
+    /// Add an option whose value is an unsigned integer, in as few bytes as possible.
+    pub(crate) fn uint_option(self, number: u16, value: u32) -> Self {
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        self.option(number, &bytes[skip..])
+    }
+
+    pub(crate) fn path(mut self, path: &str) -> Self {
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            self = self.option(URI_PATH, segment.as_bytes());
+        }
+        self
+    }
+
+    /// Complete the message with `payload`, or `None` should it be too long.
+    pub(crate) fn payload(mut self, payload: &[u8]) -> Option<Encoded> {
+        if !payload.is_empty() {
+            self.extend(&[PAYLOAD_MARKER]);
+            self.extend(payload);
+        }
+        if self.overflowed {
+            None
+        } else {
+            Some(self.encoded)
+        }
+    }
+}
+
+/// Encode an option delta or length as a nibble, and its extension.
+fn nibble(value: u16) -> (u8, Vec<u8, U2>) {
+    let mut ext = Vec::new();
+    match value {
+        0..=12 => (value as u8, ext),
+        13..=268 => {
+            ext.push((value - 13) as u8).ok();
+            (13, ext)
+        }
+        _ => {
+            ext.extend_from_slice(&(value - 269).to_be_bytes()).ok();
+            (14, ext)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Builder, Message, Type, CONTENT_FORMAT, OBSERVE};
+
+    #[test]
+    fn messages() {
+        let encoded = Builder::new(Type::Confirmable, 0x01, 0x1234, &[0xab])
+            .uint_option(OBSERVE, 0)
+            .path("/sensors/temperature")
+            .uint_option(CONTENT_FORMAT, 50)
+            .option(2049, b"")
+            .payload(b"{}")
+            .unwrap();
+        assert_eq!(
+            &encoded[..],
+            &b"\x41\x01\x12\x34\xab\x60\x57sensors\x0btemperature\x11\x32\xe0\x06\xe8\xff{}"[..]
+        );
+
+        let message = Message::decode(&encoded).unwrap();
+        assert_eq!(message.ty, Type::Confirmable);
+        assert_eq!(message.code, 0x01);
+        assert_eq!(message.id, 0x1234);
+        assert_eq!(message.token, &[0xab]);
+        assert_eq!(message.observe, Some(0));
+        assert_eq!(message.path.as_str(), "sensors/temperature");
+        assert_eq!(message.content_format, Some(50));
+        assert!(message.unknown_critical);
+        assert_eq!(message.payload, b"{}");
+
+        assert!(Message::decode(b"\x40\x01\x12").is_none());
+        assert!(Message::decode(b"\x40\x01\x00\x01\xff").is_none());
+        assert!(Builder::new(Type::Reset, 0, 0, &[])
+            .payload(&[0; 512])
+            .is_none());
+    }
+}
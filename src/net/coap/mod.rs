@@ -0,0 +1,947 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::context::UpstreamContext;
+use crate::driver::lock::{BusLock, Flag};
+use crate::net::{SocketAddress, UdpStack};
+use crate::time::{Delay, Duration, WithTimeout};
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use heapless::{consts::*, Vec};
+
+/// Encoding and decoding of CoAP messages, as of RFC 7252.
+mod message;
+
+use message::{Builder, Encoded, Message, Type, CONTENT_FORMAT, MAX_MESSAGE, OBSERVE};
+
+/// The least time awaited for a confirmable message to be acknowledged,
+/// to which up to another second is added, then doubled upon each retransmission.
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+const MAX_RETRANSMIT: usize = 4;
+
+/// The time awaited for a response, once its request has been acknowledged.
+const SEPARATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The time awaited before trying again to open the socket, or receive upon it.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The observe sequence numbers are 24 bits.
+const SEQUENCE_MASK: u32 = 0xff_ffff;
+
+/// The method of a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get = 1,
+    Post = 2,
+    Put = 3,
+    Delete = 4,
+}
+
+impl Method {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Method::Get),
+            2 => Some(Method::Post),
+            3 => Some(Method::Put),
+            4 => Some(Method::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// The code of a response, of a class and a detail, written as `2.05`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Code(pub u8);
+
+impl Code {
+    pub const CREATED: Code = Code::new(2, 1);
+    pub const DELETED: Code = Code::new(2, 2);
+    pub const VALID: Code = Code::new(2, 3);
+    pub const CHANGED: Code = Code::new(2, 4);
+    pub const CONTENT: Code = Code::new(2, 5);
+    pub const BAD_REQUEST: Code = Code::new(4, 0);
+    pub const BAD_OPTION: Code = Code::new(4, 2);
+    pub const NOT_FOUND: Code = Code::new(4, 4);
+    pub const METHOD_NOT_ALLOWED: Code = Code::new(4, 5);
+    pub const INTERNAL_SERVER_ERROR: Code = Code::new(5, 0);
+
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Code(class << 5 | detail)
+    }
+
+    pub fn class(&self) -> u8 {
+        self.0 >> 5
+    }
+
+    pub fn detail(&self) -> u8 {
+        self.0 & 0x1f
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.class() == 2
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.class(), self.detail())
+    }
+}
+
+/// Errors reported by a `CoapEndpoint`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CoapError {
+    /// The endpoint's socket is not yet open.
+    Unavailable,
+    /// The stack failed to send a message.
+    Network,
+    /// The peer did not respond in time.
+    Timeout,
+    /// The peer rejected the request outright.
+    Reset,
+    /// A message was too long to be sent.
+    TooLong,
+    /// No more resources may be registered.
+    TooManyResources,
+}
+
+/// A request made of a resource.
+#[derive(Copy, Clone, Debug)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// The resource's path, without leading or trailing `/`.
+    pub path: &'a str,
+    pub content_format: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+/// The response to a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub code: Code,
+    pub content_format: Option<u16>,
+    pub payload: Vec<u8, U256>,
+}
+
+impl Response {
+    pub fn new(code: Code) -> Self {
+        Self {
+            code,
+            content_format: None,
+            payload: Vec::new(),
+        }
+    }
+}
+
+/// A resource served by a `CoapEndpoint`, at the path it was registered upon.
+///
+/// Each method not implemented responds `4.05 Method Not Allowed`. As
+/// resources are shared by reference, any state they change upon `PUT` or
+/// `POST` must be held in cells.
+pub trait Resource {
+    fn get(&self, _request: &Request<'_>) -> Response {
+        Response::new(Code::METHOD_NOT_ALLOWED)
+    }
+
+    fn put(&self, _request: &Request<'_>) -> Response {
+        Response::new(Code::METHOD_NOT_ALLOWED)
+    }
+
+    fn post(&self, _request: &Request<'_>) -> Response {
+        Response::new(Code::METHOD_NOT_ALLOWED)
+    }
+
+    fn delete(&self, _request: &Request<'_>) -> Response {
+        Response::new(Code::METHOD_NOT_ALLOWED)
+    }
+}
+
+/// The peer observing a resource.
+struct Observer {
+    token: Vec<u8, U8>,
+    sequence: u32,
+    /// The message ID of the latest notification, which the peer may reset.
+    id: u16,
+}
+
+struct Registration {
+    path: &'static str,
+    resource: &'static dyn Resource,
+    observer: Option<Observer>,
+}
+
+/// The request made of the peer awaiting its response.
+#[derive(Copy, Clone)]
+struct Exchange {
+    id: u16,
+    token: u16,
+}
+
+/// A CoAP (RFC 7252) endpoint upon a `UdpStack`, exchanging messages with
+/// one peer, such as a gateway or a cloud service, both making requests of
+/// it and serving its requests of the resources registered, from a task
+/// spawned upon start, once its socket is open.
+///
+/// Requests made of the peer are confirmable, retransmitted with exponential
+/// backoff until acknowledged, and made one at a time. Responses may be
+/// piggybacked upon the acknowledgement or follow separately.
+///
+/// The peer may observe a resource by requesting it with the `Observe`
+/// option, and is sent the representation of the resource with each
+/// `notify(...)` of its path, as a non-confirmable notification, until it
+/// cancels the observation or resets a notification.
+///
+/// The endpoint is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static CoapEndpoint<S>>`, once the `UdpStack` is.
+pub struct CoapEndpoint<S>
+where
+    S: UdpStack + 'static,
+{
+    stack: &'static S,
+    peer: SocketAddress,
+    socket: Cell<Option<S::Socket>>,
+    resources: RefCell<Vec<Registration, U8>>,
+    next_id: Cell<u16>,
+    next_token: Cell<u16>,
+    request: BusLock,
+    exchange: Cell<Option<Exchange>>,
+    acked: Cell<bool>,
+    response: RefCell<Option<Result<Response, CoapError>>>,
+    responded: Flag,
+    /// The ID of the latest confirmable request served, and its response,
+    /// sent again should the request be.
+    served: RefCell<Option<(u16, Encoded)>>,
+    upstream: Cell<Option<&'static dyn UpstreamContext<()>>>,
+}
+
+impl<S> CoapEndpoint<S>
+where
+    S: UdpStack + 'static,
+{
+    pub fn new(stack: &'static S, peer: SocketAddress) -> Self {
+        Self {
+            stack,
+            peer,
+            socket: Cell::new(None),
+            resources: RefCell::new(Vec::new()),
+            next_id: Cell::new(1),
+            next_token: Cell::new(1),
+            request: BusLock::new(),
+            exchange: Cell::new(None),
+            acked: Cell::new(false),
+            response: RefCell::new(None),
+            responded: Flag::new(),
+            served: RefCell::new(None),
+            upstream: Cell::new(None),
+        }
+    }
+
+    /// Serve `resource` at `path`, such as `sensors/temperature`.
+    pub fn register(
+        &self,
+        path: &'static str,
+        resource: &'static dyn Resource,
+    ) -> Result<(), CoapError> {
+        self.resources
+            .borrow_mut()
+            .push(Registration {
+                path: path.trim_matches('/'),
+                resource,
+                observer: None,
+            })
+            .map_err(|_| CoapError::TooManyResources)
+    }
+
+    /// Request, *asynchronously*, the peer's resource at `path`,
+    /// returning its response.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        payload: &[u8],
+    ) -> Result<Response, CoapError> {
+        let upstream = self.upstream.get().ok_or(CoapError::Unavailable)?;
+        let _guard = self.request.lock().await;
+        let id = self.next_id();
+        let token = self.next_token.get();
+        self.next_token.set(token.wrapping_add(1));
+        let result = match Builder::new(Type::Confirmable, method as u8, id, &token.to_be_bytes())
+            .path(path)
+            .payload(payload)
+        {
+            Some(encoded) => {
+                self.exchange.set(Some(Exchange { id, token }));
+                self.acked.set(false);
+                self.response.replace(None);
+                let result = self.exchange(upstream, id, &encoded).await;
+                self.exchange.set(None);
+                result
+            }
+            None => Err(CoapError::TooLong),
+        };
+        result
+    }
+
+    /// Notify, *asynchronously*, the peer of the representation of the
+    /// resource at `path`, should it be observing it.
+    pub async fn notify(&self, path: &str) -> Result<(), CoapError> {
+        let path = path.trim_matches('/');
+        let (resource, token) = {
+            let resources = self.resources.borrow();
+            match resources.iter().find(|r| r.path == path) {
+                Some(Registration {
+                    resource,
+                    observer: Some(observer),
+                    ..
+                }) => (*resource, observer.token.clone()),
+                _ => return Ok(()),
+            }
+        };
+        let response = resource.get(&Request {
+            method: Method::Get,
+            path,
+            content_format: None,
+            payload: &[],
+        });
+
+        let id = self.next_id();
+        let sequence = {
+            let mut resources = self.resources.borrow_mut();
+            let registration = resources.iter_mut().find(|r| r.path == path);
+            match registration.and_then(|r| r.observer.as_mut()) {
+                // observed still, by the same request
+                Some(observer) if observer.token == token => {
+                    observer.sequence = (observer.sequence + 1) & SEQUENCE_MASK;
+                    observer.id = id;
+                    observer.sequence
+                }
+                _ => return Ok(()),
+            }
+        };
+        if !response.code.is_success() {
+            // which ends the observation
+            self.observer(path, None);
+        }
+        let encoded = encode(Type::NonConfirmable, id, &token, Some(sequence), &response);
+        self.send(&encoded).await
+    }
+
+    fn next_id(&self) -> u16 {
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn observer(&self, path: &str, observer: Option<Observer>) {
+        let mut resources = self.resources.borrow_mut();
+        if let Some(registration) = resources.iter_mut().find(|r| r.path == path) {
+            registration.observer = observer;
+        }
+    }
+
+    /// Send `encoded`, the request `id`, until acknowledged, and wait for
+    /// its response. The request lock must be held.
+    async fn exchange(
+        &self,
+        upstream: &'static dyn UpstreamContext<()>,
+        id: u16,
+        encoded: &[u8],
+    ) -> Result<Response, CoapError> {
+        let mut timeout = ACK_TIMEOUT + Duration::from_millis(id as u64 % 1000);
+        let mut retransmissions = 0;
+        let mut deadline = upstream.now() + timeout;
+        self.send(encoded).await?;
+        loop {
+            let woken = WithTimeout::new(self.responded.wait(), Delay::new(upstream, deadline))
+                .await
+                .is_some();
+            if let Some(result) = self.response.borrow_mut().take() {
+                return result;
+            }
+            if woken {
+                if self.acked.get() {
+                    deadline = upstream.now() + SEPARATE_TIMEOUT;
+                }
+            } else if self.acked.get() || retransmissions == MAX_RETRANSMIT {
+                return Err(CoapError::Timeout);
+            } else {
+                retransmissions += 1;
+                timeout *= 2;
+                deadline = upstream.now() + timeout;
+                self.send(encoded).await?;
+            }
+        }
+    }
+
+    async fn send(&self, encoded: &[u8]) -> Result<(), CoapError> {
+        let socket = self.socket.get().ok_or(CoapError::Unavailable)?;
+        self.stack
+            .send(socket, encoded)
+            .await
+            .map_err(|_| CoapError::Network)
+    }
+
+    /// Open, *asynchronously*, the socket, and process every message received.
+    async fn run(&self, upstream: &'static dyn UpstreamContext<()>) {
+        let socket = loop {
+            match self.stack.open(self.peer).await {
+                Ok(socket) => break socket,
+                Err(_) => Delay::new(upstream, upstream.now() + RETRY_DELAY).await,
+            }
+        };
+        self.socket.set(Some(socket));
+        self.upstream.set(Some(upstream));
+
+        let mut buf = [0; MAX_MESSAGE];
+        loop {
+            match self.stack.receive(socket, &mut buf).await {
+                Ok(len) => {
+                    if let Some(message) = Message::decode(&buf[..len]) {
+                        self.dispatch(message).await;
+                    }
+                }
+                Err(_) => Delay::new(upstream, upstream.now() + RETRY_DELAY).await,
+            }
+        }
+    }
+
+    async fn dispatch(&self, message: Message<'_>) {
+        match (message.code >> 5, message.code) {
+            (_, 0) => self.empty(&message).await,
+            (0, _) => self.serve(&message).await,
+            (2, _) | (4, _) | (5, _) => self.complete(&message).await,
+            _ => {}
+        }
+    }
+
+    /// Process an empty message: a ping, or an acknowledgement or reset of
+    /// a message sent.
+    async fn empty(&self, message: &Message<'_>) {
+        let exchanged = self.exchange.get().map(|e| e.id) == Some(message.id);
+        match message.ty {
+            Type::Confirmable => self.reset(message.id).await,
+            Type::Acknowledgement if exchanged => {
+                self.acked.set(true);
+                self.responded.raise();
+            }
+            Type::Reset if exchanged => {
+                self.response.replace(Some(Err(CoapError::Reset)));
+                self.responded.raise();
+            }
+            Type::Reset => {
+                let mut resources = self.resources.borrow_mut();
+                for registration in resources.iter_mut() {
+                    if matches!(&registration.observer, Some(o) if o.id == message.id) {
+                        registration.observer = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn reset(&self, id: u16) {
+        if let Some(encoded) = Builder::new(Type::Reset, 0, id, &[]).payload(&[]) {
+            self.send(&encoded).await.ok();
+        }
+    }
+
+    /// Serve the peer's request of a resource.
+    async fn serve(&self, message: &Message<'_>) {
+        let ty = match message.ty {
+            Type::Confirmable => {
+                let served = self.served.borrow().clone();
+                if let Some((id, encoded)) = served {
+                    if id == message.id {
+                        self.send(&encoded).await.ok();
+                        return;
+                    }
+                }
+                Type::Acknowledgement
+            }
+            Type::NonConfirmable => Type::NonConfirmable,
+            _ => return,
+        };
+
+        let path = message.path.as_str();
+        let resource = {
+            let resources = self.resources.borrow();
+            resources
+                .iter()
+                .find(|r| r.path == path)
+                .map(|r| r.resource)
+        };
+        let method = Method::from_code(message.code);
+        let mut observe = None;
+        let response = match (resource, method) {
+            _ if message.unknown_critical => Response::new(Code::BAD_OPTION),
+            (None, _) => Response::new(Code::NOT_FOUND),
+            (_, None) => Response::new(Code::METHOD_NOT_ALLOWED),
+            (Some(resource), Some(method)) => {
+                let request = Request {
+                    method,
+                    path,
+                    content_format: message.content_format,
+                    payload: message.payload,
+                };
+                match method {
+                    Method::Get => {
+                        let response = resource.get(&request);
+                        observe = self.observe(message, response.code);
+                        response
+                    }
+                    Method::Put => resource.put(&request),
+                    Method::Post => resource.post(&request),
+                    Method::Delete => resource.delete(&request),
+                }
+            }
+        };
+
+        let id = match ty {
+            Type::Acknowledgement => message.id,
+            _ => self.next_id(),
+        };
+        let encoded = encode(ty, id, message.token, observe, &response);
+        if message.ty == Type::Confirmable {
+            self.served.replace(Some((message.id, encoded.clone())));
+        }
+        self.send(&encoded).await.ok();
+    }
+
+    /// Register or deregister the peer observing the resource it requested,
+    /// returning the sequence number to respond with, if observed.
+    fn observe(&self, message: &Message<'_>, code: Code) -> Option<u32> {
+        let path = message.path.as_str();
+        match message.observe {
+            Some(0) if code.is_success() => {
+                let mut token = Vec::new();
+                token.extend_from_slice(message.token).ok();
+                self.observer(
+                    path,
+                    Some(Observer {
+                        token,
+                        sequence: 0,
+                        id: 0,
+                    }),
+                );
+                Some(0)
+            }
+            _ => {
+                self.observer(path, None);
+                None
+            }
+        }
+    }
+
+    /// Complete the request to which the peer responded.
+    async fn complete(&self, message: &Message<'_>) {
+        let exchange = match self.exchange.get() {
+            Some(exchange) if message.token == exchange.token.to_be_bytes() => exchange,
+            _ => {
+                if message.ty == Type::Confirmable {
+                    self.reset(message.id).await;
+                }
+                return;
+            }
+        };
+        match message.ty {
+            Type::Acknowledgement if message.id != exchange.id => return,
+            Type::Reset => return,
+            Type::Confirmable => {
+                if let Some(encoded) =
+                    Builder::new(Type::Acknowledgement, 0, message.id, &[]).payload(&[])
+                {
+                    self.send(&encoded).await.ok();
+                }
+            }
+            _ => {}
+        }
+
+        let mut response = Response::new(Code(message.code));
+        response.content_format = message.content_format;
+        let result = match response.payload.extend_from_slice(message.payload) {
+            Ok(()) => Ok(response),
+            Err(()) => Err(CoapError::TooLong),
+        };
+        self.response.replace(Some(result));
+        self.responded.raise();
+    }
+}
+
+/// Encode the `response`, which always fits within a message.
+fn encode(ty: Type, id: u16, token: &[u8], observe: Option<u32>, response: &Response) -> Encoded {
+    let mut builder = Builder::new(ty, response.code.0, id, token);
+    if let Some(sequence) = observe {
+        builder = builder.uint_option(OBSERVE, sequence);
+    }
+    if let Some(format) = response.content_format {
+        builder = builder.uint_option(CONTENT_FORMAT, format as u32);
+    }
+    builder.payload(&response.payload).unwrap_or_default()
+}
+
+impl<S> Component for &'static CoapEndpoint<S>
+where
+    S: UdpStack + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        let endpoint: &'static CoapEndpoint<S> = self;
+        spawn("coap", async move {
+            endpoint.run(ctx.upstream()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::message::{Builder, Message, Type, OBSERVE, URI_PATH};
+    use super::{CoapEndpoint, CoapError, Code, Method, Request, Resource, Response};
+    use crate::net::{HostStack, Ipv4Address, SocketAddress};
+    use crate::testing::{leak, poll_once, Upstream};
+    use crate::time::Duration;
+    use core::cell::Cell;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Poll;
+    use std::boxed::Box;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::thread;
+    use std::vec::Vec;
+
+    type Endpoint = CoapEndpoint<HostStack>;
+
+    type Requested = Pin<Box<dyn Future<Output = Result<Response, CoapError>>>>;
+
+    /// Runs the endpoint's task, and any request made of the peer, advancing
+    /// time by a hundred milliseconds at each step, with a std socket as the peer.
+    struct Harness {
+        upstream: &'static Upstream<()>,
+        endpoint: &'static Endpoint,
+        task: Pin<Box<dyn Future<Output = ()>>>,
+        request: Option<Requested>,
+        result: Option<Result<Response, CoapError>>,
+        peer: UdpSocket,
+        remote: Option<SocketAddr>,
+    }
+
+    impl Harness {
+        fn new(resources: &[(&'static str, &'static dyn Resource)]) -> Self {
+            let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+            peer.set_nonblocking(true).unwrap();
+            let address =
+                SocketAddress::new(Ipv4Address::LOCALHOST, peer.local_addr().unwrap().port());
+            let endpoint: &'static Endpoint =
+                leak(CoapEndpoint::new(leak(HostStack::new()), address));
+            for (path, resource) in resources {
+                endpoint.register(path, *resource).unwrap();
+            }
+            let upstream = leak(Upstream::new());
+            let mut h = Self {
+                upstream,
+                endpoint,
+                task: Box::pin(endpoint.run(upstream)),
+                request: None,
+                result: None,
+                peer,
+                remote: None,
+            };
+            h.step();
+            h
+        }
+
+        fn step(&mut self) {
+            assert_eq!(poll_once(self.task.as_mut()), Poll::Pending);
+            if let Some(request) = self.request.as_mut() {
+                if let Poll::Ready(result) = poll_once(request.as_mut()) {
+                    self.result = Some(result);
+                    self.request = None;
+                }
+            }
+            self.upstream.advance(Duration::from_millis(100));
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        fn run<F: Future>(&mut self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            for _ in 0..100 {
+                if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                    return output;
+                }
+                self.step();
+            }
+            panic!("future never completed")
+        }
+
+        /// Start a request of the peer, stepped alongside the endpoint's task.
+        fn start(&mut self, method: Method, path: &'static str, payload: &'static [u8]) {
+            self.request = Some(Box::pin(self.endpoint.request(method, path, payload)));
+        }
+
+        /// Step until the request started completes, returning its result.
+        fn result(&mut self) -> Result<Response, CoapError> {
+            for _ in 0..2000 {
+                if let Some(result) = self.result.take() {
+                    return result;
+                }
+                self.step();
+            }
+            panic!("request never completed")
+        }
+
+        /// Take the datagrams the peer has received so far.
+        fn received(&mut self) -> Vec<Vec<u8>> {
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            while let Ok((len, remote)) = self.peer.recv_from(&mut buf) {
+                self.remote = Some(remote);
+                received.push(buf[..len].to_vec());
+            }
+            received
+        }
+
+        /// Step until the peer receives a datagram, returning it.
+        fn receive(&mut self) -> Vec<u8> {
+            for _ in 0..100 {
+                let mut received = self.received();
+                if !received.is_empty() {
+                    assert_eq!(received.len(), 1);
+                    return received.remove(0);
+                }
+                self.step();
+            }
+            panic!("nothing received")
+        }
+
+        /// Step a while, asserting the peer receives nothing.
+        fn silent(&mut self) {
+            for _ in 0..10 {
+                self.step();
+            }
+            assert!(self.received().is_empty());
+        }
+
+        fn send(&self, data: &[u8]) {
+            self.peer.send_to(data, self.remote.unwrap()).unwrap();
+        }
+
+        /// Send `data` as a request, and step until responded to, returning the response.
+        fn exchange(&mut self, data: &[u8]) -> Vec<u8> {
+            self.send(data);
+            self.receive()
+        }
+    }
+
+    fn empty(ty: Type, id: u16) -> Vec<u8> {
+        Builder::new(ty, 0, id, &[]).payload(&[]).unwrap().to_vec()
+    }
+
+    #[test]
+    fn client() {
+        let mut h = Harness::new(&[]);
+
+        // unacknowledged, so retransmitted, then responded to along with the acknowledgement
+        h.start(Method::Post, "telemetry", b"21.5");
+        let first = h.receive();
+        let request = Message::decode(&first).unwrap();
+        assert_eq!(request.ty, Type::Confirmable);
+        assert_eq!(request.code, Method::Post as u8);
+        assert_eq!(request.path.as_str(), "telemetry");
+        assert_eq!(request.payload, b"21.5");
+        assert_eq!(h.receive(), first);
+        h.send(
+            &Builder::new(
+                Type::Acknowledgement,
+                Code::CHANGED.0,
+                request.id,
+                request.token,
+            )
+            .payload(b"ok")
+            .unwrap(),
+        );
+        let response = h.result().unwrap();
+        assert_eq!(response.code, Code::CHANGED);
+        assert_eq!(&response.payload[..], b"ok");
+
+        // acknowledged, then responded to separately
+        h.start(Method::Get, "config", b"");
+        let data = h.receive();
+        let request = Message::decode(&data).unwrap();
+        h.send(&empty(Type::Acknowledgement, request.id));
+        h.silent();
+        h.send(
+            &Builder::new(Type::Confirmable, Code::CONTENT.0, 0x7777, request.token)
+                .payload(b"{}")
+                .unwrap(),
+        );
+        let response = h.result().unwrap();
+        assert_eq!(response.code, Code::CONTENT);
+        assert_eq!(&response.payload[..], b"{}");
+        assert_eq!(h.receive(), empty(Type::Acknowledgement, 0x7777));
+
+        // rejected outright
+        h.start(Method::Delete, "config", b"");
+        let data = h.receive();
+        let request = Message::decode(&data).unwrap();
+        h.send(&empty(Type::Reset, request.id));
+        assert_eq!(h.result(), Err(CoapError::Reset));
+
+        // retransmitted until giving up
+        h.start(Method::Get, "config", b"");
+        assert_eq!(h.result(), Err(CoapError::Timeout));
+        assert_eq!(h.received().len(), 5);
+    }
+
+    struct Temperature {
+        value: Cell<u8>,
+    }
+
+    impl Resource for Temperature {
+        fn get(&self, _request: &Request<'_>) -> Response {
+            let mut response = Response::new(Code::CONTENT);
+            response.content_format = Some(0);
+            let value = std::format!("{}", self.value.get());
+            response
+                .payload
+                .extend_from_slice(value.as_bytes())
+                .unwrap();
+            response
+        }
+
+        fn put(&self, request: &Request<'_>) -> Response {
+            match core::str::from_utf8(request.payload)
+                .ok()
+                .and_then(|v| v.parse().ok())
+            {
+                Some(value) => {
+                    self.value.set(value);
+                    Response::new(Code::CHANGED)
+                }
+                None => Response::new(Code::BAD_REQUEST),
+            }
+        }
+    }
+
+    struct Info;
+
+    impl Resource for Info {}
+
+    #[test]
+    fn server() {
+        let temperature = leak(Temperature {
+            value: Cell::new(20),
+        });
+        let mut h = Harness::new(&[("/sensors/temperature", temperature), ("info", leak(Info))]);
+        let endpoint = h.endpoint;
+
+        // make the endpoint known to the peer
+        h.start(Method::Get, "hello", b"");
+        let data = h.receive();
+        let request = Message::decode(&data).unwrap();
+        h.send(
+            &Builder::new(
+                Type::Acknowledgement,
+                Code::CONTENT.0,
+                request.id,
+                request.token,
+            )
+            .payload(&[])
+            .unwrap(),
+        );
+        h.result().unwrap();
+
+        let get = |id, token: &[u8]| {
+            Builder::new(Type::Confirmable, Method::Get as u8, id, token)
+                .path("sensors/temperature")
+        };
+
+        let data = h.exchange(&get(0x100, &[1]).payload(&[]).unwrap());
+        let response = Message::decode(&data).unwrap();
+        assert_eq!(response.ty, Type::Acknowledgement);
+        assert_eq!(response.id, 0x100);
+        assert_eq!(response.token, &[1]);
+        assert_eq!(response.code, Code::CONTENT.0);
+        assert_eq!(response.content_format, Some(0));
+        assert_eq!(response.observe, None);
+        assert_eq!(response.payload, b"20");
+
+        let put = Builder::new(Type::Confirmable, Method::Put as u8, 0x101, &[2])
+            .path("sensors/temperature");
+        let data = h.exchange(&put.payload(b"25").unwrap());
+        assert_eq!(Message::decode(&data).unwrap().code, Code::CHANGED.0);
+        assert_eq!(temperature.value.get(), 25);
+
+        // a duplicate is responded to as before, though not served again
+        let put = Builder::new(Type::Confirmable, Method::Put as u8, 0x101, &[2])
+            .path("sensors/temperature");
+        assert_eq!(h.exchange(&put.payload(b"30").unwrap()), data);
+        assert_eq!(temperature.value.get(), 25);
+
+        let post = Builder::new(Type::Confirmable, Method::Post as u8, 0x102, &[]).path("info");
+        let data = h.exchange(&post.payload(&[]).unwrap());
+        assert_eq!(
+            Message::decode(&data).unwrap().code,
+            Code::METHOD_NOT_ALLOWED.0
+        );
+
+        let missing =
+            Builder::new(Type::NonConfirmable, Method::Get as u8, 0x103, &[3]).path("missing");
+        let data = h.exchange(&missing.payload(&[]).unwrap());
+        let response = Message::decode(&data).unwrap();
+        assert_eq!(response.ty, Type::NonConfirmable);
+        assert_eq!(response.token, &[3]);
+        assert_eq!(response.code, Code::NOT_FOUND.0);
+
+        let critical = Builder::new(Type::Confirmable, Method::Get as u8, 0x104, &[])
+            .path("info")
+            .option(URI_PATH + 2, b"");
+        let data = h.exchange(&critical.payload(&[]).unwrap());
+        assert_eq!(Message::decode(&data).unwrap().code, Code::BAD_OPTION.0);
+
+        // pinged
+        assert_eq!(
+            h.exchange(&empty(Type::Confirmable, 0x105)),
+            empty(Type::Reset, 0x105)
+        );
+
+        // observed, until a notification is reset
+        let observe = |id, token: &[u8], value| {
+            Builder::new(Type::Confirmable, Method::Get as u8, id, token)
+                .uint_option(OBSERVE, value)
+                .path("sensors/temperature")
+                .payload(&[])
+                .unwrap()
+        };
+        let data = h.exchange(&observe(0x106, &[4], 0));
+        let response = Message::decode(&data).unwrap();
+        assert_eq!(response.observe, Some(0));
+        assert_eq!(response.payload, b"25");
+
+        temperature.value.set(26);
+        assert_eq!(h.run(endpoint.notify("sensors/temperature")), Ok(()));
+        let data = h.receive();
+        let notification = Message::decode(&data).unwrap();
+        assert_eq!(notification.ty, Type::NonConfirmable);
+        assert_eq!(notification.token, &[4]);
+        assert_eq!(notification.observe, Some(1));
+        assert_eq!(notification.payload, b"26");
+
+        h.send(&empty(Type::Reset, notification.id));
+        h.silent();
+        assert_eq!(h.run(endpoint.notify("sensors/temperature")), Ok(()));
+        h.silent();
+
+        // observed again, until cancelled
+        h.exchange(&observe(0x107, &[5], 0));
+        assert_eq!(h.run(endpoint.notify("/sensors/temperature/")), Ok(()));
+        assert_eq!(Message::decode(&h.receive()).unwrap().token, &[5]);
+        let data = h.exchange(&observe(0x108, &[5], 1));
+        assert_eq!(Message::decode(&data).unwrap().observe, None);
+        assert_eq!(h.run(endpoint.notify("sensors/temperature")), Ok(()));
+        h.silent();
+    }
+}
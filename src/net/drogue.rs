@@ -0,0 +1,585 @@
+use crate::component::{spawn, Component, ComponentContext, ConnectedComponent};
+use crate::context::UpstreamContext;
+use crate::driver::lock::Flag;
+use crate::handler::Handler;
+use crate::net::mqtt::{MqttClient, MqttConfig, MqttError, MqttEvent, QoS};
+use crate::net::{SocketAddress, TcpStack};
+use crate::sensor::Reading;
+use crate::time::{Delay, Duration, Instant, WithTimeout};
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use heapless::{consts::*, String, Vec};
+
+/// The topic filter matching the commands sent to the device itself.
+const COMMANDS: &str = "command/inbox/#";
+
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(32);
+
+/// The readings held while awaiting publication, beyond which the oldest are dropped.
+type Batch = Vec<(Reading, Instant), U16>;
+
+/// The payload of one publication, as JSON, sized to fit an MQTT packet.
+type Payload = String<U448>;
+
+/// The endpoint and credentials of a device registered with Drogue Cloud.
+#[derive(Copy, Clone, Debug)]
+pub struct DrogueConfig {
+    /// The MQTT endpoint of Drogue Cloud.
+    pub endpoint: SocketAddress,
+    /// The name of the device, qualified by its application, as `device@application`.
+    pub device: &'static str,
+    pub password: &'static str,
+    /// The channel upon which readings are published.
+    pub channel: &'static str,
+    /// The number of readings published together, once queued.
+    pub batch: usize,
+    /// The longest a reading is held before being published, should
+    /// fewer than `batch` readings be queued.
+    pub linger: Duration,
+}
+
+impl DrogueConfig {
+    pub fn new(endpoint: SocketAddress, device: &'static str, password: &'static str) -> Self {
+        Self {
+            endpoint,
+            device,
+            password,
+            channel: "telemetry",
+            batch: 8,
+            linger: Duration::from_secs(10),
+        }
+    }
+
+    /// The configuration of the `MqttClient` connecting this device.
+    pub fn mqtt(&self) -> MqttConfig {
+        let mut config = MqttConfig::new(self.endpoint, self.device);
+        config.username = Some(self.device);
+        config.password = Some(self.password.as_bytes());
+        config
+    }
+}
+
+/// A command sent by Drogue Cloud to the device, parsed from its name and payload.
+pub trait Command: Sized {
+    /// Parse the command `name`, returning `None` should it be unknown,
+    /// or its `payload` invalid, in which case the command is dropped.
+    fn parse(name: &str, payload: &[u8]) -> Option<Self>;
+}
+
+/// A command as it was received, for devices parsing commands themselves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawCommand {
+    pub name: String<U32>,
+    pub payload: Vec<u8, U256>,
+}
+
+impl Command for RawCommand {
+    fn parse(name: &str, payload: &[u8]) -> Option<Self> {
+        let mut command = RawCommand {
+            name: String::new(),
+            payload: Vec::new(),
+        };
+        command.name.push_str(name).ok()?;
+        command.payload.extend_from_slice(payload).ok()?;
+        Some(command)
+    }
+}
+
+/// Messages accepted by `DrogueCloud`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DrogueMessage {
+    /// Queue a reading for publication.
+    Reading(Reading),
+    /// Publish every reading queued, without waiting for a full batch.
+    Flush,
+}
+
+/// Messages sent upstream by `DrogueCloud`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DrogueEvent<C> {
+    /// The device connected to Drogue Cloud.
+    Connected,
+    /// The connection was lost, and will be reestablished.
+    Disconnected,
+    /// Readings were published, and acknowledged by Drogue Cloud.
+    Published(usize),
+    /// Readings were dropped, as too many were queued awaiting publication.
+    Dropped(usize),
+    /// A command was received.
+    Command(C),
+}
+
+/// A component publishing `Reading`s to Drogue Cloud, and sending the
+/// commands it receives upstream as typed `DrogueEvent::Command`s.
+///
+/// Readings are queued, and published together as a JSON array, once
+/// `batch` are queued or the oldest has lingered long enough. Should a
+/// publication fail, it is attempted again, backing off exponentially,
+/// with the readings queued meanwhile published alongside, up to the 16
+/// held, beyond which the oldest are dropped.
+///
+/// Drogue Cloud is reached through an `MqttClient`, configured by
+/// `DrogueConfig::mqtt()` and placed in static memory, which is started
+/// as a child of this component.
+pub struct DrogueCloud<S, C>
+where
+    S: TcpStack + 'static,
+    C: Command + 'static,
+{
+    client: &'static MqttClient<S>,
+    connection: ConnectedComponent<&'static MqttClient<S>>,
+    config: DrogueConfig,
+    batch: RefCell<Batch>,
+    in_flight: Cell<usize>,
+    dropped: Cell<usize>,
+    queued: Flag,
+    flush: Cell<bool>,
+    ctx: Option<&'static ComponentContext<Self>>,
+}
+
+impl<S, C> DrogueCloud<S, C>
+where
+    S: TcpStack + 'static,
+    C: Command + 'static,
+{
+    pub fn new(client: &'static MqttClient<S>, config: DrogueConfig) -> Self {
+        Self {
+            client,
+            connection: ConnectedComponent::new(client),
+            config,
+            batch: RefCell::new(Vec::new()),
+            in_flight: Cell::new(0),
+            dropped: Cell::new(0),
+            queued: Flag::new(),
+            flush: Cell::new(false),
+            ctx: None,
+        }
+    }
+
+    /// The number of readings queued, including any being published.
+    pub fn queued(&self) -> usize {
+        self.batch.borrow().len()
+    }
+
+    fn control(&self, now: Instant, message: DrogueMessage) {
+        match message {
+            DrogueMessage::Reading(reading) => {
+                let mut batch = self.batch.borrow_mut();
+                if batch.len() == batch.capacity() {
+                    discard(&mut batch, 1);
+                    self.dropped.set(self.dropped.get() + 1);
+                    if self.in_flight.get() > 0 {
+                        self.in_flight.set(self.in_flight.get() - 1);
+                    }
+                }
+                batch.push((reading, now)).ok();
+            }
+            DrogueMessage::Flush => self.flush.set(true),
+        }
+        self.queued.raise();
+    }
+
+    /// Publish, *asynchronously*, each batch of readings as it becomes due,
+    /// attempting it again, backing off exponentially, should it fail.
+    async fn run(&self, upstream: &'static dyn UpstreamContext<DrogueEvent<C>>) {
+        // made upon each connection, so succeeds whether connected or not
+        self.client.subscribe(COMMANDS, QoS::AtLeastOnce).await.ok();
+
+        let mut backoff = RETRY_MIN;
+        loop {
+            self.due(upstream).await;
+            let dropped = self.dropped.replace(0);
+            if dropped > 0 {
+                upstream.send(DrogueEvent::Dropped(dropped));
+            }
+            match self.publish().await {
+                Ok(published) => {
+                    self.flush.set(false);
+                    backoff = RETRY_MIN;
+                    upstream.send(DrogueEvent::Published(published));
+                }
+                Err(_) => {
+                    Delay::new(upstream, upstream.now() + backoff).await;
+                    backoff = (backoff * 2).min(RETRY_MAX);
+                }
+            }
+        }
+    }
+
+    /// Wait, *asynchronously*, until a batch of readings is due to be published.
+    async fn due(&self, upstream: &'static dyn UpstreamContext<DrogueEvent<C>>) {
+        loop {
+            let oldest = {
+                let batch = self.batch.borrow();
+                if batch.len() >= self.config.batch.max(1).min(batch.capacity()) {
+                    return;
+                }
+                batch.first().map(|(_, at)| *at)
+            };
+            match oldest {
+                Some(_) if self.flush.get() => return,
+                Some(at) => {
+                    let deadline = at + self.config.linger;
+                    if upstream.now() >= deadline {
+                        return;
+                    }
+                    WithTimeout::new(self.queued.wait(), Delay::new(upstream, deadline)).await;
+                }
+                None => {
+                    self.flush.set(false);
+                    self.queued.wait().await;
+                }
+            }
+        }
+    }
+
+    /// Publish, *asynchronously*, as many of the queued readings as fit
+    /// one payload, returning how many were published.
+    async fn publish(&self) -> Result<usize, MqttError> {
+        let mut payload = Payload::new();
+        let count = encode(&mut payload, self.batch.borrow().iter().map(|(r, _)| r));
+        self.in_flight.set(count);
+        let result = self
+            .client
+            .publish(self.config.channel, payload.as_bytes(), QoS::AtLeastOnce)
+            .await;
+        // fewer, should any have been dropped while being published
+        let published = self.in_flight.replace(0);
+        if result.is_ok() {
+            discard(&mut self.batch.borrow_mut(), published);
+        }
+        result.map(|_| count)
+    }
+
+    fn on_event(&self, upstream: &dyn UpstreamContext<DrogueEvent<C>>, event: MqttEvent) {
+        match event {
+            MqttEvent::Connected => upstream.send(DrogueEvent::Connected),
+            MqttEvent::Disconnected => upstream.send(DrogueEvent::Disconnected),
+            MqttEvent::Message(message) => {
+                let command = message
+                    .topic
+                    .strip_prefix("command/inbox/")
+                    // the device addressed, empty for the device itself
+                    .and_then(|rest| rest.split_once('/'))
+                    .and_then(|(_, name)| C::parse(name, &message.payload));
+                if let Some(command) = command {
+                    upstream.send(DrogueEvent::Command(command));
+                }
+            }
+        }
+    }
+}
+
+/// Remove the `count` oldest readings from `batch`.
+fn discard(batch: &mut Batch, count: usize) {
+    let count = count.min(batch.len());
+    batch.rotate_left(count);
+    // popped, as heapless' `truncate` reads beyond the length
+    for _ in 0..count {
+        batch.pop();
+    }
+}
+
+/// Encode, as a JSON array, as many of `readings` as fit `payload`,
+/// returning how many were encoded.
+fn encode<'r>(payload: &mut Payload, readings: impl Iterator<Item = &'r Reading>) -> usize {
+    let mut count = 0;
+    payload.push('[').ok();
+    for reading in readings {
+        // long enough for any reading, however its numbers are written
+        let mut entry = String::<U256>::new();
+        if count > 0 {
+            entry.push(',').ok();
+        }
+        if write_reading(&mut entry, reading).is_err()
+            // leaving room to close the array
+            || payload.len() + entry.len() + 1 > payload.capacity()
+        {
+            break;
+        }
+        payload.push_str(&entry).ok();
+        count += 1;
+    }
+    payload.push(']').ok();
+    count
+}
+
+fn write_reading(entry: &mut String<U256>, reading: &Reading) -> core::fmt::Result {
+    match reading {
+        Reading::Temperature(t) => write_field(entry, "temperature", t.0),
+        Reading::Humidity(h) => write_field(entry, "humidity", h.0),
+        Reading::Pressure(p) => write_field(entry, "pressure", p.0),
+        Reading::Acceleration(a) => {
+            entry.write_str(r#"{"acceleration":{"x":"#)?;
+            write_number(entry, a.x)?;
+            entry.write_str(r#","y":"#)?;
+            write_number(entry, a.y)?;
+            entry.write_str(r#","z":"#)?;
+            write_number(entry, a.z)?;
+            entry.write_str("}}")
+        }
+    }
+}
+
+fn write_field(entry: &mut String<U256>, name: &str, value: f32) -> core::fmt::Result {
+    write!(entry, r#"{{"{}":"#, name)?;
+    write_number(entry, value)?;
+    entry.write_str("}")
+}
+
+/// Write `value` as a JSON number, or `null` should it not be finite.
+fn write_number(entry: &mut String<U256>, value: f32) -> core::fmt::Result {
+    if value.is_finite() {
+        write!(entry, "{}", value)
+    } else {
+        entry.write_str("null")
+    }
+}
+
+impl<S, C> Component for DrogueCloud<S, C>
+where
+    S: TcpStack + 'static,
+    C: Command + 'static,
+{
+    type InboundMessage = DrogueMessage;
+    type OutboundMessage = DrogueEvent<C>;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.replace(ctx);
+        self.connection.start(ctx);
+
+        let cloud: &'static Self = self;
+        spawn("drogue", async move {
+            cloud.run(ctx.upstream()).await;
+        });
+        spawn("drogue-control", async move {
+            loop {
+                let message = ctx.receive().await;
+                cloud.control(ctx.now(), message);
+            }
+        });
+    }
+}
+
+impl<S, C> Handler<MqttEvent> for DrogueCloud<S, C>
+where
+    S: TcpStack + 'static,
+    C: Command + 'static,
+{
+    fn on_message(&mut self, message: MqttEvent) {
+        if let Some(ctx) = self.ctx {
+            self.on_event(ctx.upstream(), message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{encode, Command, DrogueCloud, DrogueConfig, DrogueEvent, DrogueMessage, Payload};
+    use crate::context::UpstreamContext;
+    use crate::net::mqtt::{MqttClient, MqttEvent};
+    use crate::net::{HostStack, Ipv4Address, SocketAddress};
+    use crate::sensor::{Acceleration, Celsius, Hectopascals, Reading, RelativeHumidity};
+    use crate::testing::{broker, leak, poll_once, Upstream};
+    use crate::time::Duration;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Poll;
+    use std::boxed::Box;
+    use std::string::String;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    enum DeviceCommand {
+        Reboot { delay: u8 },
+    }
+
+    impl Command for DeviceCommand {
+        fn parse(name: &str, payload: &[u8]) -> Option<Self> {
+            match name {
+                "reboot" => Some(DeviceCommand::Reboot {
+                    delay: core::str::from_utf8(payload).ok()?.parse().ok()?,
+                }),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let mut payload = Payload::new();
+        let readings = [
+            Reading::Temperature(Celsius(21.5)),
+            Reading::Humidity(RelativeHumidity(40.0)),
+            Reading::Pressure(Hectopascals(f32::NAN)),
+            Reading::Acceleration(Acceleration {
+                x: 0.0,
+                y: -1.5,
+                z: 9.75,
+            }),
+        ];
+        assert_eq!(encode(&mut payload, readings.iter()), 4);
+        assert_eq!(
+            payload.as_str(),
+            r#"[{"temperature":21.5},{"humidity":40},{"pressure":null},{"acceleration":{"x":0,"y":-1.5,"z":9.75}}]"#
+        );
+
+        // only as many as fit
+        let mut payload = Payload::new();
+        let readings = [readings[3]; 16];
+        let count = encode(&mut payload, readings.iter());
+        assert!(count > 0 && count < 16);
+        assert!(payload.ends_with("}}]"));
+    }
+
+    type Cloud = DrogueCloud<HostStack, DeviceCommand>;
+
+    /// Runs the client's and publisher's tasks, passing the client's events
+    /// to the publisher, and advancing time by ten milliseconds at each step.
+    struct Harness {
+        events: &'static Upstream<MqttEvent>,
+        upstream: &'static Upstream<DrogueEvent<DeviceCommand>>,
+        cloud: &'static Cloud,
+        client_task: Pin<Box<dyn Future<Output = ()>>>,
+        cloud_task: Pin<Box<dyn Future<Output = ()>>>,
+        received: Vec<DrogueEvent<DeviceCommand>>,
+    }
+
+    impl Harness {
+        fn new(port: u16) -> Self {
+            let mut config = DrogueConfig::new(
+                SocketAddress::new(Ipv4Address::LOCALHOST, port),
+                "device@app",
+                "secret",
+            );
+            config.batch = 3;
+            config.linger = Duration::from_secs(1);
+            let events = leak(Upstream::new());
+            let upstream = leak(Upstream::new());
+            let client = leak(MqttClient::new(leak(HostStack::new()), config.mqtt()));
+            let cloud: &'static Cloud = leak(DrogueCloud::new(client, config));
+            Self {
+                events,
+                upstream,
+                cloud,
+                client_task: Box::pin(client.run(events)),
+                cloud_task: Box::pin(cloud.run(upstream)),
+                received: Vec::new(),
+            }
+        }
+
+        fn step(&mut self) {
+            assert_eq!(poll_once(self.client_task.as_mut()), Poll::Pending);
+            // forwarded first, as a publication they precede may complete in this step
+            for event in self.events.take() {
+                self.cloud.on_event(self.upstream, event);
+            }
+            assert_eq!(poll_once(self.cloud_task.as_mut()), Poll::Pending);
+            self.upstream.advance(Duration::from_millis(10));
+            self.events.advance(Duration::from_millis(10));
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        fn send(&mut self, message: DrogueMessage) {
+            self.cloud.control(self.upstream.now(), message);
+        }
+
+        /// Step until an event is sent upstream, returning the earliest not yet returned.
+        fn event(&mut self) -> DrogueEvent<DeviceCommand> {
+            for _ in 0..1000 {
+                self.received.extend(self.upstream.take());
+                if !self.received.is_empty() {
+                    return self.received.remove(0);
+                }
+                self.step();
+            }
+            panic!("no event sent")
+        }
+    }
+
+    #[test]
+    fn publisher() {
+        let (port, log) = broker();
+        // commands, one unknown, sent upon subscription
+        log.lock().unwrap().on_subscribe = std::vec![
+            (String::from("command/inbox//reboot"), b"5".to_vec()),
+            (String::from("command/inbox//unknown"), Vec::new()),
+        ];
+        let mut h = Harness::new(port);
+        let published = || {
+            log.lock()
+                .unwrap()
+                .published
+                .iter()
+                .map(|(topic, payload)| {
+                    (topic.clone(), String::from_utf8(payload.clone()).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(h.event(), DrogueEvent::Connected);
+        assert_eq!(
+            h.event(),
+            DrogueEvent::Command(DeviceCommand::Reboot { delay: 5 })
+        );
+        assert_eq!(
+            log.lock().unwrap().credentials,
+            [(String::from("device@app"), String::from("secret"))]
+        );
+
+        // published once a batch is queued
+        for t in [20.0, 20.5, 21.0] {
+            h.send(DrogueMessage::Reading(Reading::Temperature(Celsius(t))));
+        }
+        assert_eq!(h.event(), DrogueEvent::Published(3));
+        assert_eq!(
+            published(),
+            [(
+                String::from("telemetry"),
+                String::from(r#"[{"temperature":20},{"temperature":20.5},{"temperature":21}]"#)
+            )]
+        );
+
+        // or once lingered
+        h.send(DrogueMessage::Reading(Reading::Humidity(RelativeHumidity(
+            40.0,
+        ))));
+        for _ in 0..50 {
+            h.step();
+        }
+        assert_eq!(published().len(), 1);
+        assert!(h.upstream.take().is_empty());
+        assert_eq!(h.event(), DrogueEvent::Published(1));
+
+        // or upon request
+        h.send(DrogueMessage::Reading(Reading::Humidity(RelativeHumidity(
+            41.0,
+        ))));
+        h.send(DrogueMessage::Flush);
+        assert_eq!(h.event(), DrogueEvent::Published(1));
+        assert_eq!(published().len(), 3);
+
+        // kept, alongside those queued meanwhile, until published
+        log.lock().unwrap().drop = 1;
+        for t in [22.0, 22.5, 23.0] {
+            h.send(DrogueMessage::Reading(Reading::Temperature(Celsius(t))));
+        }
+        assert_eq!(h.event(), DrogueEvent::Disconnected);
+        h.send(DrogueMessage::Reading(Reading::Temperature(Celsius(23.5))));
+        assert_eq!(h.event(), DrogueEvent::Connected);
+        // the command, sent upon subscribing again, races the publication
+        let events = [h.event(), h.event()];
+        assert!(events.contains(&DrogueEvent::Command(DeviceCommand::Reboot { delay: 5 })));
+        assert!(events.contains(&DrogueEvent::Published(4)));
+        assert_eq!(
+            published()[3].1,
+            r#"[{"temperature":22},{"temperature":22.5},{"temperature":23},{"temperature":23.5}]"#
+        );
+        assert_eq!(h.cloud.queued(), 0);
+        assert_eq!(log.lock().unwrap().connects, 2);
+    }
+}
@@ -0,0 +1,258 @@
+extern crate std;
+
+use crate::kernel::notify_activity;
+use crate::net::{SocketAddress, TcpStack, UdpStack};
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context as FutureContext, Poll};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::vec::Vec;
+
+enum Entry {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// A handle to a socket of a `HostStack`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HostSocket(usize);
+
+/// A stack backed by the sockets of the host's operating system, so that
+/// protocol components may be exercised against real servers on the host
+/// backend. Available with the `std` feature.
+///
+/// Sockets are non-blocking, and polled cooperatively, yielding to the
+/// executor until each operation can progress, except for establishing
+/// TCP connections, which blocks.
+pub struct HostStack {
+    sockets: RefCell<Vec<Option<Entry>>>,
+}
+
+impl HostStack {
+    pub fn new() -> Self {
+        Self {
+            sockets: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn insert(&self, entry: Entry) -> HostSocket {
+        let mut sockets = self.sockets.borrow_mut();
+        match sockets.iter().position(Option::is_none) {
+            Some(index) => {
+                sockets[index] = Some(entry);
+                HostSocket(index)
+            }
+            None => {
+                sockets.push(Some(entry));
+                HostSocket(sockets.len() - 1)
+            }
+        }
+    }
+
+    fn with<T>(
+        &self,
+        socket: HostSocket,
+        op: impl FnOnce(&mut Entry) -> io::Result<T>,
+    ) -> io::Result<T> {
+        match self.sockets.borrow_mut().get_mut(socket.0) {
+            Some(Some(entry)) => op(entry),
+            _ => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn remove(&self, socket: HostSocket) {
+        if let Some(entry) = self.sockets.borrow_mut().get_mut(socket.0) {
+            *entry = None;
+        }
+    }
+}
+
+impl Default for HostStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn address(remote: SocketAddress) -> SocketAddr {
+    SocketAddr::from((remote.ip.0, remote.port))
+}
+
+impl TcpStack for HostStack {
+    type Socket = HostSocket;
+    type Error = ErrorKind;
+
+    async fn connect(&self, remote: SocketAddress) -> Result<HostSocket, ErrorKind> {
+        let stream = TcpStream::connect(address(remote)).map_err(|e| e.kind())?;
+        stream.set_nonblocking(true).map_err(|e| e.kind())?;
+        stream.set_nodelay(true).ok();
+        Ok(self.insert(Entry::Tcp(stream)))
+    }
+
+    async fn send(&self, socket: HostSocket, data: &[u8]) -> Result<usize, ErrorKind> {
+        let mut sent = 0;
+        while sent < data.len() {
+            sent += Nonblocking(|| {
+                self.with(socket, |entry| match entry {
+                    Entry::Tcp(stream) => stream.write(&data[sent..]),
+                    Entry::Udp(_) => Err(ErrorKind::InvalidInput.into()),
+                })
+            })
+            .await?;
+        }
+        Ok(sent)
+    }
+
+    async fn receive(&self, socket: HostSocket, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        Nonblocking(|| {
+            self.with(socket, |entry| match entry {
+                Entry::Tcp(stream) => stream.read(buf),
+                Entry::Udp(_) => Err(ErrorKind::InvalidInput.into()),
+            })
+        })
+        .await
+    }
+
+    async fn close(&self, socket: HostSocket) {
+        self.remove(socket)
+    }
+}
+
+impl UdpStack for HostStack {
+    type Socket = HostSocket;
+    type Error = ErrorKind;
+
+    async fn open(&self, remote: SocketAddress) -> Result<HostSocket, ErrorKind> {
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.kind())?;
+        socket.connect(address(remote)).map_err(|e| e.kind())?;
+        socket.set_nonblocking(true).map_err(|e| e.kind())?;
+        Ok(self.insert(Entry::Udp(socket)))
+    }
+
+    async fn send(&self, socket: HostSocket, data: &[u8]) -> Result<(), ErrorKind> {
+        Nonblocking(|| {
+            self.with(socket, |entry| match entry {
+                Entry::Udp(socket) => socket.send(data),
+                Entry::Tcp(_) => Err(ErrorKind::InvalidInput.into()),
+            })
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn receive(&self, socket: HostSocket, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        Nonblocking(|| {
+            self.with(socket, |entry| match entry {
+                Entry::Udp(socket) => socket.recv(buf),
+                Entry::Tcp(_) => Err(ErrorKind::InvalidInput.into()),
+            })
+        })
+        .await
+    }
+
+    async fn close(&self, socket: HostSocket) {
+        self.remove(socket)
+    }
+}
+
+/// Future polling a non-blocking operation until it no longer would block.
+struct Nonblocking<F>(F);
+
+impl<F, T> Future for Nonblocking<F>
+where
+    F: FnMut() -> io::Result<T> + Unpin,
+{
+    type Output = Result<T, ErrorKind>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        match (self.0)() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                notify_activity();
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e.kind())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::HostStack;
+    use crate::net::{Ipv4Address, SocketAddress, TcpStack, UdpStack};
+    use crate::testing::poll_once;
+    use core::future::Future;
+    use core::task::Poll;
+    use std::boxed::Box;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Poll `future` until complete, allowing the host's sockets time to progress.
+    fn wait<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        for _ in 0..5000 {
+            if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                return output;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("future never completed")
+    }
+
+    fn local(port: u16) -> SocketAddress {
+        SocketAddress::new(Ipv4Address::LOCALHOST, port)
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+
+        let stack = HostStack::new();
+        let socket = wait(TcpStack::connect(&stack, local(port))).unwrap();
+        assert_eq!(wait(TcpStack::send(&stack, socket, b"hello")), Ok(5));
+
+        let mut buf = [0; 16];
+        let mut len = 0;
+        while len < 5 {
+            len += wait(TcpStack::receive(&stack, socket, &mut buf[len..])).unwrap();
+        }
+        assert_eq!(&buf[..len], b"hello");
+
+        server.join().unwrap();
+        assert_eq!(wait(TcpStack::receive(&stack, socket, &mut buf)), Ok(0));
+        wait(TcpStack::close(&stack, socket));
+    }
+
+    #[test]
+    fn udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let stack = HostStack::new();
+        let socket = wait(UdpStack::open(&stack, local(port))).unwrap();
+        wait(UdpStack::send(&stack, socket, b"ping")).unwrap();
+
+        let mut buf = [0; 16];
+        let (len, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        server.send_to(b"pong!", from).unwrap();
+
+        let mut short = [0; 4];
+        assert_eq!(wait(UdpStack::receive(&stack, socket, &mut short)), Ok(4));
+        assert_eq!(&short, b"pong");
+        wait(UdpStack::close(&stack, socket));
+    }
+}
@@ -0,0 +1,484 @@
+use crate::component::{Component, ComponentContext};
+use crate::context::UpstreamContext;
+use crate::driver::lock::BusLock;
+use crate::net::{SocketAddress, TcpStack};
+use crate::time::{Delay, Duration, WithTimeout};
+use core::cell::Cell;
+use core::fmt::Write;
+use heapless::{consts::*, String, Vec};
+
+/// Parsing of HTTP/1.1 responses, as their bytes are received.
+mod response;
+
+use response::ResponseParser;
+
+/// The request line and headers of a request.
+type Head = String<U512>;
+
+/// The method of a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+/// Errors reported by an `HttpClient`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HttpError {
+    /// The client is not started, or could not connect to the server.
+    NotConnected,
+    /// The connection failed, or was closed, before the response was complete.
+    Network,
+    /// The server did not respond in time.
+    Timeout,
+    /// The response was not valid HTTP/1.1.
+    Malformed,
+    /// The request's head was too long to be sent, or the response's body
+    /// too long to be held.
+    TooLong,
+}
+
+/// The server of an `HttpClient`.
+#[derive(Copy, Clone, Debug)]
+pub struct HttpConfig {
+    pub server: SocketAddress,
+    /// The name of the server, sent as the `Host` header.
+    pub host: &'static str,
+    /// The time awaited for each response to be complete.
+    pub timeout: Duration,
+}
+
+impl HttpConfig {
+    pub fn new(server: SocketAddress, host: &'static str) -> Self {
+        Self {
+            server,
+            host,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The response to a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: Option<String<U64>>,
+    pub body: Vec<u8, U1024>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        self.status / 100 == 2
+    }
+}
+
+/// A minimal HTTP/1.1 client upon a `TcpStack`, making requests of one
+/// server, one at a time, over a connection kept alive between them, and
+/// connecting again should the server have closed it meanwhile.
+///
+/// Responses may be delimited by their `Content-Length`, chunked, or by
+/// the server closing the connection, and their bodies are held in fixed
+/// buffers, no longer than 1024 bytes.
+///
+/// The client is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static HttpClient<S>>`, once the `TcpStack` is.
+pub struct HttpClient<S>
+where
+    S: TcpStack + 'static,
+{
+    stack: &'static S,
+    config: HttpConfig,
+    socket: Cell<Option<S::Socket>>,
+    request: BusLock,
+    upstream: Cell<Option<&'static dyn UpstreamContext<()>>>,
+}
+
+impl<S> HttpClient<S>
+where
+    S: TcpStack + 'static,
+{
+    pub fn new(stack: &'static S, config: HttpConfig) -> Self {
+        Self {
+            stack,
+            config,
+            socket: Cell::new(None),
+            request: BusLock::new(),
+            upstream: Cell::new(None),
+        }
+    }
+
+    /// Get, *asynchronously*, the resource at `path`.
+    pub async fn get(&self, path: &str) -> Result<Response, HttpError> {
+        self.request(Method::Get, path, &[], &[]).await
+    }
+
+    /// Post, *asynchronously*, `body` of `content_type` to the resource at `path`.
+    pub async fn post(
+        &self,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<Response, HttpError> {
+        self.request(Method::Post, path, &[("Content-Type", content_type)], body)
+            .await
+    }
+
+    /// Request, *asynchronously*, the resource at `path`, with `headers`
+    /// besides `Host` and `Content-Length`, and `body`.
+    ///
+    /// Should the server have closed the connection kept alive since the
+    /// previous request, the request is made again upon a new connection.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, HttpError> {
+        let upstream = self.upstream.get().ok_or(HttpError::NotConnected)?;
+        let head = self.head(method, path, headers, body)?;
+
+        let _guard = self.request.lock().await;
+        let mut result = Err(HttpError::NotConnected);
+        for _ in 0..2 {
+            let (socket, reused) = match self.socket.get() {
+                Some(socket) => (socket, true),
+                None => match self.stack.connect(self.config.server).await {
+                    Ok(socket) => (socket, false),
+                    Err(_) => break,
+                },
+            };
+            self.socket.set(None);
+            let deadline = upstream.now() + self.config.timeout;
+            let exchanged = self.exchange(socket, method, &head, body);
+            let (keep, received) =
+                match WithTimeout::new(exchanged, Delay::new(upstream, deadline)).await {
+                    Some(Exchanged::Complete { keep, response }) => {
+                        result = response;
+                        (keep, true)
+                    }
+                    Some(Exchanged::Failed { received }) => {
+                        result = Err(HttpError::Network);
+                        (false, received)
+                    }
+                    None => {
+                        result = Err(HttpError::Timeout);
+                        (false, true)
+                    }
+                };
+            if keep {
+                self.socket.set(Some(socket));
+            } else {
+                self.stack.close(socket).await;
+            }
+            if !reused || received {
+                break;
+            }
+        }
+        result
+    }
+
+    fn head(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Head, HttpError> {
+        let mut head = Head::new();
+        let mut write = || -> core::fmt::Result {
+            write!(head, "{} {} HTTP/1.1\r\n", method.as_str(), path)?;
+            write!(head, "Host: {}\r\n", self.config.host)?;
+            for (name, value) in headers {
+                write!(head, "{}: {}\r\n", name, value)?;
+            }
+            if !body.is_empty() || method == Method::Post || method == Method::Put {
+                write!(head, "Content-Length: {}\r\n", body.len())?;
+            }
+            head.push_str("\r\n").map_err(|_| core::fmt::Error)
+        };
+        write().map_err(|_| HttpError::TooLong)?;
+        Ok(head)
+    }
+
+    /// Send, *asynchronously*, the request upon `socket`, and receive its response.
+    async fn exchange(
+        &self,
+        socket: S::Socket,
+        method: Method,
+        head: &str,
+        body: &[u8],
+    ) -> Exchanged {
+        let failed = Exchanged::Failed { received: false };
+        if self.stack.send(socket, head.as_bytes()).await.is_err() {
+            return failed;
+        }
+        if !body.is_empty() && self.stack.send(socket, body).await.is_err() {
+            return failed;
+        }
+
+        let mut parser = ResponseParser::new(method == Method::Head);
+        let mut buf = [0; 256];
+        let mut received = false;
+        while !parser.is_done() {
+            let len = match self.stack.receive(socket, &mut buf).await {
+                Ok(0) if parser.closed().is_ok() => break,
+                Ok(len) if len > 0 => len,
+                _ => return Exchanged::Failed { received },
+            };
+            received = true;
+            if let Err(e) = parser.feed(&buf[..len]) {
+                return Exchanged::Complete {
+                    keep: false,
+                    response: Err(e),
+                };
+            }
+        }
+        Exchanged::Complete {
+            keep: !parser.closes(),
+            response: parser.response(),
+        }
+    }
+}
+
+/// How an exchange of a request and its response ended.
+// moved out of the exchange once per request, so its size is of no concern
+#[allow(clippy::large_enum_variant)]
+enum Exchanged {
+    Complete {
+        /// Whether the connection may be kept alive for the next request.
+        keep: bool,
+        response: Result<Response, HttpError>,
+    },
+    Failed {
+        /// Whether any of the response was received.
+        received: bool,
+    },
+}
+
+impl<S> Component for &'static HttpClient<S>
+where
+    S: TcpStack + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.upstream.set(Some(ctx.upstream()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{HttpClient, HttpConfig, HttpError, Method};
+    use crate::net::{HostStack, Ipv4Address, SocketAddress};
+    use crate::testing::{leak, poll_once, Upstream};
+    use crate::time::Duration;
+    use core::future::Future;
+    use core::task::Poll;
+    use std::boxed::Box;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::string::{String, ToString};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::vec::Vec;
+
+    /// A request received by the server stand-in.
+    struct Received {
+        head: Vec<String>,
+        body: Vec<u8>,
+    }
+
+    #[derive(Default)]
+    struct Log {
+        connections: usize,
+        requests: Vec<Received>,
+    }
+
+    /// A server stand-in, serving each connection until closed, echoing
+    /// bodies posted, and otherwise responding as each path describes.
+    fn server() -> (u16, Arc<Mutex<Log>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = Arc::new(Mutex::new(Log::default()));
+        let shared = log.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                shared.lock().unwrap().connections += 1;
+                let log = shared.clone();
+                thread::spawn(move || serve(stream.unwrap(), &log));
+            }
+        });
+        (port, log)
+    }
+
+    fn serve(mut stream: TcpStream, log: &Mutex<Log>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                let line = line.trim_end().to_string();
+                if line.is_empty() {
+                    break;
+                }
+                head.push(line);
+            }
+            let length = head
+                .iter()
+                .find_map(|h| h.strip_prefix("Content-Length: "))
+                .map_or(0, |l| l.parse().unwrap());
+            let mut body = std::vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let path = head[0].split(' ').nth(1).unwrap().to_string();
+            log.lock().unwrap().requests.push(Received {
+                head,
+                body: body.clone(),
+            });
+
+            let response: Vec<u8> = match path.as_str() {
+                "/telemetry" => {
+                    let mut response = std::format!(
+                        "HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&body);
+                    response
+                }
+                "/chunked" => b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello,\r\n7\r\n world!\r\n0\r\n\r\n".to_vec(),
+                "/large" => {
+                    let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 2000\r\n\r\n".to_vec();
+                    response.resize(response.len() + 2000, b'x');
+                    response
+                }
+                "/close" => b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nbye".to_vec(),
+                "/drop" => b"HTTP/1.1 204 No Content\r\n\r\n".to_vec(),
+                "/slow" => continue,
+                _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            };
+            stream.write_all(&response).unwrap();
+            if path == "/close" || path == "/drop" {
+                return;
+            }
+        }
+    }
+
+    type Client = HttpClient<HostStack>;
+
+    /// Runs futures, advancing time by ten milliseconds at each step.
+    struct Harness {
+        upstream: &'static Upstream<()>,
+        client: &'static Client,
+    }
+
+    impl Harness {
+        fn new(port: u16) -> Self {
+            let mut config = HttpConfig::new(
+                SocketAddress::new(Ipv4Address::LOCALHOST, port),
+                "localhost",
+            );
+            config.timeout = Duration::from_secs(1);
+            let upstream = leak(Upstream::new());
+            let client: &'static Client = leak(HttpClient::new(leak(HostStack::new()), config));
+            client.upstream.set(Some(upstream));
+            Self { upstream, client }
+        }
+
+        fn run<F: Future>(&mut self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            for _ in 0..1000 {
+                if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                    return output;
+                }
+                self.upstream.advance(Duration::from_millis(10));
+                thread::sleep(std::time::Duration::from_millis(1));
+            }
+            panic!("future never completed")
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let (port, log) = server();
+        let mut h = Harness::new(port);
+        let client = h.client;
+        let connections = || log.lock().unwrap().connections;
+
+        let body = br#"{"temp":21.5}"#;
+        for _ in 0..2 {
+            let response = h
+                .run(client.post("/telemetry", "application/json", body))
+                .unwrap();
+            assert_eq!(response.status, 201);
+            assert!(response.is_success());
+            assert_eq!(response.content_type.as_deref(), Some("application/json"));
+            assert_eq!(&response.body[..], body);
+        }
+        {
+            let log = log.lock().unwrap();
+            let request = &log.requests[0];
+            assert_eq!(
+                request.head,
+                [
+                    "POST /telemetry HTTP/1.1",
+                    "Host: localhost",
+                    "Content-Type: application/json",
+                    "Content-Length: 13",
+                ]
+            );
+            assert_eq!(&request.body[..], body);
+        }
+        // kept alive
+        assert_eq!(connections(), 1);
+
+        let response = h.run(client.get("/chunked")).unwrap();
+        assert_eq!(&response.body[..], b"hello, world!");
+        assert_eq!(h.run(client.get("/large")), Err(HttpError::TooLong));
+        assert_eq!(h.run(client.get("/missing")).unwrap().status, 404);
+        assert_eq!(
+            h.run(client.request(Method::Delete, "/missing", &[("X-Test", "1")], &[]))
+                .unwrap()
+                .status,
+            404
+        );
+        assert_eq!(connections(), 1);
+
+        // closed by the server, without saying so, so requested again
+        assert_eq!(h.run(client.get("/drop")).unwrap().status, 204);
+        assert_eq!(h.run(client.get("/chunked")).unwrap().status, 200);
+        assert_eq!(connections(), 2);
+
+        // delimited by closing the connection
+        let response = h.run(client.get("/close")).unwrap();
+        assert_eq!(&response.body[..], b"bye");
+        assert_eq!(h.run(client.get("/chunked")).unwrap().status, 200);
+        assert_eq!(connections(), 3);
+
+        assert_eq!(h.run(client.get("/slow")), Err(HttpError::Timeout));
+        assert_eq!(h.run(client.get("/chunked")).unwrap().status, 200);
+        assert_eq!(connections(), 4);
+    }
+}
@@ -0,0 +1,268 @@
+use super::{HttpError, Response};
+use heapless::{consts::*, String, Vec};
+
+/// The longest part of a status or header line kept, the remainder being
+/// of no interest to the client.
+type Line = Vec<u8, U128>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Status,
+    Header,
+    Body { remaining: usize },
+    ChunkSize,
+    ChunkData { remaining: usize },
+    ChunkEnd,
+    Trailer,
+    UntilClosed,
+    Done,
+}
+
+/// A response being received, fed with the bytes received until done.
+pub(crate) struct ResponseParser {
+    state: State,
+    line: Line,
+    length: Option<usize>,
+    chunked: bool,
+    close: bool,
+    truncated: bool,
+    response: Response,
+}
+
+impl ResponseParser {
+    pub(crate) fn new(bodiless: bool) -> Self {
+        Self {
+            state: State::Status,
+            line: Line::new(),
+            length: if bodiless { Some(0) } else { None },
+            chunked: false,
+            close: false,
+            truncated: false,
+            response: Response {
+                status: 0,
+                content_type: None,
+                body: Vec::new(),
+            },
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Determine if the server will close the connection after this response.
+    pub(crate) fn closes(&self) -> bool {
+        self.close || self.state == State::UntilClosed
+    }
+
+    /// Parse the bytes of `data`, returning the number consumed, which is
+    /// fewer only once done.
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Result<usize, HttpError> {
+        let mut consumed = 0;
+        while consumed < data.len() && !self.is_done() {
+            let rest = &data[consumed..];
+            consumed += match self.state {
+                State::Status
+                | State::Header
+                | State::ChunkSize
+                | State::ChunkEnd
+                | State::Trailer => {
+                    let (len, complete) = match rest.iter().position(|b| *b == b'\n') {
+                        Some(at) => (at + 1, true),
+                        None => (rest.len(), false),
+                    };
+                    let room = self.line.capacity() - self.line.len();
+                    self.line.extend_from_slice(&rest[..len.min(room)]).ok();
+                    if complete {
+                        self.line()?;
+                        self.line = Line::new();
+                    }
+                    len
+                }
+                State::Body { remaining } | State::ChunkData { remaining } => {
+                    let len = remaining.min(rest.len());
+                    self.body(&rest[..len]);
+                    self.state = match (self.state, remaining - len) {
+                        (State::Body { .. }, 0) => State::Done,
+                        (State::Body { .. }, remaining) => State::Body { remaining },
+                        (_, 0) => State::ChunkEnd,
+                        (_, remaining) => State::ChunkData { remaining },
+                    };
+                    len
+                }
+                State::UntilClosed => {
+                    self.body(rest);
+                    rest.len()
+                }
+                State::Done => 0,
+            };
+        }
+        Ok(consumed)
+    }
+
+    /// Complete the response once the connection has been closed, failing
+    /// should the response have been cut short.
+    pub(crate) fn closed(&mut self) -> Result<(), HttpError> {
+        if self.state == State::UntilClosed {
+            self.state = State::Done;
+            Ok(())
+        } else {
+            Err(HttpError::Network)
+        }
+    }
+
+    /// The response, once done, or `TooLong` should its body not have fit.
+    pub(crate) fn response(self) -> Result<Response, HttpError> {
+        if self.truncated {
+            Err(HttpError::TooLong)
+        } else {
+            Ok(self.response)
+        }
+    }
+
+    fn body(&mut self, data: &[u8]) {
+        let body = &mut self.response.body;
+        let room = body.capacity() - body.len();
+        if data.len() > room {
+            self.truncated = true;
+        }
+        body.extend_from_slice(&data[..data.len().min(room)]).ok();
+    }
+
+    /// Process the line just received, without its line ending.
+    fn line(&mut self) -> Result<(), HttpError> {
+        let line = core::str::from_utf8(&self.line)
+            .map_err(|_| HttpError::Malformed)?
+            .trim_end_matches(['\r', '\n']);
+        match self.state {
+            State::Status => {
+                let mut parts = line.splitn(3, ' ');
+                let version = parts.next().unwrap_or_default();
+                if !version.starts_with("HTTP/1.") {
+                    return Err(HttpError::Malformed);
+                }
+                if version == "HTTP/1.0" {
+                    self.close = true;
+                }
+                self.response.status = parts
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or(HttpError::Malformed)?;
+                if self.response.status == 204 || self.response.status == 304 {
+                    self.length = Some(0);
+                }
+                self.state = State::Header;
+            }
+            State::Header if line.is_empty() => {
+                self.state = match (self.chunked, self.length) {
+                    // interim, to be followed by the final response
+                    _ if self.response.status / 100 == 1 => State::Status,
+                    (true, _) => State::ChunkSize,
+                    (false, Some(0)) => State::Done,
+                    (false, Some(remaining)) => State::Body { remaining },
+                    (false, None) => State::UntilClosed,
+                };
+            }
+            State::Header => {
+                let (name, value) = match line.find(':') {
+                    Some(at) => (&line[..at], line[at + 1..].trim()),
+                    None => return Err(HttpError::Malformed),
+                };
+                if name.eq_ignore_ascii_case("content-length") {
+                    if self.length.is_none() {
+                        self.length = Some(value.parse().map_err(|_| HttpError::Malformed)?);
+                    }
+                } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                    self.chunked = value.eq_ignore_ascii_case("chunked");
+                } else if name.eq_ignore_ascii_case("connection") {
+                    self.close = value.eq_ignore_ascii_case("close");
+                } else if name.eq_ignore_ascii_case("content-type") {
+                    let mut content_type = String::new();
+                    if content_type.push_str(value).is_ok() {
+                        self.response.content_type = Some(content_type);
+                    }
+                }
+                if self.length == Some(0) && self.chunked {
+                    // bodiless, whatever the encoding
+                    self.chunked = false;
+                }
+            }
+            State::ChunkSize => {
+                let size = line.split(';').next().unwrap_or_default().trim();
+                self.state = match usize::from_str_radix(size, 16) {
+                    Ok(0) => State::Trailer,
+                    Ok(remaining) => State::ChunkData { remaining },
+                    Err(_) => return Err(HttpError::Malformed),
+                };
+            }
+            State::ChunkEnd if line.is_empty() => self.state = State::ChunkSize,
+            State::ChunkEnd => return Err(HttpError::Malformed),
+            State::Trailer if line.is_empty() => self.state = State::Done,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseParser;
+    use crate::net::http::HttpError;
+
+    /// Feed `data` to a parser, a byte at a time, returning it once done.
+    fn parse(data: &[u8], bodiless: bool) -> ResponseParser {
+        let mut parser = ResponseParser::new(bodiless);
+        for byte in data.chunks(1) {
+            assert!(!parser.is_done());
+            assert_eq!(parser.feed(byte), Ok(1));
+        }
+        parser
+    }
+
+    #[test]
+    fn responses() {
+        let parser = parse(
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\ncontent-type: text/plain\r\nContent-Length: 2\r\n\r\nok",
+            false,
+        );
+        assert!(parser.is_done());
+        assert!(!parser.closes());
+        let response = parser.response().unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(&response.body[..], b"ok");
+
+        let parser = parse(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext\r\nhello\r\nA\r\n, chunked!\r\n0\r\nTrailer: 1\r\n\r\n",
+            false,
+        );
+        assert!(parser.is_done());
+        assert_eq!(&parser.response().unwrap().body[..], b"hello, chunked!");
+
+        // a response to HEAD has no body, whatever its headers
+        let parser = parse(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", true);
+        assert!(parser.is_done());
+
+        let mut parser = parse(b"HTTP/1.0 200 OK\r\n\r\nuntil closed", false);
+        assert!(parser.closes());
+        assert_eq!(parser.closed(), Ok(()));
+        assert_eq!(&parser.response().unwrap().body[..], b"until closed");
+
+        // bytes beyond the response are left unconsumed
+        let mut parser = ResponseParser::new(false);
+        assert_eq!(parser.feed(b"HTTP/1.1 204 No Content\r\n\r\nHTTP"), Ok(27));
+        assert!(parser.is_done());
+
+        let mut parser = ResponseParser::new(false);
+        assert_eq!(
+            parser.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 2000\r\n\r\n"),
+            Ok(41)
+        );
+        assert_eq!(parser.feed(&[0; 2000]), Ok(2000));
+        assert!(parser.is_done());
+        assert_eq!(parser.response(), Err(HttpError::TooLong));
+
+        let mut parser = ResponseParser::new(false);
+        assert_eq!(parser.feed(b"SMTP 220\r\n"), Err(HttpError::Malformed));
+    }
+}
@@ -0,0 +1,166 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::{Aes128, Block};
+use cmac::{Cmac, Mac};
+
+/// An AES-128 key, such as an AppKey or session key.
+pub(crate) type Key = [u8; 16];
+
+pub(crate) type Mic = [u8; 4];
+
+/// Encrypt `block` in place with `key`.
+pub(crate) fn encrypt(key: &Key, block: &mut [u8; 16]) {
+    Aes128::new(GenericArray::from_slice(key)).encrypt_block(Block::from_mut_slice(block));
+}
+
+/// The first four bytes of the AES-CMAC of the concatenated `parts`.
+pub(crate) fn mic(key: &Key, parts: &[&[u8]]) -> Mic {
+    let mut cmac = <Cmac<Aes128> as Mac>::new_from_slice(key).expect("keys are 16 bytes");
+    for part in parts {
+        cmac.update(part);
+    }
+    let mut mic = [0; 4];
+    mic.copy_from_slice(&cmac.finalize().into_bytes()[..4]);
+    mic
+}
+
+/// Derive a session key, the NwkSKey of `kind` 1 or the AppSKey of
+/// `kind` 2, from the fields of a join.
+pub(crate) fn session_key(
+    app_key: &Key,
+    kind: u8,
+    app_nonce: &[u8; 3],
+    net_id: &[u8; 3],
+    dev_nonce: u16,
+) -> Key {
+    let mut key = [0; 16];
+    key[0] = kind;
+    key[1..4].copy_from_slice(app_nonce);
+    key[4..7].copy_from_slice(net_id);
+    key[7..9].copy_from_slice(&dev_nonce.to_le_bytes());
+    encrypt(app_key, &mut key);
+    key
+}
+
+/// The block identifying a data frame, prefixed by `tag`, of `uplink` or
+/// downlink direction, `dev_addr`, `fcnt` and, for MICs, `len`.
+fn block(tag: u8, uplink: bool, dev_addr: u32, fcnt: u32, last: u8) -> [u8; 16] {
+    let mut block = [0; 16];
+    block[0] = tag;
+    block[5] = !uplink as u8;
+    block[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    block[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    block[15] = last;
+    block
+}
+
+/// Encrypt, or decrypt, the `FRMPayload` of a data frame in place.
+pub(crate) fn cipher(key: &Key, uplink: bool, dev_addr: u32, fcnt: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(16).enumerate() {
+        let mut stream = block(0x01, uplink, dev_addr, fcnt, i as u8 + 1);
+        encrypt(key, &mut stream);
+        for (b, s) in chunk.iter_mut().zip(stream.iter()) {
+            *b ^= s;
+        }
+    }
+}
+
+/// The MIC of the data frame `message`, which excludes the MIC itself.
+pub(crate) fn data_mic(key: &Key, uplink: bool, dev_addr: u32, fcnt: u32, message: &[u8]) -> Mic {
+    let b0 = block(0x49, uplink, dev_addr, fcnt, message.len() as u8);
+    mic(key, &[&b0, message])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cipher, data_mic, encrypt, mic, session_key};
+
+    const KEY: [u8; 16] = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+
+    // frames published alongside the lora-packet library
+    const APP_KEY: [u8; 16] = [
+        0xB6, 0xB5, 0x3F, 0x4A, 0x16, 0x8A, 0x7A, 0x88, 0xBD, 0xF7, 0xEA, 0x13, 0x5C, 0xE9, 0xCF,
+        0xCA,
+    ];
+    const JOIN_REQUEST: [u8; 23] = [
+        0x00, 0xDC, 0x00, 0x00, 0xD0, 0x7E, 0xD5, 0xB3, 0x70, 0x1E, 0x6F, 0xED, 0xF5, 0x7C, 0xEE,
+        0xAF, 0x00, 0x85, 0xCC, 0x58, 0x7F, 0xE9, 0x13,
+    ];
+    const JOIN_ACCEPT: [u8; 33] = [
+        0x20, 0x4D, 0xD8, 0x5A, 0xE6, 0x08, 0xB8, 0x7F, 0xC4, 0x88, 0x99, 0x70, 0xB7, 0xD2, 0x04,
+        0x2C, 0x9E, 0x72, 0x95, 0x9B, 0x00, 0x57, 0xAE, 0xD6, 0x09, 0x4B, 0x16, 0x00, 0x3D, 0xF1,
+        0x2D, 0xE1, 0x45,
+    ];
+    const NWK_SKEY: [u8; 16] = [
+        0x44, 0x02, 0x42, 0x41, 0xED, 0x4C, 0xE9, 0xA6, 0x8C, 0x6A, 0x8B, 0xC0, 0x55, 0x23, 0x3F,
+        0xD3,
+    ];
+    const APP_SKEY: [u8; 16] = [
+        0xEC, 0x92, 0x58, 0x02, 0xAE, 0x43, 0x0C, 0xA7, 0x7F, 0xD3, 0xDD, 0x73, 0xCB, 0x2C, 0xC5,
+        0x88,
+    ];
+    const UPLINK: [u8; 17] = [
+        0x40, 0xF1, 0x7D, 0xBE, 0x49, 0x00, 0x02, 0x00, 0x01, 0x95, 0x43, 0x78, 0x76, 0x2B, 0x11,
+        0xFF, 0x0D,
+    ];
+
+    #[test]
+    fn primitives() {
+        // the AES-CMAC of the empty message, from RFC 4493
+        assert_eq!(mic(&KEY, &[]), [0xBB, 0x1D, 0x69, 0x29]);
+        assert_eq!(mic(&KEY, &[b"ab", b"c"]), mic(&KEY, &[b"abc"]));
+
+        let mut data = *b"a payload of more than one block";
+        cipher(&KEY, true, 0x2601_1234, 7, &mut data);
+        assert_ne!(&data, b"a payload of more than one block");
+        // a different direction or counter yields a different stream
+        let mut other = *b"a payload of more than one block";
+        cipher(&KEY, false, 0x2601_1234, 7, &mut other);
+        assert_ne!(data, other);
+        cipher(&KEY, true, 0x2601_1234, 7, &mut data);
+        assert_eq!(&data, b"a payload of more than one block");
+
+        assert_ne!(
+            data_mic(&KEY, true, 0x2601_1234, 7, b"frame"),
+            data_mic(&KEY, true, 0x2601_1234, 8, b"frame")
+        );
+        assert_ne!(
+            session_key(&KEY, 1, &[1, 2, 3], &[0, 0, 0x13], 0),
+            session_key(&KEY, 2, &[1, 2, 3], &[0, 0, 0x13], 0)
+        );
+
+        // known answers
+        assert_eq!(&mic(&APP_KEY, &[&JOIN_REQUEST[..19]]), &JOIN_REQUEST[19..]);
+
+        // encrypted with AES decryption, so decrypted with encryption
+        let mut accept = [0; 33];
+        accept[0] = JOIN_ACCEPT[0];
+        for (plain, sealed) in accept[1..].chunks_mut(16).zip(JOIN_ACCEPT[1..].chunks(16)) {
+            let mut block = [0; 16];
+            block.copy_from_slice(sealed);
+            encrypt(&APP_KEY, &mut block);
+            plain.copy_from_slice(&block);
+        }
+        assert_eq!(
+            accept,
+            [
+                0x20, 0x3A, 0x06, 0xE5, 0x13, 0x00, 0x00, 0x43, 0x2E, 0x01, 0x26, 0x03, 0x01, 0x18,
+                0x4F, 0x84, 0xE8, 0x56, 0x84, 0xB8, 0x5E, 0x84, 0x88, 0x66, 0x84, 0x58, 0x6E, 0x84,
+                0x00, 0x55, 0x12, 0x1D, 0xE0,
+            ]
+        );
+        assert_eq!(&mic(&APP_KEY, &[&accept[..29]]), &accept[29..]);
+
+        assert_eq!(
+            &data_mic(&NWK_SKEY, true, 0x49BE_7DF1, 2, &UPLINK[..13]),
+            &UPLINK[13..]
+        );
+        let mut payload = [0; 4];
+        payload.copy_from_slice(&UPLINK[9..13]);
+        cipher(&APP_SKEY, true, 0x49BE_7DF1, 2, &mut payload);
+        assert_eq!(&payload, b"test");
+    }
+}
@@ -0,0 +1,285 @@
+use super::crypto::{self, Key};
+use heapless::{consts::*, Vec};
+
+pub(crate) const JOIN_REQUEST: u8 = 0x00;
+pub(crate) const JOIN_ACCEPT: u8 = 0x20;
+pub(crate) const UNCONFIRMED_UP: u8 = 0x40;
+pub(crate) const UNCONFIRMED_DOWN: u8 = 0x60;
+pub(crate) const CONFIRMED_UP: u8 = 0x80;
+pub(crate) const CONFIRMED_DOWN: u8 = 0xA0;
+
+const MTYPE: u8 = 0xE0;
+
+pub(crate) const FCTRL_ADR: u8 = 0x80;
+pub(crate) const FCTRL_ADR_ACK_REQ: u8 = 0x40;
+pub(crate) const FCTRL_ACK: u8 = 0x20;
+const FCTRL_FOPTS_LEN: u8 = 0x0F;
+
+/// The header, frame header less its options, and MIC of every data frame.
+const DATA_OVERHEAD: usize = 1 + 7 + 4;
+
+/// A frame, as transmitted or received.
+pub(crate) type Frame = Vec<u8, U256>;
+
+/// The MAC commands, or their answers, carried by the options of a frame.
+pub(crate) type Options = Vec<u8, U15>;
+
+/// The fields of a join-accept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct JoinAccept {
+    pub(crate) app_nonce: [u8; 3],
+    pub(crate) net_id: [u8; 3],
+    pub(crate) dev_addr: u32,
+    pub(crate) rx1_dr_offset: u8,
+    pub(crate) rx2_data_rate: u8,
+    /// The delay of the first receive window, in seconds, or zero for one.
+    pub(crate) rx_delay: u8,
+    /// The frequencies of channels beyond the default, each zero should
+    /// its channel be disabled.
+    pub(crate) channels: [u32; 5],
+}
+
+/// A data frame sent by the device.
+pub(crate) struct Uplink<'d> {
+    pub(crate) confirmed: bool,
+    pub(crate) dev_addr: u32,
+    pub(crate) fctrl: u8,
+    pub(crate) fcnt: u32,
+    pub(crate) options: &'d [u8],
+    pub(crate) port: u8,
+    pub(crate) payload: &'d [u8],
+}
+
+/// A data frame received by the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Downlink {
+    pub(crate) confirmed: bool,
+    pub(crate) fctrl: u8,
+    pub(crate) fcnt: u32,
+    pub(crate) options: Options,
+    pub(crate) port: Option<u8>,
+    pub(crate) payload: Vec<u8, U256>,
+}
+
+/// Reverse an EUI, written most significant byte first, as it is sent.
+fn eui(eui: &[u8; 8]) -> [u8; 8] {
+    let mut reversed = *eui;
+    reversed.reverse();
+    reversed
+}
+
+pub(crate) fn join_request(
+    join_eui: &[u8; 8],
+    dev_eui: &[u8; 8],
+    dev_nonce: u16,
+    app_key: &Key,
+) -> Frame {
+    let mut frame = Frame::new();
+    frame.push(JOIN_REQUEST).ok();
+    frame.extend_from_slice(&eui(join_eui)).ok();
+    frame.extend_from_slice(&eui(dev_eui)).ok();
+    frame.extend_from_slice(&dev_nonce.to_le_bytes()).ok();
+    let mic = crypto::mic(app_key, &[&frame]);
+    frame.extend_from_slice(&mic).ok();
+    frame
+}
+
+/// A frequency, in Hz, sent as three bytes counting hundreds of Hz.
+pub(crate) fn frequency(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) * 100
+}
+
+/// Decrypt and verify the join-accept `frame`.
+pub(crate) fn join_accept(app_key: &Key, frame: &[u8]) -> Option<JoinAccept> {
+    if (frame.len() != 17 && frame.len() != 33) || frame[0] != JOIN_ACCEPT {
+        return None;
+    }
+    // encrypted by the network server with AES decryption, so decrypted with encryption
+    let mut plain = [0; 33];
+    plain[0] = frame[0];
+    for (i, chunk) in frame[1..].chunks(16).enumerate() {
+        let mut block = [0; 16];
+        block.copy_from_slice(chunk);
+        crypto::encrypt(app_key, &mut block);
+        plain[1 + i * 16..1 + (i + 1) * 16].copy_from_slice(&block);
+    }
+    let (message, mic) = plain[..frame.len()].split_at(frame.len() - 4);
+    if crypto::mic(app_key, &[message]) != mic {
+        return None;
+    }
+
+    let mut accept = JoinAccept {
+        app_nonce: [message[1], message[2], message[3]],
+        net_id: [message[4], message[5], message[6]],
+        dev_addr: u32::from_le_bytes([message[7], message[8], message[9], message[10]]),
+        rx1_dr_offset: (message[11] >> 4) & 0x07,
+        rx2_data_rate: message[11] & 0x0F,
+        rx_delay: message[12] & 0x0F,
+        channels: [0; 5],
+    };
+    // a list of frequencies, should its type be zero
+    if message.len() == 29 && message[28] == 0 {
+        for (i, channel) in accept.channels.iter_mut().enumerate() {
+            *channel = frequency(&message[13 + i * 3..]);
+        }
+    }
+    Some(accept)
+}
+
+/// Encode, encrypt and sign `uplink`, returning `None` should its options
+/// be too long.
+pub(crate) fn uplink(nwk_skey: &Key, app_skey: &Key, uplink: &Uplink<'_>) -> Option<Frame> {
+    if uplink.options.len() > FCTRL_FOPTS_LEN as usize {
+        return None;
+    }
+    let mut frame = Frame::new();
+    frame
+        .push(if uplink.confirmed {
+            CONFIRMED_UP
+        } else {
+            UNCONFIRMED_UP
+        })
+        .ok();
+    frame.extend_from_slice(&uplink.dev_addr.to_le_bytes()).ok();
+    frame.push(uplink.fctrl | uplink.options.len() as u8).ok();
+    frame
+        .extend_from_slice(&(uplink.fcnt as u16).to_le_bytes())
+        .ok();
+    frame.extend_from_slice(uplink.options).ok();
+    frame.push(uplink.port).ok();
+    let start = frame.len();
+    frame.extend_from_slice(uplink.payload).ok()?;
+    let key = if uplink.port == 0 { nwk_skey } else { app_skey };
+    crypto::cipher(key, true, uplink.dev_addr, uplink.fcnt, &mut frame[start..]);
+
+    let mic = crypto::data_mic(nwk_skey, true, uplink.dev_addr, uplink.fcnt, &frame);
+    frame.extend_from_slice(&mic).ok()?;
+    Some(frame)
+}
+
+/// Verify and decrypt the data frame `frame`, should it be addressed to
+/// `dev_addr`, and its counter be at least `next_fcnt`.
+pub(crate) fn downlink(
+    nwk_skey: &Key,
+    app_skey: &Key,
+    dev_addr: u32,
+    next_fcnt: u32,
+    frame: &[u8],
+) -> Option<Downlink> {
+    if frame.len() < DATA_OVERHEAD {
+        return None;
+    }
+    let confirmed = match frame[0] & MTYPE {
+        UNCONFIRMED_DOWN => false,
+        CONFIRMED_DOWN => true,
+        _ => return None,
+    };
+    if u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]) != dev_addr {
+        return None;
+    }
+    let fctrl = frame[5];
+    // the counter's upper bits are inferred from those expected
+    let low = u16::from_le_bytes([frame[6], frame[7]]) as u32;
+    let mut fcnt = (next_fcnt & 0xFFFF_0000) | low;
+    if fcnt < next_fcnt {
+        fcnt = fcnt.checked_add(0x1_0000)?;
+    }
+
+    let (message, mic) = frame.split_at(frame.len() - 4);
+    if crypto::data_mic(nwk_skey, false, dev_addr, fcnt, message) != mic {
+        return None;
+    }
+
+    let options_len = (fctrl & FCTRL_FOPTS_LEN) as usize;
+    let rest = message.get(8..)?;
+    if rest.len() < options_len {
+        return None;
+    }
+    let (options, rest) = rest.split_at(options_len);
+    let mut downlink = Downlink {
+        confirmed,
+        fctrl: fctrl & !FCTRL_FOPTS_LEN,
+        fcnt,
+        options: Vec::from_slice(options).ok()?,
+        port: None,
+        payload: Vec::new(),
+    };
+    if let Some((port, payload)) = rest.split_first() {
+        downlink.port = Some(*port);
+        downlink.payload.extend_from_slice(payload).ok()?;
+        let key = if *port == 0 { nwk_skey } else { app_skey };
+        crypto::cipher(key, false, dev_addr, fcnt, &mut downlink.payload);
+    }
+    Some(downlink)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{downlink, join_request, uplink, Uplink, FCTRL_ADR};
+    use crate::net::lorawan::crypto;
+
+    const NWK_SKEY: [u8; 16] = [1; 16];
+    const APP_SKEY: [u8; 16] = [2; 16];
+
+    #[test]
+    fn frames() {
+        let request = join_request(
+            &[0, 0, 0, 0, 0, 0, 0, 1],
+            &[0x70, 0xB3, 0xD5, 0, 0, 0, 0, 2],
+            0x0102,
+            &[3; 16],
+        );
+        assert_eq!(request.len(), 23);
+        assert_eq!(
+            &request[..19],
+            &[0x00, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0xD5, 0xB3, 0x70, 0x02, 0x01]
+        );
+        assert_eq!(&request[19..], &crypto::mic(&[3; 16], &[&request[..19]]));
+
+        let frame = uplink(
+            &NWK_SKEY,
+            &APP_SKEY,
+            &Uplink {
+                confirmed: false,
+                dev_addr: 0x2601_1234,
+                fctrl: FCTRL_ADR,
+                fcnt: 0x1_0002,
+                options: &[0x03, 0x07],
+                port: 1,
+                payload: b"hi",
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            &frame[..11],
+            &[0x40, 0x34, 0x12, 0x01, 0x26, 0x82, 0x02, 0x00, 0x03, 0x07, 1]
+        );
+        assert_ne!(&frame[11..13], b"hi");
+        assert_eq!(frame.len(), 17);
+
+        // received as though sent the other way
+        let mut sent = frame.clone();
+        sent[0] = 0x60;
+        let len = sent.len() - 4;
+        let mut payload = [0; 2];
+        payload.copy_from_slice(&sent[11..13]);
+        crypto::cipher(&APP_SKEY, true, 0x2601_1234, 0x1_0002, &mut payload);
+        crypto::cipher(&APP_SKEY, false, 0x2601_1234, 0x1_0002, &mut payload);
+        sent[11..13].copy_from_slice(&payload);
+        let mic = crypto::data_mic(&NWK_SKEY, false, 0x2601_1234, 0x1_0002, &sent[..len]);
+        sent[len..].copy_from_slice(&mic);
+
+        let received = downlink(&NWK_SKEY, &APP_SKEY, 0x2601_1234, 0x1_0000, &sent).unwrap();
+        assert_eq!(received.fcnt, 0x1_0002);
+        assert_eq!(&received.options[..], &[0x03, 0x07]);
+        assert_eq!(received.port, Some(1));
+        assert_eq!(&received.payload[..], b"hi");
+        assert!(!received.confirmed);
+
+        // replayed, addressed elsewhere, or tampered with
+        assert!(downlink(&NWK_SKEY, &APP_SKEY, 0x2601_1234, 0x1_0003, &sent).is_none());
+        assert!(downlink(&NWK_SKEY, &APP_SKEY, 0x2601_1235, 0x1_0000, &sent).is_none());
+        sent[12] ^= 1;
+        assert!(downlink(&NWK_SKEY, &APP_SKEY, 0x2601_1234, 0x1_0000, &sent).is_none());
+    }
+}
@@ -0,0 +1,1044 @@
+use crate::component::{Component, ComponentContext};
+use crate::context::UpstreamContext;
+use crate::driver::lock::BusLock;
+use crate::driver::lora::{LoRaConfig, Radio, Received};
+use crate::time::{Delay, Duration, Instant};
+use core::cell::{Cell, RefCell};
+use heapless::{consts::*, Vec};
+
+/// AES-128 encryption and message integrity codes of LoRaWAN frames.
+mod crypto;
+
+/// Encoding and decoding of LoRaWAN 1.0 frames.
+mod frame;
+
+/// The channels, data rates and duty cycles of the EU868 region.
+mod region;
+
+use crypto::Key;
+use frame::{Downlink, Options, Uplink, FCTRL_ACK, FCTRL_ADR, FCTRL_ADR_ACK_REQ};
+use region::{
+    BANDS, DEFAULT_CHANNELS, JOIN_ACCEPT_DELAY, MAX_CHANNELS, MAX_DATA_RATE, MAX_RX1_DR_OFFSET,
+    MAX_TX_POWER, RECEIVE_DELAY, RX2_DATA_RATE, RX2_FREQUENCY,
+};
+
+/// The highest port of application data, those above being reserved.
+const MAX_PORT: u8 = 223;
+
+/// The uplinks without any downlink after which the network is asked to
+/// respond, and then after every further delay of which the device backs
+/// off towards the most robust data rate.
+const ADR_ACK_LIMIT: u32 = 64;
+const ADR_ACK_DELAY: u32 = 32;
+
+/// The symbols of each receive window, beyond a margin for timing error,
+/// within which a preamble must be detected.
+const RX_SYMBOLS: u32 = 12;
+const RX_MARGIN: Duration = Duration::from_millis(20);
+
+const DEFAULT_MASK: u16 = (1 << DEFAULT_CHANNELS.len()) - 1;
+
+const LINK_ADR_REQ: u8 = 0x03;
+const DUTY_CYCLE_REQ: u8 = 0x04;
+const RX_PARAM_SETUP_REQ: u8 = 0x05;
+const DEV_STATUS_REQ: u8 = 0x06;
+const NEW_CHANNEL_REQ: u8 = 0x07;
+const RX_TIMING_SETUP_REQ: u8 = 0x08;
+
+/// Errors reported by a `LoRaWan` device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoRaWanError {
+    /// The device is not started.
+    NotStarted,
+    /// The device has not joined a network.
+    NotJoined,
+    /// The port is reserved, being zero or above 223.
+    InvalidPort,
+    /// The payload is too long to be sent at the current data rate.
+    TooLong,
+    /// The radio failed to transmit or receive.
+    Radio,
+    /// The network did not accept the join, or acknowledge a confirmed uplink.
+    NoResponse,
+}
+
+/// The identity and root key of a `LoRaWan` device, activated over the air.
+#[derive(Copy, Clone, Debug)]
+pub struct LoRaWanConfig {
+    /// The device's EUI, most significant byte first.
+    pub dev_eui: [u8; 8],
+    /// The join server's EUI, formerly the AppEUI, most significant byte first.
+    pub join_eui: [u8; 8],
+    pub app_key: [u8; 16],
+    /// The DevNonce of the first join, counted up by each. Network servers
+    /// reject nonces already used, so it should be restored, from
+    /// `LoRaWan::dev_nonce()`, across restarts of the device.
+    pub dev_nonce: u16,
+    /// The data rate of joins, and of uplinks until adjusted by the network.
+    pub data_rate: u8,
+    /// Whether the network may adjust the data rate, transmit power and
+    /// channels of uplinks.
+    pub adr: bool,
+}
+
+impl LoRaWanConfig {
+    pub fn new(dev_eui: [u8; 8], join_eui: [u8; 8], app_key: [u8; 16]) -> Self {
+        Self {
+            dev_eui,
+            join_eui,
+            app_key,
+            dev_nonce: 0,
+            data_rate: 5,
+            adr: true,
+        }
+    }
+}
+
+/// Messages sent upstream by a `LoRaWan` device.
+// passed by value, as the kernel's messages are, so the payload is held inline
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoRaWanEvent {
+    /// The network accepted a join, assigning `dev_addr`.
+    Joined { dev_addr: u32 },
+    /// Application data was received upon `port`.
+    Received { port: u8, data: Vec<u8, U256> },
+}
+
+/// The parameters of uplinks and their receive windows, as adjusted by the network.
+#[derive(Copy, Clone, Debug)]
+struct Link {
+    /// The frequency of each channel, or zero should it be undefined.
+    channels: [u32; MAX_CHANNELS],
+    /// The channels enabled, one bit each.
+    mask: u16,
+    data_rate: u8,
+    tx_power: u8,
+    /// The transmissions of each uplink, unless a downlink is received.
+    nb_trans: u8,
+    rx1_dr_offset: u8,
+    rx2_data_rate: u8,
+    rx2_frequency: u32,
+    rx_delay: Duration,
+    /// The exponent of the aggregate duty cycle, 1/2^n of all time, or zero
+    /// should only the duty cycles of sub-bands apply.
+    max_duty_cycle: u8,
+}
+
+impl Link {
+    fn new(data_rate: u8) -> Self {
+        let mut channels = [0; MAX_CHANNELS];
+        channels[..DEFAULT_CHANNELS.len()].copy_from_slice(&DEFAULT_CHANNELS);
+        Self {
+            channels,
+            mask: DEFAULT_MASK,
+            data_rate: data_rate.min(MAX_DATA_RATE),
+            tx_power: 0,
+            nb_trans: 1,
+            rx1_dr_offset: 0,
+            rx2_data_rate: RX2_DATA_RATE,
+            rx2_frequency: RX2_FREQUENCY,
+            rx_delay: RECEIVE_DELAY,
+            max_duty_cycle: 0,
+        }
+    }
+
+    /// The channels defined, one bit each.
+    fn defined(&self) -> u16 {
+        self.channels
+            .iter()
+            .enumerate()
+            .filter(|(_, frequency)| **frequency != 0)
+            .fold(0, |mask, (index, _)| mask | (1 << index))
+    }
+
+    /// The opening of each receive window following an uplink upon
+    /// `frequency` which ended at `end`, the first after `delay`, and
+    /// the configurations with which they listen.
+    fn windows(&self, frequency: u32, end: Instant, delay: Duration) -> [(Instant, LoRaConfig); 2] {
+        let rx1_data_rate = self.data_rate.saturating_sub(self.rx1_dr_offset);
+        [
+            (
+                end + delay,
+                region::config(frequency, rx1_data_rate, 0, false),
+            ),
+            (
+                end + delay + Duration::from_secs(1),
+                region::config(self.rx2_frequency, self.rx2_data_rate, 0, false),
+            ),
+        ]
+    }
+
+    /// Step towards a more robust link, having heard nothing from the
+    /// network for too long: first to full power, then a data rate at a
+    /// time, and finally upon the default channels.
+    fn back_off(&mut self) {
+        if self.tx_power > 0 {
+            self.tx_power = 0;
+        } else if self.data_rate > 0 {
+            self.data_rate -= 1;
+        } else {
+            self.mask |= DEFAULT_MASK;
+        }
+    }
+
+    fn link_adr(&mut self, args: &[u8]) -> u8 {
+        let data_rate = args[0] >> 4;
+        let tx_power = args[0] & 0x0F;
+        let mask = match (args[3] >> 4) & 0x07 {
+            0 => Some(u16::from_le_bytes([args[1], args[2]])),
+            6 => Some(self.defined()),
+            _ => None,
+        }
+        .filter(|mask| *mask != 0 && mask & !self.defined() == 0);
+        let nb_trans = args[3] & 0x0F;
+
+        // 0xF keeps the current data rate or power
+        let mut status = 0;
+        if tx_power == 0x0F || tx_power <= MAX_TX_POWER {
+            status |= 0x04;
+        }
+        if data_rate == 0x0F || data_rate <= MAX_DATA_RATE {
+            status |= 0x02;
+        }
+        if let Some(mask) = mask {
+            status |= 0x01;
+            if status == 0x07 {
+                if data_rate != 0x0F {
+                    self.data_rate = data_rate;
+                }
+                if tx_power != 0x0F {
+                    self.tx_power = tx_power;
+                }
+                if nb_trans != 0 {
+                    self.nb_trans = nb_trans;
+                }
+                self.mask = mask;
+            }
+        }
+        status
+    }
+
+    fn rx_param_setup(&mut self, args: &[u8]) -> u8 {
+        let rx1_dr_offset = (args[0] >> 4) & 0x07;
+        let rx2_data_rate = args[0] & 0x0F;
+        let frequency = frame::frequency(&args[1..]);
+
+        let mut status = 0;
+        if region::is_valid_frequency(frequency) {
+            status |= 0x01;
+        }
+        if rx2_data_rate <= MAX_DATA_RATE {
+            status |= 0x02;
+        }
+        if rx1_dr_offset <= MAX_RX1_DR_OFFSET {
+            status |= 0x04;
+        }
+        if status == 0x07 {
+            self.rx1_dr_offset = rx1_dr_offset;
+            self.rx2_data_rate = rx2_data_rate;
+            self.rx2_frequency = frequency;
+        }
+        status
+    }
+
+    /// Define, or with a frequency of zero remove, a channel. The data rates
+    /// of each channel are not recorded, as every channel of the region
+    /// supports those the network might use.
+    fn new_channel(&mut self, args: &[u8]) -> u8 {
+        let index = args[0] as usize;
+        let frequency = frame::frequency(&args[1..]);
+        let (max, min) = (args[4] >> 4, args[4] & 0x0F);
+
+        let mut status = 0;
+        if (DEFAULT_CHANNELS.len()..MAX_CHANNELS).contains(&index)
+            && (frequency == 0 || region::band(frequency).is_some())
+        {
+            status |= 0x01;
+        }
+        if min <= max && max <= MAX_DATA_RATE {
+            status |= 0x02;
+        }
+        if status == 0x03 {
+            self.channels[index] = frequency;
+            if frequency == 0 {
+                self.mask &= !(1 << index);
+            } else {
+                self.mask |= 1 << index;
+            }
+        }
+        status
+    }
+}
+
+/// The state of a device joined to a network.
+struct Session {
+    dev_addr: u32,
+    nwk_skey: Key,
+    app_skey: Key,
+    fcnt_up: u32,
+    /// The lowest counter of the next downlink accepted.
+    fcnt_down: u32,
+    link: Link,
+    /// The uplinks sent since the last downlink was received.
+    adr_ack_cnt: u32,
+    /// Whether the last downlink was confirmed, to be acknowledged by the next uplink.
+    ack: bool,
+    /// The answers to MAC commands, sent with the next uplink.
+    answers: Options,
+}
+
+impl Session {
+    /// Carry out the MAC `commands` sent by the network, queueing their
+    /// answers. Commands after any unknown are ignored, their length
+    /// being unknown too.
+    fn command(&mut self, mut commands: &[u8], snr: i8) {
+        while let Some((&cid, rest)) = commands.split_first() {
+            let len = match cid {
+                LINK_ADR_REQ | RX_PARAM_SETUP_REQ => 4,
+                DUTY_CYCLE_REQ | RX_TIMING_SETUP_REQ => 1,
+                DEV_STATUS_REQ => 0,
+                NEW_CHANNEL_REQ => 5,
+                _ => return,
+            };
+            if rest.len() < len {
+                return;
+            }
+            let (args, rest) = rest.split_at(len);
+            commands = rest;
+
+            match cid {
+                LINK_ADR_REQ => {
+                    let status = self.link.link_adr(args);
+                    self.answer(&[cid, status]);
+                }
+                DUTY_CYCLE_REQ => {
+                    self.link.max_duty_cycle = args[0] & 0x0F;
+                    self.answer(&[cid]);
+                }
+                RX_PARAM_SETUP_REQ => {
+                    let status = self.link.rx_param_setup(args);
+                    self.answer(&[cid, status]);
+                }
+                DEV_STATUS_REQ => {
+                    // powered externally, or unable to measure, and the margin as 6 bits
+                    let margin = snr.clamp(-32, 31) as u8 & 0x3F;
+                    self.answer(&[cid, 255, margin]);
+                }
+                NEW_CHANNEL_REQ => {
+                    let status = self.link.new_channel(args);
+                    self.answer(&[cid, status]);
+                }
+                _ => {
+                    let delay = (args[0] & 0x0F).max(1);
+                    self.link.rx_delay = Duration::from_secs(delay as u64);
+                    self.answer(&[cid]);
+                }
+            }
+        }
+    }
+
+    fn answer(&mut self, answer: &[u8]) {
+        // dropped should the options be full, the network asking again
+        self.answers.extend_from_slice(answer).ok();
+    }
+}
+
+/// A LoRaWAN 1.0 end device of class A upon a LoRa `Radio`, within the
+/// EU868 region, joining a network over the air, and then sending uplinks
+/// and receiving downlinks within the two windows following each.
+///
+/// Uplinks are sent upon the channels enabled, in turn, each awaiting
+/// the duty cycle of its sub-band, and that of all sub-bands, should the
+/// network have limited it, through the kernel's time source. With ADR
+/// enabled the network may adjust the data rate, transmit power, channels
+/// and transmissions of each uplink, whereas the device itself backs off
+/// towards the most robust data rate should it hear nothing for too long.
+///
+/// MAC commands are carried out as downlinks are received, and answered
+/// by the next uplink, so a device which rarely sends should send an empty
+/// uplink, upon any port, to answer promptly. Application data received
+/// is sent upstream as `LoRaWanEvent`s.
+///
+/// The device is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static LoRaWan<R>>`, once the radio is.
+pub struct LoRaWan<R>
+where
+    R: Radio + 'static,
+{
+    radio: &'static R,
+    config: LoRaWanConfig,
+    session: RefCell<Option<Session>>,
+    /// The instant from which each sub-band is available.
+    bands: Cell<[Instant; BANDS.len()]>,
+    /// The instant from which any sub-band is available.
+    aggregate: Cell<Instant>,
+    next_channel: Cell<usize>,
+    dev_nonce: Cell<u16>,
+    request: BusLock,
+    upstream: Cell<Option<&'static dyn UpstreamContext<LoRaWanEvent>>>,
+}
+
+impl<R> LoRaWan<R>
+where
+    R: Radio + 'static,
+{
+    pub fn new(radio: &'static R, config: LoRaWanConfig) -> Self {
+        Self {
+            radio,
+            config,
+            session: RefCell::new(None),
+            bands: Cell::new([Instant::from_millis(0); BANDS.len()]),
+            aggregate: Cell::new(Instant::from_millis(0)),
+            next_channel: Cell::new(0),
+            dev_nonce: Cell::new(config.dev_nonce),
+            request: BusLock::new(),
+            upstream: Cell::new(None),
+        }
+    }
+
+    /// Determine if the device has joined a network.
+    pub fn is_joined(&self) -> bool {
+        self.session.borrow().is_some()
+    }
+
+    /// The DevNonce of the next join.
+    pub fn dev_nonce(&self) -> u16 {
+        self.dev_nonce.get()
+    }
+
+    /// Join, *asynchronously*, a network, replacing any session of a
+    /// previous join should it accept.
+    pub async fn join(&self) -> Result<(), LoRaWanError> {
+        let upstream = self.upstream.get().ok_or(LoRaWanError::NotStarted)?;
+        let _guard = self.request.lock().await;
+
+        let mut link = Link::new(self.config.data_rate);
+        let dev_nonce = self.dev_nonce.get();
+        self.dev_nonce.set(dev_nonce.wrapping_add(1));
+        let request = frame::join_request(
+            &self.config.join_eui,
+            &self.config.dev_eui,
+            dev_nonce,
+            &self.config.app_key,
+        );
+        let (frequency, end) = self.transmit(upstream, &link, &request).await?;
+
+        let windows = link.windows(frequency, end, JOIN_ACCEPT_DELAY);
+        let app_key = &self.config.app_key;
+        let (accept, _) = self
+            .listen(upstream, windows, |data| frame::join_accept(app_key, data))
+            .await?
+            .ok_or(LoRaWanError::NoResponse)?;
+
+        link.rx1_dr_offset = accept.rx1_dr_offset.min(MAX_RX1_DR_OFFSET);
+        if accept.rx2_data_rate <= MAX_DATA_RATE {
+            link.rx2_data_rate = accept.rx2_data_rate;
+        }
+        link.rx_delay = Duration::from_secs(accept.rx_delay.max(1) as u64);
+        for (i, frequency) in accept.channels.iter().enumerate() {
+            if region::band(*frequency).is_some() {
+                let index = DEFAULT_CHANNELS.len() + i;
+                link.channels[index] = *frequency;
+                link.mask |= 1 << index;
+            }
+        }
+
+        let session_key =
+            |kind| crypto::session_key(app_key, kind, &accept.app_nonce, &accept.net_id, dev_nonce);
+        self.session.replace(Some(Session {
+            dev_addr: accept.dev_addr,
+            nwk_skey: session_key(1),
+            app_skey: session_key(2),
+            fcnt_up: 0,
+            fcnt_down: 0,
+            link,
+            adr_ack_cnt: 0,
+            ack: false,
+            answers: Options::new(),
+        }));
+        upstream.send(LoRaWanEvent::Joined {
+            dev_addr: accept.dev_addr,
+        });
+        Ok(())
+    }
+
+    /// Send, *asynchronously*, `data` upon `port`, along with the answers
+    /// to any MAC commands received since the previous uplink.
+    ///
+    /// Should `confirmed` be set, this fails with `NoResponse` unless the
+    /// network acknowledges the uplink. Any downlink received in response
+    /// is sent upstream before this completes.
+    pub async fn send(&self, port: u8, data: &[u8], confirmed: bool) -> Result<(), LoRaWanError> {
+        if port == 0 || port > MAX_PORT {
+            return Err(LoRaWanError::InvalidPort);
+        }
+        let upstream = self.upstream.get().ok_or(LoRaWanError::NotStarted)?;
+        let _guard = self.request.lock().await;
+
+        let (uplink, link, dev_addr, nwk_skey, app_skey, fcnt_down) = {
+            let mut session = self.session.borrow_mut();
+            let session = session.as_mut().ok_or(LoRaWanError::NotJoined)?;
+            // the frame header, without options, and port
+            let len = 7 + session.answers.len() + 1 + data.len();
+            if len > region::max_payload(session.link.data_rate) {
+                return Err(LoRaWanError::TooLong);
+            }
+
+            let mut fctrl = 0;
+            if self.config.adr {
+                fctrl |= FCTRL_ADR;
+                let count = session.adr_ack_cnt;
+                session.adr_ack_cnt = count.saturating_add(1);
+                if count >= ADR_ACK_LIMIT {
+                    fctrl |= FCTRL_ADR_ACK_REQ;
+                }
+                if count >= ADR_ACK_LIMIT + ADR_ACK_DELAY
+                    && (count - ADR_ACK_LIMIT).is_multiple_of(ADR_ACK_DELAY)
+                {
+                    session.link.back_off();
+                }
+            }
+            if session.ack {
+                fctrl |= FCTRL_ACK;
+            }
+            let uplink = frame::uplink(
+                &session.nwk_skey,
+                &session.app_skey,
+                &Uplink {
+                    confirmed,
+                    dev_addr: session.dev_addr,
+                    fctrl,
+                    fcnt: session.fcnt_up,
+                    options: &session.answers,
+                    port,
+                    payload: data,
+                },
+            )
+            .ok_or(LoRaWanError::TooLong)?;
+            session.fcnt_up = session.fcnt_up.wrapping_add(1);
+            session.ack = false;
+            session.answers = Vec::new();
+            (
+                uplink,
+                session.link,
+                session.dev_addr,
+                session.nwk_skey,
+                session.app_skey,
+                session.fcnt_down,
+            )
+        };
+
+        let mut received = None;
+        for _ in 0..link.nb_trans.max(1) {
+            let (frequency, end) = self.transmit(upstream, &link, &uplink).await?;
+            let windows = link.windows(frequency, end, link.rx_delay);
+            received = self
+                .listen(upstream, windows, |data| {
+                    frame::downlink(&nwk_skey, &app_skey, dev_addr, fcnt_down, data)
+                })
+                .await?;
+            if received.is_some() {
+                break;
+            }
+        }
+
+        let mut acked = false;
+        if let Some((downlink, received)) = received {
+            acked = downlink.fctrl & FCTRL_ACK != 0;
+            if let Some(event) = self.accept(downlink, received) {
+                upstream.send(event);
+            }
+        }
+        if confirmed && !acked {
+            Err(LoRaWanError::NoResponse)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Update the session upon receiving `downlink`, returning any
+    /// application data it carries.
+    fn accept(&self, downlink: Downlink, received: Received) -> Option<LoRaWanEvent> {
+        let mut session = self.session.borrow_mut();
+        let session = session.as_mut()?;
+        session.fcnt_down = downlink.fcnt.wrapping_add(1);
+        session.adr_ack_cnt = 0;
+        session.ack = downlink.confirmed;
+        session.command(&downlink.options, received.snr);
+        match downlink.port {
+            Some(0) => {
+                session.command(&downlink.payload, received.snr);
+                None
+            }
+            Some(port) => Some(LoRaWanEvent::Received {
+                port,
+                data: downlink.payload,
+            }),
+            None => None,
+        }
+    }
+
+    /// The next enabled channel, in turn, of those whose sub-band is
+    /// available soonest, and that sub-band.
+    fn channel(&self, link: &Link) -> (usize, usize) {
+        let bands = self.bands.get();
+        let next = self.next_channel.get();
+        let mask = match link.mask & link.defined() {
+            0 => DEFAULT_MASK,
+            mask => mask,
+        };
+        let mut chosen: Option<(usize, usize)> = None;
+        for i in 0..MAX_CHANNELS {
+            let index = (next + i) % MAX_CHANNELS;
+            if mask & (1 << index) == 0 {
+                continue;
+            }
+            if let Some(band) = region::band(link.channels[index]) {
+                if chosen.is_none_or(|(_, b)| bands[band] < bands[b]) {
+                    chosen = Some((index, band));
+                }
+            }
+        }
+        chosen.expect("the default channels are always defined")
+    }
+
+    /// Transmit, *asynchronously*, `frame` upon the next channel available,
+    /// returning its frequency and the instant the transmission ended.
+    async fn transmit(
+        &self,
+        upstream: &'static dyn UpstreamContext<LoRaWanEvent>,
+        link: &Link,
+        frame: &[u8],
+    ) -> Result<(u32, Instant), LoRaWanError> {
+        let (index, band) = self.channel(link);
+        let available = self.bands.get()[band].max(self.aggregate.get());
+        Delay::new(upstream, available).await;
+        self.next_channel.set((index + 1) % MAX_CHANNELS);
+
+        let frequency = link.channels[index];
+        let config = region::config(frequency, link.data_rate, link.tx_power, true);
+        let start = upstream.now();
+        self.radio
+            .transmit(&config, frame)
+            .await
+            .map_err(|_| LoRaWanError::Radio)?;
+
+        let airtime = config.airtime(frame.len());
+        let mut bands = self.bands.get();
+        bands[band] = start + airtime * BANDS[band].2;
+        self.bands.set(bands);
+        if link.max_duty_cycle > 0 {
+            self.aggregate
+                .set(start + airtime * (1 << link.max_duty_cycle as u32));
+        }
+        Ok((frequency, upstream.now()))
+    }
+
+    /// Listen, *asynchronously*, within each of the receive `windows` in
+    /// turn, for a frame which `accept` decodes.
+    async fn listen<T>(
+        &self,
+        upstream: &'static dyn UpstreamContext<LoRaWanEvent>,
+        windows: [(Instant, LoRaConfig); 2],
+        mut accept: impl FnMut(&[u8]) -> Option<T>,
+    ) -> Result<Option<(T, Received)>, LoRaWanError> {
+        let mut buf = [0; 256];
+        for (at, config) in windows.iter() {
+            Delay::new(upstream, *at).await;
+            let window = config.symbol_time() * RX_SYMBOLS + RX_MARGIN;
+            match self.radio.receive(config, &mut buf, window).await {
+                Ok(Some(received)) => {
+                    if let Some(decoded) = accept(&buf[..received.len]) {
+                        return Ok(Some((decoded, received)));
+                    }
+                }
+                Ok(None) => {}
+                Err(_) => return Err(LoRaWanError::Radio),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R> Component for &'static LoRaWan<R>
+where
+    R: Radio + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = LoRaWanEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.upstream.set(Some(ctx.upstream()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::region::{self, RX2_DATA_RATE, RX2_FREQUENCY};
+    use super::{crypto, LoRaWan, LoRaWanConfig, LoRaWanError, LoRaWanEvent};
+    use super::{ADR_ACK_DELAY, ADR_ACK_LIMIT, FCTRL_ACK, FCTRL_ADR, FCTRL_ADR_ACK_REQ};
+    use crate::context::UpstreamContext;
+    use crate::driver::lora::{LoRaConfig, SpreadingFactor};
+    use crate::mock::{MockRadio, MockRadioState};
+    use crate::testing::{leak, poll_once, Upstream};
+    use crate::time::{Duration, Instant};
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockDecrypt, KeyInit};
+    use aes::{Aes128, Block};
+    use core::future::Future;
+    use core::task::Poll;
+    use heapless::Vec;
+    use std::boxed::Box;
+    use std::collections::VecDeque;
+
+    const DEV_EUI: [u8; 8] = [0x70, 0xB3, 0xD5, 0x7E, 0xD0, 0x00, 0x00, 0x01];
+    const JOIN_EUI: [u8; 8] = [0; 8];
+    const APP_KEY: [u8; 16] = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+    const DEV_ADDR: u32 = 0x2601_1234;
+    const APP_NONCE: [u8; 3] = [1, 2, 3];
+    const NET_ID: [u8; 3] = [0x13, 0, 0];
+
+    /// An uplink received by the network server stand-in.
+    struct Up {
+        at: Instant,
+        config: LoRaConfig,
+        len: usize,
+        confirmed: bool,
+        fctrl: u8,
+        fcnt: u32,
+        options: std::vec::Vec<u8>,
+        port: u8,
+        payload: std::vec::Vec<u8>,
+    }
+
+    /// A downlink sent by the network server stand-in in response to an uplink.
+    #[derive(Default)]
+    struct Down {
+        confirmed: bool,
+        options: &'static [u8],
+        port: Option<u8>,
+        payload: &'static [u8],
+        /// Whether sent within the second receive window, rather than the first.
+        rx2: bool,
+    }
+
+    type Device = LoRaWan<MockRadio>;
+
+    /// Runs futures, advancing time by ten milliseconds at each step, and
+    /// standing in for the network server, accepting any join, and
+    /// responding to each uplink with the next downlink queued, if any.
+    struct Harness {
+        upstream: &'static Upstream<LoRaWanEvent>,
+        radio: &'static MockRadioState,
+        device: &'static Device,
+        keys: Option<([u8; 16], [u8; 16])>,
+        fcnt_down: u32,
+        uplinks: std::vec::Vec<Up>,
+        downlinks: VecDeque<Down>,
+    }
+
+    /// The data rate of a 125kHz `config`.
+    fn data_rate(config: &LoRaConfig) -> u8 {
+        12 - config.spreading_factor as u8
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let upstream = leak(Upstream::new());
+            let radio = leak(MockRadioState::new());
+            let config = LoRaWanConfig::new(DEV_EUI, JOIN_EUI, APP_KEY);
+            let device: &'static Device = leak(LoRaWan::new(leak(radio.radio()), config));
+            device.upstream.set(Some(upstream));
+            Self {
+                upstream,
+                radio,
+                device,
+                keys: None,
+                fcnt_down: 0,
+                uplinks: std::vec::Vec::new(),
+                downlinks: VecDeque::new(),
+            }
+        }
+
+        fn run<F: Future>(&mut self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            for _ in 0..10_000 {
+                let polled = poll_once(future.as_mut());
+                self.serve();
+                if let Poll::Ready(output) = polled {
+                    return output;
+                }
+                self.upstream.advance(Duration::from_millis(10));
+            }
+            panic!("future never completed")
+        }
+
+        fn serve(&mut self) {
+            while let Some((config, packet)) = self.radio.take_transmitted() {
+                if packet[0] == 0x00 {
+                    self.accept_join(&config, &packet);
+                    continue;
+                }
+                let (nwk_skey, app_skey) = self.keys.expect("not joined");
+                let (message, mic) = packet.split_at(packet.len() - 4);
+                assert_eq!(&packet[1..5], &DEV_ADDR.to_le_bytes());
+                let fcnt = u16::from_le_bytes([packet[6], packet[7]]) as u32;
+                assert_eq!(
+                    &crypto::data_mic(&nwk_skey, true, DEV_ADDR, fcnt, message),
+                    mic
+                );
+
+                let fctrl = packet[5];
+                let options_len = (fctrl & 0x0F) as usize;
+                let mut payload = message[9 + options_len..].to_vec();
+                crypto::cipher(&app_skey, true, DEV_ADDR, fcnt, &mut payload);
+                let up = Up {
+                    at: self.upstream.now(),
+                    config,
+                    len: packet.len(),
+                    confirmed: packet[0] == 0x80,
+                    fctrl: fctrl & 0xF0,
+                    fcnt,
+                    options: message[8..8 + options_len].to_vec(),
+                    port: message[8 + options_len],
+                    payload,
+                };
+                if let Some(down) = self.downlinks.pop_front() {
+                    self.respond(&up, down);
+                }
+                self.uplinks.push(up);
+            }
+        }
+
+        fn window(uplink: &LoRaConfig, rx2: bool) -> LoRaConfig {
+            if rx2 {
+                region::config(RX2_FREQUENCY, RX2_DATA_RATE, 0, false)
+            } else {
+                region::config(uplink.frequency, data_rate(uplink), 0, false)
+            }
+        }
+
+        fn accept_join(&mut self, config: &LoRaConfig, request: &[u8]) {
+            let (message, mic) = request.split_at(19);
+            assert_eq!(&crypto::mic(&APP_KEY, &[message]), mic);
+            let dev_nonce = u16::from_le_bytes([request[17], request[18]]);
+
+            let mut accept = std::vec![0x20];
+            accept.extend_from_slice(&APP_NONCE);
+            accept.extend_from_slice(&NET_ID);
+            accept.extend_from_slice(&DEV_ADDR.to_le_bytes());
+            accept.extend_from_slice(&[0x00, 1]);
+            let mic = crypto::mic(&APP_KEY, &[&accept]);
+            accept.extend_from_slice(&mic);
+            let cipher = Aes128::new(GenericArray::from_slice(&APP_KEY));
+            for block in accept[1..].chunks_mut(16) {
+                cipher.decrypt_block(Block::from_mut_slice(block));
+            }
+
+            let session_key =
+                |kind| crypto::session_key(&APP_KEY, kind, &APP_NONCE, &NET_ID, dev_nonce);
+            self.keys = Some((session_key(1), session_key(2)));
+            self.fcnt_down = 0;
+            self.radio.respond(&Self::window(config, false), &accept);
+        }
+
+        fn respond(&mut self, up: &Up, down: Down) {
+            let (nwk_skey, app_skey) = self.keys.unwrap();
+            let fcnt = self.fcnt_down;
+            self.fcnt_down += 1;
+
+            let mut frame = std::vec![if down.confirmed { 0xA0 } else { 0x60 }];
+            frame.extend_from_slice(&DEV_ADDR.to_le_bytes());
+            let ack = if up.confirmed { FCTRL_ACK } else { 0 };
+            frame.push(ack | down.options.len() as u8);
+            frame.extend_from_slice(&(fcnt as u16).to_le_bytes());
+            frame.extend_from_slice(down.options);
+            if let Some(port) = down.port {
+                frame.push(port);
+                let start = frame.len();
+                frame.extend_from_slice(down.payload);
+                let key = if port == 0 { &nwk_skey } else { &app_skey };
+                crypto::cipher(key, false, DEV_ADDR, fcnt, &mut frame[start..]);
+            }
+            let mic = crypto::data_mic(&nwk_skey, false, DEV_ADDR, fcnt, &frame);
+            frame.extend_from_slice(&mic);
+            self.radio
+                .respond(&Self::window(&up.config, down.rx2), &frame);
+        }
+
+        fn last(&self) -> &Up {
+            self.uplinks.last().unwrap()
+        }
+    }
+
+    #[test]
+    fn join_and_exchange() {
+        let mut h = Harness::new();
+        let device = h.device;
+
+        assert_eq!(
+            h.run(device.send(1, b"21.5", false)),
+            Err(LoRaWanError::NotJoined)
+        );
+        assert_eq!(
+            h.run(device.send(0, b"21.5", false)),
+            Err(LoRaWanError::InvalidPort)
+        );
+        assert_eq!(h.run(device.join()), Ok(()));
+        assert!(device.is_joined());
+        assert_eq!(device.dev_nonce(), 1);
+        assert_eq!(
+            h.upstream.take(),
+            [LoRaWanEvent::Joined { dev_addr: DEV_ADDR }]
+        );
+
+        h.downlinks.push_back(Down {
+            port: Some(2),
+            payload: b"on",
+            ..Down::default()
+        });
+        assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        {
+            let up = h.last();
+            assert_eq!(up.port, 1);
+            assert_eq!(up.payload, b"21.5");
+            assert_eq!(up.fcnt, 0);
+            assert_eq!(up.fctrl, FCTRL_ADR);
+            assert!(!up.confirmed);
+            assert_eq!(up.config.spreading_factor, SpreadingFactor::SF7);
+            assert_eq!(up.config.power, 14);
+        }
+        assert_eq!(
+            h.upstream.take(),
+            [LoRaWanEvent::Received {
+                port: 2,
+                data: Vec::from_slice(b"on").unwrap()
+            }]
+        );
+
+        // confirmed, within the second window, and so acknowledged
+        h.downlinks.push_back(Down {
+            confirmed: true,
+            port: Some(3),
+            payload: b"reboot",
+            rx2: true,
+            ..Down::default()
+        });
+        assert_eq!(h.run(device.send(1, b"21.6", false)), Ok(()));
+        assert_eq!(h.upstream.take().len(), 1);
+
+        // acknowledged, and then asked for status, and to slow to DR3 at 10dBm
+        h.downlinks.push_back(Down {
+            options: &[0x03, 0x32, 0x07, 0x00, 0x01, 0x06],
+            ..Down::default()
+        });
+        assert_eq!(h.run(device.send(1, b"21.7", false)), Ok(()));
+        assert_eq!(h.last().fctrl, FCTRL_ADR | FCTRL_ACK);
+        assert_eq!(h.last().fcnt, 2);
+        assert!(h.upstream.take().is_empty());
+
+        assert_eq!(h.run(device.send(1, b"21.8", false)), Ok(()));
+        {
+            let up = h.last();
+            assert_eq!(up.fctrl, FCTRL_ADR);
+            assert_eq!(up.options, [0x03, 0x07, 0x06, 255, 7]);
+            assert_eq!(up.config.spreading_factor, SpreadingFactor::SF9);
+            assert_eq!(up.config.power, 10);
+        }
+
+        // an acknowledgement requested
+        h.downlinks.push_back(Down::default());
+        assert_eq!(h.run(device.send(4, b"alarm", true)), Ok(()));
+        assert!(h.last().confirmed);
+        assert!(h.last().options.is_empty());
+
+        while h.radio.take_listened().is_some() {}
+        assert_eq!(
+            h.run(device.send(4, b"alarm", true)),
+            Err(LoRaWanError::NoResponse)
+        );
+        let frequency = h.last().config.frequency;
+        let rx1 = h.radio.take_listened().unwrap();
+        assert_eq!(rx1.frequency, frequency);
+        assert_eq!(rx1.spreading_factor, SpreadingFactor::SF9);
+        assert!(rx1.invert_iq);
+        let rx2 = h.radio.take_listened().unwrap();
+        assert_eq!(rx2.frequency, RX2_FREQUENCY);
+        assert_eq!(rx2.spreading_factor, SpreadingFactor::SF12);
+        assert!(h.radio.take_listened().is_none());
+
+        // too long for DR3, and reserved
+        assert_eq!(
+            h.run(device.send(1, &[0; 120], false)),
+            Err(LoRaWanError::TooLong)
+        );
+        assert_eq!(
+            h.run(device.send(224, b"21.5", false)),
+            Err(LoRaWanError::InvalidPort)
+        );
+    }
+
+    #[test]
+    fn duty_cycle_and_adr() {
+        let mut h = Harness::new();
+        let device = h.device;
+        assert_eq!(h.run(device.join()), Ok(()));
+
+        for _ in 0..4 {
+            assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        }
+        let frequencies: std::vec::Vec<u32> =
+            h.uplinks.iter().map(|up| up.config.frequency).collect();
+        assert_eq!(
+            frequencies,
+            [868_300_000, 868_500_000, 868_100_000, 868_300_000]
+        );
+        // every default channel within the same sub-band, of a 1% duty cycle
+        for pair in h.uplinks.windows(2) {
+            let airtime = pair[0].config.airtime(pair[0].len);
+            let gap = pair[1].at - pair[0].at;
+            assert!(gap + Duration::from_millis(1) >= airtime * 100);
+            assert!(gap <= airtime * 100 + Duration::from_millis(10));
+        }
+
+        // a channel within another sub-band, available at once
+        h.downlinks.push_back(Down {
+            options: &[0x07, 3, 0x90, 0xB8, 0x84, 0x50],
+            ..Down::default()
+        });
+        assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        let before = h.last().at;
+        assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        {
+            let up = h.last();
+            assert_eq!(up.options, [0x07, 0x03]);
+            assert_eq!(up.config.frequency, 869_800_000);
+            assert!(up.at - before < Duration::from_secs(3));
+        }
+
+        // unheard for too long, so asking for a response, then backing off
+        let adr_ack_cnt = |count| device.session.borrow_mut().as_mut().unwrap().adr_ack_cnt = count;
+        adr_ack_cnt(ADR_ACK_LIMIT);
+        assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        assert_eq!(h.last().fctrl, FCTRL_ADR | FCTRL_ADR_ACK_REQ);
+        assert_eq!(h.last().config.spreading_factor, SpreadingFactor::SF7);
+
+        adr_ack_cnt(ADR_ACK_LIMIT + ADR_ACK_DELAY);
+        h.downlinks.push_back(Down::default());
+        assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        assert_eq!(h.last().fctrl, FCTRL_ADR | FCTRL_ADR_ACK_REQ);
+        assert_eq!(h.last().config.spreading_factor, SpreadingFactor::SF8);
+
+        // heard, so reset
+        assert_eq!(h.run(device.send(1, b"21.5", false)), Ok(()));
+        assert_eq!(h.last().fctrl, FCTRL_ADR);
+        assert_eq!(h.last().config.spreading_factor, SpreadingFactor::SF8);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::driver::lora::{Bandwidth, CodingRate, LoRaConfig, SpreadingFactor};
+use crate::time::Duration;
+
+/// The channels every device may use, which may not be modified.
+pub(crate) const DEFAULT_CHANNELS: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+
+/// The most channels a device may be given.
+pub(crate) const MAX_CHANNELS: usize = 16;
+
+pub(crate) const RX2_FREQUENCY: u32 = 869_525_000;
+pub(crate) const RX2_DATA_RATE: u8 = 0;
+
+pub(crate) const MAX_DATA_RATE: u8 = 6;
+pub(crate) const MAX_RX1_DR_OFFSET: u8 = 5;
+
+/// The highest transmit power index, each lowering the power by 2dB.
+pub(crate) const MAX_TX_POWER: u8 = 7;
+
+/// The conducted power of transmit power index 0, in dBm, being
+/// the maximum EIRP of 16dBm less an antenna gain of 2dBi.
+const MAX_POWER: i8 = 14;
+
+pub(crate) const RECEIVE_DELAY: Duration = Duration::from_secs(1);
+pub(crate) const JOIN_ACCEPT_DELAY: Duration = Duration::from_secs(5);
+
+/// The frequencies within which a device may transmit.
+const FREQUENCIES: (u32, u32) = (863_000_000, 870_000_000);
+
+/// The sub-bands regulating duty cycle, from the lowest frequency of each,
+/// inclusive, to the highest, exclusive, and the ratio of each transmission's
+/// time on air to the time from its start until the sub-band is available.
+pub(crate) const BANDS: [(u32, u32, u32); 5] = [
+    (863_000_000, 868_000_000, 100),
+    (868_000_000, 868_600_000, 100),
+    (868_700_000, 869_200_000, 1000),
+    (869_400_000, 869_650_000, 10),
+    (869_700_000, 870_000_000, 100),
+];
+
+/// The sub-band of `frequency`, should it be within one.
+pub(crate) fn band(frequency: u32) -> Option<usize> {
+    BANDS
+        .iter()
+        .position(|(low, high, _)| (*low..*high).contains(&frequency))
+}
+
+/// Determine if a device may transmit or receive upon `frequency`.
+pub(crate) fn is_valid_frequency(frequency: u32) -> bool {
+    (FREQUENCIES.0..FREQUENCIES.1).contains(&frequency)
+}
+
+/// The longest `MACPayload` of a frame sent at `data_rate`.
+pub(crate) fn max_payload(data_rate: u8) -> usize {
+    match data_rate {
+        0..=2 => 59,
+        3 => 123,
+        _ => 230,
+    }
+}
+
+/// The configuration of the radio with which to transmit, should `uplink`
+/// be set, or else receive, upon `frequency` at `data_rate`.
+pub(crate) fn config(frequency: u32, data_rate: u8, tx_power: u8, uplink: bool) -> LoRaConfig {
+    let (spreading_factor, bandwidth) = match data_rate {
+        0 => (SpreadingFactor::SF12, Bandwidth::Khz125),
+        1 => (SpreadingFactor::SF11, Bandwidth::Khz125),
+        2 => (SpreadingFactor::SF10, Bandwidth::Khz125),
+        3 => (SpreadingFactor::SF9, Bandwidth::Khz125),
+        4 => (SpreadingFactor::SF8, Bandwidth::Khz125),
+        5 => (SpreadingFactor::SF7, Bandwidth::Khz125),
+        _ => (SpreadingFactor::SF7, Bandwidth::Khz250),
+    };
+    LoRaConfig {
+        frequency,
+        spreading_factor,
+        bandwidth,
+        coding_rate: CodingRate::Cr4_5,
+        power: MAX_POWER - 2 * tx_power.min(MAX_TX_POWER) as i8,
+        invert_iq: !uplink,
+        crc: uplink,
+    }
+}
@@ -0,0 +1,147 @@
+use core::fmt;
+use core::future::Future;
+
+#[cfg(any(test, feature = "std"))]
+mod host;
+
+#[cfg(any(test, feature = "std"))]
+pub use host::{HostSocket, HostStack};
+
+/// Support for exchanging requests and responses with a CoAP peer.
+pub mod coap;
+
+/// Support for publishing telemetry to, and receiving commands from, Drogue Cloud.
+pub mod drogue;
+
+/// Support for making requests of an HTTP server.
+pub mod http;
+
+/// Support for LoRaWAN end devices, upon any LoRa `Radio`.
+#[cfg(feature = "lorawan")]
+pub mod lorawan;
+
+/// Support for publishing and subscribing to messages through an MQTT broker.
+pub mod mqtt;
+
+/// Support for TCP/IP through smoltcp upon an Ethernet MAC.
+#[cfg(feature = "tcpip")]
+pub mod tcpip;
+
+/// Support for securing connections with TLS 1.3, upon any `TcpStack`.
+#[cfg(feature = "tls")]
+pub mod tls;
+
+/// An IPv4 address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const LOCALHOST: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4Address([a, b, c, d])
+    }
+
+    pub fn octets(&self) -> [u8; 4] {
+        self.0
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+/// An IPv4 address and port.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SocketAddress {
+    pub ip: Ipv4Address,
+    pub port: u16,
+}
+
+impl SocketAddress {
+    pub const fn new(ip: Ipv4Address, port: u16) -> Self {
+        Self { ip, port }
+    }
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.ip, self.port)
+    }
+}
+
+/// A stack providing TCP connections.
+///
+/// Network adapter components, such as `EspWifi`, implement this trait, and
+/// are shared by reference with the components using them, which are given
+/// the stack upon construction, and may be written generically over it.
+/// Stacks are not reached through a component's `ComponentContext`, as the
+/// context knows its parent only as a `dyn UpstreamContext`, whereas a trait
+/// of async methods cannot be made into such an object.
+pub trait TcpStack {
+    /// A handle to a connection, valid until closed.
+    type Socket: Copy;
+    type Error: fmt::Debug;
+
+    /// Connect, *asynchronously*, to `remote`.
+    fn connect(
+        &self,
+        remote: SocketAddress,
+    ) -> impl Future<Output = Result<Self::Socket, Self::Error>>;
+
+    /// Send, *asynchronously*, all of `data`, returning its length.
+    fn send(
+        &self,
+        socket: Self::Socket,
+        data: &[u8],
+    ) -> impl Future<Output = Result<usize, Self::Error>>;
+
+    /// Receive, *asynchronously*, at least one byte into `buf`, returning the
+    /// number of bytes received, or zero once the connection has been closed
+    /// by its peer.
+    fn receive(
+        &self,
+        socket: Self::Socket,
+        buf: &mut [u8],
+    ) -> impl Future<Output = Result<usize, Self::Error>>;
+
+    /// Close, *asynchronously*, the connection, releasing its handle.
+    fn close(&self, socket: Self::Socket) -> impl Future<Output = ()>;
+}
+
+/// A stack providing UDP sockets, each exchanging datagrams with one remote.
+///
+/// Implemented, and shared with the components using it, as a `TcpStack` is.
+pub trait UdpStack {
+    /// A handle to a socket, valid until closed.
+    type Socket: Copy;
+    type Error: fmt::Debug;
+
+    /// Open, *asynchronously*, a socket exchanging datagrams with `remote`.
+    fn open(
+        &self,
+        remote: SocketAddress,
+    ) -> impl Future<Output = Result<Self::Socket, Self::Error>>;
+
+    /// Send, *asynchronously*, `data` as one datagram.
+    fn send(
+        &self,
+        socket: Self::Socket,
+        data: &[u8],
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Receive, *asynchronously*, one datagram into `buf`, returning its
+    /// length. Should `buf` be too short, the remainder is discarded.
+    fn receive(
+        &self,
+        socket: Self::Socket,
+        buf: &mut [u8],
+    ) -> impl Future<Output = Result<usize, Self::Error>>;
+
+    /// Close, *asynchronously*, the socket, releasing its handle.
+    fn close(&self, socket: Self::Socket) -> impl Future<Output = ()>;
+}
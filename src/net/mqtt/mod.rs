@@ -0,0 +1,595 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::context::UpstreamContext;
+use crate::driver::lock::{BusLock, Flag};
+use crate::net::{SocketAddress, TcpStack};
+use crate::time::{Delay, Duration, Instant, WithTimeout};
+use core::cell::{Cell, RefCell};
+use heapless::{consts::*, String, Vec};
+
+/// Encoding and decoding of the MQTT 3.1.1 control packets used by the client.
+mod packet;
+
+use packet::{Packet, PacketBuffer};
+
+/// The time awaited for the broker to acknowledge a connection, publication or subscription.
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The attempts made to publish a message at least once before giving up.
+const PUBLISH_ATTEMPTS: usize = 3;
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(32);
+
+/// The quality of service with which a message is delivered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+/// Errors reported by an `MqttClient`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MqttError {
+    /// The client is not connected to the broker.
+    NotConnected,
+    /// The broker did not acknowledge in time.
+    Timeout,
+    /// The broker refused the subscription.
+    Rejected,
+    /// A packet was too long to be sent.
+    TooLong,
+}
+
+/// The broker and credentials of an `MqttClient`.
+#[derive(Copy, Clone, Debug)]
+pub struct MqttConfig {
+    pub broker: SocketAddress,
+    pub client_id: &'static str,
+    pub username: Option<&'static str>,
+    pub password: Option<&'static [u8]>,
+    /// The longest period without any packet sent to the broker, after which
+    /// a ping is sent, or zero to disable pings. The connection is considered
+    /// lost should the broker not respond within the same period.
+    pub keep_alive: Duration,
+}
+
+impl MqttConfig {
+    pub fn new(broker: SocketAddress, client_id: &'static str) -> Self {
+        Self {
+            broker,
+            client_id,
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A message received upon a topic subscribed to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String<U64>,
+    pub payload: Vec<u8, U256>,
+}
+
+/// Messages sent upstream by an `MqttClient`.
+// passed by value, as there is no allocator to box messages into
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MqttEvent {
+    /// The broker accepted the connection.
+    Connected,
+    /// The connection was lost, and will be reestablished.
+    Disconnected,
+    /// A message was received. Those with topics or payloads too long
+    /// to be held are dropped.
+    Message(Message),
+}
+
+/// Why a connection to the broker ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Ended {
+    /// The connection could not be established.
+    Unconnected,
+    /// The connection was established, then lost.
+    Lost,
+}
+
+/// An MQTT 3.1.1 client upon a `TcpStack`, publishing messages with QoS 0
+/// or 1, and receiving those published upon topics subscribed to.
+///
+/// The client connects, and reconnects whenever the connection is lost,
+/// from a task spawned upon start, and sends the messages it receives
+/// upstream as `MqttEvent`s.
+///
+/// Messages to be acknowledged, and subscriptions, are performed one at a
+/// time, in the order in which the client becomes available. Every session
+/// is clean, with the client subscribing again upon reconnecting to every
+/// topic filter subscribed so far, so any message published while
+/// disconnected is lost.
+///
+/// The client is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static MqttClient<S>>`, once the `TcpStack` is.
+pub struct MqttClient<S>
+where
+    S: TcpStack + 'static,
+{
+    stack: &'static S,
+    config: MqttConfig,
+    socket: Cell<Option<S::Socket>>,
+    connected: Cell<bool>,
+    write: BusLock,
+    last_sent: Cell<Instant>,
+    request: BusLock,
+    pending: Cell<Option<u16>>,
+    ack: Cell<Option<u8>>,
+    acked: Flag,
+    next_id: Cell<u16>,
+    subscriptions: RefCell<Vec<(String<U64>, QoS), U8>>,
+    upstream: Cell<Option<&'static dyn UpstreamContext<MqttEvent>>>,
+}
+
+impl<S> MqttClient<S>
+where
+    S: TcpStack + 'static,
+{
+    pub fn new(stack: &'static S, config: MqttConfig) -> Self {
+        Self {
+            stack,
+            config,
+            socket: Cell::new(None),
+            connected: Cell::new(false),
+            write: BusLock::new(),
+            last_sent: Cell::new(Instant::from_millis(0)),
+            request: BusLock::new(),
+            pending: Cell::new(None),
+            ack: Cell::new(None),
+            acked: Flag::new(),
+            next_id: Cell::new(1),
+            subscriptions: RefCell::new(Vec::new()),
+            upstream: Cell::new(None),
+        }
+    }
+
+    /// Determine if the broker has accepted the current connection.
+    pub fn is_connected(&self) -> bool {
+        self.connected.get()
+    }
+
+    /// Publish, *asynchronously*, `payload` upon `topic`.
+    ///
+    /// With `QoS::AtLeastOnce`, this completes once the broker has
+    /// acknowledged the message, sending it again should it not in time.
+    pub async fn publish(&self, topic: &str, payload: &[u8], qos: QoS) -> Result<(), MqttError> {
+        if qos == QoS::AtMostOnce {
+            let packet = packet::publish(topic, payload, qos, 0, false)?;
+            return self.send(&packet).await;
+        }
+
+        let _guard = self.request.lock().await;
+        let id = self.next_id();
+        let mut result = Err(MqttError::Timeout);
+        for attempt in 0..PUBLISH_ATTEMPTS {
+            let packet = packet::publish(topic, payload, qos, id, attempt > 0);
+            result = match packet {
+                Ok(packet) => self.exchange(id, &packet).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            if result != Err(MqttError::Timeout) {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Subscribe, *asynchronously*, to messages published upon topics
+    /// matching `filter`, delivered with at most `qos`.
+    ///
+    /// The subscription is made again upon each reconnection, and,
+    /// should the client not currently be connected, is made only then.
+    pub async fn subscribe(&self, filter: &str, qos: QoS) -> Result<(), MqttError> {
+        let mut topic = String::new();
+        topic.push_str(filter).map_err(|_| MqttError::TooLong)?;
+        {
+            let mut subscriptions = self.subscriptions.borrow_mut();
+            if !subscriptions.iter().any(|(t, _)| *t == topic) {
+                subscriptions
+                    .push((topic, qos))
+                    .map_err(|_| MqttError::TooLong)?;
+            }
+        }
+        if !self.is_connected() {
+            return Ok(());
+        }
+
+        let _guard = self.request.lock().await;
+        let id = self.next_id();
+        let result = match packet::subscribe(id, filter, qos) {
+            Ok(packet) => self.exchange(id, &packet).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(0x80) => {
+                let mut subscriptions = self.subscriptions.borrow_mut();
+                if let Some(index) = subscriptions.iter().position(|(t, _)| t == filter) {
+                    subscriptions.swap_remove(index);
+                }
+                Err(MqttError::Rejected)
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn next_id(&self) -> u16 {
+        let id = self.next_id.get();
+        self.next_id.set(id.checked_add(1).unwrap_or(1));
+        id
+    }
+
+    /// Send `packet`, and wait for the broker to acknowledge it as `id`,
+    /// returning the code acknowledged. The request lock must be held.
+    async fn exchange(&self, id: u16, packet: &[u8]) -> Result<u8, MqttError> {
+        let upstream = self.upstream.get().ok_or(MqttError::NotConnected)?;
+        self.ack.set(None);
+        self.pending.set(Some(id));
+        let result = match self.send(packet).await {
+            Ok(()) => {
+                let deadline = upstream.now() + ACK_TIMEOUT;
+                match WithTimeout::new(self.acked.wait(), Delay::new(upstream, deadline)).await {
+                    // woken without an acknowledgement upon disconnection
+                    Some(()) => self.ack.take().ok_or(MqttError::NotConnected),
+                    None => Err(MqttError::Timeout),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        self.pending.set(None);
+        result
+    }
+
+    /// Send, *asynchronously*, `packet` upon the current connection.
+    async fn send(&self, packet: &[u8]) -> Result<(), MqttError> {
+        let _guard = self.write.lock().await;
+        let result = match self.socket.get() {
+            Some(socket) => match self.stack.send(socket, packet).await {
+                Ok(_) => {
+                    if let Some(upstream) = self.upstream.get() {
+                        self.last_sent.set(upstream.now());
+                    }
+                    Ok(())
+                }
+                Err(_) => Err(MqttError::NotConnected),
+            },
+            None => Err(MqttError::NotConnected),
+        };
+        result
+    }
+
+    /// Connect, *asynchronously*, to the broker, and then again whenever
+    /// the connection is lost, backing off exponentially between attempts.
+    pub(crate) async fn run(&self, upstream: &'static dyn UpstreamContext<MqttEvent>) {
+        self.upstream.set(Some(upstream));
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            if self.session(upstream).await == Ended::Lost {
+                upstream.send(MqttEvent::Disconnected);
+                backoff = RECONNECT_MIN;
+            }
+            Delay::new(upstream, upstream.now() + backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    /// Connect, *asynchronously*, to the broker, and process everything
+    /// it sends until the connection is lost.
+    async fn session(&self, upstream: &'static dyn UpstreamContext<MqttEvent>) -> Ended {
+        let socket = match self.stack.connect(self.config.broker).await {
+            Ok(socket) => socket,
+            Err(_) => return Ended::Unconnected,
+        };
+        self.socket.set(Some(socket));
+        let mut buf = PacketBuffer::new();
+
+        let mut ended = Ended::Unconnected;
+        if self.handshake(upstream, socket, &mut buf).await {
+            self.connected.set(true);
+            upstream.send(MqttEvent::Connected);
+            ended = Ended::Lost;
+            self.resubscribe().await;
+            self.process(upstream, socket, &mut buf).await;
+        }
+
+        self.connected.set(false);
+        self.socket.set(None);
+        self.stack.close(socket).await;
+        if self.pending.get().is_some() {
+            self.acked.raise();
+        }
+        ended
+    }
+
+    /// Send `CONNECT`, and wait for the broker to accept it.
+    async fn handshake(
+        &self,
+        upstream: &'static dyn UpstreamContext<MqttEvent>,
+        socket: S::Socket,
+        buf: &mut PacketBuffer,
+    ) -> bool {
+        match packet::connect(&self.config) {
+            Ok(packet) if self.send(&packet).await.is_ok() => {}
+            _ => return false,
+        }
+        let deadline = upstream.now() + ACK_TIMEOUT;
+        loop {
+            if let Some((header, body, len)) = buf.packet() {
+                let accepted = packet::decode(header, body) == Some(Packet::ConnAck { code: 0 });
+                buf.consume(len);
+                return accepted;
+            }
+            let received = self.stack.receive(socket, buf.spare());
+            match WithTimeout::new(received, Delay::new(upstream, deadline)).await {
+                Some(Ok(len)) if len > 0 => buf.filled(len),
+                _ => return false,
+            }
+        }
+    }
+
+    async fn resubscribe(&self) {
+        let mut index = 0;
+        loop {
+            let subscription = self.subscriptions.borrow().get(index).cloned();
+            let (filter, qos) = match subscription {
+                Some(subscription) => subscription,
+                None => return,
+            };
+            // acknowledged by the broker in turn, though unawaited
+            if let Ok(packet) = packet::subscribe(self.next_id(), &filter, qos) {
+                if self.send(&packet).await.is_err() {
+                    return;
+                }
+            }
+            index += 1;
+        }
+    }
+
+    /// Process, *asynchronously*, everything the broker sends, pinging it
+    /// as the keep-alive requires, until the connection is lost.
+    async fn process(
+        &self,
+        upstream: &'static dyn UpstreamContext<MqttEvent>,
+        socket: S::Socket,
+        buf: &mut PacketBuffer,
+    ) {
+        let keep_alive = self.config.keep_alive;
+        let mut pinged: Option<Instant> = None;
+        loop {
+            while let Some((header, body, len)) = buf.packet() {
+                let result = match packet::decode(header, body) {
+                    Some(Packet::PingResp) => {
+                        pinged = None;
+                        Ok(())
+                    }
+                    Some(packet) => self.dispatch(upstream, packet).await,
+                    None => Ok(()),
+                };
+                buf.consume(len);
+                if result.is_err() {
+                    return;
+                }
+            }
+
+            let received = self.stack.receive(socket, buf.spare());
+            let result = if keep_alive == Duration::from_millis(0) {
+                Some(received.await)
+            } else {
+                let deadline = match pinged {
+                    Some(at) => at + keep_alive,
+                    None => self.last_sent.get() + keep_alive,
+                };
+                WithTimeout::new(received, Delay::new(upstream, deadline)).await
+            };
+            match result {
+                Some(Ok(len)) if len > 0 => buf.filled(len),
+                Some(_) => return,
+                None if pinged.is_some() => return,
+                None => {
+                    let now = upstream.now();
+                    if now >= self.last_sent.get() + keep_alive {
+                        match packet::pingreq() {
+                            Ok(packet) if self.send(&packet).await.is_ok() => {}
+                            _ => return,
+                        }
+                        pinged = Some(now);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn dispatch(
+        &self,
+        upstream: &'static dyn UpstreamContext<MqttEvent>,
+        packet: Packet<'_>,
+    ) -> Result<(), MqttError> {
+        match packet {
+            Packet::Publish {
+                id, topic, payload, ..
+            } => {
+                if let Some(id) = id {
+                    self.send(&packet::puback(id)?).await?;
+                }
+                let mut message = Message {
+                    topic: String::new(),
+                    payload: Vec::new(),
+                };
+                if message.topic.push_str(topic).is_ok()
+                    && message.payload.extend_from_slice(payload).is_ok()
+                {
+                    upstream.send(MqttEvent::Message(message));
+                }
+            }
+            Packet::PubAck { id } => self.acknowledge(id, 0),
+            Packet::SubAck { id, code } => self.acknowledge(id, code),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn acknowledge(&self, id: u16, code: u8) {
+        if self.pending.get() == Some(id) {
+            self.ack.set(Some(code));
+            self.acked.raise();
+        }
+    }
+}
+
+impl<S> Component for &'static MqttClient<S>
+where
+    S: TcpStack + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = MqttEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        let client: &'static MqttClient<S> = self;
+        spawn("mqtt", async move {
+            client.run(ctx.upstream()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{MqttClient, MqttConfig, MqttError, MqttEvent, QoS};
+    use crate::net::{HostStack, Ipv4Address, SocketAddress};
+    use crate::testing::{broker, leak, poll_once, Upstream};
+    use crate::time::Duration;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Poll;
+    use std::boxed::Box;
+    use std::thread;
+
+    type Client = MqttClient<HostStack>;
+
+    /// Runs futures alongside the client's own task, advancing time by
+    /// ten milliseconds at each step.
+    struct Harness {
+        upstream: &'static Upstream<MqttEvent>,
+        client: &'static Client,
+        task: Pin<Box<dyn Future<Output = ()>>>,
+    }
+
+    impl Harness {
+        fn new(port: u16) -> Self {
+            let mut config =
+                MqttConfig::new(SocketAddress::new(Ipv4Address::LOCALHOST, port), "device");
+            config.keep_alive = Duration::from_secs(1);
+            let upstream = leak(Upstream::new());
+            let client: &'static Client = leak(MqttClient::new(leak(HostStack::new()), config));
+            Self {
+                upstream,
+                client,
+                task: Box::pin(client.run(upstream)),
+            }
+        }
+
+        fn step(&mut self) {
+            assert_eq!(poll_once(self.task.as_mut()), Poll::Pending);
+            self.upstream.advance(Duration::from_millis(10));
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        fn run<F: Future>(&mut self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            for _ in 0..1000 {
+                if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                    return output;
+                }
+                self.step();
+            }
+            panic!("future never completed")
+        }
+
+        /// Step until an event is sent upstream, returning it.
+        fn event(&mut self) -> MqttEvent {
+            for _ in 0..1000 {
+                let mut events = self.upstream.take();
+                if !events.is_empty() {
+                    assert_eq!(events.len(), 1);
+                    return events.remove(0);
+                }
+                self.step();
+            }
+            panic!("no event sent")
+        }
+    }
+
+    #[test]
+    fn session() {
+        let (port, log) = broker();
+        let mut h = Harness::new(port);
+        let client = h.client;
+
+        assert_eq!(
+            h.run(client.publish("sensors/temp", b"0", QoS::AtMostOnce)),
+            Err(MqttError::NotConnected)
+        );
+        assert_eq!(h.event(), MqttEvent::Connected);
+        assert!(client.is_connected());
+
+        assert_eq!(
+            h.run(client.subscribe("sensors/temp", QoS::AtLeastOnce)),
+            Ok(())
+        );
+        assert_eq!(
+            h.run(client.subscribe("forbidden", QoS::AtMostOnce)),
+            Err(MqttError::Rejected)
+        );
+
+        // echoed with QoS 1, so acknowledged in turn
+        assert_eq!(
+            h.run(client.publish("sensors/temp", b"21.5", QoS::AtLeastOnce)),
+            Ok(())
+        );
+        match h.event() {
+            MqttEvent::Message(message) => {
+                assert_eq!(message.topic.as_str(), "sensors/temp");
+                assert_eq!(&message.payload[..], b"21.5");
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        for _ in 0..10 {
+            h.step();
+        }
+        assert_eq!(log.lock().unwrap().acknowledged, std::vec![0x1234]);
+
+        // idle beyond the keep-alive
+        for _ in 0..150 {
+            h.step();
+        }
+        assert!(log.lock().unwrap().pings >= 1);
+        assert!(h.upstream.take().is_empty());
+
+        // reconnect once dropped, subscribing again
+        assert_eq!(h.run(client.publish("kick", b"", QoS::AtMostOnce)), Ok(()));
+        assert_eq!(h.event(), MqttEvent::Disconnected);
+        assert_eq!(h.event(), MqttEvent::Connected);
+        assert_eq!(log.lock().unwrap().connects, 2);
+        for _ in 0..10 {
+            h.step();
+        }
+        assert_eq!(
+            h.run(client.publish("sensors/temp", b"22.0", QoS::AtMostOnce)),
+            Ok(())
+        );
+        match h.event() {
+            MqttEvent::Message(message) => assert_eq!(&message.payload[..], b"22.0"),
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+}
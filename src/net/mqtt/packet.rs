@@ -0,0 +1,296 @@
+use super::{MqttConfig, MqttError, QoS};
+use heapless::{consts::*, Vec};
+
+/// The longest packet sent or received.
+pub(crate) const MAX_PACKET: usize = 512;
+
+pub(crate) type Encoded = Vec<u8, U512>;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+
+/// A packet received from the broker.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Packet<'a> {
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        qos: QoS,
+        id: Option<u16>,
+        topic: &'a str,
+        payload: &'a [u8],
+    },
+    PubAck {
+        id: u16,
+    },
+    SubAck {
+        id: u16,
+        code: u8,
+    },
+    PingResp,
+}
+
+/// Build a packet of type `header`, whose body is written by `body`.
+fn packet(
+    header: u8,
+    body: impl FnOnce(&mut Encoded) -> Result<(), ()>,
+) -> Result<Encoded, MqttError> {
+    let mut encoded = Encoded::new();
+    body(&mut encoded).map_err(|_| MqttError::TooLong)?;
+
+    let mut packet = Encoded::new();
+    packet.push(header).ok();
+    let mut remaining = encoded.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte).ok();
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet
+        .extend_from_slice(&encoded)
+        .map_err(|_| MqttError::TooLong)?;
+    Ok(packet)
+}
+
+fn u16(buf: &mut Encoded, value: u16) -> Result<(), ()> {
+    buf.extend_from_slice(&value.to_be_bytes())
+}
+
+fn string(buf: &mut Encoded, value: &[u8]) -> Result<(), ()> {
+    if value.len() > u16::MAX as usize {
+        return Err(());
+    }
+    u16(buf, value.len() as u16)?;
+    buf.extend_from_slice(value)
+}
+
+pub(crate) fn connect(config: &MqttConfig) -> Result<Encoded, MqttError> {
+    packet(CONNECT, |buf| {
+        string(buf, b"MQTT")?;
+        // protocol level 4, with a clean session
+        let mut flags = 0x02;
+        if config.username.is_some() {
+            flags |= 0x80;
+        }
+        if config.password.is_some() {
+            flags |= 0x40;
+        }
+        buf.extend_from_slice(&[4, flags])?;
+        u16(buf, config.keep_alive.as_secs().min(u16::MAX as u64) as u16)?;
+        string(buf, config.client_id.as_bytes())?;
+        if let Some(username) = config.username {
+            string(buf, username.as_bytes())?;
+        }
+        if let Some(password) = config.password {
+            string(buf, password)?;
+        }
+        Ok(())
+    })
+}
+
+pub(crate) fn publish(
+    topic: &str,
+    payload: &[u8],
+    qos: QoS,
+    id: u16,
+    dup: bool,
+) -> Result<Encoded, MqttError> {
+    let header = PUBLISH | (dup as u8) << 3 | (qos as u8) << 1;
+    packet(header, |buf| {
+        string(buf, topic.as_bytes())?;
+        if qos != QoS::AtMostOnce {
+            u16(buf, id)?;
+        }
+        buf.extend_from_slice(payload)
+    })
+}
+
+pub(crate) fn puback(id: u16) -> Result<Encoded, MqttError> {
+    packet(PUBACK, |buf| u16(buf, id))
+}
+
+pub(crate) fn subscribe(id: u16, filter: &str, qos: QoS) -> Result<Encoded, MqttError> {
+    packet(SUBSCRIBE, |buf| {
+        u16(buf, id)?;
+        string(buf, filter.as_bytes())?;
+        buf.push(qos as u8).map_err(|_| ())
+    })
+}
+
+pub(crate) fn pingreq() -> Result<Encoded, MqttError> {
+    packet(PINGREQ, |_| Ok(()))
+}
+
+/// Decode the packet of type `header`, whose body is `body`, or `None`
+/// should it be malformed, or of a type the client ignores.
+pub(crate) fn decode(header: u8, body: &[u8]) -> Option<Packet<'_>> {
+    let id = |at: usize| Some(u16::from_be_bytes([*body.get(at)?, *body.get(at + 1)?]));
+    match header & 0xf0 {
+        CONNACK => Some(Packet::ConnAck {
+            code: *body.get(1)?,
+        }),
+        PUBLISH => {
+            let qos = match (header >> 1) & 0x03 {
+                0 => QoS::AtMostOnce,
+                1 => QoS::AtLeastOnce,
+                _ => return None,
+            };
+            let len = id(0)? as usize;
+            let topic = core::str::from_utf8(body.get(2..2 + len)?).ok()?;
+            let (id, payload) = match qos {
+                QoS::AtMostOnce => (None, &body[2 + len..]),
+                QoS::AtLeastOnce => (Some(id(2 + len)?), body.get(4 + len..)?),
+            };
+            Some(Packet::Publish {
+                qos,
+                id,
+                topic,
+                payload,
+            })
+        }
+        PUBACK => Some(Packet::PubAck { id: id(0)? }),
+        SUBACK => Some(Packet::SubAck {
+            id: id(0)?,
+            code: *body.get(2)?,
+        }),
+        PINGRESP => Some(Packet::PingResp),
+        _ => None,
+    }
+}
+
+/// Bytes received from the broker, accumulated until forming whole packets.
+/// Packets too long to be buffered are skipped.
+pub(crate) struct PacketBuffer {
+    data: [u8; MAX_PACKET],
+    len: usize,
+    /// The bytes of a skipped packet yet to be received.
+    skip: usize,
+}
+
+impl PacketBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: [0; MAX_PACKET],
+            len: 0,
+            skip: 0,
+        }
+    }
+
+    /// The space into which to receive more bytes.
+    pub(crate) fn spare(&mut self) -> &mut [u8] {
+        &mut self.data[self.len..]
+    }
+
+    /// Accept `len` bytes received into `spare()`.
+    pub(crate) fn filled(&mut self, len: usize) {
+        let skipped = len.min(self.skip);
+        self.skip -= skipped;
+        let start = self.len;
+        self.data.copy_within(start + skipped..start + len, start);
+        self.len += len - skipped;
+    }
+
+    /// The header and body of the first whole packet, if any, which must
+    /// then be `consume()`d.
+    pub(crate) fn packet(&mut self) -> Option<(u8, &[u8], usize)> {
+        loop {
+            if self.len < 2 {
+                return None;
+            }
+            let mut remaining = 0;
+            let mut at = 1;
+            loop {
+                if at > 4 {
+                    // malformed, so nothing more can be made sense of
+                    self.len = 0;
+                    return None;
+                }
+                let byte = *self.data[..self.len].get(at)?;
+                remaining |= ((byte & 0x7f) as usize) << (7 * (at - 1));
+                at += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            let total = at + remaining;
+            if total > MAX_PACKET {
+                self.skip = total - self.len;
+                self.len = 0;
+                continue;
+            }
+            if self.len < total {
+                return None;
+            }
+            return Some((self.data[0], &self.data[at..total], total));
+        }
+    }
+
+    /// Discard the first `len` bytes, those of a packet processed.
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.data.copy_within(len..self.len, 0);
+        self.len -= len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, publish, subscribe, Packet, PacketBuffer};
+    use crate::net::mqtt::QoS;
+
+    #[test]
+    fn packets() {
+        assert_eq!(
+            &publish("a/b", b"hi", QoS::AtLeastOnce, 7, true).unwrap()[..],
+            b"\x3a\x09\x00\x03a/b\x00\x07hi"
+        );
+        assert_eq!(
+            &subscribe(1, "a/#", QoS::AtMostOnce).unwrap()[..],
+            b"\x82\x08\x00\x01\x00\x03a/#\x00"
+        );
+        assert_eq!(
+            publish("t", &[0; 600], QoS::AtMostOnce, 0, false).map(|_| ()),
+            Err(crate::net::mqtt::MqttError::TooLong)
+        );
+
+        let mut buf = PacketBuffer::new();
+        // a packet too long to buffer is skipped, even across receptions
+        let received: &[&[u8]] = &[
+            b"\x30\xff\x04",
+            &[0; 300],
+            &[0; 339],
+            b"\x90\x03\x00\x01\x01\xd0",
+        ];
+        let mut packet = None;
+        for chunk in received {
+            assert!(packet.is_none());
+            buf.spare()[..chunk.len()].copy_from_slice(chunk);
+            buf.filled(chunk.len());
+            packet = buf.packet().map(|(_, _, len)| len);
+        }
+        assert_eq!(packet, Some(5));
+        let (header, body, len) = buf.packet().unwrap();
+        assert_eq!(
+            decode(header, body),
+            Some(Packet::SubAck { id: 1, code: 1 })
+        );
+        buf.consume(len);
+        assert!(buf.packet().is_none());
+        buf.spare()[0] = 0;
+        buf.filled(1);
+        let (header, body, _) = buf.packet().unwrap();
+        assert_eq!(decode(header, body), Some(Packet::PingResp));
+    }
+}
@@ -0,0 +1,727 @@
+use crate::component::{spawn, Component, ComponentContext};
+use crate::driver::lock::Flag;
+use crate::handler::Handler;
+use crate::interrupt::{ConnectedInterrupt, Interrupt, InterruptContext};
+use crate::kernel::{notify_activity, Yield};
+use crate::net::{Ipv4Address, SocketAddress, TcpStack, UdpStack};
+use crate::time::{Duration, Instant, WithTimeout};
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::Poll;
+use heapless::{consts::*, Vec};
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet, SocketStorage};
+use smoltcp::phy::Device;
+use smoltcp::socket::{tcp, udp};
+use smoltcp::time::{Duration as SmolDuration, Instant as SmolInstant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address as SmolIpv4};
+
+/// The number of simultaneous TCP connections supported.
+pub const TCP_SOCKETS: usize = 2;
+
+/// The number of simultaneous UDP sockets supported.
+pub const UDP_SOCKETS: usize = 2;
+
+const TCP_BUFFER: usize = 1024;
+const UDP_BUFFER: usize = 1024;
+const UDP_PACKETS: usize = 4;
+
+const CONNECT_TIMEOUT: SmolDuration = SmolDuration::from_secs(10);
+
+/// The first of the ephemeral ports used locally by sockets.
+const EPHEMERAL_PORT: u16 = 49152;
+
+/// The receive interrupt of an Ethernet MAC, whose pending flags must be
+/// cleared by its interrupt handler.
+///
+/// HALs expose this differently, so applications implement this trait
+/// for their HAL's MAC, or the portion of it handling interrupts.
+pub trait MacIrq {
+    /// Clear the pending flags of the MAC's receive interrupt.
+    fn clear_pending(&mut self);
+}
+
+/// Message sent upstream by a `MacInterrupt`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FramesReceived;
+
+/// The receive interrupt of an Ethernet MAC, held by the `TcpIpStack`
+/// driving it.
+pub struct MacInterrupt<M: MacIrq> {
+    mac: M,
+    irq: u8,
+}
+
+impl<M: MacIrq> MacInterrupt<M> {
+    pub fn new(mac: M, irq: u8) -> Self {
+        Self { mac, irq }
+    }
+}
+
+impl<M: MacIrq> Interrupt for MacInterrupt<M> {
+    type OutboundMessage = FramesReceived;
+
+    fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    fn on_interrupt(&mut self, context: &InterruptContext<Self>) {
+        self.mac.clear_pending();
+        context.send(FramesReceived);
+    }
+}
+
+/// Errors reported by a `TcpIpStack`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StackError {
+    /// Every socket of the stack is in use.
+    NoSocket,
+    /// The connection was refused, or timed out.
+    ConnectFailed,
+    /// The remote address cannot be reached through the interface.
+    Unaddressable,
+    /// The socket has been closed.
+    Closed,
+    /// A datagram was too long to be buffered.
+    TooLong,
+}
+
+/// A TCP connection of a `TcpIpStack`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TcpSocket(usize);
+
+/// A UDP socket of a `TcpIpStack`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UdpSocket(usize);
+
+/// The addresses of a `TcpIpStack`'s interface.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackConfig {
+    /// The MAC address of the interface.
+    pub mac: [u8; 6],
+    pub address: Ipv4Address,
+    /// The length of the network prefix of `address`, such as 24 for a /24.
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Address>,
+    /// Seed for the choice of initial sequence numbers, which should
+    /// differ upon each boot.
+    pub seed: u64,
+}
+
+/// The socket buffers of a `TcpIpStack`, placed in static memory,
+/// such as a `StaticCell`, on its behalf.
+pub struct StackResources {
+    sockets: [SocketStorage<'static>; TCP_SOCKETS + UDP_SOCKETS],
+    tcp: [[u8; TCP_BUFFER]; 2 * TCP_SOCKETS],
+    udp: [[u8; UDP_BUFFER]; 2 * UDP_SOCKETS],
+    udp_metadata: [[udp::PacketMetadata; UDP_PACKETS]; 2 * UDP_SOCKETS],
+}
+
+impl StackResources {
+    pub const fn new() -> Self {
+        Self {
+            sockets: [SocketStorage::EMPTY; TCP_SOCKETS + UDP_SOCKETS],
+            tcp: [[0; TCP_BUFFER]; 2 * TCP_SOCKETS],
+            udp: [[0; UDP_BUFFER]; 2 * UDP_SOCKETS],
+            udp_metadata: [[udp::PacketMetadata::EMPTY; UDP_PACKETS]; 2 * UDP_SOCKETS],
+        }
+    }
+}
+
+impl Default for StackResources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TcpSlot {
+    handle: SocketHandle,
+    in_use: Cell<bool>,
+}
+
+struct UdpSlot {
+    handle: SocketHandle,
+    /// The remote with which datagrams are exchanged, while in use.
+    remote: Cell<Option<IpEndpoint>>,
+}
+
+type StackContext<D, M> = ComponentContext<&'static TcpIpStack<D, M>>;
+
+/// A TCP/IP stack driving smoltcp upon an Ethernet MAC, and providing TCP
+/// and UDP sockets to other components, as a `TcpStack` and `UdpStack`.
+/// Available with the `tcpip` feature.
+///
+/// A task spawned upon start polls the interface whenever the MAC's
+/// receive interrupt fires, a socket is used, or smoltcp's timers, such
+/// as for retransmissions, expire according to the kernel's time source.
+///
+/// The stack is shared by reference, so should itself be placed in static
+/// memory, such as a `StaticCell`, and be started as a component, as
+/// `ConnectedComponent<&'static TcpIpStack<D, M>>`, through which it
+/// starts its `MacInterrupt`.
+pub struct TcpIpStack<D, M>
+where
+    D: Device + 'static,
+    M: MacIrq + 'static,
+{
+    device: RefCell<D>,
+    iface: RefCell<Interface>,
+    sockets: RefCell<SocketSet<'static>>,
+    tcp: Vec<TcpSlot, U2>,
+    udp: Vec<UdpSlot, U2>,
+    rx: ConnectedInterrupt<MacInterrupt<M>>,
+    activity: Flag,
+    next_port: Cell<u16>,
+}
+
+impl<D, M> TcpIpStack<D, M>
+where
+    D: Device + 'static,
+    M: MacIrq + 'static,
+{
+    pub fn new(
+        mut device: D,
+        rx: MacInterrupt<M>,
+        config: StackConfig,
+        resources: &'static mut StackResources,
+    ) -> Self {
+        let mut iface_config = Config::new(EthernetAddress(config.mac).into());
+        iface_config.random_seed = config.seed;
+        let mut iface = Interface::new(iface_config, &mut device, SmolInstant::ZERO);
+        iface.update_ip_addrs(|addrs| {
+            let address = IpAddress::Ipv4(SmolIpv4(config.address.0));
+            addrs.push(IpCidr::new(address, config.prefix_len)).ok();
+        });
+        if let Some(gateway) = config.gateway {
+            iface
+                .routes_mut()
+                .add_default_ipv4_route(SmolIpv4(gateway.0))
+                .ok();
+        }
+
+        let StackResources {
+            sockets: storage,
+            tcp: tcp_buffers,
+            udp: udp_buffers,
+            udp_metadata,
+        } = resources;
+        let mut sockets = SocketSet::new(&mut storage[..]);
+
+        let mut tcp = Vec::new();
+        let mut buffers = tcp_buffers.iter_mut();
+        while let (Some(rx), Some(tx)) = (buffers.next(), buffers.next()) {
+            let socket = tcp::Socket::new(
+                tcp::SocketBuffer::new(&mut rx[..]),
+                tcp::SocketBuffer::new(&mut tx[..]),
+            );
+            let handle = sockets.add(socket);
+            tcp.push(TcpSlot {
+                handle,
+                in_use: Cell::new(false),
+            })
+            .ok();
+        }
+
+        let mut udp = Vec::new();
+        let mut buffers = udp_buffers.iter_mut().zip(udp_metadata.iter_mut());
+        while let (Some(rx), Some(tx)) = (buffers.next(), buffers.next()) {
+            let socket = udp::Socket::new(
+                udp::PacketBuffer::new(&mut rx.1[..], &mut rx.0[..]),
+                udp::PacketBuffer::new(&mut tx.1[..], &mut tx.0[..]),
+            );
+            let handle = sockets.add(socket);
+            udp.push(UdpSlot {
+                handle,
+                remote: Cell::new(None),
+            })
+            .ok();
+        }
+
+        Self {
+            device: RefCell::new(device),
+            iface: RefCell::new(iface),
+            sockets: RefCell::new(sockets),
+            tcp,
+            udp,
+            rx: ConnectedInterrupt::new(rx),
+            activity: Flag::new(),
+            next_port: Cell::new(EPHEMERAL_PORT),
+        }
+    }
+
+    /// Process frames received and sockets' pending output, returning
+    /// the delay until the interface must next be polled, if any.
+    fn poll(&self, now: Instant) -> Option<Duration> {
+        let now = SmolInstant::from_millis(now.as_millis() as i64);
+        let mut iface = self.iface.borrow_mut();
+        let mut sockets = self.sockets.borrow_mut();
+        if iface.poll(now, &mut *self.device.borrow_mut(), &mut sockets) {
+            // sockets' wakers may have been woken
+            notify_activity();
+        }
+        iface
+            .poll_delay(now, &sockets)
+            .map(|delay| Duration::from_millis(delay.total_millis()))
+    }
+
+    /// Poll the interface, *asynchronously*, whenever activity is signalled
+    /// or smoltcp's timers expire.
+    async fn run(&self, ctx: &'static StackContext<D, M>) {
+        loop {
+            match self.poll(ctx.now()) {
+                Some(delay) if delay.as_millis() == 0 => Yield::new().await,
+                Some(delay) => {
+                    WithTimeout::new(self.activity.wait(), ctx.delay(delay)).await;
+                }
+                None => self.activity.wait().await,
+            }
+        }
+    }
+
+    fn ephemeral_port(&self) -> u16 {
+        let port = self.next_port.get();
+        self.next_port
+            .set(port.checked_add(1).unwrap_or(EPHEMERAL_PORT));
+        port
+    }
+
+    fn with_tcp<R>(&self, socket: TcpSocket, f: impl FnOnce(&mut tcp::Socket<'static>) -> R) -> R {
+        let handle = self.tcp[socket.0].handle;
+        let result = f(self.sockets.borrow_mut().get_mut(handle));
+        // the socket may have output for the interface to send
+        self.activity.raise();
+        result
+    }
+
+    fn with_udp<R>(&self, socket: UdpSocket, f: impl FnOnce(&mut udp::Socket<'static>) -> R) -> R {
+        let handle = self.udp[socket.0].handle;
+        let result = f(self.sockets.borrow_mut().get_mut(handle));
+        self.activity.raise();
+        result
+    }
+
+    /// Connect, *asynchronously*, to `remote`.
+    pub async fn connect(&self, remote: SocketAddress) -> Result<TcpSocket, StackError> {
+        let index = self
+            .tcp
+            .iter()
+            .position(|slot| !slot.in_use.get())
+            .ok_or(StackError::NoSocket)?;
+        let socket = TcpSocket(index);
+        let local_port = self.ephemeral_port();
+        let result = self.with_tcp(socket, |s| {
+            // the socket may be lingering from a previous connection
+            s.abort();
+            s.set_timeout(Some(CONNECT_TIMEOUT));
+            let mut iface = self.iface.borrow_mut();
+            s.connect(iface.context(), endpoint(remote), local_port)
+        });
+        if result.is_err() {
+            return Err(StackError::Unaddressable);
+        }
+        self.tcp[index].in_use.set(true);
+
+        let result = poll_fn(|cx| {
+            let handle = self.tcp[index].handle;
+            let mut sockets = self.sockets.borrow_mut();
+            let s = sockets.get_mut::<tcp::Socket>(handle);
+            match s.state() {
+                tcp::State::Established => Poll::Ready(Ok(())),
+                tcp::State::SynSent | tcp::State::SynReceived => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                _ => Poll::Ready(Err(StackError::ConnectFailed)),
+            }
+        })
+        .await;
+
+        match result {
+            Ok(()) => {
+                self.with_tcp(socket, |s| s.set_timeout(None));
+                Ok(socket)
+            }
+            Err(e) => {
+                self.tcp[index].in_use.set(false);
+                Err(e)
+            }
+        }
+    }
+
+    /// Send, *asynchronously*, all of `data` upon `socket`.
+    pub async fn send(&self, socket: TcpSocket, data: &[u8]) -> Result<usize, StackError> {
+        let mut sent = 0;
+        while sent < data.len() {
+            sent += poll_fn(|cx| {
+                self.with_tcp(socket, |s| {
+                    if !s.may_send() {
+                        Poll::Ready(Err(StackError::Closed))
+                    } else if s.can_send() {
+                        Poll::Ready(s.send_slice(&data[sent..]).map_err(|_| StackError::Closed))
+                    } else {
+                        s.register_send_waker(cx.waker());
+                        Poll::Pending
+                    }
+                })
+            })
+            .await?;
+        }
+        Ok(sent)
+    }
+
+    /// Receive, *asynchronously*, at least one byte from `socket` into `buf`,
+    /// returning the number of bytes received, or zero once the connection
+    /// has been closed by its peer and every byte received has been read.
+    pub async fn receive(&self, socket: TcpSocket, buf: &mut [u8]) -> Result<usize, StackError> {
+        if !self.tcp[socket.0].in_use.get() {
+            return Err(StackError::Closed);
+        }
+        poll_fn(|cx| {
+            // closed meanwhile, by another task
+            if !self.tcp[socket.0].in_use.get() {
+                return Poll::Ready(Err(StackError::Closed));
+            }
+            self.with_tcp(socket, |s| {
+                if s.can_recv() {
+                    Poll::Ready(s.recv_slice(buf).map_err(|_| StackError::Closed))
+                } else if !s.may_recv() {
+                    Poll::Ready(Ok(0))
+                } else {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Close `socket`, which lingers until the connection is shut down
+    /// gracefully, unless reused sooner.
+    pub fn close(&self, socket: TcpSocket) {
+        self.with_tcp(socket, |s| s.close());
+        self.tcp[socket.0].in_use.set(false);
+    }
+
+    /// Open a socket exchanging datagrams with `remote`.
+    pub fn open(&self, remote: SocketAddress) -> Result<UdpSocket, StackError> {
+        let index = self
+            .udp
+            .iter()
+            .position(|slot| slot.remote.get().is_none())
+            .ok_or(StackError::NoSocket)?;
+        let socket = UdpSocket(index);
+        let local_port = self.ephemeral_port();
+        self.with_udp(socket, |s| {
+            s.close();
+            s.bind(local_port)
+        })
+        .map_err(|_| StackError::Unaddressable)?;
+        self.udp[index].remote.set(Some(endpoint(remote)));
+        Ok(socket)
+    }
+
+    /// Send, *asynchronously*, `data` as one datagram upon `socket`.
+    pub async fn send_datagram(&self, socket: UdpSocket, data: &[u8]) -> Result<(), StackError> {
+        let remote = self.udp[socket.0].remote.get().ok_or(StackError::Closed)?;
+        poll_fn(|cx| {
+            self.with_udp(socket, |s| {
+                if data.len() > s.payload_send_capacity() {
+                    return Poll::Ready(Err(StackError::TooLong));
+                }
+                match s.send_slice(data, remote) {
+                    Ok(()) => Poll::Ready(Ok(())),
+                    Err(udp::SendError::Unaddressable) => {
+                        Poll::Ready(Err(StackError::Unaddressable))
+                    }
+                    Err(udp::SendError::BufferFull) => {
+                        s.register_send_waker(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    /// Receive, *asynchronously*, one datagram from the remote of `socket`
+    /// into `buf`, discarding any of it not fitting.
+    pub async fn receive_datagram(
+        &self,
+        socket: UdpSocket,
+        buf: &mut [u8],
+    ) -> Result<usize, StackError> {
+        let remote = self.udp[socket.0].remote.get().ok_or(StackError::Closed)?;
+        poll_fn(|cx| {
+            self.with_udp(socket, |s| loop {
+                match s.recv() {
+                    Ok((data, meta)) if meta.endpoint == remote => {
+                        let len = data.len().min(buf.len());
+                        buf[..len].copy_from_slice(&data[..len]);
+                        return Poll::Ready(Ok(len));
+                    }
+                    // from elsewhere
+                    Ok(_) => {}
+                    Err(_) => {
+                        s.register_recv_waker(cx.waker());
+                        return Poll::Pending;
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    /// Close `socket`, discarding any datagrams not yet received.
+    pub fn close_datagram(&self, socket: UdpSocket) {
+        self.with_udp(socket, |s| s.close());
+        self.udp[socket.0].remote.set(None);
+    }
+}
+
+fn endpoint(address: SocketAddress) -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(SmolIpv4(address.ip.0)), address.port)
+}
+
+impl<D, M> TcpStack for TcpIpStack<D, M>
+where
+    D: Device + 'static,
+    M: MacIrq + 'static,
+{
+    type Socket = TcpSocket;
+    type Error = StackError;
+
+    async fn connect(&self, remote: SocketAddress) -> Result<TcpSocket, StackError> {
+        TcpIpStack::connect(self, remote).await
+    }
+
+    async fn send(&self, socket: TcpSocket, data: &[u8]) -> Result<usize, StackError> {
+        TcpIpStack::send(self, socket, data).await
+    }
+
+    async fn receive(&self, socket: TcpSocket, buf: &mut [u8]) -> Result<usize, StackError> {
+        TcpIpStack::receive(self, socket, buf).await
+    }
+
+    async fn close(&self, socket: TcpSocket) {
+        TcpIpStack::close(self, socket)
+    }
+}
+
+impl<D, M> UdpStack for TcpIpStack<D, M>
+where
+    D: Device + 'static,
+    M: MacIrq + 'static,
+{
+    type Socket = UdpSocket;
+    type Error = StackError;
+
+    async fn open(&self, remote: SocketAddress) -> Result<UdpSocket, StackError> {
+        TcpIpStack::open(self, remote)
+    }
+
+    async fn send(&self, socket: UdpSocket, data: &[u8]) -> Result<(), StackError> {
+        self.send_datagram(socket, data).await
+    }
+
+    async fn receive(&self, socket: UdpSocket, buf: &mut [u8]) -> Result<usize, StackError> {
+        self.receive_datagram(socket, buf).await
+    }
+
+    async fn close(&self, socket: UdpSocket) {
+        self.close_datagram(socket)
+    }
+}
+
+impl<D, M> Component for &'static TcpIpStack<D, M>
+where
+    D: Device + 'static,
+    M: MacIrq + 'static,
+{
+    type InboundMessage = ();
+    type OutboundMessage = ();
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        let stack: &'static TcpIpStack<D, M> = self;
+        stack.rx.start(ctx);
+        spawn("tcpip", async move {
+            stack.run(ctx).await;
+        });
+    }
+}
+
+impl<D, M> Handler<FramesReceived> for &'static TcpIpStack<D, M>
+where
+    D: Device + 'static,
+    M: MacIrq + 'static,
+{
+    fn on_message(&mut self, _message: FramesReceived) {
+        self.activity.raise();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{
+        endpoint, MacInterrupt, StackConfig, StackError, StackResources, TcpIpStack, TcpSocket,
+        UdpSocket,
+    };
+    use crate::mock::{MockMac, MockMacState};
+    use crate::net::{Ipv4Address, SocketAddress, TcpStack, UdpStack};
+    use crate::testing::{leak, poll_once};
+    use crate::time::Instant;
+    use core::future::Future;
+    use core::task::Poll;
+    use smoltcp::socket::{tcp, udp};
+    use std::boxed::Box;
+
+    type Stack = TcpIpStack<MockMac, MockMac>;
+
+    const ADDRESS: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+
+    /// Runs futures alongside the polling of a stack looped back upon
+    /// itself, advancing time by a millisecond at each step.
+    struct Harness {
+        stack: &'static Stack,
+        now: u64,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let mac = leak(MockMacState::new());
+            let config = StackConfig {
+                mac: [0x02, 0, 0, 0, 0, 1],
+                address: ADDRESS,
+                prefix_len: 24,
+                gateway: None,
+                seed: 42,
+            };
+            let rx = MacInterrupt::new(mac.mac(), 0);
+            let resources = Box::leak(Box::new(StackResources::new()));
+            Self {
+                stack: leak(TcpIpStack::new(mac.mac(), rx, config, resources)),
+                now: 0,
+            }
+        }
+
+        fn poll(&mut self) {
+            self.now += 1;
+            self.stack.poll(Instant::from_millis(self.now));
+        }
+
+        fn run<F: Future>(&mut self, future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            for _ in 0..1000 {
+                if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                    return output;
+                }
+                self.poll();
+            }
+            panic!("future never completed")
+        }
+
+        fn tcp<R>(&self, socket: TcpSocket, f: impl FnOnce(&mut tcp::Socket<'static>) -> R) -> R {
+            let handle = self.stack.tcp[socket.0].handle;
+            f(self.stack.sockets.borrow_mut().get_mut(handle))
+        }
+
+        fn udp<R>(&self, socket: UdpSocket, f: impl FnOnce(&mut udp::Socket<'static>) -> R) -> R {
+            let handle = self.stack.udp[socket.0].handle;
+            f(self.stack.sockets.borrow_mut().get_mut(handle))
+        }
+    }
+
+    #[test]
+    fn tcp() {
+        let mut h = Harness::new();
+        let stack = h.stack;
+
+        // the last socket plays the server
+        let server = TcpSocket(1);
+        stack.tcp[1].in_use.set(true);
+        h.tcp(server, |s| s.listen(7)).unwrap();
+
+        let remote = SocketAddress::new(ADDRESS, 7);
+        let client = h.run(TcpStack::connect(stack, remote)).unwrap();
+        assert_eq!(client, TcpSocket(0));
+        assert_eq!(
+            h.run(TcpStack::connect(stack, remote)),
+            Err(StackError::NoSocket)
+        );
+
+        assert_eq!(h.run(TcpStack::send(stack, client, b"hello")), Ok(5));
+        for _ in 0..4 {
+            h.poll();
+        }
+        let mut buf = [0; 16];
+        assert_eq!(h.tcp(server, |s| s.recv_slice(&mut buf)), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+
+        h.tcp(server, |s| {
+            s.send_slice(b"world").unwrap();
+            s.close();
+        });
+        assert_eq!(h.run(TcpStack::receive(stack, client, &mut buf)), Ok(5));
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(h.run(TcpStack::receive(stack, client, &mut buf)), Ok(0));
+
+        h.run(TcpStack::close(stack, client));
+        assert_eq!(
+            h.run(TcpStack::receive(stack, client, &mut buf)),
+            Err(StackError::Closed)
+        );
+
+        // nothing listens upon another port
+        let refused = SocketAddress::new(ADDRESS, 8);
+        assert_eq!(
+            h.run(TcpStack::connect(stack, refused)),
+            Err(StackError::ConnectFailed)
+        );
+    }
+
+    #[test]
+    fn udp() {
+        let mut h = Harness::new();
+        let stack = h.stack;
+
+        let server = UdpSocket(1);
+        stack.udp[1]
+            .remote
+            .set(Some(endpoint(SocketAddress::new(ADDRESS, 0))));
+        h.udp(server, |s| s.bind(7)).unwrap();
+
+        let socket = h
+            .run(UdpStack::open(stack, SocketAddress::new(ADDRESS, 7)))
+            .unwrap();
+        assert_eq!(socket, UdpSocket(0));
+        assert_eq!(h.run(UdpStack::send(stack, socket, b"ping")), Ok(()));
+        assert_eq!(
+            h.run(UdpStack::send(stack, socket, &[0; 2048])),
+            Err(StackError::TooLong)
+        );
+        for _ in 0..4 {
+            h.poll();
+        }
+
+        let mut buf = [0; 16];
+        let (len, meta) = h.udp(server, |s| s.recv_slice(&mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        h.udp(server, |s| {
+            s.send_slice(b"pong!", meta.endpoint).unwrap();
+            s.send_slice(b"hi", meta.endpoint).unwrap();
+        });
+
+        // datagrams remain distinct, and are truncated to fit
+        let mut short = [0; 4];
+        assert_eq!(h.run(UdpStack::receive(stack, socket, &mut short)), Ok(4));
+        assert_eq!(&short, b"pong");
+        assert_eq!(h.run(UdpStack::receive(stack, socket, &mut short)), Ok(2));
+        assert_eq!(&short[..2], b"hi");
+
+        h.run(UdpStack::close(stack, socket));
+        assert_eq!(
+            h.run(UdpStack::receive(stack, socket, &mut short)),
+            Err(StackError::Closed)
+        );
+    }
+}
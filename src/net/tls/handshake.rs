@@ -0,0 +1,436 @@
+use super::keys::{derive_secret, derived, extract, finished, Secret, TrafficKeys, HASH_LEN};
+use super::x509::{self, Certificate};
+use super::{
+    alert, Connection, CryptoRng, RngCore, TlsError, TlsStack, ALERT, CHANGE_CIPHER_SPEC, HANDSHAKE,
+};
+use crate::net::TcpStack;
+use heapless::{consts::*, Vec};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+pub(crate) const NEW_SESSION_TICKET: u8 = 4;
+const ENCRYPTED_EXTENSIONS: u8 = 8;
+const CERTIFICATE: u8 = 11;
+const CERTIFICATE_VERIFY: u8 = 15;
+const FINISHED: u8 = 20;
+pub(crate) const KEY_UPDATE: u8 = 24;
+
+/// A `KeyUpdate` not requesting one in return.
+pub(crate) const KEY_UPDATED: [u8; 5] = [KEY_UPDATE, 0, 0, 1, 0];
+
+const SERVER_NAME: u16 = 0;
+const MAX_FRAGMENT_LENGTH: u16 = 1;
+const SUPPORTED_GROUPS: u16 = 10;
+const SIGNATURE_ALGORITHMS: u16 = 13;
+const PRE_SHARED_KEY: u16 = 41;
+const SUPPORTED_VERSIONS: u16 = 43;
+const PSK_KEY_EXCHANGE_MODES: u16 = 45;
+const KEY_SHARE: u16 = 51;
+
+const TLS13: u16 = 0x0304;
+const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
+const X25519: u16 = 0x001d;
+const ECDSA_SECP256R1_SHA256: u16 = 0x0403;
+/// The `max_fragment_length` of 4096 bytes.
+const FRAGMENT_4096: u8 = 4;
+const PSK_DHE_KE: u8 = 1;
+
+/// The random of a `ServerHello` which is instead a `HelloRetryRequest`.
+const HELLO_RETRY_REQUEST: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// The length of the binders of the `pre_shared_key` extension, which
+/// ends the `ClientHello`, so the part of it they are computed over.
+const BINDERS_LEN: usize = 2 + 1 + HASH_LEN;
+
+type Hello = Vec<u8, U512>;
+
+fn put(hello: &mut Hello, data: &[u8]) -> Result<(), TlsError> {
+    hello.extend_from_slice(data).map_err(|_| TlsError::TooLong)
+}
+
+fn put_extension(hello: &mut Hello, ty: u16, data: &[&[u8]]) -> Result<(), TlsError> {
+    let len: usize = data.iter().map(|d| d.len()).sum();
+    put(hello, &ty.to_be_bytes())?;
+    put(hello, &(len as u16).to_be_bytes())?;
+    for d in data {
+        put(hello, d)?;
+    }
+    Ok(())
+}
+
+/// Reads the fields of a handshake message.
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], TlsError> {
+        if self.0.len() < len {
+            return Err(TlsError::Protocol);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, TlsError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, TlsError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Result<usize, TlsError> {
+        let b = self.take(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    fn vec8(&mut self) -> Result<&'a [u8], TlsError> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Result<&'a [u8], TlsError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn vec24(&mut self) -> Result<&'a [u8], TlsError> {
+        let len = self.u24()?;
+        self.take(len)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Compare two MACs in time independent of where they differ.
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |d, (x, y)| d | (x ^ y)) == 0
+}
+
+/// What the `ServerHello` selected.
+struct ServerHello<'a> {
+    key: &'a [u8],
+    psk: bool,
+}
+
+/// Parse a `ServerHello`, which may select a PSK only should one have been `offered`.
+fn server_hello(body: &[u8], offered: bool) -> Result<ServerHello<'_>, TlsError> {
+    let mut hello = Cursor(body);
+    hello.u16()?;
+    if hello.take(32)? == HELLO_RETRY_REQUEST {
+        // the offered key share is the only one supported anyway
+        return Err(TlsError::Unsupported);
+    }
+    if !hello.vec8()?.is_empty() {
+        return Err(TlsError::Protocol);
+    }
+    if hello.u16()? != TLS_AES_128_GCM_SHA256 {
+        return Err(TlsError::Unsupported);
+    }
+    hello.u8()?;
+
+    let mut extensions = Cursor(hello.vec16()?);
+    let mut version = None;
+    let mut key = None;
+    let mut psk = false;
+    while !extensions.is_empty() {
+        let ty = extensions.u16()?;
+        let mut data = Cursor(extensions.vec16()?);
+        match ty {
+            SUPPORTED_VERSIONS => version = Some(data.u16()?),
+            KEY_SHARE => {
+                if data.u16()? != X25519 {
+                    return Err(TlsError::Unsupported);
+                }
+                key = Some(data.vec16()?);
+            }
+            PRE_SHARED_KEY => {
+                // selecting a PSK never offered would skip authenticating the server
+                if !offered || data.u16()? != 0 {
+                    return Err(TlsError::Protocol);
+                }
+                psk = true;
+            }
+            _ => {}
+        }
+    }
+    if version != Some(TLS13) {
+        return Err(TlsError::Unsupported);
+    }
+    match key {
+        Some(key) if key.len() == 32 => Ok(ServerHello { key, psk }),
+        Some(_) => Err(TlsError::Protocol),
+        // a PSK-only exchange, which was not offered
+        None => Err(TlsError::Unsupported),
+    }
+}
+
+impl<S, R> TlsStack<S, R>
+where
+    S: TcpStack + 'static,
+    R: RngCore + CryptoRng,
+{
+    /// Build the `ClientHello`, offering `key`, binding it to the pre-shared
+    /// key, if any, derived into `early`.
+    fn client_hello(&self, key: &PublicKey, early: &Secret) -> Result<Hello, TlsError> {
+        let mut random = [0; 32];
+        self.rng.borrow_mut().fill_bytes(&mut random);
+
+        let mut hello = Hello::new();
+        put(&mut hello, &[CLIENT_HELLO, 0, 0, 0, 3, 3])?;
+        put(&mut hello, &random)?;
+        put(&mut hello, &[0])?;
+        put(&mut hello, &[0, 2])?;
+        put(&mut hello, &TLS_AES_128_GCM_SHA256.to_be_bytes())?;
+        put(&mut hello, &[1, 0])?;
+        put(&mut hello, &[0, 0])?;
+        let extensions = hello.len();
+
+        let name = self.config.server_name.as_bytes();
+        let name_len = (name.len() as u16).to_be_bytes();
+        let list_len = (name.len() as u16 + 3).to_be_bytes();
+        put_extension(&mut hello, SERVER_NAME, &[&list_len, &[0], &name_len, name])?;
+        put_extension(
+            &mut hello,
+            SUPPORTED_VERSIONS,
+            &[&[2], &TLS13.to_be_bytes()],
+        )?;
+        put_extension(
+            &mut hello,
+            SUPPORTED_GROUPS,
+            &[&[0, 2], &X25519.to_be_bytes()],
+        )?;
+        put_extension(
+            &mut hello,
+            SIGNATURE_ALGORITHMS,
+            &[&[0, 2], &ECDSA_SECP256R1_SHA256.to_be_bytes()],
+        )?;
+        put_extension(&mut hello, MAX_FRAGMENT_LENGTH, &[&[FRAGMENT_4096]])?;
+        put_extension(
+            &mut hello,
+            KEY_SHARE,
+            &[&[0, 36], &X25519.to_be_bytes(), &[0, 32], key.as_bytes()],
+        )?;
+        if let Some(psk) = self.config.psk {
+            put_extension(&mut hello, PSK_KEY_EXCHANGE_MODES, &[&[1, PSK_DHE_KE]])?;
+            let identity_len = (psk.identity.len() as u16).to_be_bytes();
+            let identities_len = (psk.identity.len() as u16 + 6).to_be_bytes();
+            let binders = [0; BINDERS_LEN];
+            put_extension(
+                &mut hello,
+                PRE_SHARED_KEY,
+                &[
+                    &identities_len,
+                    &identity_len,
+                    psk.identity,
+                    &[0; 4],
+                    &binders,
+                ],
+            )?;
+        }
+
+        let len = hello.len();
+        hello[1..4].copy_from_slice(&(len as u32 - 4).to_be_bytes()[1..]);
+        let extensions_len = (len - extensions) as u16;
+        hello[extensions - 2..extensions].copy_from_slice(&extensions_len.to_be_bytes());
+
+        if self.config.psk.is_some() {
+            let binder_key = derive_secret(early, "ext binder", &Sha256::digest(b""));
+            let binder = finished(&binder_key, &Sha256::digest(&hello[..len - BINDERS_LEN]));
+            let binders = &mut hello[len - BINDERS_LEN..];
+            binders[..3].copy_from_slice(&[0, 1 + HASH_LEN as u8, HASH_LEN as u8]);
+            binders[3..].copy_from_slice(&binder);
+        }
+        Ok(hello)
+    }
+
+    /// Receive, *asynchronously*, handshake records until the next handshake
+    /// message has been, returning its type and length, its header included.
+    async fn message(
+        &self,
+        connection: &Connection<S::Socket>,
+        transport: S::Socket,
+    ) -> Result<(u8, usize), TlsError> {
+        loop {
+            {
+                let messages = self.messages.borrow();
+                if messages.len() >= 4 {
+                    let len =
+                        4 + u32::from_be_bytes([0, messages[1], messages[2], messages[3]]) as usize;
+                    if len > messages.capacity() {
+                        return Err(TlsError::TooLong);
+                    }
+                    if messages.len() >= len {
+                        return Ok((messages[0], len));
+                    }
+                }
+            }
+            match self.next_record(connection, transport).await? {
+                None => return Err(TlsError::Transport),
+                // sent for the sake of middleboxes
+                Some(CHANGE_CIPHER_SPEC) => {}
+                Some(HANDSHAKE) => {
+                    let reader = connection.reader.borrow();
+                    self.messages
+                        .borrow_mut()
+                        .extend_from_slice(reader.content())
+                        .map_err(|_| TlsError::TooLong)?;
+                }
+                Some(ALERT) => return Err(alert(connection.reader.borrow().content())),
+                Some(_) => return Err(TlsError::Protocol),
+            }
+        }
+    }
+
+    /// Add the first handshake message, `len` long, to `transcript`, then discard it.
+    fn consume(&self, transcript: &mut Sha256, len: usize) {
+        let mut messages = self.messages.borrow_mut();
+        transcript.update(&messages[..len]);
+        let remaining = Vec::from_slice(&messages[len..]).expect("shorter than before");
+        *messages = remaining;
+    }
+
+    /// Make, *asynchronously*, the handshake upon the newly connected
+    /// `transport`, installing the application traffic keys once done.
+    pub(super) async fn handshake(
+        &self,
+        connection: &Connection<S::Socket>,
+        transport: S::Socket,
+    ) -> Result<(), TlsError> {
+        *self.messages.borrow_mut() = Vec::new();
+        let zeros = [0; HASH_LEN];
+        let psk = self.config.psk.map(|psk| psk.key).unwrap_or(&zeros);
+        let early = extract(&zeros, psk);
+
+        let secret = EphemeralSecret::random_from_rng(&mut *self.rng.borrow_mut());
+        let hello = self.client_hello(&PublicKey::from(&secret), &early)?;
+        let mut transcript = Sha256::new();
+        transcript.update(&hello);
+        self.write(connection, transport, HANDSHAKE, &hello).await?;
+
+        let (ty, len) = self.message(connection, transport).await?;
+        if ty != SERVER_HELLO {
+            return Err(TlsError::Protocol);
+        }
+        let (key, with_psk) = {
+            let messages = self.messages.borrow();
+            let hello = server_hello(&messages[4..len], self.config.psk.is_some())?;
+            let mut key = [0; 32];
+            key.copy_from_slice(hello.key);
+            (key, hello.psk)
+        };
+        self.consume(&mut transcript, len);
+        let shared = secret.diffie_hellman(&PublicKey::from(key));
+        if !shared.was_contributory() {
+            return Err(TlsError::Protocol);
+        }
+        let early = if with_psk {
+            early
+        } else {
+            extract(&zeros, &zeros)
+        };
+        let anchor = match (with_psk, self.config.ca) {
+            (true, _) => None,
+            (false, Some(ca)) => Some(Certificate::parse(ca).ok_or(TlsError::Unsupported)?),
+            (false, None) => return Err(TlsError::Authentication),
+        };
+
+        let hash = transcript.clone().finalize();
+        let secret = extract(&derived(&early), shared.as_bytes());
+        let client = derive_secret(&secret, "c hs traffic", &hash);
+        let server = derive_secret(&secret, "s hs traffic", &hash);
+        connection.reader.borrow_mut().keys = Some(TrafficKeys::new(server));
+
+        let (ty, len) = self.message(connection, transport).await?;
+        if ty != ENCRYPTED_EXTENSIONS {
+            return Err(TlsError::Protocol);
+        }
+        self.consume(&mut transcript, len);
+
+        if let Some(anchor) = anchor {
+            let (ty, len) = self.message(connection, transport).await?;
+            if ty != CERTIFICATE {
+                return Err(TlsError::Protocol);
+            }
+            let leaf: Vec<u8, U65> = {
+                let messages = self.messages.borrow();
+                let mut message = Cursor(&messages[4..len]);
+                message.vec8()?;
+                let mut list = Cursor(message.vec24()?);
+                let mut certificates: Vec<&[u8], U4> = Vec::new();
+                while !list.is_empty() {
+                    let certificate = list.vec24()?;
+                    list.vec16()?;
+                    // any beyond are not needed to reach a well-known anchor
+                    certificates.push(certificate).ok();
+                }
+                let key = x509::verify_chain(&certificates, &anchor, self.config.server_name)
+                    .ok_or(TlsError::Authentication)?;
+                Vec::from_slice(key).map_err(|_| TlsError::Authentication)?
+            };
+            self.consume(&mut transcript, len);
+
+            let (ty, len) = self.message(connection, transport).await?;
+            if ty != CERTIFICATE_VERIFY {
+                return Err(TlsError::Protocol);
+            }
+            {
+                let messages = self.messages.borrow();
+                let mut message = Cursor(&messages[4..len]);
+                if message.u16()? != ECDSA_SECP256R1_SHA256 {
+                    return Err(TlsError::Unsupported);
+                }
+                let signature = message.vec16()?;
+                let mut content = [b' '; 64 + 34 + HASH_LEN];
+                content[64..98].copy_from_slice(b"TLS 1.3, server CertificateVerify\0");
+                content[98..].copy_from_slice(&transcript.clone().finalize());
+                if !x509::verify(&leaf, &content, signature) {
+                    return Err(TlsError::Authentication);
+                }
+            }
+            self.consume(&mut transcript, len);
+        }
+
+        let (ty, len) = self.message(connection, transport).await?;
+        if ty != FINISHED {
+            return Err(TlsError::Protocol);
+        }
+        {
+            let expected = finished(&server, &transcript.clone().finalize());
+            if !equal(&self.messages.borrow()[4..len], &expected) {
+                return Err(TlsError::Authentication);
+            }
+        }
+        self.consume(&mut transcript, len);
+        if !self.messages.borrow().is_empty() {
+            return Err(TlsError::Protocol);
+        }
+
+        let hash = transcript.clone().finalize();
+        let mut message = [0; 4 + HASH_LEN];
+        message[..4].copy_from_slice(&[FINISHED, 0, 0, HASH_LEN as u8]);
+        message[4..].copy_from_slice(&finished(&client, &hash));
+        connection
+            .write_keys
+            .replace(Some(TrafficKeys::new(client)));
+        self.write(connection, transport, HANDSHAKE, &message)
+            .await?;
+
+        let secret = extract(&derived(&secret), &zeros);
+        let client = derive_secret(&secret, "c ap traffic", &hash);
+        let server = derive_secret(&secret, "s ap traffic", &hash);
+        connection.reader.borrow_mut().keys = Some(TrafficKeys::new(server));
+        connection
+            .write_keys
+            .replace(Some(TrafficKeys::new(client)));
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use super::TlsError;
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce, Tag};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+pub(crate) const HASH_LEN: usize = 32;
+pub(crate) const TAG_LEN: usize = 16;
+
+pub(crate) type Secret = [u8; HASH_LEN];
+
+pub(crate) fn extract(salt: &[u8], ikm: &[u8]) -> Secret {
+    Hkdf::<Sha256>::extract(Some(salt), ikm).0.into()
+}
+
+/// `HKDF-Expand-Label(secret, label, context, out.len())`.
+pub(crate) fn expand_label(secret: &Secret, label: &str, context: &[u8], out: &mut [u8]) {
+    let len = (out.len() as u16).to_be_bytes();
+    let label_len = [(6 + label.len()) as u8];
+    let context_len = [context.len() as u8];
+    let info: [&[u8]; 6] = [
+        &len,
+        &label_len,
+        b"tls13 ",
+        label.as_bytes(),
+        &context_len,
+        context,
+    ];
+    let hkdf = Hkdf::<Sha256>::from_prk(secret).expect("secrets are as long as the hash");
+    hkdf.expand_multi_info(&info, out)
+        .expect("outputs are far shorter than the limit");
+}
+
+/// `Derive-Secret(secret, label, messages)`, given the hash of the messages.
+pub(crate) fn derive_secret(secret: &Secret, label: &str, hash: &[u8]) -> Secret {
+    let mut derived = [0; HASH_LEN];
+    expand_label(secret, label, hash, &mut derived);
+    derived
+}
+
+/// Derive the secret from which that of the next stage is extracted.
+pub(crate) fn derived(secret: &Secret) -> Secret {
+    derive_secret(secret, "derived", &Sha256::digest(b""))
+}
+
+/// The verify data of a `Finished` message, or a PSK binder, keyed by
+/// `secret` over the transcript `hash`.
+pub(crate) fn finished(secret: &Secret, hash: &[u8]) -> [u8; HASH_LEN] {
+    let mut key = [0; HASH_LEN];
+    expand_label(secret, "finished", &[], &mut key);
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("keys may be of any length");
+    mac.update(hash);
+    mac.finalize().into_bytes().into()
+}
+
+/// The keys protecting the records sent in one direction.
+pub(crate) struct TrafficKeys {
+    secret: Secret,
+    key: [u8; 16],
+    iv: [u8; 12],
+    sequence: u64,
+}
+
+impl TrafficKeys {
+    pub(crate) fn new(secret: Secret) -> Self {
+        let mut keys = Self {
+            secret,
+            key: [0; 16],
+            iv: [0; 12],
+            sequence: 0,
+        };
+        expand_label(&keys.secret, "key", &[], &mut keys.key);
+        expand_label(&keys.secret, "iv", &[], &mut keys.iv);
+        keys
+    }
+
+    /// Derive the keys following a `KeyUpdate`.
+    pub(crate) fn update(&mut self) {
+        *self = Self::new(derive_secret(&self.secret, "traffic upd", &[]));
+    }
+
+    fn nonce(&mut self) -> Nonce<aes_gcm::aead::consts::U12> {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..]
+            .iter_mut()
+            .zip(self.sequence.to_be_bytes().iter())
+        {
+            *n ^= s;
+        }
+        self.sequence += 1;
+        nonce.into()
+    }
+
+    /// Encrypt `data` in place, authenticating the record's `header` too,
+    /// returning the tag to follow it.
+    pub(crate) fn seal(&mut self, header: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+        let nonce = self.nonce();
+        let cipher = Aes128Gcm::new(&self.key.into());
+        cipher
+            .encrypt_in_place_detached(&nonce, header, data)
+            .expect("records are far shorter than the limit")
+            .into()
+    }
+
+    /// Decrypt `data`, followed by its tag, in place, returning the length
+    /// of the plaintext.
+    pub(crate) fn open(&mut self, header: &[u8], data: &mut [u8]) -> Result<usize, TlsError> {
+        if data.len() < TAG_LEN {
+            return Err(TlsError::Decrypt);
+        }
+        let len = data.len() - TAG_LEN;
+        let (data, tag) = data.split_at_mut(len);
+        let nonce = self.nonce();
+        let cipher = Aes128Gcm::new(&self.key.into());
+        cipher
+            .decrypt_in_place_detached(&nonce, header, data, Tag::from_slice(tag))
+            .map_err(|_| TlsError::Decrypt)?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{derive_secret, derived, extract, TrafficKeys, HASH_LEN};
+
+    fn hex(s: &str) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, b) in bytes.iter_mut().enumerate().take(s.len() / 2) {
+            *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    /// The simple 1-RTT handshake traced in RFC 8448, section 3.
+    #[test]
+    fn schedule() {
+        let early = extract(&[0; HASH_LEN], &[0; HASH_LEN]);
+        assert_eq!(
+            early,
+            hex("33ad0a1c607ec03b09e6cd9893680ce210adf300aa1f2660e1b22e10f170f92a")
+        );
+        let shared = hex("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d");
+        let handshake = extract(&derived(&early), &shared);
+        assert_eq!(
+            handshake,
+            hex("1dc826e93606aa6fdc0aadc12f741b01046aa6b99f691ed221a9f0ca043fbeac")
+        );
+        let hash = hex("860c06edc07858ee8e78f0e7428c58edd6b43f2ca3e6e95f02ed063cf0e1cad8");
+        let server = derive_secret(&handshake, "s hs traffic", &hash);
+        assert_eq!(
+            server,
+            hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38")
+        );
+
+        let mut keys = TrafficKeys::new(server);
+        assert_eq!(keys.key[..], hex("3fce516009c21727d0f2e4e86ee403bc")[..16]);
+        assert_eq!(keys.iv[..], hex("5d313eb2671276ee13000b30")[..12]);
+
+        let header = [23, 3, 3, 0, 4 + 16];
+        let mut record = [0; 4 + 16];
+        record[..4].copy_from_slice(b"ping");
+        let tag = TrafficKeys::new(server).seal(&header, &mut record[..4]);
+        record[4..].copy_from_slice(&tag);
+        assert_eq!(keys.open(&header, &mut record), Ok(4));
+        assert_eq!(&record[..4], b"ping");
+        // decrypted with the next sequence number, so unauthenticated
+        assert!(keys.open(&header, &mut record).is_err());
+    }
+}
@@ -0,0 +1,866 @@
+use crate::driver::lock::BusLock;
+use crate::net::{SocketAddress, TcpStack};
+use core::cell::{Cell, RefCell};
+use heapless::{consts::*, Vec};
+
+pub use rand_core::{CryptoRng, RngCore};
+
+/// The client's side of a full TLS 1.3 handshake, authenticating the
+/// server by its certificate or by a pre-shared key.
+mod handshake;
+
+/// The TLS 1.3 key schedule, for `TLS_AES_128_GCM_SHA256`, and the
+/// protection of records with the traffic keys it derives.
+mod keys;
+
+/// Just enough of X.509 to verify a chain of certificates signed with
+/// ECDSA upon P-256, and the DNS names of the server they identify.
+mod x509;
+
+use keys::{TrafficKeys, TAG_LEN};
+
+/// The connections which may be open at once.
+const TLS_SOCKETS: usize = 2;
+
+/// The longest record plaintext the server is asked to send, by the
+/// `max_fragment_length` extension.
+const MAX_FRAGMENT: usize = 4096;
+
+/// The longest record plaintext sent.
+const MAX_SEND: usize = 1024;
+
+const HEADER_LEN: usize = 5;
+
+/// Room for the longest record received, whose ciphertext may exceed its
+/// plaintext by up to 256 bytes.
+const RX_BUFFER: usize = HEADER_LEN + MAX_FRAGMENT + 256;
+
+/// Room for the longest record sent, with its content type and tag.
+const TX_BUFFER: usize = HEADER_LEN + MAX_SEND + 1 + TAG_LEN;
+
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+const CLOSE_NOTIFY: u8 = 0;
+
+/// Errors reported by a `TlsStack`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TlsError {
+    /// No more connections may be open at once.
+    NoSocket,
+    /// The underlying stack failed, or the connection was closed during the handshake.
+    Transport,
+    /// The server did not follow the protocol.
+    Protocol,
+    /// The server chose parameters which are not supported.
+    Unsupported,
+    /// The server could not be authenticated, by its certificate or the pre-shared key.
+    Authentication,
+    /// A record could not be decrypted.
+    Decrypt,
+    /// The server sent an alert, of this description.
+    Alert(u8),
+    /// A record or handshake message was too long to be buffered.
+    TooLong,
+}
+
+/// A key shared with the server beforehand, and its identity.
+#[derive(Copy, Clone, Debug)]
+pub struct Psk {
+    pub identity: &'static [u8],
+    pub key: &'static [u8],
+}
+
+/// How a `TlsStack` authenticates the servers it connects to.
+#[derive(Copy, Clone, Debug)]
+pub struct TlsConfig {
+    /// The name of the servers, sent to them, and which their certificates must identify.
+    pub server_name: &'static str,
+    /// The DER-encoded certificate of the authority the certificates of
+    /// servers must have been issued by, directly or through intermediates.
+    pub ca: Option<&'static [u8]>,
+    /// The key with which to authenticate servers instead, should they accept it.
+    pub psk: Option<Psk>,
+}
+
+impl TlsConfig {
+    pub fn new(server_name: &'static str) -> Self {
+        Self {
+            server_name,
+            ca: None,
+            psk: None,
+        }
+    }
+}
+
+/// A handle to a connection of a `TlsStack`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TlsSocket(usize);
+
+/// The records received upon a connection, decrypted one at a time, in place.
+struct Reader {
+    data: [u8; RX_BUFFER],
+    filled: usize,
+    /// The length of the current record, if any, at the start of `data`.
+    record: usize,
+    /// The content type of the current record.
+    ty: u8,
+    /// The current record's content yet to be taken.
+    content: (usize, usize),
+    keys: Option<TrafficKeys>,
+    /// Whether the server has closed the connection.
+    closed: bool,
+}
+
+impl Reader {
+    fn new() -> Self {
+        Self {
+            data: [0; RX_BUFFER],
+            filled: 0,
+            record: 0,
+            ty: 0,
+            content: (0, 0),
+            keys: None,
+            closed: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.filled = 0;
+        self.record = 0;
+        self.discard();
+        self.keys = None;
+        self.closed = false;
+    }
+
+    /// Discard the current record, if any.
+    fn discard(&mut self) {
+        self.data.copy_within(self.record..self.filled, 0);
+        self.filled -= self.record;
+        self.record = 0;
+        self.ty = 0;
+        self.content = (0, 0);
+    }
+
+    /// The room for more bytes of the next record.
+    fn spare(&self) -> usize {
+        RX_BUFFER - self.filled
+    }
+
+    fn fill(&mut self, data: &[u8]) {
+        self.data[self.filled..self.filled + data.len()].copy_from_slice(data);
+        self.filled += data.len();
+    }
+
+    /// Open the next record, should it have been received whole, returning
+    /// its content type.
+    fn open(&mut self) -> Result<Option<u8>, TlsError> {
+        if self.filled < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = HEADER_LEN + u16::from_be_bytes([self.data[3], self.data[4]]) as usize;
+        if len > RX_BUFFER {
+            return Err(TlsError::TooLong);
+        }
+        if self.filled < len {
+            return Ok(None);
+        }
+
+        self.record = len;
+        let (header, fragment) = self.data[..len].split_at_mut(HEADER_LEN);
+        let ty = match (header[0], self.keys.as_mut()) {
+            (APPLICATION_DATA, Some(keys)) => {
+                let len = keys.open(header, fragment)?;
+                // the content is followed by its type, then any padding
+                let end = fragment[..len]
+                    .iter()
+                    .rposition(|b| *b != 0)
+                    .ok_or(TlsError::Protocol)?;
+                self.content = (HEADER_LEN, HEADER_LEN + end);
+                fragment[end]
+            }
+            (APPLICATION_DATA, None) => return Err(TlsError::Protocol),
+            // once encrypted, an unauthenticated alert could truncate the stream
+            (ty, Some(_)) if ty != CHANGE_CIPHER_SPEC => return Err(TlsError::Protocol),
+            (ty, _) => {
+                self.content = (HEADER_LEN, len);
+                ty
+            }
+        };
+        self.ty = ty;
+        Ok(Some(ty))
+    }
+
+    /// The current record's content yet to be taken.
+    fn content(&self) -> &[u8] {
+        &self.data[self.content.0..self.content.1]
+    }
+
+    /// Take as much of the current record's application data as fits in `buf`.
+    fn take(&mut self, buf: &mut [u8]) -> usize {
+        if self.ty != APPLICATION_DATA {
+            return 0;
+        }
+        let content = self.content();
+        let len = content.len().min(buf.len());
+        buf[..len].copy_from_slice(&content[..len]);
+        self.content.0 += len;
+        len
+    }
+}
+
+/// The error reported for an alert other than `close_notify`, given its content.
+fn alert(content: &[u8]) -> TlsError {
+    TlsError::Alert(content.get(1).copied().unwrap_or(0))
+}
+
+struct Connection<T> {
+    socket: Cell<Option<T>>,
+    open: Cell<bool>,
+    read: BusLock,
+    reader: RefCell<Reader>,
+    write: BusLock,
+    write_keys: RefCell<Option<TrafficKeys>>,
+}
+
+impl<T> Connection<T> {
+    fn new() -> Self {
+        Self {
+            socket: Cell::new(None),
+            open: Cell::new(false),
+            read: BusLock::new(),
+            reader: RefCell::new(Reader::new()),
+            write: BusLock::new(),
+            write_keys: RefCell::new(None),
+        }
+    }
+}
+
+/// A TLS 1.3 client upon another `TcpStack`, with which it connects to
+/// servers authenticated either by a key shared beforehand, or by their
+/// certificates, issued by a configured authority, identifying their name.
+///
+/// Being itself a `TcpStack`, it may wrap that of `EspWifi` or the
+/// `TcpIpStack`, and be used unchanged by the components written over the
+/// trait, such as the `MqttClient`.
+///
+/// Only `TLS_AES_128_GCM_SHA256` with ECDHE upon X25519, and certificates
+/// signed with ECDSA upon P-256, are supported. As devices may not know the
+/// date, the validity periods of certificates are not verified.
+///
+/// Records are received into a fixed buffer per connection, so servers
+/// are asked to send records of no more than 4096 bytes, which those not
+/// supporting the `max_fragment_length` extension may still exceed, with
+/// `TooLong` then reported.
+///
+/// The handshake is made as part of `connect(...)`, one at a time, with
+/// `rng` generating the ephemeral keys, which must therefore be
+/// cryptographically secure, such as those of a hardware generator.
+pub struct TlsStack<S, R>
+where
+    S: TcpStack + 'static,
+    R: RngCore + CryptoRng,
+{
+    stack: &'static S,
+    config: TlsConfig,
+    rng: RefCell<R>,
+    connections: Vec<Connection<S::Socket>, U2>,
+    handshake: BusLock,
+    /// The handshake messages received, yet to be processed.
+    messages: RefCell<Vec<u8, U4096>>,
+}
+
+impl<S, R> TlsStack<S, R>
+where
+    S: TcpStack + 'static,
+    R: RngCore + CryptoRng,
+{
+    pub fn new(stack: &'static S, config: TlsConfig, rng: R) -> Self {
+        let mut connections = Vec::new();
+        for _ in 0..TLS_SOCKETS {
+            connections.push(Connection::new()).ok();
+        }
+        Self {
+            stack,
+            config,
+            rng: RefCell::new(rng),
+            connections,
+            handshake: BusLock::new(),
+            messages: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn connection(
+        &self,
+        socket: TlsSocket,
+    ) -> Result<(&Connection<S::Socket>, S::Socket), TlsError> {
+        let connection = self.connections.get(socket.0).ok_or(TlsError::NoSocket)?;
+        match connection.socket.get() {
+            Some(transport) if connection.open.get() => Ok((connection, transport)),
+            _ => Err(TlsError::NoSocket),
+        }
+    }
+
+    /// Receive, *asynchronously*, the next record, returning its content
+    /// type, or `None` should the underlying connection have been closed.
+    async fn next_record(
+        &self,
+        connection: &Connection<S::Socket>,
+        transport: S::Socket,
+    ) -> Result<Option<u8>, TlsError> {
+        connection.reader.borrow_mut().discard();
+        loop {
+            let mut chunk = [0; 256];
+            let spare = {
+                let mut reader = connection.reader.borrow_mut();
+                if let Some(ty) = reader.open()? {
+                    return Ok(Some(ty));
+                }
+                reader.spare().min(chunk.len())
+            };
+            let len = self
+                .stack
+                .receive(transport, &mut chunk[..spare])
+                .await
+                .map_err(|_| TlsError::Transport)?;
+            if len == 0 {
+                return Ok(None);
+            }
+            connection.reader.borrow_mut().fill(&chunk[..len]);
+        }
+    }
+
+    /// Send, *asynchronously*, `data` as records of type `ty`, encrypted
+    /// once there are keys to do so. The write lock must be held.
+    async fn write(
+        &self,
+        connection: &Connection<S::Socket>,
+        transport: S::Socket,
+        ty: u8,
+        data: &[u8],
+    ) -> Result<(), TlsError> {
+        for chunk in data.chunks(MAX_SEND) {
+            let mut record = [0; TX_BUFFER];
+            let len = match connection.write_keys.borrow_mut().as_mut() {
+                Some(keys) => {
+                    let len = chunk.len() + 1 + TAG_LEN;
+                    record[..HEADER_LEN].copy_from_slice(&header(APPLICATION_DATA, len));
+                    let (header, fragment) = record.split_at_mut(HEADER_LEN);
+                    fragment[..chunk.len()].copy_from_slice(chunk);
+                    fragment[chunk.len()] = ty;
+                    let tag = keys.seal(header, &mut fragment[..chunk.len() + 1]);
+                    fragment[chunk.len() + 1..len].copy_from_slice(&tag);
+                    HEADER_LEN + len
+                }
+                None => {
+                    record[..HEADER_LEN].copy_from_slice(&header(ty, chunk.len()));
+                    record[HEADER_LEN..HEADER_LEN + chunk.len()].copy_from_slice(chunk);
+                    HEADER_LEN + chunk.len()
+                }
+            };
+            self.stack
+                .send(transport, &record[..len])
+                .await
+                .map_err(|_| TlsError::Transport)?;
+        }
+        Ok(())
+    }
+
+    /// Process the handshake messages the server may send after the
+    /// handshake, answering any request to update the keys. The read lock
+    /// must be held.
+    async fn post_handshake(
+        &self,
+        connection: &Connection<S::Socket>,
+        transport: S::Socket,
+    ) -> Result<(), TlsError> {
+        let mut requested = false;
+        {
+            let mut reader = connection.reader.borrow_mut();
+            let mut content = reader.content();
+            let mut updates = 0;
+            // messages spanning records are not expected, so not reassembled
+            while content.len() >= 4 {
+                let len = 4 + u32::from_be_bytes([0, content[1], content[2], content[3]]) as usize;
+                let message = content.get(..len).ok_or(TlsError::Protocol)?;
+                match message[0] {
+                    handshake::NEW_SESSION_TICKET => {}
+                    handshake::KEY_UPDATE => {
+                        updates += 1;
+                        requested |= message.get(4) == Some(&1);
+                    }
+                    _ => return Err(TlsError::Protocol),
+                }
+                content = &content[len..];
+            }
+            for _ in 0..updates {
+                if let Some(keys) = reader.keys.as_mut() {
+                    keys.update();
+                }
+            }
+        }
+        if requested {
+            let _guard = connection.write.lock().await;
+            let result = self
+                .write(connection, transport, HANDSHAKE, &handshake::KEY_UPDATED)
+                .await;
+            if let Some(keys) = connection.write_keys.borrow_mut().as_mut() {
+                keys.update();
+            }
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Receive, *asynchronously*, application data into `buf`. The read
+    /// lock must be held.
+    async fn read(
+        &self,
+        connection: &Connection<S::Socket>,
+        transport: S::Socket,
+        buf: &mut [u8],
+    ) -> Result<usize, TlsError> {
+        loop {
+            {
+                let mut reader = connection.reader.borrow_mut();
+                if reader.closed {
+                    return Ok(0);
+                }
+                let len = reader.take(buf);
+                if len > 0 {
+                    return Ok(len);
+                }
+            }
+            match self.next_record(connection, transport).await? {
+                None => return Ok(0),
+                Some(APPLICATION_DATA) | Some(CHANGE_CIPHER_SPEC) => {}
+                Some(HANDSHAKE) => self.post_handshake(connection, transport).await?,
+                Some(ALERT) => {
+                    let mut reader = connection.reader.borrow_mut();
+                    match reader.content() {
+                        [_, CLOSE_NOTIFY] => reader.closed = true,
+                        content => return Err(alert(content)),
+                    }
+                }
+                Some(_) => return Err(TlsError::Protocol),
+            }
+        }
+    }
+
+    fn release(&self, connection: &Connection<S::Socket>) {
+        connection.socket.set(None);
+        connection.write_keys.replace(None);
+        connection.open.set(false);
+    }
+}
+
+fn header(ty: u8, len: usize) -> [u8; HEADER_LEN] {
+    let len = (len as u16).to_be_bytes();
+    [ty, 3, 3, len[0], len[1]]
+}
+
+impl<S, R> TcpStack for TlsStack<S, R>
+where
+    S: TcpStack + 'static,
+    R: RngCore + CryptoRng,
+{
+    type Socket = TlsSocket;
+    type Error = TlsError;
+
+    async fn connect(&self, remote: SocketAddress) -> Result<TlsSocket, TlsError> {
+        let index = self
+            .connections
+            .iter()
+            .position(|c| !c.open.get())
+            .ok_or(TlsError::NoSocket)?;
+        let connection = &self.connections[index];
+        connection.open.set(true);
+        let transport = match self.stack.connect(remote).await {
+            Ok(transport) => transport,
+            Err(_) => {
+                self.release(connection);
+                return Err(TlsError::Transport);
+            }
+        };
+        connection.socket.set(Some(transport));
+        connection.reader.borrow_mut().reset();
+
+        let result = {
+            let _guard = self.handshake.lock().await;
+            self.handshake(connection, transport).await
+        };
+        match result {
+            Ok(()) => Ok(TlsSocket(index)),
+            Err(e) => {
+                self.stack.close(transport).await;
+                self.release(connection);
+                Err(e)
+            }
+        }
+    }
+
+    async fn send(&self, socket: TlsSocket, data: &[u8]) -> Result<usize, TlsError> {
+        let (connection, transport) = self.connection(socket)?;
+        let _guard = connection.write.lock().await;
+        let result = self
+            .write(connection, transport, APPLICATION_DATA, data)
+            .await;
+        result.map(|_| data.len())
+    }
+
+    async fn receive(&self, socket: TlsSocket, buf: &mut [u8]) -> Result<usize, TlsError> {
+        let (connection, transport) = self.connection(socket)?;
+        let _guard = connection.read.lock().await;
+        let result = self.read(connection, transport, buf).await;
+        result
+    }
+
+    async fn close(&self, socket: TlsSocket) {
+        if let Ok((connection, transport)) = self.connection(socket) {
+            {
+                let _guard = connection.write.lock().await;
+                self.write(connection, transport, ALERT, &[1, CLOSE_NOTIFY])
+                    .await
+                    .ok();
+            }
+            self.stack.close(transport).await;
+            self.release(connection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::keys::{TrafficKeys, HASH_LEN};
+    use super::{
+        CryptoRng, Psk, Reader, RngCore, TlsConfig, TlsError, TlsStack, ALERT, CHANGE_CIPHER_SPEC,
+        CLOSE_NOTIFY,
+    };
+    use crate::net::{HostStack, Ipv4Address, SocketAddress, TcpStack};
+    use crate::testing::{leak, poll_once};
+    use core::future::Future;
+    use core::task::Poll;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use std::boxed::Box;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::process::{Command, Stdio};
+    use std::string::ToString;
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// The alert sent upon a PSK binder failing to verify.
+    const DECRYPT_ERROR: u8 = 51;
+
+    /// Not at all secure, but as random as tests need.
+    struct XorShift(u64);
+
+    impl RngCore for XorShift {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for XorShift {}
+
+    type Stack = TlsStack<HostStack, XorShift>;
+
+    fn stack(config: TlsConfig) -> &'static Stack {
+        leak(TlsStack::new(
+            leak(HostStack::new()),
+            config,
+            XorShift(0x2545_f491_4f6c_dd1d),
+        ))
+    }
+
+    fn run<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        for _ in 0..5000 {
+            if let Poll::Ready(output) = poll_once(future.as_mut()) {
+                return output;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("future never completed")
+    }
+
+    fn localhost(port: u16) -> SocketAddress {
+        SocketAddress::new(Ipv4Address::LOCALHOST, port)
+    }
+
+    fn der(data: &[u8]) -> &'static [u8] {
+        Box::leak(data.to_vec().into_boxed_slice())
+    }
+
+    fn authority(key: &KeyPair) -> rcgen::Certificate {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.self_signed(key).unwrap()
+    }
+
+    /// An echo server, identified as "localhost" by a certificate issued
+    /// through an intermediate by the authority returned.
+    fn server() -> (u16, &'static [u8]) {
+        let root_key = KeyPair::generate().unwrap();
+        let root = authority(&root_key);
+        let intermediate_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let intermediate = params
+            .signed_by(&intermediate_key, &root, &root_key)
+            .unwrap();
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&leaf_key, &intermediate, &intermediate_key)
+            .unwrap();
+        let port = serve(
+            vec![leaf.der().clone(), intermediate.der().clone()],
+            &leaf_key,
+        );
+        (port, der(root.der()))
+    }
+
+    /// An echo server presenting `chain`, the first certificate of which is of `key`.
+    fn serve(chain: Vec<CertificateDer<'static>>, key: &KeyPair) -> u16 {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                chain,
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut tls = rustls::StreamOwned::new(connection, stream.unwrap());
+                thread::spawn(move || {
+                    let mut buf = [0; 1024];
+                    while let Ok(len @ 1..) = tls.read(&mut buf) {
+                        if tls.write_all(&buf[..len]).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    /// Receive exactly `len` bytes.
+    async fn receive(stack: &Stack, socket: super::TlsSocket, len: usize) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buf = [0; 512];
+        while received.len() < len {
+            let n = stack.receive(socket, &mut buf).await.unwrap();
+            assert!(n > 0, "closed early");
+            received.extend_from_slice(&buf[..n]);
+        }
+        received
+    }
+
+    #[test]
+    fn certificates() {
+        let (port, ca) = server();
+        let mut config = TlsConfig::new("localhost");
+        config.ca = Some(ca);
+        let tls = stack(config);
+
+        let socket = run(tls.connect(localhost(port))).unwrap();
+        assert_eq!(run(tls.send(socket, b"hello")), Ok(5));
+        assert_eq!(run(receive(tls, socket, 5)), b"hello");
+        let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        assert_eq!(run(tls.send(socket, &data)), Ok(3000));
+        assert_eq!(run(receive(tls, socket, 3000)), data);
+
+        let other = run(tls.connect(localhost(port))).unwrap();
+        assert_eq!(run(tls.connect(localhost(port))), Err(TlsError::NoSocket));
+        run(tls.close(other));
+        run(tls.close(socket));
+        assert_eq!(run(tls.send(socket, b"closed")), Err(TlsError::NoSocket));
+
+        // issued by another authority
+        let mut config = TlsConfig::new("localhost");
+        config.ca = Some(der(authority(&KeyPair::generate().unwrap()).der()));
+        let tls = stack(config);
+        assert_eq!(
+            run(tls.connect(localhost(port))),
+            Err(TlsError::Authentication)
+        );
+        // failing without holding onto the connection
+        assert_eq!(
+            run(tls.connect(localhost(port))),
+            Err(TlsError::Authentication)
+        );
+
+        // identifying another server
+        let mut config = TlsConfig::new("example.com");
+        config.ca = Some(ca);
+        assert_eq!(
+            run(stack(config).connect(localhost(port))),
+            Err(TlsError::Authentication)
+        );
+    }
+
+    #[test]
+    fn issued_by_leaf() {
+        let root_key = KeyPair::generate().unwrap();
+        let root = authority(&root_key);
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = CertificateParams::new(vec!["attacker.com".to_string()])
+            .unwrap()
+            .signed_by(&leaf_key, &root, &root_key)
+            .unwrap();
+        // validly signed, though not by a certificate authority
+        let forged_key = KeyPair::generate().unwrap();
+        let forged = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&forged_key, &leaf, &leaf_key)
+            .unwrap();
+        let port = serve(vec![forged.der().clone(), leaf.der().clone()], &forged_key);
+
+        let mut config = TlsConfig::new("localhost");
+        config.ca = Some(der(root.der()));
+        assert_eq!(
+            run(stack(config).connect(localhost(port))),
+            Err(TlsError::Authentication)
+        );
+    }
+
+    #[test]
+    fn plaintext_after_keys() {
+        let mut reader = Reader::new();
+        reader.keys = Some(TrafficKeys::new([0; HASH_LEN]));
+        reader.fill(&[CHANGE_CIPHER_SPEC, 3, 3, 0, 1, 1]);
+        assert_eq!(reader.open(), Ok(Some(CHANGE_CIPHER_SPEC)));
+        reader.discard();
+        // a forged close_notify
+        reader.fill(&[ALERT, 3, 3, 0, 2, 1, CLOSE_NOTIFY]);
+        assert_eq!(reader.open(), Err(TlsError::Protocol));
+        assert!(!reader.closed);
+    }
+
+    /// A server stand-in, answering any `ClientHello` with a `ServerHello`
+    /// selecting the first PSK offered, whether or not any was.
+    fn psk_selecting_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut header = [0; 5];
+            stream.read_exact(&mut header).unwrap();
+            let mut hello = vec![0; u16::from_be_bytes([header[3], header[4]]) as usize];
+            stream.read_exact(&mut hello).unwrap();
+
+            let mut extensions = vec![0, 43, 0, 2, 0x03, 0x04];
+            extensions.extend_from_slice(&[0, 51, 0, 36, 0x00, 0x1d, 0, 32]);
+            extensions.extend_from_slice(&[9; 32]);
+            extensions.extend_from_slice(&[0, 41, 0, 2, 0, 0]);
+            let mut body = vec![0x03, 0x03];
+            body.extend_from_slice(&[7; 32]);
+            body.extend_from_slice(&[0, 0x13, 0x01, 0]);
+            body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+            body.extend_from_slice(&extensions);
+            let mut record = vec![22, 0x03, 0x03];
+            record.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+            record.extend_from_slice(&[2, 0]);
+            record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            record.extend_from_slice(&body);
+            stream.write_all(&record).unwrap();
+            stream.read_to_end(&mut Vec::new()).ok();
+        });
+        port
+    }
+
+    #[test]
+    fn unoffered_pre_shared_key() {
+        let port = psk_selecting_server();
+        let mut config = TlsConfig::new("localhost");
+        config.ca = Some(der(authority(&KeyPair::generate().unwrap()).der()));
+        assert_eq!(
+            run(stack(config).connect(localhost(port))),
+            Err(TlsError::Protocol)
+        );
+    }
+
+    /// Against `openssl s_server`, reversing each line.
+    #[test]
+    #[ignore = "requires openssl installed, to serve as a PSK server"]
+    fn pre_shared_key() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = Command::new("openssl")
+            .args(["s_server", "-tls1_3", "-nocert", "-rev", "-naccept", "2"])
+            .args(["-psk", "a11ce5ec9e7d0e5a11ce5ec9e7d0e5ff"])
+            .args(["-psk_identity", "device"])
+            .args(["-accept", &port.to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut server = server.expect("openssl is not installed");
+        let mut stdout = BufReader::new(server.stdout.take().unwrap());
+        let mut line = std::string::String::new();
+        while stdout.read_line(&mut line).unwrap() > 0 && !line.contains("ACCEPT") {}
+
+        let mut config = TlsConfig::new("localhost");
+        config.psk = Some(Psk {
+            identity: b"device",
+            key: &[
+                0xa1, 0x1c, 0xe5, 0xec, 0x9e, 0x7d, 0x0e, 0x5a, 0x11, 0xce, 0x5e, 0xc9, 0xe7, 0xd0,
+                0xe5, 0xff,
+            ],
+        });
+        let tls = stack(config);
+        let socket = run(tls.connect(localhost(port))).unwrap();
+        assert_eq!(run(tls.send(socket, b"hello\n")), Ok(6));
+        assert_eq!(run(receive(tls, socket, 6)), b"olleh\n");
+        run(tls.close(socket));
+
+        // the binder of the wrong key failing to verify
+        let mut config = TlsConfig::new("localhost");
+        config.psk = Some(Psk {
+            identity: b"device",
+            key: &[0x5e; 16],
+        });
+        assert_eq!(
+            run(stack(config).connect(localhost(port))),
+            Err(TlsError::Alert(DECRYPT_ERROR))
+        );
+        server.kill().ok();
+        server.wait().ok();
+    }
+}
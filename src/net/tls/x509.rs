@@ -0,0 +1,201 @@
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+
+const SEQUENCE: u8 = 0x30;
+const OID: u8 = 0x06;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const BOOLEAN: u8 = 0x01;
+const VERSION: u8 = 0xa0;
+const EXTENSIONS: u8 = 0xa3;
+const DNS_NAME: u8 = 0x82;
+
+/// ecdsa-with-SHA256
+const ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+/// id-ecPublicKey
+const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// prime256v1
+const P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// subjectAltName
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+/// basicConstraints
+const BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+
+/// Split the DER element at the start of `data` into its tag, its content,
+/// and whatever follows it.
+fn element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81 => (*rest.first()? as usize, &rest[1..]),
+        0x82 => (
+            u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize,
+            &rest[2..],
+        ),
+        _ => return None,
+    };
+    let content = rest.get(..len)?;
+    Some((tag, content, &rest[len..]))
+}
+
+/// The content of the element at the start of `data`, should it be tagged `tag`.
+fn tagged(tag: u8, data: &[u8]) -> Option<(&[u8], &[u8])> {
+    match element(data)? {
+        (t, content, rest) if t == tag => Some((content, rest)),
+        _ => None,
+    }
+}
+
+/// A certificate, signed with ECDSA upon P-256, of a P-256 key.
+#[derive(Copy, Clone)]
+pub(crate) struct Certificate<'a> {
+    /// The signed part of the certificate, as encoded.
+    tbs: &'a [u8],
+    signature: &'a [u8],
+    /// The SEC1 encoding of the subject's key.
+    key: &'a [u8],
+    /// The subject's alternative names, if any.
+    names: &'a [u8],
+    /// Whether the subject is a certificate authority, so may sign others.
+    ca: bool,
+}
+
+impl<'a> Certificate<'a> {
+    /// Parse `der`, or `None` should it be malformed, or use algorithms other than P-256.
+    pub(crate) fn parse(der: &'a [u8]) -> Option<Self> {
+        let (certificate, _) = tagged(SEQUENCE, der)?;
+        let (tbs_content, rest) = tagged(SEQUENCE, certificate)?;
+        let tbs = &certificate[..certificate.len() - rest.len()];
+        let (algorithm, rest) = tagged(SEQUENCE, rest)?;
+        let (signature, _) = tagged(BIT_STRING, rest)?;
+        if tagged(OID, algorithm)?.0 != ECDSA_SHA256 {
+            return None;
+        }
+        let signature = match signature.split_first()? {
+            (0, signature) => signature,
+            _ => return None,
+        };
+
+        let mut rest = tbs_content;
+        if let Some((_, after)) = tagged(VERSION, rest) {
+            rest = after;
+        }
+        // the serial number, signature algorithm, issuer, validity and subject
+        for _ in 0..5 {
+            rest = element(rest)?.2;
+        }
+        let (info, rest) = tagged(SEQUENCE, rest)?;
+        let (algorithm, info) = tagged(SEQUENCE, info)?;
+        let (kind, curve) = tagged(OID, algorithm)?;
+        if kind != EC_PUBLIC_KEY || tagged(OID, curve)?.0 != P256 {
+            return None;
+        }
+        let key = match tagged(BIT_STRING, info)?.0.split_first()? {
+            (0, key) => key,
+            _ => return None,
+        };
+
+        let mut names: &[u8] = &[];
+        let mut ca = false;
+        let mut rest = rest;
+        while let Some((tag, content, after)) = element(rest) {
+            rest = after;
+            if tag != EXTENSIONS {
+                continue;
+            }
+            let (mut extensions, _) = tagged(SEQUENCE, content)?;
+            while !extensions.is_empty() {
+                let (extension, after) = tagged(SEQUENCE, extensions)?;
+                extensions = after;
+                let (id, mut value) = tagged(OID, extension)?;
+                if let Some((_, after)) = tagged(BOOLEAN, value) {
+                    value = after;
+                }
+                if id == SUBJECT_ALT_NAME {
+                    names = tagged(SEQUENCE, tagged(OCTET_STRING, value)?.0)?.0;
+                } else if id == BASIC_CONSTRAINTS {
+                    // cA, should it not be omitted as false, then any path length
+                    let constraints = tagged(SEQUENCE, tagged(OCTET_STRING, value)?.0)?.0;
+                    ca = matches!(tagged(BOOLEAN, constraints), Some((&[flag], _)) if flag != 0);
+                }
+            }
+        }
+
+        Some(Self {
+            tbs,
+            signature,
+            key,
+            names,
+            ca,
+        })
+    }
+
+    /// Determine if this certificate was signed by `issuer`, which must be
+    /// a certificate authority.
+    pub(crate) fn is_issued_by(&self, issuer: &Certificate<'_>) -> bool {
+        issuer.ca && verify(issuer.key, self.tbs, self.signature)
+    }
+
+    /// Determine if any DNS name of the subject matches `name`, the name
+    /// being at most wildcarded in its leftmost label.
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        let mut names = self.names;
+        while let Some((tag, value, rest)) = element(names) {
+            names = rest;
+            let value = match core::str::from_utf8(value) {
+                Ok(value) if tag == DNS_NAME => value,
+                _ => continue,
+            };
+            let matched = match value.strip_prefix("*.") {
+                Some(suffix) => match name.find('.') {
+                    Some(at) => name[at + 1..].eq_ignore_ascii_case(suffix),
+                    None => false,
+                },
+                None => value.eq_ignore_ascii_case(name),
+            };
+            if matched {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Verify the DER-encoded ECDSA `signature` of `message`, by the holder of
+/// `key`, a SEC1-encoded P-256 key.
+pub(crate) fn verify(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    match (
+        VerifyingKey::from_sec1_bytes(key),
+        Signature::from_der(signature),
+    ) {
+        (Ok(key), Ok(signature)) => key.verify(message, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+/// Verify that the chain of DER-encoded `certificates`, each issued by the
+/// next, a certificate authority, leads to `anchor`, and that the first
+/// identifies `name`, returning the key of the first.
+pub(crate) fn verify_chain<'a>(
+    certificates: &[&'a [u8]],
+    anchor: &Certificate<'_>,
+    name: &str,
+) -> Option<&'a [u8]> {
+    let leaf = Certificate::parse(certificates.first()?)?;
+    if !leaf.matches(name) {
+        return None;
+    }
+    let mut current = leaf;
+    let mut issuers = certificates[1..].iter();
+    loop {
+        if current.is_issued_by(anchor) {
+            return Some(leaf.key);
+        }
+        let issuer = Certificate::parse(issuers.next()?)?;
+        if !current.is_issued_by(&issuer) {
+            return None;
+        }
+        current = issuer;
+    }
+}
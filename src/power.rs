@@ -0,0 +1,67 @@
+/// The sleep states a device may enter, ordered from shallowest to deepest.
+///
+/// Components declare the deepest state they can tolerate through
+/// `ComponentContext::limit_sleep(...)`, and the kernel will only enter
+/// a state permitted by every component.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepState {
+    /// The core remains running.
+    Awake = 0,
+    /// The core is halted, with peripherals and clocks left running.
+    Sleep = 1,
+    /// Most clocks are stopped, and only the registered IRQs wake the device.
+    Stop = 2,
+}
+
+/// The low-power mode entered by the `Backend` when the executor is idle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SleepMode {
+    /// Sleep using `WFI`, waking upon any unmasked interrupt.
+    WaitForInterrupt,
+    /// Sleep using `WFE`, waking upon any event or unmasked interrupt.
+    WaitForEvent,
+    /// Enter a stop (deep-sleep) mode, waking only upon the IRQs
+    /// registered with the kernel.
+    Stop,
+}
+
+impl SleepMode {
+    /// The sleep state entered by this mode.
+    pub fn state(&self) -> SleepState {
+        match self {
+            SleepMode::WaitForInterrupt | SleepMode::WaitForEvent => SleepState::Sleep,
+            SleepMode::Stop => SleepState::Stop,
+        }
+    }
+
+    /// Restrict this mode to one no deeper than `allowed`, if possible.
+    pub fn limit(self, allowed: SleepState) -> Option<SleepMode> {
+        if self.state() <= allowed {
+            Some(self)
+        } else if allowed == SleepState::Sleep {
+            Some(SleepMode::WaitForInterrupt)
+        } else {
+            None
+        }
+    }
+}
+
+/// The policy applied by the kernel once every task spawned by
+/// the tree is pending.
+#[derive(Copy, Clone, Debug)]
+pub enum IdlePolicy {
+    /// Never sleep, the executor simply spins.
+    Spin,
+    /// Enter the given low-power mode, or the deepest mode
+    /// currently permitted by the components if shallower.
+    Sleep(SleepMode),
+    /// Invoke a custom hook, unless a component is currently
+    /// requiring the device to remain awake.
+    Custom(fn()),
+}
+
+#[doc(hidden)]
+pub trait SleepListener {
+    fn on_sleep(&self, state: SleepState);
+    fn on_wake(&self, state: SleepState);
+}
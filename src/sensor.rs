@@ -0,0 +1,311 @@
+use crate::component::{spawn, Component, ComponentContext, ConnectedComponent};
+use crate::handler::Handler;
+use crate::time::Duration;
+use core::cell::Cell;
+
+/// Standard gravity, in metres per second squared.
+pub const STANDARD_GRAVITY: f32 = 9.80665;
+
+/// A temperature, in degrees Celsius.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Celsius(pub f32);
+
+impl Celsius {
+    pub fn fahrenheit(&self) -> f32 {
+        self.0 * 9.0 / 5.0 + 32.0
+    }
+}
+
+/// A relative humidity, in percent.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct RelativeHumidity(pub f32);
+
+/// A pressure, in hectopascals.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Hectopascals(pub f32);
+
+/// An acceleration along three axes, in metres per second squared.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Acceleration {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Acceleration {
+    /// Create an acceleration from components in multiples of standard gravity.
+    pub fn from_g(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            x: x * STANDARD_GRAVITY,
+            y: y * STANDARD_GRAVITY,
+            z: z * STANDARD_GRAVITY,
+        }
+    }
+}
+
+/// A single reading taken by a `Sensor`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Reading {
+    Temperature(Celsius),
+    Humidity(RelativeHumidity),
+    Pressure(Hectopascals),
+    Acceleration(Acceleration),
+}
+
+/// Errors reported by a `Sensor`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SensorError {
+    /// Communication with the sensor failed.
+    Bus,
+    /// The sensor did not identify itself as expected.
+    NotFound,
+    /// The sensor had no fresh data to be read.
+    NotReady,
+    /// The sensor's factory calibration could not be applied.
+    Calibration,
+}
+
+/// Messages accepted by a `Sensor`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SensorRequest {
+    /// Take a sample, sending each resulting `Reading` upstream.
+    Sample,
+}
+
+/// Messages sent upstream by a `Sensor`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SensorEvent {
+    Reading(Reading),
+    Error(SensorError),
+}
+
+/// A component taking samples upon request, and sending
+/// standardized `Reading`s upstream.
+///
+/// Any component accepting `SensorRequest`s and sending `SensorEvent`s
+/// is a sensor. A single sample may produce several readings, such
+/// as both a temperature and a humidity, each sent as a separate event.
+pub trait Sensor: Component<InboundMessage = SensorRequest, OutboundMessage = SensorEvent> {}
+
+impl<C> Sensor for C where
+    C: Component<InboundMessage = SensorRequest, OutboundMessage = SensorEvent>
+{
+}
+
+/// Messages accepted by a `Sampler`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplerMessage {
+    /// Change the interval between samples, effective after the next sample.
+    SetInterval(Duration),
+    /// Take a sample immediately, in addition to those taken periodically.
+    SampleNow,
+    /// Stop taking periodic samples.
+    Pause,
+    /// Resume taking periodic samples.
+    Resume,
+}
+
+/// A component periodically sampling the `Sensor` it contains, and
+/// forwarding each `SensorEvent` upstream, so that its parent need
+/// not be aware of the particular sensor.
+///
+/// Samples are timed using the kernel's time source, so the
+/// `systick` option of `device!` must be enabled.
+pub struct Sampler<S: Sensor>
+where
+    S: 'static,
+{
+    sensor: ConnectedComponent<S>,
+    interval: Cell<Duration>,
+    paused: Cell<bool>,
+    ctx: Option<&'static ComponentContext<Self>>,
+}
+
+impl<S: Sensor> Sampler<S> {
+    /// Create a new sampler, taking a sample every `interval`.
+    pub fn new(sensor: S, interval: Duration) -> Self {
+        Self {
+            sensor: ConnectedComponent::new(sensor),
+            interval: Cell::new(interval),
+            paused: Cell::new(false),
+            ctx: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval.get()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.get()
+    }
+
+    fn control(&self, message: SamplerMessage) {
+        match message {
+            SamplerMessage::SetInterval(interval) => self.interval.set(interval),
+            SamplerMessage::SampleNow => self.sensor.send(SensorRequest::Sample),
+            SamplerMessage::Pause => self.paused.set(true),
+            SamplerMessage::Resume => self.paused.set(false),
+        }
+    }
+
+    async fn sample_periodically(&self, ctx: &'static ComponentContext<Self>) {
+        loop {
+            if !self.paused.get() {
+                self.sensor.send(SensorRequest::Sample);
+            }
+            ctx.delay(self.interval.get()).await;
+        }
+    }
+
+    async fn receive_control(&self, ctx: &'static ComponentContext<Self>) {
+        loop {
+            self.control(ctx.receive().await);
+        }
+    }
+}
+
+impl<S: Sensor> Component for Sampler<S> {
+    type InboundMessage = SamplerMessage;
+    type OutboundMessage = SensorEvent;
+
+    fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+        self.ctx.replace(ctx);
+        self.sensor.start(ctx);
+
+        let sampler: &'static Self = self;
+        spawn("sampler", sampler.sample_periodically(ctx));
+        spawn("sampler-control", sampler.receive_control(ctx));
+    }
+}
+
+impl<S: Sensor> Handler<SensorEvent> for Sampler<S> {
+    fn on_message(&mut self, message: SensorEvent) {
+        if let Some(ctx) = self.ctx {
+            ctx.send(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{
+        Acceleration, Celsius, Reading, Sampler, SamplerMessage, SensorError, SensorEvent,
+        SensorRequest, STANDARD_GRAVITY,
+    };
+    use crate::component::{spawn, Component, ComponentContext, ConnectedComponent};
+    use crate::testing::{leak, poll_once, Upstream};
+    use crate::time::Duration;
+    use core::cell::Cell;
+    use std::boxed::Box;
+
+    #[test]
+    fn units() {
+        assert_eq!(Celsius(100.0).fahrenheit(), 212.0);
+        assert_eq!(Celsius(-40.0).fahrenheit(), -40.0);
+
+        let a = Acceleration::from_g(0.0, -0.5, 1.0);
+        assert_eq!(a.y, -STANDARD_GRAVITY / 2.0);
+        assert_eq!(a.z, STANDARD_GRAVITY);
+    }
+
+    /// A sensor reading the number of samples taken as its temperature,
+    /// unless failing.
+    struct MockSensor {
+        samples: Cell<u32>,
+        failing: Cell<bool>,
+    }
+
+    impl MockSensor {
+        async fn run(&self, ctx: &'static ComponentContext<Self>) {
+            loop {
+                match ctx.receive().await {
+                    SensorRequest::Sample => {
+                        self.samples.set(self.samples.get() + 1);
+                        ctx.send(if self.failing.get() {
+                            SensorEvent::Error(SensorError::Bus)
+                        } else {
+                            temperature(self.samples.get() as f32)
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    impl Component for MockSensor {
+        type InboundMessage = SensorRequest;
+        type OutboundMessage = SensorEvent;
+
+        fn start(&'static mut self, ctx: &'static ComponentContext<Self>) {
+            spawn("mock-sensor", self.run(ctx));
+        }
+    }
+
+    fn temperature(celsius: f32) -> SensorEvent {
+        SensorEvent::Reading(Reading::Temperature(Celsius(celsius)))
+    }
+
+    #[test]
+    fn sampler() {
+        let upstream = leak(Upstream::new());
+        let connected = leak(ConnectedComponent::new(Sampler::new(
+            MockSensor {
+                samples: Cell::new(0),
+                failing: Cell::new(false),
+            },
+            Duration::from_millis(100),
+        )));
+        // started as `start(...)` would, but with each task polled here
+        let (sampler, ctx) = connected.connect(upstream);
+        let sampler: &'static mut Sampler<MockSensor> = unsafe { &mut *sampler };
+        sampler.ctx.replace(ctx);
+        let sampler: &'static Sampler<MockSensor> = sampler;
+        let (sensor, sensor_ctx) = sampler.sensor.connect(ctx);
+        let sensor: &'static MockSensor = unsafe { &*sensor };
+
+        let mut periodic = Box::pin(sampler.sample_periodically(ctx));
+        let mut control = Box::pin(sampler.receive_control(ctx));
+        let mut sensing = Box::pin(sensor.run(sensor_ctx));
+        let mut poll = || {
+            assert!(poll_once(control.as_mut()).is_pending());
+            assert!(poll_once(periodic.as_mut()).is_pending());
+            assert!(poll_once(sensing.as_mut()).is_pending());
+            upstream.take()
+        };
+
+        // sampled immediately, then every interval
+        assert_eq!(&poll()[..], &[temperature(1.0)]);
+        upstream.advance(Duration::from_millis(99));
+        assert_eq!(&poll()[..], &[]);
+        upstream.advance(Duration::from_millis(1));
+        assert_eq!(&poll()[..], &[temperature(2.0)]);
+
+        // errors are forwarded just as readings are
+        sensor.failing.set(true);
+        upstream.advance(Duration::from_millis(100));
+        assert_eq!(&poll()[..], &[SensorEvent::Error(SensorError::Bus)]);
+        sensor.failing.set(false);
+
+        // paused, though still sampled upon request
+        connected.send(SamplerMessage::Pause);
+        assert_eq!(&poll()[..], &[]);
+        assert!(sampler.is_paused());
+        upstream.advance(Duration::from_millis(100));
+        assert_eq!(&poll()[..], &[]);
+        connected.send(SamplerMessage::SampleNow);
+        assert_eq!(&poll()[..], &[temperature(4.0)]);
+
+        // resumed at a new interval, effective after the next sample
+        connected.send(SamplerMessage::SetInterval(Duration::from_millis(50)));
+        connected.send(SamplerMessage::Resume);
+        assert_eq!(&poll()[..], &[]);
+        upstream.advance(Duration::from_millis(100));
+        assert_eq!(&poll()[..], &[temperature(5.0)]);
+        upstream.advance(Duration::from_millis(50));
+        assert_eq!(&poll()[..], &[temperature(6.0)]);
+        assert_eq!(sampler.interval(), Duration::from_millis(50));
+    }
+}
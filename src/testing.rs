@@ -0,0 +1,250 @@
+extern crate std;
+
+use crate::context::UpstreamContext;
+use crate::interrupt::Interruptable;
+use crate::power::{SleepListener, SleepState};
+use crate::time::{Duration, Instant};
+use crate::watchdog::Liveness;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::boxed::Box;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::string::String;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::vec::Vec;
+
+/// Place a value in `'static` memory for the remainder of the test run.
+pub fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    unsafe { Waker::from_raw(clone(core::ptr::null())) }
+}
+
+/// Poll a future once, without an executor.
+pub fn poll_once<F: Future + ?Sized>(future: Pin<&mut F>) -> Poll<F::Output> {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    future.poll(&mut cx)
+}
+
+/// Poll a future to completion, without an executor, panicking if it
+/// remains pending after a generous number of polls.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    for _ in 0..10_000 {
+        if let Poll::Ready(output) = poll_once(future.as_mut()) {
+            return output;
+        }
+    }
+    panic!("future never completed")
+}
+
+/// An upstream context for components' tasks run without a kernel, recording
+/// the messages sent, and whose time only advances through `advance(...)`.
+///
+/// Delays never wake their tasks, which must instead be polled repeatedly.
+pub struct Upstream<M> {
+    messages: RefCell<Vec<M>>,
+    now: Cell<Instant>,
+}
+
+impl<M> Upstream<M> {
+    pub fn new() -> Self {
+        Self {
+            messages: RefCell::new(Vec::new()),
+            now: Cell::new(Instant::from_millis(0)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration)
+    }
+
+    /// Take the messages sent so far.
+    pub fn take(&self) -> Vec<M> {
+        self.messages.replace(Vec::new())
+    }
+}
+
+impl<M> UpstreamContext<M> for Upstream<M> {
+    fn send(&self, message: M) {
+        self.messages.borrow_mut().push(message)
+    }
+
+    fn register_irq(&self, _irq: u8, _interrupt: &'static dyn Interruptable) {}
+
+    fn register_sleep_listener(&self, _listener: &'static dyn SleepListener) {}
+
+    fn vote_sleep(&self, _previous: SleepState, _current: SleepState) {}
+
+    fn register_liveness(&self, _liveness: &'static Liveness) {}
+
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn schedule(&self, _deadline: Instant, _waker: Waker) {}
+
+    fn pend(&self, _irq: u8) {}
+}
+
+/// What the MQTT broker stand-in has received, and how it is to behave.
+#[derive(Default)]
+pub struct BrokerLog {
+    pub connects: usize,
+    /// The username and password of each connection, each empty if absent.
+    pub credentials: Vec<(String, String)>,
+    pub pings: usize,
+    pub published: Vec<(String, Vec<u8>)>,
+    pub acknowledged: Vec<u16>,
+    /// Publications upon which to drop the connection, unacknowledged.
+    pub drop: usize,
+    /// Messages sent, at QoS 0, upon every subscription granted.
+    pub on_subscribe: Vec<(String, Vec<u8>)>,
+}
+
+fn read_string(body: &[u8]) -> (String, &[u8]) {
+    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let value = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+    (value, &body[2 + len..])
+}
+
+/// An MQTT broker stand-in on the loopback interface, accepting one
+/// connection at a time, which echoes messages published upon topics
+/// subscribed to, refuses subscriptions to `forbidden`, and drops the
+/// connection upon a message to `kick`. Returns its port and its log.
+pub fn broker() -> (u16, Arc<Mutex<BrokerLog>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let log = Arc::new(Mutex::new(BrokerLog::default()));
+    let shared = log.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve(stream.unwrap(), &shared);
+        }
+    });
+    (port, log)
+}
+
+/// A publication of `payload` upon `topic`, identified by `id` should `qos`
+/// exceed zero.
+fn publication(topic: &str, qos: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = std::vec![0x30 | qos << 1, 0];
+    packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+    packet.extend_from_slice(topic.as_bytes());
+    if qos > 0 {
+        packet.extend_from_slice(&id.to_be_bytes());
+    }
+    packet.extend_from_slice(payload);
+    packet[1] = (packet.len() - 2) as u8;
+    packet
+}
+
+fn serve(mut stream: TcpStream, log: &Mutex<BrokerLog>) {
+    let mut subscriptions: Vec<(String, u8)> = Vec::new();
+    loop {
+        let mut header = [0; 1];
+        if stream.read_exact(&mut header).is_err() {
+            return;
+        }
+        let mut len = 0;
+        let mut shift = 0;
+        loop {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).unwrap();
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = std::vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+
+        match header[0] >> 4 {
+            1 => {
+                // protocol name, level, flags and keep-alive precede the payload
+                let flags = body[7];
+                let (_, rest) = read_string(&body[10..]);
+                let (username, rest) = if flags & 0x80 != 0 {
+                    read_string(rest)
+                } else {
+                    (String::new(), rest)
+                };
+                let password = if flags & 0x40 != 0 {
+                    read_string(rest).0
+                } else {
+                    String::new()
+                };
+                let mut log = log.lock().unwrap();
+                log.connects += 1;
+                log.credentials.push((username, password));
+                stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            }
+            3 => {
+                let qos = (header[0] >> 1) & 0x03;
+                let (topic, rest) = read_string(&body);
+                let mut log = log.lock().unwrap();
+                if log.drop > 0 {
+                    log.drop -= 1;
+                    return;
+                }
+                let payload = if qos > 0 {
+                    stream.write_all(&[0x40, 2, rest[0], rest[1]]).unwrap();
+                    &rest[2..]
+                } else {
+                    rest
+                };
+                log.published.push((topic.clone(), payload.to_vec()));
+                if topic == "kick" {
+                    return;
+                }
+                for (filter, granted) in &subscriptions {
+                    if *filter == topic {
+                        let packet = publication(&topic, qos.min(*granted), 0x1234, payload);
+                        stream.write_all(&packet).unwrap();
+                    }
+                }
+            }
+            4 => {
+                let id = u16::from_be_bytes([body[0], body[1]]);
+                log.lock().unwrap().acknowledged.push(id);
+            }
+            8 => {
+                let (filter, rest) = read_string(&body[2..]);
+                if filter == "forbidden" {
+                    stream
+                        .write_all(&[0x90, 3, body[0], body[1], 0x80])
+                        .unwrap();
+                    continue;
+                }
+                subscriptions.push((filter, rest[0]));
+                stream
+                    .write_all(&[0x90, 3, body[0], body[1], rest[0]])
+                    .unwrap();
+                for (topic, payload) in &log.lock().unwrap().on_subscribe {
+                    stream
+                        .write_all(&publication(topic, 0, 0, payload))
+                        .unwrap();
+                }
+            }
+            12 => {
+                log.lock().unwrap().pings += 1;
+                stream.write_all(&[0xd0, 0]).unwrap();
+            }
+            _ => return,
+        }
+    }
+}
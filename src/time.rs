@@ -0,0 +1,108 @@
+use crate::context::UpstreamContext;
+use core::future::Future;
+use core::ops::{Add, Sub};
+use core::pin::Pin;
+use core::task::{Context as FutureContext, Poll};
+pub use core::time::Duration;
+
+/// A point in time, measured in milliseconds since the kernel's
+/// time source was started.
+///
+/// On Cortex-M the time source is the `SysTick`, enabled through the
+/// `systick` option of `device!`. On the host, time only advances
+/// through `Host::advance(...)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    millis: u64,
+}
+
+impl Instant {
+    pub const fn from_millis(millis: u64) -> Self {
+        Self { millis }
+    }
+
+    /// Milliseconds since the time source was started.
+    pub fn as_millis(&self) -> u64 {
+        self.millis
+    }
+
+    /// The duration elapsed from `earlier` until this instant,
+    /// or zero if `earlier` is in fact later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_millis(self.millis.saturating_sub(earlier.millis))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Instant::from_millis(self.millis + rhs.as_millis() as u64)
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// Future which completes once the kernel's time source reaches
+/// a deadline, relying upon the kernel's `tick()` to be woken.
+pub(crate) struct Delay<'c, M> {
+    context: &'c dyn UpstreamContext<M>,
+    deadline: Instant,
+}
+
+impl<'c, M> Delay<'c, M> {
+    pub(crate) fn new(context: &'c dyn UpstreamContext<M>, deadline: Instant) -> Self {
+        Self { context, deadline }
+    }
+}
+
+impl<'c, M> Future for Delay<'c, M> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        if self.context.now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            self.context.schedule(self.deadline, cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A future completing with that of `future`, or `None` should `delay` complete first.
+pub(crate) struct WithTimeout<F, D> {
+    future: F,
+    delay: D,
+}
+
+impl<F, D> WithTimeout<F, D> {
+    pub(crate) fn new(future: F, delay: D) -> Self {
+        Self { future, delay }
+    }
+}
+
+impl<F, D> Future for WithTimeout<F, D>
+where
+    F: Future,
+    D: Future<Output = ()>,
+{
+    type Output = Option<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut FutureContext<'_>) -> Poll<Self::Output> {
+        // neither field is ever moved out of its pinned parent
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        match unsafe { Pin::new_unchecked(&mut this.delay) }.poll(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::backend::{self, Backend, Host};
+use crate::time::{Duration, Instant};
+use core::cell::{Cell, RefCell};
+use embedded_hal::watchdog::Watchdog;
+
+/// A hardware watchdog, fed by the kernel only while every component
+/// registered through `ComponentContext::register_watchdog(...)` has
+/// checked in within its declared deadline.
+///
+/// Once a component stalls, the kernel reports it through
+/// `Kernel::on_watchdog_stall(...)` and stops feeding, allowing the
+/// watchdog to reset the device.
+pub trait HardwareWatchdog {
+    /// Feed the watchdog, postponing a reset.
+    fn feed(&self);
+}
+
+/// Adapter for any embedded-hal `Watchdog`, which should already
+/// have been started.
+pub struct HalWatchdog<W: Watchdog> {
+    watchdog: RefCell<W>,
+}
+
+impl<W: Watchdog> HalWatchdog<W> {
+    pub fn new(watchdog: W) -> Self {
+        Self {
+            watchdog: RefCell::new(watchdog),
+        }
+    }
+}
+
+impl<W: Watchdog> HardwareWatchdog for HalWatchdog<W> {
+    fn feed(&self) {
+        self.watchdog.borrow_mut().feed()
+    }
+}
+
+/// A watchdog for the `Host` backend, which expires if not fed
+/// within its timeout according to the host's simulated time.
+pub struct SimulatedWatchdog {
+    host: &'static Host,
+    timeout: Duration,
+    last_feed: Cell<Instant>,
+    feeds: Cell<usize>,
+}
+
+impl SimulatedWatchdog {
+    pub fn new(host: &'static Host, timeout: Duration) -> Self {
+        Self {
+            host,
+            timeout,
+            last_feed: Cell::new(host.now()),
+            feeds: Cell::new(0),
+        }
+    }
+
+    /// The number of times the watchdog has been fed.
+    pub fn feeds(&self) -> usize {
+        self.feeds.get()
+    }
+
+    /// Determine if a real watchdog would have reset the device by now.
+    pub fn has_expired(&self) -> bool {
+        self.host.now() - self.last_feed.get() > self.timeout
+    }
+}
+
+impl HardwareWatchdog for SimulatedWatchdog {
+    fn feed(&self) {
+        self.last_feed.set(self.host.now());
+        self.feeds.set(self.feeds.get() + 1);
+    }
+}
+
+#[doc(hidden)]
+pub struct Liveness {
+    name: &'static str,
+    deadline: Cell<Option<Duration>>,
+    last_checkin: Cell<Instant>,
+}
+
+impl Liveness {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            deadline: Cell::new(None),
+            last_checkin: Cell::new(Instant::from_millis(0)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn is_registered(&self) -> bool {
+        self.deadline.get().is_some()
+    }
+
+    // read by the kernel's `tick()`, from the `SysTick` handler, so never
+    // written in halves
+    pub(crate) fn set_deadline(&self, deadline: Duration, now: Instant) {
+        backend::free(|_| {
+            self.deadline.set(Some(deadline));
+            self.last_checkin.set(now);
+        })
+    }
+
+    pub(crate) fn checkin(&self, now: Instant) {
+        backend::free(|_| self.last_checkin.set(now))
+    }
+
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        match self.deadline.get() {
+            Some(deadline) => now - self.last_checkin.get() > deadline,
+            None => false,
+        }
+    }
+}